@@ -0,0 +1,159 @@
+//! User-defined slash-command aliases loaded from `~/.rust_chat_aliases`
+//!
+//! Each line maps an alias to a built-in client command:
+//!
+//! ```text
+//! # Lines starting with '#' are ignored
+//! /w = /dm
+//! /l = /list
+//! ```
+
+use shared::commands::client as commands;
+use shared::logger;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const ALIASES_FILE_NAME: &str = ".rust_chat_aliases";
+
+static INSTALLED: OnceLock<Aliases> = OnceLock::new();
+
+#[derive(Debug, Default)]
+pub struct Aliases {
+    /// Maps alias (e.g. "/w") to the built-in command name (e.g. "/dm")
+    map: HashMap<String, &'static str>,
+}
+
+impl Aliases {
+    /// Load aliases from the user's home directory, warning about invalid entries
+    pub fn load() -> Self {
+        let Some(path) = aliases_path() else {
+            return Self::default();
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                logger::log_warning(&format!("Failed to read {}: {}", path.display(), e));
+                return Self::default();
+            }
+        };
+
+        let (aliases, warnings) = Self::parse(&contents);
+        for warning in warnings {
+            logger::log_warning(&format!("{}: {}", path.display(), warning));
+        }
+        if !aliases.map.is_empty() {
+            logger::log_info(&format!(
+                "Loaded {} command alias(es) from {}",
+                aliases.map.len(),
+                path.display()
+            ));
+        }
+        aliases
+    }
+
+    /// Parse alias definitions, returning the valid aliases and a warning per bad line
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut map = HashMap::new();
+        let mut warnings = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((alias, target)) = line.split_once('=') else {
+                warnings.push(format!(
+                    "line {}: expected '<alias> = <command>'",
+                    line_number
+                ));
+                continue;
+            };
+            let (alias, target) = (alias.trim(), target.trim());
+
+            if !alias.starts_with('/') || alias.len() < 2 || alias.contains(char::is_whitespace) {
+                warnings.push(format!(
+                    "line {}: alias '{}' must be a single word starting with '/'",
+                    line_number, alias
+                ));
+                continue;
+            }
+            if commands::ALL.iter().any(|cmd| cmd.matches(alias)) {
+                warnings.push(format!(
+                    "line {}: alias '{}' would shadow a built-in command",
+                    line_number, alias
+                ));
+                continue;
+            }
+            let Some(command) = commands::ALL.iter().find(|cmd| cmd.matches(target)) else {
+                warnings.push(format!(
+                    "line {}: unknown command '{}' for alias '{}'",
+                    line_number, target, alias
+                ));
+                continue;
+            };
+
+            map.insert(alias.to_string(), command.name);
+        }
+
+        (Aliases { map }, warnings)
+    }
+
+    /// Map an alias to its built-in command, or return the input unchanged
+    pub fn resolve<'a>(&self, cmd: &'a str) -> &'a str {
+        self.map.get(cmd).copied().unwrap_or(cmd)
+    }
+
+    /// All alias names (for completion)
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.map.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+/// Make `aliases` the set used for input parsing and completion
+pub fn install(aliases: Aliases) {
+    let _ = INSTALLED.set(aliases);
+}
+
+/// The installed aliases (empty if none were installed)
+pub fn installed() -> &'static Aliases {
+    INSTALLED.get_or_init(Aliases::default)
+}
+
+fn aliases_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(ALIASES_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_valid_aliases() {
+        let (aliases, warnings) = Aliases::parse("# my aliases\n/w = /dm\n\n/l=/list\n");
+        assert!(warnings.is_empty());
+        assert_eq!(aliases.resolve("/w"), "/dm");
+        assert_eq!(aliases.resolve("/l"), "/list");
+        assert_eq!(aliases.resolve("/help"), "/help");
+        assert_eq!(aliases.names(), vec!["/l", "/w"]);
+    }
+
+    #[test]
+    fn test_parse_invalid_aliases_warned() {
+        let (aliases, warnings) =
+            Aliases::parse("/w = /whisper\n/dm = /list\nw = /dm\n/x /dm\n/ok = /quit\n");
+        assert_eq!(warnings.len(), 4);
+        assert!(warnings[0].contains("line 1") && warnings[0].contains("/whisper"));
+        assert!(warnings[1].contains("shadow"));
+        assert!(warnings[2].contains("starting with '/'"));
+        assert!(warnings[3].contains("expected"));
+        assert_eq!(aliases.names(), vec!["/ok"]);
+    }
+}
@@ -1,22 +1,43 @@
+use crate::completer::CompletionState;
+use crate::downloads::{self, DOWNLOADS_DIR, OverwritePolicy};
+use crate::e2e::{self, E2eSession, KeyUpdate};
 use crate::input::{self, ClientUserInput};
-use crate::readline_helper;
+use crate::latency::{LatencyTracker, PONG_TIMEOUT};
+use crate::notify::{self, BellEvent, BellEvents, Notifier};
+use crate::pause::{PAUSE_BUFFER_LIMIT, PauseBuffer};
+use crate::profiles::{Profile, Profiles};
+use crate::readline_helper::{self, ReadlineInput};
+use crate::resize;
+use crate::stdio::{self, StdioEvent};
 use rustls::ClientConfig;
 use rustls::pki_types::ServerName;
 use shared::commands::client as commands;
+use shared::compression::COMPRESSION_ALGORITHM;
+use shared::file_transfer::{
+    FileAckHeader, FileOfferHeader, FileRequestHeader, FileResponseHeader, FileTransferError,
+    FileTransferHeader, FileUploadHeader,
+};
+use shared::limits::{self, MAX_CAPTION_LENGTH, MAX_SHARED_FILE_SIZE, ServerLimits};
 use shared::logger;
-use shared::message::{ChatMessage, ChatMessageError, MessageTypes};
+use shared::message::{
+    AWAY_STATUS_PREFIX, ChatMessage, ChatMessageError, DND_STATUS, ErrorCode, MessageTypes,
+    PollSummary, READONLY_JOIN_FLAG, SESSION_SECRET_JOIN_PREFIX, ServerInfo, UserListPage,
+    mentions_user, parse_error, parse_name_suggestion, parse_stamped_chat_content,
+};
 use shared::network::{MAX_FILE_SIZE, TcpMessageHandler};
 use shared::version::VERSION;
-use std::collections::{HashMap, HashSet};
-use std::io;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
 use std::net::AddrParseError;
 use std::path::Path;
 use std::pin::Pin;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio_rustls::TlsConnector;
 use tokio_rustls::client::TlsStream;
@@ -30,6 +51,41 @@ pub struct PendingOutgoingTransfer {
     pub file_name: String,
     #[allow(dead_code)]
     pub file_size: usize,
+    /// Contents piped on stdin (`--send`); files are read from `file_path` instead
+    pub data: Option<Vec<u8>>,
+}
+
+/// Largest file that can be sent (the frame limit less room for headers)
+pub const MAX_FILE_CONTENT_SIZE: usize = MAX_FILE_SIZE - 1024;
+
+/// Read piped file contents (e.g. stdin for `--send`), whose length isn't
+/// known upfront, failing once they exceed `MAX_FILE_CONTENT_SIZE`
+pub fn read_piped_file(reader: impl Read) -> io::Result<Vec<u8>> {
+    read_to_limit(reader, MAX_FILE_CONTENT_SIZE)
+}
+
+/// A file size for display, e.g. `512 bytes`, `3.2 KB` or `1.5 MB`
+fn format_file_size(file_size: usize) -> String {
+    if file_size >= 1024 * 1024 {
+        format!("{:.1} MB", file_size as f64 / (1024.0 * 1024.0))
+    } else if file_size >= 1024 {
+        format!("{:.1} KB", file_size as f64 / 1024.0)
+    } else {
+        format!("{} bytes", file_size)
+    }
+}
+
+fn read_to_limit(reader: impl Read, limit: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    // One byte past the limit is enough to tell the input is too large
+    reader.take(limit as u64 + 1).read_to_end(&mut data)?;
+    if data.len() > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Input too large (max {} bytes / ~100MB)", limit),
+        ));
+    }
+    Ok(data)
 }
 
 /// Pending file transfer request (for receivers)
@@ -45,8 +101,44 @@ pub struct PendingIncomingTransfer {
 #[derive(Debug)]
 pub enum ChatClientError {
     InvalidAddress,
-    IoError,
-    ChatMessageError,
+    /// The server host name could not be resolved
+    DnsError(io::Error),
+    /// Nothing is listening on the server address
+    ConnectionRefused(io::Error),
+    /// Invalid server name for TLS or a failed handshake
+    TlsError(io::Error),
+    IoError(io::Error),
+    ChatMessageError(ChatMessageError),
+    /// A file transfer field (e.g. a file name) doesn't fit the wire format
+    FileTransferError(FileTransferError),
+}
+
+impl std::fmt::Display for ChatClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatClientError::InvalidAddress => write!(f, "Invalid server address"),
+            ChatClientError::DnsError(e) => write!(f, "Could not resolve server address: {}", e),
+            ChatClientError::ConnectionRefused(e) => write!(f, "Connection refused: {}", e),
+            ChatClientError::TlsError(e) => write!(f, "TLS Error: {}", e),
+            ChatClientError::IoError(e) => write!(f, "IO Error: {}", e),
+            ChatClientError::ChatMessageError(e) => write!(f, "Message Error: {}", e),
+            ChatClientError::FileTransferError(e) => write!(f, "File Transfer Error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChatClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChatClientError::InvalidAddress => None,
+            ChatClientError::DnsError(e)
+            | ChatClientError::ConnectionRefused(e)
+            | ChatClientError::TlsError(e)
+            | ChatClientError::IoError(e) => Some(e),
+            ChatClientError::ChatMessageError(e) => Some(e),
+            ChatClientError::FileTransferError(e) => Some(e),
+        }
+    }
 }
 
 impl From<AddrParseError> for ChatClientError {
@@ -56,14 +148,116 @@ impl From<AddrParseError> for ChatClientError {
 }
 
 impl From<io::Error> for ChatClientError {
-    fn from(_: io::Error) -> Self {
-        ChatClientError::IoError
+    fn from(e: io::Error) -> Self {
+        ChatClientError::IoError(e)
     }
 }
 
 impl From<ChatMessageError> for ChatClientError {
-    fn from(_: ChatMessageError) -> Self {
-        ChatClientError::ChatMessageError
+    fn from(e: ChatMessageError) -> Self {
+        ChatClientError::ChatMessageError(e)
+    }
+}
+
+impl From<FileTransferError> for ChatClientError {
+    fn from(e: FileTransferError) -> Self {
+        ChatClientError::FileTransferError(e)
+    }
+}
+
+/// Port used when a server address doesn't name one
+const DEFAULT_SERVER_PORT: u16 = 8080;
+
+/// A parsed server address: `[tls://]host[:port][/channel][#nickname]`, with
+/// IPv6 hosts in brackets (e.g. `tls://[::1]:8443/general#alice`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddr {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    /// Channel to join on connect (ignored until servers have channels)
+    pub channel: Option<String>,
+    /// Username suggested by whoever shared the address
+    pub nickname: Option<String>,
+}
+
+/// `host:port`, bracketing IPv6 hosts so the port stays unambiguous
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Resolve `host` and connect to the first address that accepts, keeping
+/// DNS failures and refused connections apart from other IO errors
+async fn connect_tcp(host: &str, port: u16) -> Result<TcpStream, ChatClientError> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(ChatClientError::DnsError)?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(match last_error {
+        Some(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            ChatClientError::ConnectionRefused(e)
+        }
+        Some(e) => ChatClientError::IoError(e),
+        None => ChatClientError::DnsError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for {}", host),
+        )),
+    })
+}
+
+/// Wrap `stream` in TLS for `host`, verified against the bundled web PKI roots
+async fn connect_tls(
+    host: &str,
+    stream: TcpStream,
+) -> Result<TlsStream<TcpStream>, ChatClientError> {
+    let mut root_cert_store = rustls::RootCertStore::empty();
+    root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth();
+
+    let connector = TlsConnector::from(Arc::new(config));
+    let server_name = ServerName::try_from(host.to_string()).map_err(|e| {
+        ChatClientError::TlsError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid server name '{}': {}", host, e),
+        ))
+    })?;
+
+    connector
+        .connect(server_name, stream)
+        .await
+        .map_err(ChatClientError::TlsError)
+}
+
+/// Whether a failed TLS handshake means the server doesn't speak TLS at all:
+/// it hung up, or answered with something that isn't a TLS record. A
+/// certificate or verification failure means a TLS server we can't trust,
+/// which must never be retried in plaintext.
+fn server_lacks_tls(error: &ChatClientError) -> bool {
+    let ChatClientError::TlsError(e) = error else {
+        return false;
+    };
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset => true,
+        _ => matches!(
+            e.get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>()),
+            Some(rustls::Error::InvalidMessage(_))
+        ),
     }
 }
 
@@ -112,6 +306,62 @@ impl AsyncWrite for ClientStream {
     }
 }
 
+/// What to do when the TLS handshake with a `tls://` server fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsFallback {
+    /// Give up (the server may not speak TLS; drop the `tls://` prefix)
+    #[default]
+    Never,
+    /// Ask on the terminal before retrying as plain TCP
+    Prompt,
+    /// Retry as plain TCP without asking
+    Auto,
+}
+
+impl TlsFallback {
+    /// Whether to retry the connection without TLS
+    async fn allows_plaintext(self) -> bool {
+        match self {
+            TlsFallback::Never => false,
+            TlsFallback::Auto => true,
+            TlsFallback::Prompt => tokio::task::spawn_blocking(confirm_plaintext)
+                .await
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Ask whether to continue unencrypted (anything but "y" declines)
+fn confirm_plaintext() -> bool {
+    logger::log_warning("Retry without TLS? Messages and files will NOT be encrypted [y/N]:");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+/// Show the saved connection profiles
+fn list_profiles() {
+    let profiles = match Profiles::load() {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            logger::log_error(&format!("Failed to read profiles: {}", e));
+            return;
+        }
+    };
+    if profiles.is_empty() {
+        logger::log_info("No saved profiles. Save this server with /profiles save <name>");
+        return;
+    }
+    logger::log_info("Saved profiles:");
+    for (name, profile) in profiles.iter() {
+        match &profile.username {
+            Some(username) => {
+                logger::log_info(&format!(" - {}: {} as {}", name, profile.address, username))
+            }
+            None => logger::log_info(&format!(" - {}: {}", name, profile.address)),
+        }
+    }
+}
+
 pub struct ChatClient {
     connection: ClientStream,
     server_host: String,
@@ -120,52 +370,159 @@ pub struct ChatClient {
     chat_name: String,
     /// Session token used to identify reconnecting clients and reclaim ghost sessions
     session_token: String,
-    last_dm_sender: Option<String>,
-    connected_users: Arc<RwLock<HashSet<String>>>,
+    /// Secret the server issued on our last join, echoed to reclaim the session
+    session_secret: Option<String>,
+    /// Users, pending senders and last DM sender shared with tab completion
+    completion: CompletionState,
     was_kicked: bool,
+    /// Set when the server turned us away at capacity; the next reconnect waits longer
+    server_full: bool,
+    /// Free name the server suggested after a rename collision (taken by a bare /rename)
+    suggested_name: Option<String>,
+    /// Chat messages sent but not yet acknowledged by the server, oldest first
+    unconfirmed: VecDeque<UnconfirmedChat>,
+    /// Last message or DM sent, resent by /again (replies are kept as a DM to the
+    /// user replied to)
+    last_sent: Option<input::ClientUserInput>,
     current_status: Option<String>,
+    /// Channel topic last announced by the server
+    topic: Option<String>,
+    /// Display name the server sent on join (`CHAT_SERVER_NAME`), if any
+    server_name: Option<String>,
+    /// True while the status was set by /afk (cleared on the next sent message)
+    afk: bool,
     /// Pending outgoing transfers (keyed by recipient name)
     pending_outgoing: HashMap<String, PendingOutgoingTransfer>,
     /// Pending incoming transfers (keyed by sender name)
     pending_incoming: HashMap<String, PendingIncomingTransfer>,
+    /// End-to-end encryption state for DMs (None when disabled)
+    e2e: Option<E2eSession>,
+    /// Limits advertised by the server (defaults until it sends them)
+    server_limits: ServerLimits,
+    /// Read plain lines from stdin and print events as tab-separated lines
+    stdio: bool,
+    /// Join as a read-only spectator (receive messages, never send them)
+    readonly: bool,
+    /// Offer compression to the server when joining
+    request_compression: bool,
+    /// The server accepted compression, so large frames we send are compressed
+    compress: bool,
+    /// Desktop notifications for DMs and mentions (None when disabled)
+    notifier: Option<Notifier>,
+    /// Events that ring the terminal bell (none by default)
+    bell: BellEvents,
+    /// What to do when a received file's name already exists in downloads/
+    overwrite_policy: OverwritePolicy,
+    /// Copy of every received message for embedding programs (see `subscribe`)
+    incoming: Option<mpsc::UnboundedSender<ChatMessage>>,
+    /// Channel messages held by /pause (None when not paused)
+    paused: Option<PauseBuffer>,
+    /// Do not disturb: channel traffic and announcements are dropped (/dnd)
+    dnd: bool,
+    /// Round-trip times of recent /quality pings
+    latency: LatencyTracker,
+    /// Leave once no outgoing transfer is pending (see `run_until_sent`)
+    exit_when_sent: bool,
+    /// Files whose data has been sent this session
+    files_sent: usize,
+    /// The server rejected our version, so an upgrade is needed to reconnect
+    version_mismatch: bool,
+}
+
+/// How long a sent chat message may go unacknowledged before the user is warned
+const CHAT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the server to confirm an explicit Leave before closing anyway
+const LEAVE_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Chat message shown locally and waiting for the server's `ChatAck`
+#[derive(Debug)]
+struct UnconfirmedChat {
+    sent_at: Instant,
+    text: String,
+}
+
+/// Handle for driving a client started with [`ChatClient::spawn`]
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    input: mpsc::UnboundedSender<Option<ReadlineInput>>,
+}
+
+impl ClientHandle {
+    /// Queue a chat message or `/command`, handled exactly like a line typed at
+    /// the prompt. Never blocks; returns false if the client has stopped.
+    pub fn send(&self, line: impl Into<String>) -> bool {
+        let input = ReadlineInput::Line(line.into());
+        self.input.send(Some(input)).is_ok()
+    }
+
+    /// Stop the client's I/O loop (same as dropping every handle)
+    pub fn quit(&self) {
+        let _ = self.input.send(None);
+    }
 }
 
 impl ChatClient {
     pub async fn new(server_addr: &str, name: String) -> Result<Self, ChatClientError> {
-        // Parse address - could be host:port or just host
-        let (host, port, use_tls) = Self::parse_server_addr(server_addr)?;
+        Self::with_tls_fallback(server_addr, name, TlsFallback::Never).await
+    }
 
-        logger::log_info(&format!("Connecting to {}:{}...", host, port));
-        let stream = TcpStream::connect(format!("{}:{}", host, port))
-            .await
-            .map_err(|e| {
-                logger::log_error(&format!("Failed to connect to {}:{} - {}", host, port, e));
-                ChatClientError::IoError
-            })?;
+    /// Like [`ChatClient::new`], but a failed TLS handshake may be retried as
+    /// plain TCP according to `tls_fallback`
+    pub async fn with_tls_fallback(
+        server_addr: &str,
+        name: String,
+        tls_fallback: TlsFallback,
+    ) -> Result<Self, ChatClientError> {
+        let ServerAddr {
+            host,
+            port,
+            tls: mut use_tls,
+            channel,
+            ..
+        } = Self::parse_server_addr(server_addr)?;
+        if let Some(channel) = channel {
+            logger::log_info(&format!(
+                "Ignoring channel '{}' in the address: this server has a single channel",
+                channel
+            ));
+        }
+
+        let display_addr = host_port(&host, port);
+        logger::log_info(&format!("Connecting to {}...", display_addr));
+        let stream = connect_tcp(&host, port).await.map_err(|e| {
+            logger::log_error(&format!("Failed to connect to {} - {}", display_addr, e));
+            e
+        })?;
 
-        logger::log_success(&format!("TCP connection established to {}:{}", host, port));
+        logger::log_success(&format!("TCP connection established to {}", display_addr));
 
         let connection = if use_tls {
             logger::log_info("Establishing TLS connection...");
-            let mut root_cert_store = rustls::RootCertStore::empty();
-            root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-            let config = ClientConfig::builder()
-                .with_root_certificates(root_cert_store)
-                .with_no_client_auth();
-
-            let connector = TlsConnector::from(Arc::new(config));
-            let server_name = ServerName::try_from(host.clone()).map_err(|e| {
-                logger::log_error(&format!("Invalid server name '{}': {:?}", host, e));
-                io::Error::new(io::ErrorKind::InvalidInput, "Invalid server name")
-            })?;
-
-            let tls_stream = connector.connect(server_name, stream).await.map_err(|e| {
-                logger::log_error(&format!("TLS handshake failed: {}", e));
-                ChatClientError::IoError
-            })?;
-            logger::log_success("TLS connection established");
-            ClientStream::Tls(Box::new(tls_stream))
+            match connect_tls(&host, stream).await {
+                Ok(tls_stream) => {
+                    logger::log_success("TLS connection established");
+                    ClientStream::Tls(Box::new(tls_stream))
+                }
+                Err(e) => {
+                    logger::log_error(&format!("TLS connection failed: {}", e));
+                    if !server_lacks_tls(&e) {
+                        return Err(e);
+                    }
+                    if !tls_fallback.allows_plaintext().await {
+                        logger::log_info(&format!(
+                            "If the server doesn't use TLS, connect to {} without tls://",
+                            host_port(&host, port)
+                        ));
+                        return Err(e);
+                    }
+                    logger::log_warning(
+                        "Retrying WITHOUT TLS - messages and files will be sent unencrypted",
+                    );
+                    use_tls = false;
+                    ClientStream::Plain(connect_tcp(&host, port).await?)
+                }
+            }
         } else {
             logger::log_info("Using plain TCP (no encryption)");
             ClientStream::Plain(stream)
@@ -182,33 +539,162 @@ impl ChatClient {
             use_tls,
             chat_name: name,
             session_token,
-            last_dm_sender: None,
-            connected_users: Arc::new(RwLock::new(HashSet::new())),
+            session_secret: None,
+            completion: CompletionState::default(),
             was_kicked: false,
+            server_full: false,
+            suggested_name: None,
+            unconfirmed: VecDeque::new(),
+            last_sent: None,
             current_status: None,
+            topic: None,
+            server_name: None,
+            afk: false,
             pending_outgoing: HashMap::new(),
             pending_incoming: HashMap::new(),
+            e2e: None,
+            server_limits: ServerLimits::default(),
+            stdio: false,
+            readonly: false,
+            request_compression: false,
+            compress: false,
+            notifier: None,
+            bell: BellEvents::default(),
+            overwrite_policy: OverwritePolicy::default(),
+            incoming: None,
+            paused: None,
+            dnd: false,
+            latency: LatencyTracker::default(),
+            exit_when_sent: false,
+            files_sent: 0,
+            version_mismatch: false,
         })
     }
 
-    fn parse_server_addr(addr: &str) -> Result<(String, u16, bool), ChatClientError> {
+    /// Encrypt direct messages end-to-end using a fresh keypair for this session
+    pub fn enable_e2e(&mut self) {
+        let session = E2eSession::new();
+        logger::log_info(&format!(
+            "End-to-end encryption enabled for DMs (fingerprint: {})",
+            session.fingerprint()
+        ));
+        self.e2e = Some(session);
+    }
+
+    /// Join read-only: the server delivers messages but rejects anything we send
+    pub fn enable_readonly(&mut self) {
+        logger::log_info("Read-only mode: you will receive messages but cannot send them");
+        self.readonly = true;
+    }
+
+    /// Offer compression when joining; large frames are compressed both ways
+    /// once the server confirms it (older servers ignore the offer)
+    pub fn enable_compression(&mut self) {
+        self.request_compression = true;
+    }
+
+    /// Notify on incoming DMs and messages that mention our name
+    pub fn enable_notifications(&mut self) {
+        self.notifier = Some(Notifier::default());
+    }
+
+    /// Ring the terminal bell for the chosen events
+    pub fn set_bell_events(&mut self, events: BellEvents) {
+        self.bell = events;
+    }
+
+    /// Choose how received files with an existing name are saved
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.overwrite_policy = policy;
+    }
+
+    /// Drive the client over stdin/stdout with a line protocol instead of rustyline
+    pub fn enable_stdio(&mut self) {
+        self.stdio = true;
+    }
+
+    /// Whether the server rejected this client's version (the run loop then
+    /// stops without reconnecting; only an upgrade helps)
+    pub fn version_mismatch(&self) -> bool {
+        self.version_mismatch
+    }
+
+    /// Receive a copy of every message from the server. The client still
+    /// handles each message itself (transfers, E2E keys, logging).
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ChatMessage> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.incoming = Some(tx);
+        rx
+    }
+
+    /// Run the I/O loop in a background task, taking input from the returned
+    /// handle instead of the terminal
+    pub fn spawn(mut self) -> (ClientHandle, JoinHandle<io::Result<()>>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move { self.run_with_input(&mut rx).await });
+        (ClientHandle { input: tx }, task)
+    }
+
+    /// Parse a server address as typed or shared. Plain `host` and
+    /// `host:port` still work; the port defaults to 8080.
+    pub fn parse_server_addr(addr: &str) -> Result<ServerAddr, ChatClientError> {
         // Check if address starts with tls://
-        let (use_tls, addr) = if let Some(stripped) = addr.strip_prefix("tls://") {
+        let (tls, addr) = if let Some(stripped) = addr.strip_prefix("tls://") {
             (true, stripped)
         } else {
             (false, addr)
         };
 
-        // Parse host:port
-        if let Some((host, port)) = addr.rsplit_once(':') {
-            let port = port
-                .parse::<u16>()
-                .map_err(|_| ChatClientError::InvalidAddress)?;
-            Ok((host.to_string(), port, use_tls))
+        // Peel off #nickname, then /channel, leaving host[:port]
+        let (addr, nickname) = match addr.split_once('#') {
+            Some((addr, nickname)) => (addr, nickname),
+            None => (addr, ""),
+        };
+        let (authority, channel) = match addr.split_once('/') {
+            Some((authority, channel)) => (authority, channel.trim_matches('/')),
+            None => (addr, ""),
+        };
+
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or(ChatClientError::InvalidAddress)?;
+            match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None if rest.is_empty() => (host, None),
+                None => return Err(ChatClientError::InvalidAddress),
+            }
         } else {
-            // No port specified, use default
-            Ok((addr.to_string(), 8080, use_tls))
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| ChatClientError::InvalidAddress)?,
+            None => DEFAULT_SERVER_PORT,
+        };
+        if host.is_empty() {
+            return Err(ChatClientError::InvalidAddress);
         }
+
+        let non_empty = |part: &str| (!part.is_empty()).then(|| part.to_string());
+        Ok(ServerAddr {
+            host: host.to_string(),
+            port,
+            tls,
+            channel: non_empty(channel),
+            nickname: non_empty(nickname),
+        })
+    }
+
+    /// Connection string for this server in the form `parse_server_addr` accepts
+    fn server_link(&self) -> String {
+        let scheme = if self.use_tls { "tls://" } else { "" };
+        let addr = host_port(&self.server_host, self.server_port);
+        format!("{}{}", scheme, addr)
     }
 
     pub async fn join_server(&mut self) -> Result<(), ChatClientError> {
@@ -218,11 +704,46 @@ impl ChatClient {
             MessageTypes::VersionCheck,
             Some(VERSION.as_bytes().to_vec()),
         )?;
-        self.send_message_chunked(version_message).await?;
+        if let Err(e) = self.send_message_chunked(version_message).await {
+            // A TLS-only server rejects plain connections at the first message
+            if !self.use_tls
+                && matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::InvalidData
+                )
+            {
+                logger::log_warning(&format!(
+                    "No valid reply to the version check; if the server requires TLS, connect to tls://{}",
+                    host_port(&self.server_host, self.server_port)
+                ));
+            }
+            return Err(e.into());
+        }
+
+        // A new connection starts uncompressed until the server accepts again
+        self.compress = false;
+        if self.request_compression {
+            let offer = ChatMessage::try_new(
+                MessageTypes::Compression,
+                Some(COMPRESSION_ALGORITHM.as_bytes().to_vec()),
+            )?;
+            self.send_message_chunked(offer).await?;
+        }
 
         // Send join message with username and session token
-        // Format: username|session_token
-        let join_content = format!("{}|{}", self.chat_name, self.session_token);
+        // Format: username|session_token[|readonly][|secret=...]
+        let mut join_content = format!("{}|{}", self.chat_name, self.session_token);
+        if self.readonly {
+            join_content.push('|');
+            join_content.push_str(READONLY_JOIN_FLAG);
+        }
+        if let Some(secret) = &self.session_secret {
+            join_content.push('|');
+            join_content.push_str(SESSION_SECRET_JOIN_PREFIX);
+            join_content.push_str(secret);
+        }
         let chat_message =
             ChatMessage::try_new(MessageTypes::Join, Some(join_content.into_bytes()))?;
         self.send_message_chunked(chat_message).await?;
@@ -233,41 +754,40 @@ impl ChatClient {
         const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
         const MAX_BACKOFF: Duration = Duration::from_secs(60);
         const BACKOFF_MULTIPLIER: u32 = 2;
+        const SERVER_FULL_BACKOFF: Duration = Duration::from_secs(30);
 
         // Explicitly shutdown the old connection before reconnecting
         let _ = self.connection.shutdown().await;
+        self.expire_unconfirmed(Duration::ZERO);
 
         // Give the server time to detect the closure and clean up
         sleep(Duration::from_millis(100)).await;
 
+        // Reconnecting right away would just be rejected again
+        if std::mem::take(&mut self.server_full) {
+            logger::log_info(&format!(
+                "Server is full, waiting {:?} before reconnecting...",
+                SERVER_FULL_BACKOFF
+            ));
+            sleep(SERVER_FULL_BACKOFF).await;
+        }
+
         let mut backoff = INITIAL_BACKOFF;
         let mut attempt = 1;
 
         loop {
             logger::log_info(&format!(
-                "Attempting to reconnect to {}:{} (attempt {})...",
-                self.server_host, self.server_port, attempt
+                "Attempting to reconnect to {} (attempt {})...",
+                host_port(&self.server_host, self.server_port),
+                attempt
             ));
 
-            match TcpStream::connect(format!("{}:{}", self.server_host, self.server_port)).await {
+            match connect_tcp(&self.server_host, self.server_port).await {
                 Ok(stream) => {
                     // Re-establish TLS if needed
                     let connection = if self.use_tls {
                         logger::log_info("Re-establishing TLS connection...");
-                        let mut root_cert_store = rustls::RootCertStore::empty();
-                        root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-                        let config = ClientConfig::builder()
-                            .with_root_certificates(root_cert_store)
-                            .with_no_client_auth();
-
-                        let connector = TlsConnector::from(Arc::new(config));
-                        let server_name =
-                            ServerName::try_from(self.server_host.clone()).map_err(|_| {
-                                io::Error::new(io::ErrorKind::InvalidInput, "Invalid server name")
-                            })?;
-
-                        let tls_stream = connector.connect(server_name, stream).await?;
+                        let tls_stream = connect_tls(&self.server_host, stream).await?;
                         logger::log_success("TLS connection re-established");
                         ClientStream::Tls(Box::new(tls_stream))
                     } else {
@@ -279,7 +799,7 @@ impl ChatClient {
 
                     // Rejoin the server with the same username
                     if let Err(e) = self.join_server().await {
-                        logger::log_error(&format!("Failed to rejoin server: {:?}", e));
+                        logger::log_error(&format!("Failed to rejoin server: {}", e));
                         return Err(e);
                     }
 
@@ -312,6 +832,35 @@ impl ChatClient {
         }
     }
 
+    /// Ring the terminal bell if `event` is enabled (never in stdio mode, where
+    /// stdout carries protocol lines)
+    fn ring_bell(&self, event: BellEvent) {
+        if !self.stdio && self.bell.contains(event) {
+            notify::ring_bell();
+        }
+    }
+
+    /// Show a /quality result: the latest round trip and recent min/avg/max
+    fn report_latency(&self, rtt: Duration) {
+        let Some(stats) = self.latency.stats() else {
+            return;
+        };
+        let health = if rtt > PONG_TIMEOUT {
+            "slow (reply took longer than the timeout)"
+        } else {
+            "healthy"
+        };
+        logger::log_info(&format!(
+            "Latency {} ms (last {}: min {} / avg {} / max {} ms), connection {}",
+            rtt.as_millis(),
+            stats.samples,
+            stats.min.as_millis(),
+            stats.avg.as_millis(),
+            stats.max.as_millis(),
+            health
+        ));
+    }
+
     fn get_message_content(&self, message: &ChatMessage, msg_type_name: &str) -> Option<String> {
         message.content_as_string().or_else(|| {
             logger::log_error(&format!("Received invalid UTF-8 {} message", msg_type_name));
@@ -320,6 +869,20 @@ impl ChatClient {
     }
 
     async fn handle_message(&mut self, message: ChatMessage) -> bool {
+        // Do not disturb drops what /pause would hold, and announcements too
+        if self.dnd && dnd_hides(message.msg_type) {
+            if message.msg_type == MessageTypes::Topic {
+                self.topic = message.content_as_string();
+            }
+            return true;
+        }
+        // Channel chat waits for /resume; everything else still comes through
+        if message.msg_type == MessageTypes::ChatMessage
+            && let Some(paused) = &mut self.paused
+        {
+            paused.push(message);
+            return true;
+        }
         match message.msg_type {
             MessageTypes::Ping => {
                 // Respond to server ping with pong
@@ -333,44 +896,81 @@ impl ChatClient {
             }
             MessageTypes::Join => {
                 if let Some(content) = self.get_message_content(&message, "join") {
-                    logger::log_system(&format!("{} has joined the chat", content));
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Join(&content));
+                    } else {
+                        logger::log_system(&format!("{} has joined the chat", content));
+                    }
                 }
             }
             MessageTypes::Leave => {
                 if let Some(content) = self.get_message_content(&message, "leave") {
-                    logger::log_system(&format!("{} has left the chat", content));
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Leave(&content));
+                    } else {
+                        logger::log_system(&format!("{} has left the chat", content));
+                    }
                 }
             }
             MessageTypes::UserRename => {
                 if let Some(content) = self.get_message_content(&message, "rename") {
-                    logger::log_success(&format!("You have been renamed to '{}'", content));
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Renamed(&content));
+                    } else {
+                        logger::log_success(&format!("You have been renamed to '{}'", content));
+                    }
                     self.chat_name = content;
                 }
             }
             MessageTypes::ChatMessage => {
                 if let Some(content) = self.get_message_content(&message, "chat") {
+                    let (timestamp, content) = parse_stamped_chat_content(&content);
                     let should_display = content
                         .split_once(": ")
                         .is_none_or(|(username, _)| username != self.chat_name);
 
-                    if should_display {
-                        logger::log_chat(&content);
+                    if should_display && self.stdio {
+                        let (sender, text) = content.split_once(": ").unwrap_or(("", content));
+                        stdio::emit(StdioEvent::Message { sender, text });
+                    } else if should_display {
+                        logger::log_incoming_chat(content, timestamp, &self.chat_name);
+                    }
+
+                    if should_display
+                        && let Some((sender, text)) = content.split_once(": ")
+                        && mentions_user(text, &self.chat_name)
+                    {
+                        self.ring_bell(BellEvent::Mention);
+                        if let Some(notifier) = &mut self.notifier {
+                            notifier.notify(&format!("{} mentioned you", sender), text);
+                        }
                     }
                 }
             }
             MessageTypes::ListUsers => {
                 if let Some(content) = self.get_message_content(&message, "list users") {
-                    // Update the connected users list for autocomplete
-                    let mut users = self.connected_users.write().unwrap();
-                    users.clear();
-                    for user in content.lines() {
-                        users.insert(user.to_string());
+                    let mut lines = content.lines().peekable();
+                    let page = lines.peek().copied().and_then(UserListPage::parse_header);
+                    if page.is_some() {
+                        lines.next();
+                    } else {
+                        // Only the full list replaces the autocomplete users
+                        self.completion.set_users(content.lines());
                     }
-                    drop(users);
 
-                    logger::log_info("Current users online:");
-                    for user in content.lines() {
-                        logger::log_info(&format!(" - {}", user));
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Users(lines.collect()));
+                    } else {
+                        match page {
+                            Some(page) => logger::log_info(&format!(
+                                "Users online (page {}/{}, {} total):",
+                                page.page, page.pages, page.total
+                            )),
+                            None => logger::log_info("Current users online:"),
+                        }
+                        for user in lines {
+                            logger::log_info(&format!(" - {}", user));
+                        }
                     }
                 }
             }
@@ -381,28 +981,89 @@ impl ChatClient {
                 {
                     // Only display if we are the recipient (not the sender - we already showed it locally)
                     if recipient == self.chat_name {
-                        logger::log_warning(&format!("[DM from {}]: {}", sender, msg));
+                        self.ring_bell(BellEvent::DirectMessage);
+                        if let Some(notifier) = &mut self.notifier {
+                            let body = if e2e::is_encrypted(msg) {
+                                "Encrypted message"
+                            } else {
+                                msg
+                            };
+                            notifier.notify(&format!("Direct message from {}", sender), body);
+                        }
+                        if e2e::is_encrypted(msg) {
+                            return self.handle_encrypted_dm(sender, msg).await;
+                        }
+                        if self.stdio {
+                            stdio::emit(StdioEvent::DirectMessage { sender, text: msg });
+                        } else {
+                            logger::log_warning(&format!("[DM from {}]: {}", sender, msg));
+                        }
                         // Track the sender so we can reply with /r
-                        self.last_dm_sender = Some(sender.to_string());
+                        self.completion.set_last_dm_sender(sender);
+                    }
+                }
+            }
+            MessageTypes::Page => {
+                if let Some(content) = self.get_message_content(&message, "page")
+                    && let Some((sender, msg)) = content.split_once('|')
+                {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Page { sender, text: msg });
+                    } else {
+                        notify::ring_page_bell();
+                        logger::log_page(sender, msg);
                     }
+                    if let Some(notifier) = &mut self.notifier {
+                        notifier.notify_urgent(&format!("Page from {}", sender), msg);
+                    }
+                    // Let /r answer the page
+                    self.completion.set_last_dm_sender(sender);
                 }
             }
             MessageTypes::Error => {
                 if let Some(content) = self.get_message_content(&message, "error") {
-                    logger::log_error(&content);
-                    // Check if this is a kick message
-                    if content.contains("kicked") {
-                        self.was_kicked = true;
+                    let (code, text) = parse_error(&content);
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Error(text));
+                    } else {
+                        logger::log_error(text);
+                    }
+                    match code {
+                        // Messages are handled in order, so this is the oldest one we sent
+                        ErrorCode::RateLimited => {
+                            if let Some(rejected) = self.unconfirmed.pop_front() {
+                                logger::log_warning(&format!(
+                                    "Not delivered: \"{}\" (use /again to resend)",
+                                    rejected.text
+                                ));
+                            }
+                        }
+                        // Don't reconnect after being removed by the operator
+                        ErrorCode::Kicked | ErrorCode::Banned => self.was_kicked = true,
+                        ErrorCode::ServerFull => self.server_full = true,
+                        // A one-shot send has nothing else in flight, so the error is
+                        // about its offer (e.g. the recipient isn't online)
+                        _ if self.exit_when_sent => self.pending_outgoing.clear(),
+                        ErrorCode::NameTaken => {
+                            if let Some(suggestion) = parse_name_suggestion(text) {
+                                if !self.stdio {
+                                    logger::log_info(&format!(
+                                        "Type /rename to become '{}', or /rename <name> to pick another",
+                                        suggestion
+                                    ));
+                                }
+                                self.suggested_name = Some(suggestion.to_string());
+                            }
+                        }
+                        _ => {}
                     }
                 }
             }
             MessageTypes::FileTransfer => {
-                self.handle_file_transfer(&message);
+                return self.handle_file_transfer(&message).await;
             }
             MessageTypes::FileTransferAck => {
-                if let Some(content) = self.get_message_content(&message, "file ack") {
-                    logger::log_success(&content);
-                }
+                self.handle_file_transfer_ack(&message);
             }
             MessageTypes::FileTransferRequest => {
                 self.handle_file_transfer_request(&message);
@@ -410,13 +1071,36 @@ impl ChatClient {
             MessageTypes::FileTransferResponse => {
                 return self.handle_file_transfer_response(&message).await;
             }
+            MessageTypes::FileOffer => {
+                self.handle_file_offer(&message);
+            }
+            MessageTypes::FileGet => {
+                self.handle_shared_file(&message);
+            }
+            MessageTypes::ServerNotice => {
+                if let Some(content) = self.get_message_content(&message, "server notice") {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Notice(&content));
+                    } else {
+                        logger::log_system(&content);
+                    }
+                }
+            }
             MessageTypes::SetStatus => {
                 if let Some(content) = self.get_message_content(&message, "status") {
                     logger::log_success(&content);
                 }
             }
             MessageTypes::Pong => {
-                // Ignore pong messages (we don't send pings from client)
+                // Replies to /quality pings echo the ping's sequence number
+                let seq = message
+                    .content_as_string()
+                    .and_then(|content| content.parse::<u64>().ok());
+                if let Some(seq) = seq
+                    && let Some(rtt) = self.latency.finish(seq, Instant::now())
+                {
+                    self.report_latency(rtt);
+                }
             }
             MessageTypes::VersionMismatch => {
                 if let Some(content) = self.get_message_content(&message, "version mismatch") {
@@ -437,12 +1121,105 @@ impl ChatClient {
                     }
                     // Mark as kicked so we don't try to reconnect
                     self.was_kicked = true;
+                    self.version_mismatch = true;
                     return false;
                 }
             }
             MessageTypes::VersionCheck => {
                 // Server shouldn't send this to client, ignore
             }
+            MessageTypes::KeyExchange => {
+                return self.handle_key_exchange(&message).await;
+            }
+            MessageTypes::ChatAck => {
+                self.unconfirmed.pop_front();
+            }
+            MessageTypes::LeaveAck => {
+                // Only expected while leaving, which reads it directly
+            }
+            MessageTypes::ServerLimits => {
+                if let Some(content) = self.get_message_content(&message, "server limits") {
+                    self.server_limits = ServerLimits::parse(&content);
+                }
+            }
+            MessageTypes::Compression => {
+                if message.content_as_string().as_deref() == Some(COMPRESSION_ALGORITHM) {
+                    logger::log_info(&format!("Compression enabled ({})", COMPRESSION_ALGORITHM));
+                    self.compress = true;
+                }
+            }
+            MessageTypes::SessionSecret => {
+                // Replaces the previous one, which the server no longer accepts
+                self.session_secret = message.content_as_string();
+            }
+            MessageTypes::ServerName => {
+                if let Some(content) = self.get_message_content(&message, "server name") {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Server(&content));
+                    } else {
+                        logger::log_success(&format!("Joined {} as {}", content, self.chat_name));
+                    }
+                    self.server_name = Some(content);
+                }
+            }
+            MessageTypes::Topic => {
+                if let Some(content) = self.get_message_content(&message, "topic") {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Topic(&content));
+                    } else {
+                        logger::log_banner(&format!("Topic: {}", content));
+                    }
+                    self.topic = Some(content);
+                }
+            }
+            MessageTypes::Whois
+            | MessageTypes::SeenRequest
+            | MessageTypes::Search
+            | MessageTypes::Block => {
+                if let Some(content) = self.get_message_content(&message, "user lookup") {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Notice(&content));
+                    } else {
+                        logger::log_info(&content);
+                    }
+                }
+            }
+            MessageTypes::ServerInfo => {
+                if let Some(content) = self.get_message_content(&message, "server info") {
+                    match ServerInfo::parse(&content) {
+                        Some(info) if self.stdio => {
+                            stdio::emit(StdioEvent::Notice(&info.summary()))
+                        }
+                        Some(info) => logger::log_info(&info.summary()),
+                        None => logger::log_warning("Received invalid server info"),
+                    }
+                }
+            }
+            MessageTypes::Poll | MessageTypes::PollResult => {
+                if let Some(content) = self.get_message_content(&message, "poll") {
+                    match PollSummary::parse(&content) {
+                        Some(poll) if self.stdio => stdio::emit(StdioEvent::Poll(&poll)),
+                        Some(poll) if message.msg_type == MessageTypes::Poll => {
+                            logger::log_banner(&format!(
+                                "{}\nVote with /vote {} <option>",
+                                poll.render(),
+                                poll.id
+                            ));
+                        }
+                        Some(poll) => logger::log_info(&poll.render()),
+                        None => logger::log_warning("Received invalid poll"),
+                    }
+                }
+            }
+            MessageTypes::Motd => {
+                if let Some(content) = self.get_message_content(&message, "motd") {
+                    if self.stdio {
+                        stdio::emit(StdioEvent::Motd(&content));
+                    } else {
+                        logger::log_banner(&content);
+                    }
+                }
+            }
             _ => {
                 logger::log_warning(&format!("Unknown message type: {:?}", message.msg_type));
             }
@@ -450,77 +1227,115 @@ impl ChatClient {
         true
     }
 
-    fn handle_file_transfer(&self, message: &ChatMessage) {
-        let content = match message.get_content() {
-            Some(c) => c,
-            None => {
-                logger::log_error("Received empty file transfer");
-                return;
-            }
+    async fn handle_encrypted_dm(&mut self, sender: &str, payload: &str) -> bool {
+        let Some(e2e) = &self.e2e else {
+            logger::log_error(&format!(
+                "Received an encrypted DM from {} but end-to-end encryption is disabled (set CHAT_E2E=1)",
+                sender
+            ));
+            return true;
         };
 
-        // Parse binary format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filedata
-        if content.len() < 2 {
-            logger::log_error("Invalid file transfer format");
-            return;
+        match e2e.decrypt(sender, payload) {
+            Ok(plaintext) => {
+                if self.stdio {
+                    stdio::emit(StdioEvent::DirectMessage {
+                        sender,
+                        text: &plaintext,
+                    });
+                } else {
+                    logger::log_warning(&format!("[E2E DM from {}]: {}", sender, plaintext));
+                }
+                self.completion.set_last_dm_sender(sender);
+                true
+            }
+            Err(e) => {
+                // The sender may hold a stale key for us (e.g. after we restarted),
+                // so offer our current key to resynchronize
+                logger::log_error(&format!(
+                    "Could not decrypt DM from {} ({:?}); re-sending encryption key",
+                    sender, e
+                ));
+                self.send_key_exchange(sender).await.is_ok()
+            }
         }
+    }
 
-        // First extract recipient to check if this file is for us
-        let recipient_len = content[0] as usize;
-        if content.len() < 1 + recipient_len + 1 {
-            logger::log_error("Invalid file transfer format");
-            return;
-        }
+    async fn handle_key_exchange(&mut self, message: &ChatMessage) -> bool {
+        let Some(content) = self.get_message_content(message, "key exchange") else {
+            return true;
+        };
+        let Some((sender, key_hex)) = content.split_once('|') else {
+            logger::log_error("Invalid key exchange format");
+            return true;
+        };
+        let Some(e2e) = &mut self.e2e else {
+            logger::log_info(&format!(
+                "{} wants to use end-to-end encrypted DMs (set CHAT_E2E=1 to enable)",
+                sender
+            ));
+            return true;
+        };
 
-        let recipient = match std::str::from_utf8(&content[1..1 + recipient_len]) {
-            Ok(s) => s,
+        let update = match e2e.add_peer(sender, key_hex) {
+            Ok(update) => update,
             Err(_) => {
-                logger::log_error("Invalid recipient name in file transfer");
-                return;
+                logger::log_error(&format!("Invalid encryption key from {}", sender));
+                return true;
             }
         };
-
-        // Check if this file is for us
-        if recipient != self.chat_name {
-            return; // Not for us, ignore
+        let fingerprint = e2e.peer_fingerprint(sender).unwrap_or_default();
+        match update {
+            KeyUpdate::New => logger::log_success(&format!(
+                "Encryption key received from {} (fingerprint: {})",
+                sender, fingerprint
+            )),
+            KeyUpdate::Changed => logger::log_warning(&format!(
+                "Encryption key for {} changed! New fingerprint: {}",
+                sender, fingerprint
+            )),
+            KeyUpdate::Unchanged => {}
         }
+        let queued = e2e.take_queued(sender);
 
-        // Now extract sender
-        let sender_start = 1 + recipient_len;
-        let sender_len = content[sender_start] as usize;
-        if content.len() < sender_start + 1 + sender_len + 1 {
-            logger::log_error("Invalid file transfer format");
-            return;
+        // Answer with our own key so the peer can encrypt back to us. An unchanged
+        // key means the peer already has ours, which stops the exchange ping-ponging.
+        if update != KeyUpdate::Unchanged && self.send_key_exchange(sender).await.is_err() {
+            return false;
         }
+        for msg in queued {
+            if self.send_direct_message(sender, &msg).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
 
-        let sender =
-            match std::str::from_utf8(&content[sender_start + 1..sender_start + 1 + sender_len]) {
-                Ok(s) => s,
-                Err(_) => {
-                    logger::log_error("Invalid sender name in file transfer");
-                    return;
-                }
-            };
-
-        // Extract filename
-        let filename_len_pos = sender_start + 1 + sender_len;
-        let filename_len = content[filename_len_pos] as usize;
-        let filename_start = filename_len_pos + 1;
-        if content.len() < filename_start + filename_len {
-            logger::log_error("Invalid file transfer format");
-            return;
-        }
+    async fn handle_file_transfer(&mut self, message: &ChatMessage) -> bool {
+        let content = match message.get_content() {
+            Some(c) => c,
+            None => {
+                logger::log_error("Received empty file transfer");
+                return true;
+            }
+        };
 
-        let filename =
-            match std::str::from_utf8(&content[filename_start..filename_start + filename_len]) {
-                Ok(s) => s,
-                Err(_) => {
-                    logger::log_error("Invalid filename in file transfer");
-                    return;
-                }
-            };
+        // Parse binary format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filedata
+        let header = match FileTransferHeader::parse_relayed(content) {
+            Ok(header) => header,
+            Err(e) => {
+                logger::log_error(&format!("Invalid file transfer: {}", e));
+                return true;
+            }
+        };
 
-        let file_data = &content[filename_start + filename_len..];
+        // Check if this file is for us
+        if header.recipient != self.chat_name {
+            return true; // Not for us, ignore
+        }
+        let sender = header.sender.unwrap_or_default();
+        let filename = header.filename;
+        let file_data = header.data;
 
         logger::log_warning(&format!(
             "[FILE from {}]: '{}' ({} bytes)",
@@ -529,107 +1344,165 @@ impl ChatClient {
             file_data.len()
         ));
 
-        // Save file to downloads directory or current directory
-        let save_path = format!("downloads/{}", filename);
+        // Let the sender know whether the file landed
+        let saved = self.save_download(filename, file_data);
+        let error = saved.err().map(|e| e.to_string());
+        self.send_file_transfer_ack(sender, filename, error.as_deref())
+            .await
+            .is_ok()
+    }
+
+    /// Announce a file someone shared with the room
+    fn handle_file_offer(&mut self, message: &ChatMessage) {
+        let Some(content) = message.get_content() else {
+            logger::log_error("Received empty file offer");
+            return;
+        };
+        // Format: file_id(8)|sender_len(1)|sender|filename_len(1)|filename|filesize(8)
+        let offer = match FileOfferHeader::parse(content) {
+            Ok(offer) => offer,
+            Err(e) => {
+                logger::log_error(&format!("Invalid file offer: {}", e));
+                return;
+            }
+        };
+        let size_display = format_file_size(offer.file_size as usize);
+
+        if offer.sender == self.chat_name {
+            logger::log_success(&format!(
+                "Shared '{}' ({}) as #{}",
+                offer.filename, size_display, offer.file_id
+            ));
+            return;
+        }
+        self.ring_bell(BellEvent::FileOffer);
+        logger::log_warning(&format!(
+            "[SHARED FILE from {}]: '{}' ({}) - /get {} to download",
+            offer.sender, offer.filename, size_display, offer.file_id
+        ));
+    }
+
+    /// Save a room file fetched with /get. Unlike a direct transfer, the
+    /// sender isn't waiting for an ack.
+    fn handle_shared_file(&self, message: &ChatMessage) {
+        let Some(content) = message.get_content() else {
+            logger::log_error("Received empty shared file");
+            return;
+        };
+        let header = match FileTransferHeader::parse_relayed(content) {
+            Ok(header) => header,
+            Err(e) => {
+                logger::log_error(&format!("Invalid shared file: {}", e));
+                return;
+            }
+        };
+        if header.recipient != self.chat_name {
+            return;
+        }
+
+        logger::log_info(&format!(
+            "[SHARED FILE from {}]: '{}' ({} bytes)",
+            header.sender.unwrap_or_default(),
+            header.filename,
+            header.data.len()
+        ));
+        let _ = self.save_download(header.filename, header.data);
+    }
 
+    /// Write a received file into downloads/, resolving name collisions by policy
+    fn save_download(&self, filename: &str, file_data: &[u8]) -> io::Result<()> {
         // Create downloads directory if it doesn't exist
-        if let Err(e) = std::fs::create_dir_all("downloads") {
+        if let Err(e) = std::fs::create_dir_all(DOWNLOADS_DIR) {
             logger::log_error(&format!("Failed to create downloads directory: {}", e));
-            return;
+            return Err(e);
         }
+        let Some(save_path) =
+            downloads::resolve_save_path(Path::new(DOWNLOADS_DIR), filename, self.overwrite_policy)
+        else {
+            logger::log_warning(&format!(
+                "Skipped '{}': a file with that name already exists in {}/",
+                filename, DOWNLOADS_DIR
+            ));
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' already exists on the receiver", filename),
+            ));
+        };
 
         match std::fs::write(&save_path, file_data) {
             Ok(_) => {
-                logger::log_success(&format!("File saved to: {}", save_path));
+                logger::log_success(&format!("File saved to: {}", save_path.display()));
+                Ok(())
             }
             Err(e) => {
                 logger::log_error(&format!("Failed to save file: {}", e));
+                // Don't leave a truncated file behind (e.g. disk full mid-write)
+                let _ = std::fs::remove_file(&save_path);
+                Err(e)
             }
         }
     }
 
-    fn handle_file_transfer_request(&mut self, message: &ChatMessage) {
+    fn handle_file_transfer_ack(&self, message: &ChatMessage) {
         let content = match message.get_content() {
             Some(c) => c,
             None => {
-                logger::log_error("Received empty file transfer request");
+                logger::log_error("Received empty file transfer ack");
                 return;
             }
         };
 
-        // Parse binary format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filesize(8 bytes)
-        if content.len() < 2 {
-            logger::log_error("Invalid file transfer request format");
-            return;
-        }
-
-        // Extract recipient
-        let recipient_len = content[0] as usize;
-        if content.len() < 1 + recipient_len + 1 {
-            logger::log_error("Invalid file transfer request format");
-            return;
-        }
-
-        let recipient = match std::str::from_utf8(&content[1..1 + recipient_len]) {
-            Ok(s) => s,
-            Err(_) => {
-                logger::log_error("Invalid recipient name in file transfer request");
+        // Parse format: recipient_len(1)|recipient|receiver_len(1)|receiver|success(1)|filename_len(1)|filename|error
+        let ack = match FileAckHeader::parse_relayed(content) {
+            Ok(ack) => ack,
+            Err(e) => {
+                logger::log_error(&format!("Invalid file transfer ack: {}", e));
                 return;
             }
         };
 
-        // Check if this request is for us
-        if recipient != self.chat_name {
+        // Check if this ack is for us
+        if ack.file_sender != self.chat_name {
             return; // Not for us, ignore
         }
+        // The one who saved the file
+        let receiver = ack.receiver.unwrap_or_default();
 
-        // Extract sender
-        let sender_start = 1 + recipient_len;
-        let sender_len = content[sender_start] as usize;
-        if content.len() < sender_start + 1 + sender_len + 1 {
-            logger::log_error("Invalid file transfer request format");
-            return;
+        if ack.saved {
+            logger::log_success(&format!("{} saved '{}'", receiver, ack.filename));
+        } else {
+            logger::log_error(&format!(
+                "{} could not save '{}': {}",
+                receiver, ack.filename, ack.error
+            ));
         }
+    }
 
-        let sender =
-            match std::str::from_utf8(&content[sender_start + 1..sender_start + 1 + sender_len]) {
-                Ok(s) => s,
-                Err(_) => {
-                    logger::log_error("Invalid sender name in file transfer request");
-                    return;
-                }
-            };
-
-        // Extract filename
-        let filename_len_pos = sender_start + 1 + sender_len;
-        let filename_len = content[filename_len_pos] as usize;
-        let filename_start = filename_len_pos + 1;
-        if content.len() < filename_start + filename_len + 8 {
-            logger::log_error("Invalid file transfer request format");
-            return;
-        }
+    fn handle_file_transfer_request(&mut self, message: &ChatMessage) {
+        let content = match message.get_content() {
+            Some(c) => c,
+            None => {
+                logger::log_error("Received empty file transfer request");
+                return;
+            }
+        };
 
-        let filename =
-            match std::str::from_utf8(&content[filename_start..filename_start + filename_len]) {
-                Ok(s) => s,
-                Err(_) => {
-                    logger::log_error("Invalid filename in file transfer request");
-                    return;
-                }
-            };
+        // Parse binary format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filesize(8 bytes)[|caption_len(1)|caption]
+        let request = match FileRequestHeader::parse_relayed(content) {
+            Ok(request) => request,
+            Err(e) => {
+                logger::log_error(&format!("Invalid file transfer request: {}", e));
+                return;
+            }
+        };
 
-        // Extract file size (8 bytes, big-endian u64)
-        let size_start = filename_start + filename_len;
-        let file_size = u64::from_be_bytes([
-            content[size_start],
-            content[size_start + 1],
-            content[size_start + 2],
-            content[size_start + 3],
-            content[size_start + 4],
-            content[size_start + 5],
-            content[size_start + 6],
-            content[size_start + 7],
-        ]) as usize;
+        // Check if this request is for us
+        if request.recipient != self.chat_name {
+            return; // Not for us, ignore
+        }
+        let sender = request.sender.unwrap_or_default();
+        let filename = request.filename;
+        let file_size = request.file_size as usize;
 
         // Store the pending transfer
         self.pending_incoming.insert(
@@ -640,20 +1513,22 @@ impl ChatClient {
                 file_size,
             },
         );
-
-        // Format file size for display
-        let size_display = if file_size >= 1024 * 1024 {
-            format!("{:.1} MB", file_size as f64 / (1024.0 * 1024.0))
-        } else if file_size >= 1024 {
-            format!("{:.1} KB", file_size as f64 / 1024.0)
-        } else {
-            format!("{} bytes", file_size)
-        };
-
-        logger::log_warning(&format!(
-            "[FILE REQUEST from {}]: '{}' ({})",
-            sender, filename, size_display
-        ));
+        self.completion
+            .set_pending_senders(self.pending_incoming.keys());
+        self.ring_bell(BellEvent::FileOffer);
+
+        let size_display = format_file_size(file_size);
+
+        match request.caption {
+            Some(caption) => logger::log_warning(&format!(
+                "[FILE REQUEST from {}]: '{}' ({}) - \"{}\"",
+                sender, filename, size_display, caption
+            )),
+            None => logger::log_warning(&format!(
+                "[FILE REQUEST from {}]: '{}' ({})",
+                sender, filename, size_display
+            )),
+        }
         logger::log_info(&format!(
             "Use /accept {} to accept or /reject {} to decline",
             sender, sender
@@ -670,51 +1545,21 @@ impl ChatClient {
         };
 
         // Parse format: recipient_len(1)|recipient|sender_len(1)|sender|accepted(1)
-        if content.len() < 2 {
-            logger::log_error("Invalid file transfer response format");
-            return true;
-        }
-
-        // Extract recipient (original sender of the file request)
-        let recipient_len = content[0] as usize;
-        if content.len() < 1 + recipient_len + 1 {
-            logger::log_error("Invalid file transfer response format");
-            return true;
-        }
-
-        let recipient = match std::str::from_utf8(&content[1..1 + recipient_len]) {
-            Ok(s) => s,
-            Err(_) => {
-                logger::log_error("Invalid recipient name in file transfer response");
+        let response = match FileResponseHeader::parse_relayed(content) {
+            Ok(response) => response,
+            Err(e) => {
+                logger::log_error(&format!("Invalid file transfer response: {}", e));
                 return true;
             }
         };
 
         // Check if this response is for us (we're the original sender)
-        if recipient != self.chat_name {
+        if response.file_sender != self.chat_name {
             return true; // Not for us, ignore
         }
-
-        // Extract sender (the one who accepted/rejected)
-        let sender_start = 1 + recipient_len;
-        let sender_len = content[sender_start] as usize;
-        if content.len() < sender_start + 1 + sender_len + 1 {
-            logger::log_error("Invalid file transfer response format");
-            return true;
-        }
-
-        let responder =
-            match std::str::from_utf8(&content[sender_start + 1..sender_start + 1 + sender_len]) {
-                Ok(s) => s,
-                Err(_) => {
-                    logger::log_error("Invalid sender name in file transfer response");
-                    return true;
-                }
-            };
-
-        // Extract accepted flag
-        let accepted_pos = sender_start + 1 + sender_len;
-        let accepted = content[accepted_pos] == 1;
+        // The one who accepted/rejected
+        let responder = response.responder.unwrap_or_default();
+        let accepted = response.accepted;
 
         if accepted {
             // Look up the pending transfer and send the file
@@ -724,10 +1569,7 @@ impl ChatClient {
                     responder, transfer.file_name
                 ));
                 // Actually send the file now
-                if let Err(e) = self
-                    .send_file_data(&transfer.recipient, &transfer.file_path)
-                    .await
-                {
+                if let Err(e) = self.send_file_data(transfer).await {
                     logger::log_error(&format!("Failed to send file: {:?}", e));
                 }
             } else {
@@ -758,15 +1600,54 @@ impl ChatClient {
         &mut self,
         user_input: input::ClientUserInput,
     ) -> Result<(), ChatClientError> {
+        let user_input = match user_input {
+            input::ClientUserInput::Again => match self.last_sent.clone() {
+                Some(last_sent) => last_sent,
+                None => {
+                    logger::log_error("Nothing to resend yet");
+                    return Ok(());
+                }
+            },
+            user_input => user_input,
+        };
+        if self.readonly
+            && matches!(
+                user_input,
+                input::ClientUserInput::Message(_)
+                    | input::ClientUserInput::DirectMessage { .. }
+                    | input::ClientUserInput::Reply(_)
+                    | input::ClientUserInput::Page { .. }
+                    | input::ClientUserInput::Poll { .. }
+                    | input::ClientUserInput::Vote { .. }
+            )
+        {
+            logger::log_error("Read-only mode: sending messages is disabled");
+            return Ok(());
+        }
         match user_input {
             input::ClientUserInput::Message(msg) => {
                 if msg.trim().is_empty() {
                     return Ok(());
                 }
+                let max_length = self.server_limits.max_message_length;
+                if msg.len() > max_length {
+                    logger::log_error(&format!(
+                        "Message too long ({} bytes, max {})",
+                        msg.len(),
+                        max_length
+                    ));
+                    return Ok(());
+                }
+                self.clear_afk().await?;
                 // Display locally immediately
                 let display_msg = format!("{}: {}", self.chat_name, msg);
                 logger::log_chat(&display_msg);
 
+                self.last_sent = Some(input::ClientUserInput::Message(msg.clone()));
+                self.unconfirmed.push_back(UnconfirmedChat {
+                    sent_at: Instant::now(),
+                    text: msg.clone(),
+                });
                 let message =
                     ChatMessage::try_new(MessageTypes::ChatMessage, Some(msg.into_bytes()))?;
                 self.send_message_chunked(message).await?;
@@ -779,14 +1660,37 @@ impl ChatClient {
                 if msg.trim().is_empty() {
                     return Ok(());
                 }
+                self.clear_afk().await?;
                 // Display DM locally immediately
-                logger::log_info(&format!("[DM to {}]: {}", recipient, msg));
-
-                let dm_content = format!("{}|{}", recipient, msg);
-                let message = ChatMessage::try_new(
-                    MessageTypes::DirectMessage,
-                    Some(dm_content.into_bytes()),
-                )?;
+                self.echo_direct_message(&recipient, &msg);
+                self.remember_direct_message(&recipient, &msg);
+                self.send_direct_message(&recipient, &msg).await
+            }
+            input::ClientUserInput::Page {
+                recipient,
+                message: msg,
+            } => {
+                if msg.trim().is_empty() {
+                    return Ok(());
+                }
+                self.clear_afk().await?;
+                // Pages are relayed in the clear, even with end-to-end encryption on
+                logger::log_info(&format!("[Page to {}]: {}", recipient, msg));
+                let content = format!("{}|{}", recipient, msg);
+                let message = ChatMessage::try_new(MessageTypes::Page, Some(content.into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Poll { question, options } => {
+                let content = std::iter::once(question).chain(options).collect::<Vec<_>>();
+                let message =
+                    ChatMessage::try_new(MessageTypes::Poll, Some(content.join("|").into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Vote { id, option } => {
+                let content = format!("{}|{}", id, option);
+                let message = ChatMessage::try_new(MessageTypes::Vote, Some(content.into_bytes()))?;
                 self.send_message_chunked(message).await?;
                 Ok(())
             }
@@ -794,17 +1698,12 @@ impl ChatClient {
                 if msg.trim().is_empty() {
                     return Ok(());
                 }
-                if let Some(recipient) = &self.last_dm_sender {
+                if let Some(recipient) = self.completion.last_dm_sender() {
+                    self.clear_afk().await?;
                     // Display reply locally immediately
-                    logger::log_info(&format!("[DM to {}]: {}", recipient, msg));
-
-                    let dm_content = format!("{}|{}", recipient, msg);
-                    let message = ChatMessage::try_new(
-                        MessageTypes::DirectMessage,
-                        Some(dm_content.into_bytes()),
-                    )?;
-                    self.send_message_chunked(message).await?;
-                    Ok(())
+                    self.echo_direct_message(&recipient, &msg);
+                    self.remember_direct_message(&recipient, &msg);
+                    self.send_direct_message(&recipient, &msg).await
                 } else {
                     logger::log_error("No one to reply to. Use /dm <username> <message> first.");
                     Ok(())
@@ -816,12 +1715,17 @@ impl ChatClient {
                 }
                 Ok(())
             }
-            input::ClientUserInput::ListUsers => {
-                let message = ChatMessage::try_new(MessageTypes::ListUsers, None)?;
+            input::ClientUserInput::ListUsers(page) => {
+                let content = page.map(|page| page.to_string().into_bytes());
+                let message = ChatMessage::try_new(MessageTypes::ListUsers, content)?;
                 self.send_message_chunked(message).await?;
                 Ok(())
             }
             input::ClientUserInput::Rename(new_name) => {
+                let Some(new_name) = new_name.or_else(|| self.suggested_name.take()) else {
+                    logger::log_error("No suggested name yet - use /rename <new_name>");
+                    return Ok(());
+                };
                 let message =
                     ChatMessage::try_new(MessageTypes::RenameRequest, Some(new_name.into_bytes()))?;
                 self.send_message_chunked(message).await?;
@@ -830,39 +1734,468 @@ impl ChatClient {
             input::ClientUserInput::SendFile {
                 recipient,
                 file_path,
-            } => self.send_file_request(&recipient, &file_path).await,
+                caption,
+            } => {
+                self.send_file_request(&recipient, &file_path, caption.as_deref())
+                    .await
+            }
             input::ClientUserInput::AcceptFile { sender } => {
                 self.accept_file_transfer(&sender).await
             }
             input::ClientUserInput::RejectFile { sender } => {
                 self.reject_file_transfer(&sender).await
             }
+            input::ClientUserInput::ShareFile(file_path) => self.share_file(&file_path).await,
+            input::ClientUserInput::GetFile(file_id) => {
+                let message = ChatMessage::try_new(
+                    MessageTypes::FileGet,
+                    Some(file_id.to_be_bytes().to_vec()),
+                )?;
+                self.send_message_chunked(message).await?;
+                logger::log_info(&format!("Downloading shared file #{}...", file_id));
+                Ok(())
+            }
             input::ClientUserInput::Status(status) => {
-                // Store status locally so we can restore it after reconnection
-                self.current_status = status.clone();
-                let content = status.map(|s| s.into_bytes());
-                let message = ChatMessage::try_new(MessageTypes::SetStatus, content)?;
+                // An explicit status replaces any /afk status
+                self.afk = false;
+                self.set_status(status).await
+            }
+            input::ClientUserInput::Afk(reason) => {
+                let status = match reason {
+                    Some(reason) => format!("{}: {}", AWAY_STATUS_PREFIX, reason),
+                    None => AWAY_STATUS_PREFIX.to_string(),
+                };
+                self.set_status(Some(status)).await?;
+                if self.current_status.is_some() {
+                    self.afk = true;
+                    logger::log_info("You are now AFK (cleared when you next send a message)");
+                }
+                Ok(())
+            }
+            input::ClientUserInput::Keys => {
+                match &self.e2e {
+                    Some(e2e) => {
+                        logger::log_info(&format!("Your fingerprint: {}", e2e.fingerprint()));
+                        let peers = e2e.peer_fingerprints();
+                        if peers.is_empty() {
+                            logger::log_info("No peer keys exchanged yet");
+                        } else {
+                            logger::log_info("Known peer fingerprints:");
+                            for (name, fingerprint) in peers {
+                                logger::log_info(&format!(" - {}: {}", name, fingerprint));
+                            }
+                        }
+                    }
+                    None => logger::log_info(
+                        "End-to-end encryption is disabled (set CHAT_E2E=1 to enable)",
+                    ),
+                }
+                Ok(())
+            }
+            input::ClientUserInput::Topic(None) => {
+                match &self.topic {
+                    Some(topic) => logger::log_info(&format!("Topic: {}", topic)),
+                    None => logger::log_info("No topic is set"),
+                }
+                Ok(())
+            }
+            input::ClientUserInput::Topic(Some(text)) => {
+                let message = ChatMessage::try_new(MessageTypes::Topic, Some(text.into_bytes()))?;
                 self.send_message_chunked(message).await?;
                 Ok(())
             }
+            input::ClientUserInput::Link => {
+                let invite = match &self.server_name {
+                    Some(name) => format!("Share this address to invite others to {}", name),
+                    None => "Share this address to invite others".to_string(),
+                };
+                logger::log_info(&format!("{}: {}", invite, self.server_link()));
+                Ok(())
+            }
+            input::ClientUserInput::Whois(username) => {
+                let message =
+                    ChatMessage::try_new(MessageTypes::Whois, Some(username.into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Seen(username) => {
+                let message =
+                    ChatMessage::try_new(MessageTypes::SeenRequest, Some(username.into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Search(term) => {
+                let message = ChatMessage::try_new(MessageTypes::Search, Some(term.into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Block(username) => {
+                self.send_block_request(&format!("block|{}", username))
+                    .await
+            }
+            input::ClientUserInput::Unblock(username) => {
+                self.send_block_request(&format!("unblock|{}", username))
+                    .await
+            }
+            input::ClientUserInput::Blocked => self.send_block_request("list").await,
+            input::ClientUserInput::Oper(password) => {
+                self.send_moderation_request(&format!("oper|{}", password))
+                    .await
+            }
+            input::ClientUserInput::Op(username) => {
+                self.send_moderation_request(&format!("op|{}", username))
+                    .await
+            }
+            input::ClientUserInput::Deop(username) => {
+                self.send_moderation_request(&format!("deop|{}", username))
+                    .await
+            }
+            input::ClientUserInput::Kick(username) => {
+                self.send_moderation_request(&format!("kick|{}", username))
+                    .await
+            }
+            input::ClientUserInput::ServerInfo => {
+                let message = ChatMessage::try_new(MessageTypes::ServerInfo, None)?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Quality => {
+                let now = Instant::now();
+                if let Some(waited) = self.latency.overdue(now) {
+                    logger::log_warning(&format!(
+                        "No reply to the last ping after {}s; the connection may be unhealthy",
+                        waited.as_secs()
+                    ));
+                }
+                let seq = self.latency.start(now);
+                let message =
+                    ChatMessage::try_new(MessageTypes::Ping, Some(seq.to_string().into_bytes()))?;
+                self.send_message_chunked(message).await?;
+                Ok(())
+            }
+            input::ClientUserInput::Connect(profile) => self.connect_profile(&profile).await,
+            input::ClientUserInput::Profiles(None) => {
+                list_profiles();
+                Ok(())
+            }
+            input::ClientUserInput::Profiles(Some(name)) => {
+                self.save_profile(&name);
+                Ok(())
+            }
+            // Resolved to the last sent message above
+            input::ClientUserInput::Again => Ok(()),
+            input::ClientUserInput::Pause => {
+                if self.paused.is_some() {
+                    logger::log_info("Already paused - /resume shows the held messages");
+                } else {
+                    self.paused = Some(PauseBuffer::new(PAUSE_BUFFER_LIMIT));
+                    logger::log_info(
+                        "Paused: channel messages are held until /resume (DMs and notices still show)",
+                    );
+                }
+                Ok(())
+            }
+            input::ClientUserInput::Resume => {
+                let Some(paused) = self.paused.take() else {
+                    logger::log_info("Not paused");
+                    return Ok(());
+                };
+                let (held, dropped) = paused.into_parts();
+                logger::log_info(&format!("Resumed: {} held messages", held.len()));
+                if dropped > 0 {
+                    logger::log_warning(&format!("({} messages dropped)", dropped));
+                }
+                for message in held {
+                    self.handle_message(message).await;
+                }
+                Ok(())
+            }
+            input::ClientUserInput::Dnd => {
+                if self.dnd {
+                    self.dnd = false;
+                    if self.current_status.as_deref() == Some(DND_STATUS) {
+                        self.set_status(None).await?;
+                    }
+                    logger::log_info("Do not disturb off: all messages are shown again");
+                } else {
+                    // Replaces any /afk status
+                    self.afk = false;
+                    self.set_status(Some(DND_STATUS.to_string())).await?;
+                    self.dnd = true;
+                    logger::log_info(
+                        "Do not disturb on: only DMs, pages and errors are shown until /dnd again",
+                    );
+                }
+                Ok(())
+            }
             input::ClientUserInput::Quit => {
-                // Send Leave message to server so it knows this is an explicit quit
-                // (as opposed to a connection drop that might be a reconnection)
-                let message = ChatMessage::try_new(MessageTypes::Leave, None)?;
-                let _ = self.send_message_chunked(message).await;
+                self.leave().await;
                 Ok(())
             }
         }
     }
 
+    /// Tell the server this is an explicit quit (as opposed to a connection drop
+    /// that might be a reconnection), wait briefly for its `LeaveAck`, then close
+    /// the connection. Returns whether the server confirmed the Leave.
+    async fn leave(&mut self) -> bool {
+        let mut acked = false;
+        if let Ok(message) = ChatMessage::try_new(MessageTypes::Leave, None)
+            && self.send_message_chunked(message).await.is_ok()
+        {
+            // Anything else still in flight is discarded, we're leaving
+            let wait_for_ack = async {
+                while let Ok(message) = self.read_message_chunked().await {
+                    if message.msg_type == MessageTypes::LeaveAck {
+                        return true;
+                    }
+                }
+                false
+            };
+            acked = tokio::time::timeout(LEAVE_ACK_TIMEOUT, wait_for_ack)
+                .await
+                .unwrap_or(false);
+        }
+        let _ = self.connection.shutdown().await;
+        acked
+    }
+
+    /// Warn about chat messages the server hasn't acknowledged within `max_age`
+    /// (all of them for `Duration::ZERO`, e.g. when the connection dropped)
+    fn expire_unconfirmed(&mut self, max_age: Duration) {
+        while let Some(oldest) = self.unconfirmed.pop_front() {
+            if oldest.sent_at.elapsed() < max_age {
+                self.unconfirmed.push_front(oldest);
+                break;
+            }
+            logger::log_warning(&format!(
+                "The server never confirmed \"{}\" - it may not have been delivered (use /again to resend)",
+                oldest.text
+            ));
+        }
+    }
+
+    /// Keep a sent DM for /again
+    fn remember_direct_message(&mut self, recipient: &str, message: &str) {
+        self.last_sent = Some(input::ClientUserInput::DirectMessage {
+            recipient: recipient.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Validate and send a status update (None clears it)
+    async fn set_status(&mut self, status: Option<String>) -> Result<(), ChatClientError> {
+        // Mirror the server's sanitizing and length check for immediate feedback
+        let status = status
+            .map(|s| limits::strip_control_chars(&s).trim().to_string())
+            .filter(|s| !s.is_empty());
+        let max_length = self.server_limits.max_status_length;
+        if let Some(text) = &status
+            && text.len() > max_length
+        {
+            logger::log_error(&format!(
+                "Status too long ({} characters, max {})",
+                text.len(),
+                max_length
+            ));
+            return Ok(());
+        }
+        // Store status locally so we can restore it after reconnection
+        self.current_status = status.clone();
+        let content = status.map(|s| s.into_bytes());
+        let message = ChatMessage::try_new(MessageTypes::SetStatus, content)?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
+    /// Clear an /afk status once the user is active again
+    async fn clear_afk(&mut self) -> Result<(), ChatClientError> {
+        if self.afk {
+            self.afk = false;
+            logger::log_info("Welcome back! Clearing AFK status");
+            self.set_status(None).await?;
+        }
+        Ok(())
+    }
+
+    fn echo_direct_message(&self, recipient: &str, msg: &str) {
+        let label = if self.e2e.is_some() { "E2E DM" } else { "DM" };
+        logger::log_info(&format!("[{} to {}]: {}", label, recipient, msg));
+    }
+
+    /// Send a DM, encrypting it first when end-to-end encryption is enabled.
+    /// Messages to peers whose key we don't have yet are held until it arrives.
+    async fn send_direct_message(
+        &mut self,
+        recipient: &str,
+        msg: &str,
+    ) -> Result<(), ChatClientError> {
+        let body = match &mut self.e2e {
+            Some(e2e) if !e2e.has_peer(recipient) => {
+                e2e.queue(recipient, msg.to_string());
+                logger::log_info(&format!(
+                    "Exchanging encryption keys with {}; message will be sent once they reply",
+                    recipient
+                ));
+                return self.send_key_exchange(recipient).await;
+            }
+            Some(e2e) => match e2e.encrypt(recipient, msg) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    logger::log_error(&format!("Failed to encrypt DM: {:?}", e));
+                    return Ok(());
+                }
+            },
+            None => msg.to_string(),
+        };
+
+        let dm_content = format!("{}|{}", recipient, body);
+        let message =
+            ChatMessage::try_new(MessageTypes::DirectMessage, Some(dm_content.into_bytes()))?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
+    /// Ask the server to change or list our blocks (format: block|user, unblock|user or list)
+    async fn send_block_request(&mut self, request: &str) -> Result<(), ChatClientError> {
+        let message = ChatMessage::try_new(MessageTypes::Block, Some(request.as_bytes().to_vec()))?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
+    /// Ask the server for an operator action (format: oper|password, op|user,
+    /// deop|user or kick|user)
+    async fn send_moderation_request(&mut self, request: &str) -> Result<(), ChatClientError> {
+        let message =
+            ChatMessage::try_new(MessageTypes::Moderation, Some(request.as_bytes().to_vec()))?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
+    /// Leave this server and join the one saved in profile `name`. The current
+    /// connection is kept if the new server can't be reached.
+    async fn connect_profile(&mut self, name: &str) -> Result<(), ChatClientError> {
+        let profiles = match Profiles::load() {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                logger::log_error(&format!("Failed to read profiles: {}", e));
+                return Ok(());
+            }
+        };
+        let Some(profile) = profiles.get(name) else {
+            logger::log_error(&format!("No profile named '{}' (see /profiles)", name));
+            return Ok(());
+        };
+        let username = profile
+            .username
+            .clone()
+            .unwrap_or_else(|| self.chat_name.clone());
+        let next = match ChatClient::new(&profile.address, username).await {
+            Ok(next) => next,
+            Err(e) => {
+                logger::log_error(&format!("Could not connect to profile '{}': {}", name, e));
+                logger::log_info(&format!("Still connected to {}", self.server_link()));
+                return Ok(());
+            }
+        };
+
+        // Leave explicitly so the old server doesn't hold our session for a reconnect
+        self.leave().await;
+
+        self.connection = next.connection;
+        self.server_host = next.server_host;
+        self.server_port = next.server_port;
+        self.use_tls = next.use_tls;
+        self.chat_name = next.chat_name;
+        self.session_token = next.session_token;
+        self.session_secret = next.session_secret;
+
+        // Nothing from the old server carries over
+        self.expire_unconfirmed(Duration::ZERO);
+        self.was_kicked = false;
+        self.server_full = false;
+        self.suggested_name = None;
+        self.current_status = None;
+        self.afk = false;
+        self.dnd = false;
+        self.topic = None;
+        self.server_name = None;
+        self.latency = LatencyTracker::default();
+        self.pending_outgoing.clear();
+        self.pending_incoming.clear();
+        self.server_limits = ServerLimits::default();
+        self.completion.set_users(std::iter::empty());
+        self.completion.set_pending_senders(std::iter::empty());
+        if self.e2e.is_some() {
+            // Same names on another server may be different people
+            self.enable_e2e();
+        }
+
+        logger::log_info(&format!(
+            "Switched to profile '{}' ({})",
+            name, profile.address
+        ));
+        self.join_server().await
+    }
+
+    /// Save the current server and username as profile `name`
+    fn save_profile(&self, name: &str) {
+        if name.contains('=') {
+            logger::log_error("Profile names can't contain '='");
+            return;
+        }
+        let mut profiles = match Profiles::load() {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                logger::log_error(&format!("Failed to read profiles: {}", e));
+                return;
+            }
+        };
+        profiles.insert(
+            name,
+            Profile {
+                address: self.server_link(),
+                username: Some(self.chat_name.clone()),
+            },
+        );
+        match profiles.save() {
+            Ok(path) => logger::log_success(&format!(
+                "Saved profile '{}' ({} as {}) to {}",
+                name,
+                self.server_link(),
+                self.chat_name,
+                path.display()
+            )),
+            Err(e) => logger::log_error(&format!("Failed to save profiles: {}", e)),
+        }
+    }
+
+    /// Send our public key to `peer` (format: peer|hex_public_key)
+    async fn send_key_exchange(&mut self, peer: &str) -> Result<(), ChatClientError> {
+        let Some(e2e) = &self.e2e else {
+            return Ok(());
+        };
+        let content = format!("{}|{}", peer, e2e.public_key_hex());
+        let message = ChatMessage::try_new(MessageTypes::KeyExchange, Some(content.into_bytes()))?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
     /// Send a file transfer request (not the actual file data)
     async fn send_file_request(
         &mut self,
         recipient: &str,
         file_path: &str,
+        caption: Option<&str>,
     ) -> Result<(), ChatClientError> {
         let path = Path::new(file_path);
 
+        // Stdin carries commands here; piping a file needs the --send flag
+        if file_path == "-" {
+            logger::log_error("To send stdin as a file, start the client with --send <username>");
+            return Ok(());
+        }
+
         // Check if file exists
         if !path.exists() {
             logger::log_error(&format!("File not found: {}", file_path));
@@ -878,57 +2211,104 @@ impl ChatClient {
             }
         };
 
-        let file_size = metadata.len() as usize;
+        // Get file name
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+
+        let transfer = PendingOutgoingTransfer {
+            recipient: recipient.to_string(),
+            file_path: file_path.to_string(),
+            file_name: file_name.to_string(),
+            file_size: metadata.len() as usize,
+            data: None,
+        };
+        self.offer_file(transfer, caption).await
+    }
+
+    /// Offer `data` to `recipient` as a file named `file_name`, like `/send`
+    /// does for a file on disk. The data is sent once they accept.
+    pub async fn send_data(
+        &mut self,
+        recipient: &str,
+        file_name: &str,
+        data: Vec<u8>,
+        caption: Option<&str>,
+    ) -> Result<(), ChatClientError> {
+        let transfer = PendingOutgoingTransfer {
+            recipient: recipient.to_string(),
+            file_path: "-".to_string(),
+            file_name: file_name.to_string(),
+            file_size: data.len(),
+            data: Some(data),
+        };
+        self.offer_file(transfer, caption).await
+    }
+
+    /// Ask the recipient to accept `transfer`, keeping it until they answer
+    async fn offer_file(
+        &mut self,
+        transfer: PendingOutgoingTransfer,
+        caption: Option<&str>,
+    ) -> Result<(), ChatClientError> {
+        let recipient = transfer.recipient.as_str();
+        let file_name = transfer.file_name.as_str();
+        let file_size = transfer.file_size;
+
+        if let Some(caption) = caption
+            && caption.len() > MAX_CAPTION_LENGTH
+        {
+            logger::log_error(&format!(
+                "Caption too long (max {} bytes)",
+                MAX_CAPTION_LENGTH
+            ));
+            return Ok(());
+        }
+
+        // The server rejects requests beyond its per-user cap (0 = unlimited)
+        let max_transfers = self.server_limits.max_file_transfers;
+        if max_transfers > 0
+            && !self.pending_outgoing.contains_key(recipient)
+            && self.pending_outgoing.len() >= max_transfers
+        {
+            logger::log_error(&format!(
+                "Too many file transfers in progress (max {})",
+                max_transfers
+            ));
+            return Ok(());
+        }
 
         // Check file size (100MB limit, minus some overhead for metadata)
-        let max_content_size = MAX_FILE_SIZE - 1024; // Leave room for headers
-        if file_size > max_content_size {
+        if file_size > MAX_FILE_CONTENT_SIZE {
             logger::log_error(&format!(
                 "File too large: {} bytes (max {} bytes / ~100MB)",
-                file_size, max_content_size
+                file_size, MAX_FILE_CONTENT_SIZE
             ));
             return Ok(());
         }
 
-        // Get file name
-        let file_name = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-
-        // Format file size for display
-        let size_display = if file_size >= 1024 * 1024 {
-            format!("{:.1} MB", file_size as f64 / (1024.0 * 1024.0))
-        } else if file_size >= 1024 {
-            format!("{:.1} KB", file_size as f64 / 1024.0)
-        } else {
-            format!("{} bytes", file_size)
-        };
+        let size_display = format_file_size(file_size);
 
         logger::log_info(&format!(
             "Requesting to send '{}' ({}) to {}...",
             file_name, size_display, recipient
         ));
 
-        // Store the pending transfer
-        self.pending_outgoing.insert(
-            recipient.to_string(),
-            PendingOutgoingTransfer {
-                recipient: recipient.to_string(),
-                file_path: file_path.to_string(),
-                file_name: file_name.to_string(),
-                file_size,
-            },
-        );
-
         // Build file transfer request message
-        // Format: recipient_len(1)|recipient|filename_len(1)|filename|filesize(8 bytes)
-        let mut content = Vec::new();
-        content.push(recipient.len() as u8);
-        content.extend_from_slice(recipient.as_bytes());
-        content.push(file_name.len() as u8);
-        content.extend_from_slice(file_name.as_bytes());
-        content.extend_from_slice(&(file_size as u64).to_be_bytes());
+        // Format: recipient_len(1)|recipient|filename_len(1)|filename|filesize(8 bytes)[|caption_len(1)|caption]
+        let content = FileRequestHeader {
+            recipient,
+            sender: None,
+            filename: file_name,
+            file_size: file_size as u64,
+            caption,
+        }
+        .encode()?;
+        let recipient = recipient.to_string();
+
+        // Store the pending transfer
+        self.pending_outgoing.insert(recipient.clone(), transfer);
 
         let message = ChatMessage::try_new(MessageTypes::FileTransferRequest, Some(content))?;
         self.send_message_chunked(message).await?;
@@ -940,27 +2320,24 @@ impl ChatClient {
         Ok(())
     }
 
-    /// Actually send the file data (called after recipient accepts)
-    async fn send_file_data(
-        &mut self,
-        recipient: &str,
-        file_path: &str,
-    ) -> Result<(), ChatClientError> {
+    /// Upload a file for everyone in the room to fetch with /get
+    async fn share_file(&mut self, file_path: &str) -> Result<(), ChatClientError> {
         let path = Path::new(file_path);
-
-        // Check if file still exists
-        if !path.exists() {
-            logger::log_error(&format!("File no longer exists: {}", file_path));
+        let file_size = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len() as usize,
+            Err(e) => {
+                logger::log_error(&format!("Can't share {}: {}", file_path, e));
+                return Ok(());
+            }
+        };
+        if file_size > MAX_SHARED_FILE_SIZE {
+            logger::log_error(&format!(
+                "File too large to share: {} (max {}); use /send for bigger files",
+                format_file_size(file_size),
+                format_file_size(MAX_SHARED_FILE_SIZE)
+            ));
             return Ok(());
         }
-
-        // Get file name
-        let file_name = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-
-        // Read file
         let file_data = match std::fs::read(path) {
             Ok(data) => data,
             Err(e) => {
@@ -968,6 +2345,55 @@ impl ChatClient {
                 return Ok(());
             }
         };
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+
+        logger::log_info(&format!(
+            "Sharing '{}' ({}) with the room...",
+            file_name,
+            format_file_size(file_data.len())
+        ));
+        let content = FileUploadHeader {
+            filename: file_name,
+            data: &file_data,
+        }
+        .encode()?;
+        let message = ChatMessage::try_new(MessageTypes::FileOffer, Some(content))?;
+        self.send_message_chunked(message).await?;
+        Ok(())
+    }
+
+    /// Actually send the file data (called after recipient accepts)
+    async fn send_file_data(
+        &mut self,
+        transfer: PendingOutgoingTransfer,
+    ) -> Result<(), ChatClientError> {
+        let recipient = transfer.recipient.as_str();
+        let file_name = transfer.file_name.as_str();
+
+        let file_data = match transfer.data {
+            Some(data) => data,
+            None => {
+                let path = Path::new(&transfer.file_path);
+
+                // Check if file still exists
+                if !path.exists() {
+                    logger::log_error(&format!("File no longer exists: {}", transfer.file_path));
+                    return Ok(());
+                }
+
+                // Read file
+                match std::fs::read(path) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        logger::log_error(&format!("Failed to read file: {}", e));
+                        return Ok(());
+                    }
+                }
+            }
+        };
 
         logger::log_info(&format!(
             "Sending file '{}' ({} bytes) to {}...",
@@ -978,17 +2404,41 @@ impl ChatClient {
 
         // Build file transfer message: recipient|filename|filedata
         // We use a binary format: recipient_len(1)|recipient|filename_len(1)|filename|filedata
-        let mut content = Vec::new();
-        content.push(recipient.len() as u8);
-        content.extend_from_slice(recipient.as_bytes());
-        content.push(file_name.len() as u8);
-        content.extend_from_slice(file_name.as_bytes());
-        content.extend_from_slice(&file_data);
+        let content = FileTransferHeader {
+            recipient,
+            sender: None,
+            filename: file_name,
+            data: &file_data,
+        }
+        .encode()?;
 
         let message = ChatMessage::try_new(MessageTypes::FileTransfer, Some(content))?;
         self.send_message_chunked(message).await?;
 
         logger::log_success(&format!("File '{}' sent to {}", file_name, recipient));
+        self.files_sent += 1;
+        Ok(())
+    }
+
+    /// Tell the original sender whether a received file was saved
+    async fn send_file_transfer_ack(
+        &mut self,
+        sender: &str,
+        file_name: &str,
+        error: Option<&str>,
+    ) -> Result<(), ChatClientError> {
+        // Format: sender_len(1)|sender|success(1)|filename_len(1)|filename|error
+        let content = FileAckHeader {
+            file_sender: sender,
+            receiver: None,
+            saved: error.is_none(),
+            filename: file_name,
+            error: error.unwrap_or_default(),
+        }
+        .encode()?;
+
+        let message = ChatMessage::try_new(MessageTypes::FileTransferAck, Some(content))?;
+        self.send_message_chunked(message).await?;
         Ok(())
     }
 
@@ -996,6 +2446,8 @@ impl ChatClient {
     async fn accept_file_transfer(&mut self, sender: &str) -> Result<(), ChatClientError> {
         // Check if there's a pending transfer from this sender
         if let Some(transfer) = self.pending_incoming.remove(sender) {
+            self.completion
+                .set_pending_senders(self.pending_incoming.keys());
             logger::log_info(&format!(
                 "Accepting file '{}' from {}...",
                 transfer.file_name, sender
@@ -1003,10 +2455,12 @@ impl ChatClient {
 
             // Build response message
             // Format: sender_len(1)|sender|accepted(1)
-            let mut content = Vec::new();
-            content.push(sender.len() as u8);
-            content.extend_from_slice(sender.as_bytes());
-            content.push(1u8); // accepted = true
+            let content = FileResponseHeader {
+                file_sender: sender,
+                responder: None,
+                accepted: true,
+            }
+            .encode()?;
 
             let message = ChatMessage::try_new(MessageTypes::FileTransferResponse, Some(content))?;
             self.send_message_chunked(message).await?;
@@ -1021,6 +2475,8 @@ impl ChatClient {
     async fn reject_file_transfer(&mut self, sender: &str) -> Result<(), ChatClientError> {
         // Check if there's a pending transfer from this sender
         if let Some(transfer) = self.pending_incoming.remove(sender) {
+            self.completion
+                .set_pending_senders(self.pending_incoming.keys());
             logger::log_info(&format!(
                 "Rejecting file '{}' from {}",
                 transfer.file_name, sender
@@ -1028,10 +2484,12 @@ impl ChatClient {
 
             // Build response message
             // Format: sender_len(1)|sender|accepted(1)
-            let mut content = Vec::new();
-            content.push(sender.len() as u8);
-            content.extend_from_slice(sender.as_bytes());
-            content.push(0u8); // accepted = false
+            let content = FileResponseHeader {
+                file_sender: sender,
+                responder: None,
+                accepted: false,
+            }
+            .encode()?;
 
             let message = ChatMessage::try_new(MessageTypes::FileTransferResponse, Some(content))?;
             self.send_message_chunked(message).await?;
@@ -1044,16 +2502,91 @@ impl ChatClient {
 
     pub async fn run(&mut self) -> io::Result<()> {
         // Spawn readline handler in a blocking thread with username as prompt
-        let mut readline_rx = readline_helper::spawn_readline_handler(
-            self.connected_users.clone(),
-            self.chat_name.clone(),
-        );
+        // (or a plain stdin reader in stdio mode)
+        let (mut reader, resize_watcher) = if self.stdio {
+            (stdio::spawn_stdin_reader(), None)
+        } else {
+            let reader = readline_helper::spawn_readline_handler(
+                self.completion.clone(),
+                self.chat_name.clone(),
+            );
+            (reader, Some(resize::spawn_resize_watcher()))
+        };
+        let result = self.run_with_input(&mut reader.rx).await;
+        if let Some(resize_watcher) = resize_watcher {
+            resize_watcher.abort();
+        }
+        reader.shutdown();
+        result
+    }
+
+    /// Handle server messages until the join has completed. The server echoes
+    /// our own join after its other join replies (limits, MOTD, topic...), so
+    /// anything sent after this doesn't cross them. Useful before sending
+    /// straight after `join_server`, as `--send` does.
+    pub async fn wait_until_joined(&mut self) -> io::Result<()> {
+        loop {
+            let message = match self.read_message_chunked().await {
+                Ok(message) => message,
+                Err(shared::network::TcpMessageHandlerError::IoError(e)) => return Err(e),
+                Err(shared::network::TcpMessageHandlerError::Disconnect) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Disconnected while joining",
+                    ));
+                }
+            };
+            let own_join = message.msg_type == MessageTypes::Join
+                && message.content_as_string().as_deref() == Some(self.chat_name.as_str());
+            self.handle_message(message).await;
+            if own_join {
+                return Ok(());
+            }
+        }
+    }
 
+    /// Handle server messages until every file offered with `send_data` (or
+    /// `/send`) has been sent or turned down, then leave. For one-shot sends
+    /// such as `--send`; fails unless every offered file was sent.
+    pub async fn run_until_sent(&mut self) -> io::Result<()> {
+        let offered = self.pending_outgoing.len();
+        let sent_before = self.files_sent;
+        self.exit_when_sent = true;
+        // No user input: the sender is held so the channel stays open
+        let (_input, mut rx) = mpsc::unbounded_channel();
+        self.run_with_input(&mut rx).await?;
+
+        let sent = self.files_sent - sent_before;
+        if offered == 0 || sent < offered {
+            return Err(io::Error::other(format!(
+                "File transfer did not complete ({} of {} sent)",
+                sent, offered
+            )));
+        }
+        Ok(())
+    }
+
+    /// Handle server messages and user input until the user quits or the input
+    /// channel closes
+    async fn run_with_input(
+        &mut self,
+        readline_rx: &mut mpsc::UnboundedReceiver<Option<ReadlineInput>>,
+    ) -> io::Result<()> {
         loop {
+            // Checked between events rather than on a timer: a timer branch would
+            // cancel reads part-way through a frame. Server pings bound the delay.
+            self.expire_unconfirmed(CHAT_ACK_TIMEOUT);
+            if self.exit_when_sent && self.pending_outgoing.is_empty() {
+                self.leave().await;
+                return Ok(());
+            }
             tokio::select! {
                 result = self.read_message_chunked() => {
                     match result {
                         Ok(message) => {
+                            if let Some(incoming) = &self.incoming {
+                                let _ = incoming.send(message.clone());
+                            }
                             if !self.handle_message(message).await {
                                 // handle_message returned false, indicating a connection issue
                                 logger::log_warning("Connection issue detected while handling message");
@@ -1063,7 +2596,11 @@ impl ChatClient {
                         Err(shared::network::TcpMessageHandlerError::Disconnect) => {
                             logger::log_warning("Disconnected from server");
 
-                            // Don't reconnect if we were kicked
+                            // Don't reconnect if we were kicked (or need an upgrade)
+                            if self.version_mismatch {
+                                logger::log_info("Not reconnecting - upgrade the client to match the server");
+                                return Ok(());
+                            }
                             if self.was_kicked {
                                 logger::log_info("Not reconnecting - you were kicked from the server");
                                 return Ok(());
@@ -1075,20 +2612,45 @@ impl ChatClient {
                                     // Connection restored
                                 }
                                 Err(e) => {
-                                    logger::log_error(&format!("Failed to reconnect: {:?}", e));
+                                    logger::log_error(&format!("Failed to reconnect: {}", e));
                                     return Err(io::Error::other("Reconnection failed"));
                                 }
                             }
                         }
                     }
                 }
-                Some(line) = readline_rx.recv() => {
-                    match line {
-                        Some(input_line) => {
-                            match ClientUserInput::try_from(input_line.as_str()) {
-                                Ok(input::ClientUserInput::Quit) => return Ok(()),
-                                Ok(input::ClientUserInput::ListUsers) => {
-                                    let message = ChatMessage::try_new(MessageTypes::ListUsers, None)
+                line = readline_rx.recv() => {
+                    match line.flatten() {
+                        Some(input) => {
+                            let (parsed, typed_line) = match input {
+                                ReadlineInput::Line(input_line) => (ClientUserInput::try_from(input_line.as_str()), Some(input_line)),
+                                // Multi-line blocks are always chat messages, even if they start with '/'
+                                ReadlineInput::Block(block) => (Ok(ClientUserInput::Message(block)), None),
+                                // The input thread has stopped; leave as if the user typed /quit
+                                ReadlineInput::Eof => {
+                                    logger::log_info("End of input, leaving the chat...");
+                                    (Ok(ClientUserInput::Quit), None)
+                                }
+                                ReadlineInput::Interrupted => {
+                                    logger::log_info("Interrupted, leaving the chat...");
+                                    (Ok(ClientUserInput::Quit), None)
+                                }
+                                ReadlineInput::Failed(e) => {
+                                    logger::log_error(&format!("Failed to read input: {e}"));
+                                    self.leave().await;
+                                    return Err(io::Error::other(format!("Failed to read input: {e}")));
+                                }
+                            };
+                            match parsed {
+                                Ok(input::ClientUserInput::Quit) => {
+                                    if !self.leave().await {
+                                        logger::log_warning("The server did not confirm the leave; it may hold your session briefly");
+                                    }
+                                    return Ok(());
+                                }
+                                Ok(input::ClientUserInput::ListUsers(page)) => {
+                                    let content = page.map(|page| page.to_string().into_bytes());
+                                    let message = ChatMessage::try_new(MessageTypes::ListUsers, content)
                                         .map_err(|e| io::Error::other(format!("Failed to create ListUsers message: {e:?}")))?;
                                     if let Err(e) = self.send_message_chunked(message).await {
                                         logger::log_warning("Connection lost while sending message");
@@ -1099,7 +2661,7 @@ impl ChatClient {
                                                     // Connection restored
                                                 }
                                                 Err(reconnect_err) => {
-                                                    logger::log_error(&format!("Failed to reconnect: {:?}", reconnect_err));
+                                                    logger::log_error(&format!("Failed to reconnect: {}", reconnect_err));
                                                     return Err(io::Error::other(format!("Failed to send ListUsers message: {e:?}")));
                                                 }
                                             }
@@ -1109,7 +2671,7 @@ impl ChatClient {
                                 Ok(user_input) => {
                                     if let Err(e) = self.handle_user_input(user_input).await {
                                         // Check if this is a connection error that needs reconnection
-                                        if matches!(e, ChatClientError::IoError) {
+                                        if matches!(e, ChatClientError::IoError(_)) {
                                             logger::log_warning("Connection lost while sending message");
 
                                             if !self.was_kicked {
@@ -1118,23 +2680,26 @@ impl ChatClient {
                                                         // Connection restored
                                                     }
                                                     Err(reconnect_err) => {
-                                                        logger::log_error(&format!("Failed to reconnect: {:?}", reconnect_err));
+                                                        logger::log_error(&format!("Failed to reconnect: {}", reconnect_err));
                                                         return Err(io::Error::other("Reconnection failed"));
                                                     }
                                                 }
                                             }
                                         } else {
-                                            logger::log_error(&format!("Error: {e:?}"));
+                                            logger::log_error(&format!("Error: {e}"));
                                         }
                                     }
                                 }
                                 Err(e) => {
                                     logger::log_error(&format!("Input error: {e:?}"));
+                                    if let Some(hint) = typed_line.as_deref().and_then(input::invalid_command_hint) {
+                                        logger::log_info(&hint);
+                                    }
                                 }
                             }
                         }
                         None => {
-                            // EOF or error from readline
+                            // Every handle dropped (or ClientHandle::quit)
                             return Ok(());
                         }
                     }
@@ -1149,4 +2714,188 @@ impl TcpMessageHandler for ChatClient {
     fn get_stream(&mut self) -> &mut Self::Stream {
         &mut self.connection
     }
+    fn compression(&self) -> bool {
+        self.compress
+    }
+    // Replies to the join may be compressed before the confirmation arrives,
+    // so accept them once we've offered it
+    fn accepts_compressed(&self) -> bool {
+        self.request_compression
+    }
+}
+
+/// Messages dropped while do not disturb is on: channel chat, presence and
+/// server announcements. DMs, pages, errors, file transfers and replies to
+/// the user's own commands still show.
+fn dnd_hides(msg_type: MessageTypes) -> bool {
+    matches!(
+        msg_type,
+        MessageTypes::ChatMessage
+            | MessageTypes::Join
+            | MessageTypes::Leave
+            | MessageTypes::ServerNotice
+            | MessageTypes::Topic
+            | MessageTypes::Motd
+            | MessageTypes::Poll
+            | MessageTypes::PollResult
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_dnd_keeps_messages_for_us() {
+        assert!(dnd_hides(MessageTypes::ChatMessage));
+        assert!(dnd_hides(MessageTypes::Join));
+        assert!(dnd_hides(MessageTypes::ServerNotice));
+        assert!(!dnd_hides(MessageTypes::DirectMessage));
+        assert!(!dnd_hides(MessageTypes::Page));
+        assert!(!dnd_hides(MessageTypes::Error));
+        assert!(!dnd_hides(MessageTypes::FileTransferRequest));
+        assert!(!dnd_hides(MessageTypes::Ping));
+    }
+
+    #[test]
+    fn test_piped_input_capped() {
+        assert_eq!(read_to_limit(&b"report"[..], 6).unwrap(), b"report");
+        let err = read_to_limit(&b"report!"[..], 6).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_connection_refused_keeps_source() {
+        // Grab a free port, then close it so nothing is listening
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let err = connect_tcp("127.0.0.1", port).await.unwrap_err();
+        assert!(matches!(err, ChatClientError::ConnectionRefused(_)));
+        assert!(err.source().is_some());
+        assert!(err.to_string().starts_with("Connection refused"));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_fallback_only_for_non_tls_servers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            // One server hangs up, the other answers in plain text
+            let (first, _) = listener.accept().await.unwrap();
+            drop(first);
+            let (mut second, _) = listener.accept().await.unwrap();
+            second.write_all(b"not a TLS record").await.unwrap();
+        });
+        for _ in 0..2 {
+            let stream = connect_tcp("127.0.0.1", port).await.unwrap();
+            let err = connect_tls("localhost", stream).await.unwrap_err();
+            assert!(server_lacks_tls(&err), "{}", err);
+        }
+
+        let untrusted = io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer),
+        );
+        assert!(!server_lacks_tls(&ChatClientError::TlsError(untrusted)));
+    }
+
+    struct FakeServer(tokio::net::TcpStream);
+
+    impl TcpMessageHandler for FakeServer {
+        type Stream = tokio::net::TcpStream;
+        fn get_stream(&mut self) -> &mut Self::Stream {
+            &mut self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_leave_waits_for_ack_then_closes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = FakeServer(stream);
+            let leave = server.read_message_chunked().await.ok().unwrap();
+            assert_eq!(leave.msg_type, MessageTypes::Leave);
+            let ack = ChatMessage::try_new(MessageTypes::LeaveAck, None).unwrap();
+            server.send_message_chunked(ack).await.unwrap();
+            // The client closes its side once the Leave is confirmed
+            matches!(
+                server.read_message_chunked().await,
+                Err(shared::network::TcpMessageHandlerError::Disconnect)
+            )
+        });
+
+        let mut client = ChatClient::new(&addr, "alice".to_string()).await.unwrap();
+        assert!(client.leave().await);
+        assert!(server.await.unwrap());
+    }
+
+    #[test]
+    fn test_parse_plain_server_addr() {
+        let addr = ChatClient::parse_server_addr("chat.example.com:9000").unwrap();
+        assert_eq!(
+            addr,
+            ServerAddr {
+                host: "chat.example.com".to_string(),
+                port: 9000,
+                tls: false,
+                channel: None,
+                nickname: None,
+            }
+        );
+        let addr = ChatClient::parse_server_addr("tls://chat.example.com").unwrap();
+        assert_eq!((addr.port, addr.tls), (DEFAULT_SERVER_PORT, true));
+
+        assert!(ChatClient::parse_server_addr("chat.example.com:port").is_err());
+        assert!(ChatClient::parse_server_addr(":8080").is_err());
+    }
+
+    #[test]
+    fn test_parse_server_addr_with_channel_and_nickname() {
+        let addr = ChatClient::parse_server_addr("tls://host:8443/general#alice").unwrap();
+        assert_eq!((addr.host.as_str(), addr.port), ("host", 8443));
+        assert!(addr.tls);
+        assert_eq!(addr.channel.as_deref(), Some("general"));
+        assert_eq!(addr.nickname.as_deref(), Some("alice"));
+
+        let addr = ChatClient::parse_server_addr("host/general/").unwrap();
+        assert_eq!(addr.port, DEFAULT_SERVER_PORT);
+        assert_eq!(addr.channel.as_deref(), Some("general"));
+        assert_eq!(addr.nickname, None);
+
+        let addr = ChatClient::parse_server_addr("host:8080#bob").unwrap();
+        assert_eq!(addr.channel, None);
+        assert_eq!(addr.nickname.as_deref(), Some("bob"));
+
+        // Empty parts are the same as leaving them out
+        let addr = ChatClient::parse_server_addr("host:8080/#").unwrap();
+        assert_eq!((addr.channel, addr.nickname), (None, None));
+    }
+
+    #[test]
+    fn test_parse_bracketed_ipv6_server_addr() {
+        let addr = ChatClient::parse_server_addr("tls://[::1]:8443/general#alice").unwrap();
+        assert_eq!((addr.host.as_str(), addr.port), ("::1", 8443));
+        assert_eq!(addr.channel.as_deref(), Some("general"));
+        assert_eq!(addr.nickname.as_deref(), Some("alice"));
+
+        let addr = ChatClient::parse_server_addr("[2001:db8::7]").unwrap();
+        assert_eq!((addr.host.as_str(), addr.port), ("2001:db8::7", 8080));
+        assert_eq!(host_port(&addr.host, addr.port), "[2001:db8::7]:8080");
+
+        assert!(ChatClient::parse_server_addr("[::1:8080").is_err());
+        assert!(ChatClient::parse_server_addr("[::1]8080").is_err());
+    }
+
+    #[test]
+    fn test_message_error_source() {
+        let err = ChatClientError::from(ChatMessageError::InvalidLength);
+        assert_eq!(err.to_string(), "Message Error: Invalid message length");
+        assert!(err.source().is_some());
+        assert!(ChatClientError::InvalidAddress.source().is_none());
+    }
 }
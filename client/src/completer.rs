@@ -1,3 +1,4 @@
+use crate::aliases;
 use rustyline::completion::{Completer, Pair};
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
@@ -7,37 +8,90 @@ use shared::commands::client as commands;
 use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
 
+/// Client state shared with the completer, kept up to date by `ChatClient`
+#[derive(Clone, Default)]
+pub struct CompletionState {
+    /// Connected users (refreshed by /list)
+    pub users: Arc<RwLock<HashSet<String>>>,
+    /// Senders with a pending incoming file transfer
+    pub pending_senders: Arc<RwLock<HashSet<String>>>,
+    /// Last user who sent us a DM (target of /r)
+    pub last_dm_sender: Arc<RwLock<Option<String>>>,
+}
+
+impl CompletionState {
+    pub fn set_users<'a>(&self, users: impl IntoIterator<Item = &'a str>) {
+        let mut current = self.users.write().unwrap();
+        current.clear();
+        current.extend(users.into_iter().map(String::from));
+    }
+
+    pub fn set_pending_senders<'a>(&self, senders: impl IntoIterator<Item = &'a String>) {
+        let mut current = self.pending_senders.write().unwrap();
+        current.clear();
+        current.extend(senders.into_iter().cloned());
+    }
+
+    pub fn last_dm_sender(&self) -> Option<String> {
+        self.last_dm_sender.read().unwrap().clone()
+    }
+
+    pub fn set_last_dm_sender(&self, sender: &str) {
+        *self.last_dm_sender.write().unwrap() = Some(sender.to_string());
+    }
+}
+
 /// Client command and username completer
 pub struct ClientCompleter {
     commands: Vec<&'static str>,
-    users: Arc<RwLock<HashSet<String>>>,
+    state: CompletionState,
 }
 
 impl ClientCompleter {
-    pub fn new(users: Arc<RwLock<HashSet<String>>>) -> Self {
-        Self {
-            commands: commands::completion_names(),
-            users,
-        }
+    pub fn new(state: CompletionState) -> Self {
+        let mut commands = commands::completion_names();
+        commands.extend(aliases::installed().names());
+        Self { commands, state }
+    }
+
+    /// Names that can follow `cmd` as its first argument
+    fn argument_candidates(&self, cmd: &str) -> Option<Vec<String>> {
+        let names = if commands::DM.matches(cmd)
+            || commands::PAGE.matches(cmd)
+            || commands::SEND.matches(cmd)
+        {
+            self.state.users.read().unwrap().iter().cloned().collect()
+        } else if commands::ACCEPT.matches(cmd) || commands::REJECT.matches(cmd) {
+            self.state
+                .pending_senders
+                .read()
+                .unwrap()
+                .iter()
+                .cloned()
+                .collect()
+        } else if commands::REPLY.matches(cmd) {
+            self.state.last_dm_sender().into_iter().collect()
+        } else {
+            return None;
+        };
+        Some(names)
     }
 
     fn get_candidates(&self, line: &str) -> Vec<String> {
         let trimmed = line.trim_start();
 
-        // If line starts with /dm or /send and has a space, complete usernames
-        if trimmed.starts_with("/dm ") || trimmed.starts_with("/send ") {
-            let parts: Vec<&str> = trimmed.splitn(3, ' ').collect();
-            if parts.len() == 2 {
-                // Complete username after /dm or /send
-                let cmd = parts[0];
-                let prefix = parts[1];
-                let users = self.users.read().unwrap();
-                return users
-                    .iter()
-                    .filter(|u| u.starts_with(prefix))
-                    .map(|u| format!("{} {}", cmd, u))
-                    .collect();
-            }
+        // After a command that takes a username, complete the first argument
+        if let Some((cmd, rest)) = trimmed.split_once(' ')
+            && !rest.contains(' ')
+            && let Some(names) = self.argument_candidates(aliases::installed().resolve(cmd))
+        {
+            let mut candidates: Vec<String> = names
+                .iter()
+                .filter(|name| name.starts_with(rest))
+                .map(|name| format!("{} {}", cmd, name))
+                .collect();
+            candidates.sort();
+            return candidates;
         }
 
         // Complete commands
@@ -96,3 +150,57 @@ impl Highlighter for ClientCompleter {}
 impl Validator for ClientCompleter {}
 
 impl Helper for ClientCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completer_with(users: &[&str], pending: &[&str], last_dm: Option<&str>) -> ClientCompleter {
+        let state = CompletionState::default();
+        state.set_users(users.iter().copied());
+        state.set_pending_senders(&pending.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        if let Some(sender) = last_dm {
+            state.set_last_dm_sender(sender);
+        }
+        ClientCompleter::new(state)
+    }
+
+    #[test]
+    fn test_dm_completes_connected_users() {
+        let completer = completer_with(&["Alice", "Bob"], &[], None);
+        assert_eq!(completer.get_candidates("/dm A"), vec!["/dm Alice"]);
+        assert_eq!(
+            completer.get_candidates("/send "),
+            vec!["/send Alice", "/send Bob"]
+        );
+    }
+
+    #[test]
+    fn test_accept_reject_complete_pending_senders() {
+        let completer = completer_with(&["Alice", "Bob", "Carol"], &["Bob", "Carol"], None);
+        assert_eq!(
+            completer.get_candidates("/accept "),
+            vec!["/accept Bob", "/accept Carol"]
+        );
+        assert_eq!(completer.get_candidates("/reject C"), vec!["/reject Carol"]);
+        assert!(completer.get_candidates("/accept A").is_empty());
+    }
+
+    #[test]
+    fn test_reply_completes_last_dm_sender() {
+        let completer = completer_with(&[], &[], Some("Dave"));
+        assert_eq!(completer.get_candidates("/r "), vec!["/r Dave"]);
+        assert_eq!(completer.get_candidates("/r D"), vec!["/r Dave"]);
+        assert!(completer.get_candidates("/r hello there").is_empty());
+
+        let completer = completer_with(&[], &[], None);
+        assert!(completer.get_candidates("/r ").is_empty());
+    }
+
+    #[test]
+    fn test_command_completion() {
+        let completer = completer_with(&[], &[], None);
+        assert_eq!(completer.get_candidates("/acc"), vec!["/accept"]);
+        assert!(completer.get_candidates("hello").is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Where received files are saved, and what happens when the name is taken
+
+use std::path::{Path, PathBuf};
+
+pub const DOWNLOADS_DIR: &str = "downloads";
+
+/// How to save a received file whose name already exists in `downloads/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// Refuse the file and keep the existing one
+    Skip,
+    /// Save as "name (1).ext", "name (2).ext", ...
+    #[default]
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+}
+
+impl OverwritePolicy {
+    /// Parse a `CHAT_DOWNLOAD_OVERWRITE` value (case-insensitive)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "skip" => Some(OverwritePolicy::Skip),
+            "rename" => Some(OverwritePolicy::Rename),
+            "overwrite" => Some(OverwritePolicy::Overwrite),
+            _ => None,
+        }
+    }
+}
+
+/// Path to save `filename` under in `dir`, or None if `policy` says to skip it
+pub fn resolve_save_path(dir: &Path, filename: &str, policy: OverwritePolicy) -> Option<PathBuf> {
+    resolve_with(dir, filename, policy, |path| path.exists())
+}
+
+fn resolve_with(
+    dir: &Path,
+    filename: &str,
+    policy: OverwritePolicy,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    let path = dir.join(filename);
+    if !exists(&path) {
+        return Some(path);
+    }
+
+    match policy {
+        OverwritePolicy::Skip => None,
+        OverwritePolicy::Overwrite => Some(path),
+        OverwritePolicy::Rename => {
+            let name = Path::new(filename);
+            let stem = name
+                .file_stem()
+                .map_or(filename.into(), |stem| stem.to_string_lossy());
+            let extension = name
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy()))
+                .unwrap_or_default();
+            (1..)
+                .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+                .find(|candidate| !exists(candidate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(existing: &[&str], filename: &str, policy: OverwritePolicy) -> Option<PathBuf> {
+        let dir = Path::new(DOWNLOADS_DIR);
+        resolve_with(dir, filename, policy, |path| {
+            existing.iter().any(|name| dir.join(name) == path)
+        })
+    }
+
+    fn saved(name: &str) -> Option<PathBuf> {
+        Some(Path::new(DOWNLOADS_DIR).join(name))
+    }
+
+    #[test]
+    fn test_free_name_used_as_is() {
+        for policy in [
+            OverwritePolicy::Skip,
+            OverwritePolicy::Rename,
+            OverwritePolicy::Overwrite,
+        ] {
+            assert_eq!(resolve(&[], "report.pdf", policy), saved("report.pdf"));
+        }
+    }
+
+    #[test]
+    fn test_rename_appends_counter() {
+        let existing = ["report.pdf", "report (1).pdf", "notes"];
+        assert_eq!(
+            resolve(&existing, "report.pdf", OverwritePolicy::Rename),
+            saved("report (2).pdf")
+        );
+        assert_eq!(
+            resolve(&existing, "notes", OverwritePolicy::Rename),
+            saved("notes (1)")
+        );
+    }
+
+    #[test]
+    fn test_skip_and_overwrite() {
+        let existing = ["report.pdf"];
+        assert_eq!(
+            resolve(&existing, "report.pdf", OverwritePolicy::Skip),
+            None
+        );
+        assert_eq!(
+            resolve(&existing, "report.pdf", OverwritePolicy::Overwrite),
+            saved("report.pdf")
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(OverwritePolicy::parse("Skip"), Some(OverwritePolicy::Skip));
+        assert_eq!(
+            OverwritePolicy::parse("overwrite"),
+            Some(OverwritePolicy::Overwrite)
+        );
+        assert_eq!(OverwritePolicy::parse("clobber"), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! Optional end-to-end encryption for direct messages
+//!
+//! Each client generates an x25519 keypair at startup. Public keys are swapped
+//! via `MessageTypes::KeyExchange` the first time two users DM each other, and
+//! DM bodies are then sealed with XSalsa20-Poly1305 (crypto_box) so the server
+//! only ever relays ciphertext.
+
+use crypto_box::aead::{Aead, AeadCore, OsRng};
+use crypto_box::{KEY_SIZE, Nonce, PublicKey, SalsaBox, SecretKey};
+use shared::message::E2E_DM_PREFIX;
+use std::collections::HashMap;
+
+const NONCE_SIZE: usize = 24;
+/// Number of public key bytes shown in a fingerprint
+const FINGERPRINT_BYTES: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum E2eError {
+    UnknownPeer,
+    InvalidKey,
+    InvalidPayload,
+    DecryptionFailed,
+}
+
+/// Result of storing a peer's public key
+#[derive(Debug, PartialEq)]
+pub enum KeyUpdate {
+    New,
+    Changed,
+    Unchanged,
+}
+
+pub struct E2eSession {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+    /// Known peer public keys (keyed by username)
+    peers: HashMap<String, PublicKey>,
+    /// DMs waiting for the recipient's public key (keyed by recipient)
+    queued: HashMap<String, Vec<String>>,
+}
+
+impl Default for E2eSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl E2eSession {
+    pub fn new() -> Self {
+        let secret_key = SecretKey::generate(&mut OsRng);
+        let public_key = secret_key.public_key();
+        E2eSession {
+            secret_key,
+            public_key,
+            peers: HashMap::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        to_hex(self.public_key.as_bytes())
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.public_key)
+    }
+
+    /// Fingerprints of all known peers, sorted by username
+    pub fn peer_fingerprints(&self) -> Vec<(String, String)> {
+        let mut peers: Vec<(String, String)> = self
+            .peers
+            .iter()
+            .map(|(name, key)| (name.clone(), fingerprint(key)))
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    pub fn peer_fingerprint(&self, peer: &str) -> Option<String> {
+        self.peers.get(peer).map(fingerprint)
+    }
+
+    pub fn has_peer(&self, peer: &str) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    /// Store a peer's hex-encoded public key
+    pub fn add_peer(&mut self, peer: &str, key_hex: &str) -> Result<KeyUpdate, E2eError> {
+        let bytes: [u8; KEY_SIZE] = from_hex(key_hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(E2eError::InvalidKey)?;
+        let key = PublicKey::from(bytes);
+
+        let update = match self.peers.get(peer) {
+            None => KeyUpdate::New,
+            Some(existing) if existing == &key => KeyUpdate::Unchanged,
+            Some(_) => KeyUpdate::Changed,
+        };
+        self.peers.insert(peer.to_string(), key);
+        Ok(update)
+    }
+
+    /// Hold a DM until `recipient`'s public key arrives
+    pub fn queue(&mut self, recipient: &str, message: String) {
+        self.queued
+            .entry(recipient.to_string())
+            .or_default()
+            .push(message);
+    }
+
+    pub fn take_queued(&mut self, peer: &str) -> Vec<String> {
+        self.queued.remove(peer).unwrap_or_default()
+    }
+
+    /// Encrypt a DM body for `peer`, returning the prefixed wire payload
+    pub fn encrypt(&self, peer: &str, plaintext: &str) -> Result<String, E2eError> {
+        let peer_key = self.peers.get(peer).ok_or(E2eError::UnknownPeer)?;
+        let salsa_box = SalsaBox::new(peer_key, &self.secret_key);
+        let nonce = SalsaBox::generate_nonce(&mut OsRng);
+        let ciphertext = salsa_box
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| E2eError::InvalidPayload)?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", E2E_DM_PREFIX, to_hex(&sealed)))
+    }
+
+    /// Decrypt a prefixed wire payload received from `peer`
+    pub fn decrypt(&self, peer: &str, payload: &str) -> Result<String, E2eError> {
+        let peer_key = self.peers.get(peer).ok_or(E2eError::UnknownPeer)?;
+        let sealed = payload
+            .strip_prefix(E2E_DM_PREFIX)
+            .and_then(from_hex)
+            .ok_or(E2eError::InvalidPayload)?;
+        if sealed.len() <= NONCE_SIZE {
+            return Err(E2eError::InvalidPayload);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let salsa_box = SalsaBox::new(peer_key, &self.secret_key);
+        let plaintext = salsa_box
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| E2eError::DecryptionFailed)?;
+        String::from_utf8(plaintext).map_err(|_| E2eError::InvalidPayload)
+    }
+}
+
+/// Check whether a DM body carries end-to-end encrypted ciphertext
+pub fn is_encrypted(body: &str) -> bool {
+    body.starts_with(E2E_DM_PREFIX)
+}
+
+/// Short, human-comparable form of a public key (e.g. "a1b2:c3d4:...")
+fn fingerprint(key: &PublicKey) -> String {
+    let hex = to_hex(&key.as_bytes()[..FINGERPRINT_BYTES]);
+    hex.as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paired_sessions() -> (E2eSession, E2eSession) {
+        let mut alice = E2eSession::new();
+        let mut bob = E2eSession::new();
+        alice.add_peer("bob", &bob.public_key_hex()).unwrap();
+        bob.add_peer("alice", &alice.public_key_hex()).unwrap();
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let (alice, bob) = paired_sessions();
+        let payload = alice.encrypt("bob", "meet at noon").unwrap();
+        assert!(is_encrypted(&payload));
+        assert!(!payload.contains("meet at noon"));
+        assert_eq!(bob.decrypt("alice", &payload).unwrap(), "meet at noon");
+    }
+
+    #[test]
+    fn test_tampered_payload_rejected() {
+        let (alice, bob) = paired_sessions();
+        let mut payload = alice.encrypt("bob", "secret").unwrap();
+        let last = payload.pop().unwrap();
+        payload.push(if last == '0' { '1' } else { '0' });
+        assert_eq!(
+            bob.decrypt("alice", &payload),
+            Err(E2eError::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        let alice = E2eSession::new();
+        assert_eq!(alice.encrypt("bob", "hi"), Err(E2eError::UnknownPeer));
+    }
+
+    #[test]
+    fn test_add_peer_detects_key_change() {
+        let mut alice = E2eSession::new();
+        let bob = E2eSession::new();
+        let bob_again = E2eSession::new();
+        assert_eq!(
+            alice.add_peer("bob", &bob.public_key_hex()),
+            Ok(KeyUpdate::New)
+        );
+        assert_eq!(
+            alice.add_peer("bob", &bob.public_key_hex()),
+            Ok(KeyUpdate::Unchanged)
+        );
+        assert_eq!(
+            alice.add_peer("bob", &bob_again.public_key_hex()),
+            Ok(KeyUpdate::Changed)
+        );
+        assert_eq!(alice.add_peer("bob", "not-hex"), Err(E2eError::InvalidKey));
+        assert_eq!(alice.add_peer("bob", "abcd"), Err(E2eError::InvalidKey));
+    }
+
+    #[test]
+    fn test_queued_messages_flushed_once() {
+        let mut alice = E2eSession::new();
+        alice.queue("bob", "first".to_string());
+        alice.queue("bob", "second".to_string());
+        assert_eq!(alice.take_queued("bob"), vec!["first", "second"]);
+        assert!(alice.take_queued("bob").is_empty());
+    }
+}
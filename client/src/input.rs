@@ -1,20 +1,38 @@
+use crate::aliases::{self, Aliases};
 use shared::commands::client as commands;
 use shared::input::{UserInput, UserInputError};
+use shared::message::MAX_POLL_OPTIONS;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ClientUserInput {
     Help,
-    ListUsers,
+    /// None requests the full list; Some(n) requests page n
+    ListUsers(Option<usize>),
     Message(String),
     DirectMessage {
         recipient: String,
         message: String,
     },
     Reply(String),
-    Rename(String),
+    Page {
+        recipient: String,
+        message: String,
+    },
+    Poll {
+        question: String,
+        options: Vec<String>,
+    },
+    /// Vote for option `option` (1-based) in poll `id`
+    Vote {
+        id: u64,
+        option: usize,
+    },
+    /// None accepts the name the server suggested after a collision
+    Rename(Option<String>),
     SendFile {
         recipient: String,
         file_path: String,
+        caption: Option<String>,
     },
     AcceptFile {
         sender: String,
@@ -22,10 +40,57 @@ pub enum ClientUserInput {
     RejectFile {
         sender: String,
     },
+    /// Upload a file for everyone in the room to fetch
+    ShareFile(String),
+    /// Download the room file with this id
+    GetFile(u64),
     Status(Option<String>),
+    Afk(Option<String>),
+    Keys,
+    /// None shows the current topic
+    Topic(Option<String>),
+    Link,
+    ServerInfo,
+    /// Ping the server and summarise recent round-trip times
+    Quality,
+    Whois(String),
+    Seen(String),
+    /// Text to look for in the server's chat history
+    Search(String),
+    Block(String),
+    Unblock(String),
+    Blocked,
+    /// Operator password for `/oper`
+    Oper(String),
+    Op(String),
+    Deop(String),
+    Kick(String),
+    /// Switch to the server in the named profile
+    Connect(String),
+    /// None lists profiles; Some(name) saves the current server under that name
+    Profiles(Option<String>),
+    /// Resend the last message or DM
+    Again,
+    /// Hold channel messages until Resume
+    Pause,
+    Resume,
+    /// Toggle do not disturb
+    Dnd,
     Quit,
 }
 
+/// Help for a command line that didn't parse: the usage of a known command
+/// given bad arguments, or the closest name to a mistyped one
+pub fn invalid_command_hint(line: &str) -> Option<String> {
+    let typed = line.split_whitespace().next()?;
+    let cmd = aliases::installed().resolve(typed);
+    if let Some(command) = commands::find(cmd) {
+        return Some(format!("Usage: {}", command.help_line()));
+    }
+    shared::commands::suggest(cmd, &commands::completion_names())
+        .map(|name| format!("Unknown command {} - did you mean {}?", typed, name))
+}
+
 impl UserInput for ClientUserInput {
     fn get_quit_command() -> Self {
         ClientUserInput::Quit
@@ -36,14 +101,27 @@ impl TryFrom<&str> for ClientUserInput {
     type Error = UserInputError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::parse_with_aliases(value, aliases::installed())
+    }
+}
+
+impl ClientUserInput {
+    /// Parse input, expanding user-defined aliases before built-in matching
+    pub fn parse_with_aliases(value: &str, aliases: &Aliases) -> Result<Self, UserInputError> {
         let trimmed = value.trim();
         let parts: Vec<&str> = trimmed.split_whitespace().collect();
-        let cmd = parts.first().copied().unwrap_or("");
+        let cmd = aliases.resolve(parts.first().copied().unwrap_or(""));
 
         if commands::QUIT.matches(cmd) {
             Ok(ClientUserInput::Quit)
         } else if commands::LIST.matches(cmd) {
-            Ok(ClientUserInput::ListUsers)
+            match parts.get(1) {
+                None => Ok(ClientUserInput::ListUsers(None)),
+                Some(page) => match page.parse::<usize>() {
+                    Ok(page) if page > 0 => Ok(ClientUserInput::ListUsers(Some(page))),
+                    _ => Err(UserInputError::InvalidCommand),
+                },
+            }
         } else if commands::HELP.matches(cmd) {
             Ok(ClientUserInput::Help)
         } else if commands::DM.matches(cmd) {
@@ -54,6 +132,38 @@ impl TryFrom<&str> for ClientUserInput {
                 let message = parts[2..].join(" ");
                 Ok(ClientUserInput::DirectMessage { recipient, message })
             }
+        } else if commands::PAGE.matches(cmd) {
+            if parts.len() < 3 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                let recipient = parts[1].to_string();
+                let message = parts[2..].join(" ");
+                Ok(ClientUserInput::Page { recipient, message })
+            }
+        } else if commands::POLL.matches(cmd) {
+            let text = parts[1..].join(" ");
+            let mut fields = text.split('|').map(|field| field.trim().to_string());
+            let question = fields.next().unwrap_or_default();
+            let options: Vec<String> = fields.collect();
+            if question.is_empty()
+                || options.len() < 2
+                || options.len() > MAX_POLL_OPTIONS
+                || options.iter().any(String::is_empty)
+            {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Poll { question, options })
+            }
+        } else if commands::VOTE.matches(cmd) {
+            match (
+                parts
+                    .get(1)
+                    .and_then(|id| id.trim_start_matches('#').parse().ok()),
+                parts.get(2).and_then(|option| option.parse().ok()),
+            ) {
+                (Some(id), Some(option)) if option > 0 => Ok(ClientUserInput::Vote { id, option }),
+                _ => Err(UserInputError::InvalidCommand),
+            }
         } else if commands::REPLY.matches(cmd) {
             if parts.len() < 2 {
                 Err(UserInputError::InvalidCommand)
@@ -62,21 +172,23 @@ impl TryFrom<&str> for ClientUserInput {
                 Ok(ClientUserInput::Reply(message))
             }
         } else if commands::RENAME.matches(cmd) {
-            if parts.len() < 2 {
-                Err(UserInputError::InvalidCommand)
-            } else {
-                let new_name = parts[1].to_string();
-                Ok(ClientUserInput::Rename(new_name))
-            }
+            let new_name = parts.get(1).map(|name| name.to_string());
+            Ok(ClientUserInput::Rename(new_name))
         } else if commands::SEND.matches(cmd) {
             if parts.len() < 3 {
                 Err(UserInputError::InvalidCommand)
             } else {
                 let recipient = parts[1].to_string();
-                let file_path = parts[2..].join(" ");
+                let rest = parts[2..].join(" ");
+                // Anything after " -- " is a caption shown with the offer
+                let (file_path, caption) = match rest.split_once(" -- ") {
+                    Some((path, caption)) => (path.to_string(), Some(caption.to_string())),
+                    None => (rest, None),
+                };
                 Ok(ClientUserInput::SendFile {
                     recipient,
                     file_path,
+                    caption,
                 })
             }
         } else if commands::ACCEPT.matches(cmd) {
@@ -93,6 +205,18 @@ impl TryFrom<&str> for ClientUserInput {
                 let sender = parts[1].to_string();
                 Ok(ClientUserInput::RejectFile { sender })
             }
+        } else if commands::SHARE.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::ShareFile(parts[1..].join(" ")))
+            }
+        } else if commands::GET.matches(cmd) {
+            let id = parts.get(1).map(|id| id.trim_start_matches('#'));
+            match id.and_then(|id| id.parse::<u64>().ok()) {
+                Some(id) => Ok(ClientUserInput::GetFile(id)),
+                None => Err(UserInputError::InvalidCommand),
+            }
         } else if commands::STATUS.matches(cmd) {
             if parts.len() < 2 {
                 // No status provided - clear status
@@ -101,6 +225,102 @@ impl TryFrom<&str> for ClientUserInput {
                 let status = parts[1..].join(" ");
                 Ok(ClientUserInput::Status(Some(status)))
             }
+        } else if commands::AFK.matches(cmd) {
+            if parts.len() < 2 {
+                Ok(ClientUserInput::Afk(None))
+            } else {
+                Ok(ClientUserInput::Afk(Some(parts[1..].join(" "))))
+            }
+        } else if commands::KEYS.matches(cmd) {
+            Ok(ClientUserInput::Keys)
+        } else if commands::TOPIC.matches(cmd) {
+            if parts.len() < 2 {
+                Ok(ClientUserInput::Topic(None))
+            } else {
+                Ok(ClientUserInput::Topic(Some(parts[1..].join(" "))))
+            }
+        } else if commands::LINK.matches(cmd) {
+            Ok(ClientUserInput::Link)
+        } else if commands::WHOIS.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Whois(parts[1].to_string()))
+            }
+        } else if commands::SEEN.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Seen(parts[1].to_string()))
+            }
+        } else if commands::SEARCH.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Search(parts[1..].join(" ")))
+            }
+        } else if commands::BLOCK.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Block(parts[1].to_string()))
+            }
+        } else if commands::UNBLOCK.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Unblock(parts[1].to_string()))
+            }
+        } else if commands::BLOCKED.matches(cmd) {
+            Ok(ClientUserInput::Blocked)
+        } else if commands::OPER.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Oper(parts[1].to_string()))
+            }
+        } else if commands::OP.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Op(parts[1].to_string()))
+            }
+        } else if commands::DEOP.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Deop(parts[1].to_string()))
+            }
+        } else if commands::KICK.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Kick(parts[1].to_string()))
+            }
+        } else if commands::SERVERINFO.matches(cmd) {
+            Ok(ClientUserInput::ServerInfo)
+        } else if commands::QUALITY.matches(cmd) {
+            Ok(ClientUserInput::Quality)
+        } else if commands::CONNECT.matches(cmd) {
+            if parts.len() < 2 {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ClientUserInput::Connect(parts[1].to_string()))
+            }
+        } else if commands::PROFILES.matches(cmd) {
+            match parts[1..] {
+                [] => Ok(ClientUserInput::Profiles(None)),
+                ["save", name] => Ok(ClientUserInput::Profiles(Some(name.to_string()))),
+                _ => Err(UserInputError::InvalidCommand),
+            }
+        } else if commands::AGAIN.matches(cmd) {
+            Ok(ClientUserInput::Again)
+        } else if commands::PAUSE.matches(cmd) {
+            Ok(ClientUserInput::Pause)
+        } else if commands::RESUME.matches(cmd) {
+            Ok(ClientUserInput::Resume)
+        } else if commands::DND.matches(cmd) {
+            Ok(ClientUserInput::Dnd)
         } else if trimmed.starts_with('/') {
             Err(UserInputError::InvalidCommand)
         } else {
@@ -139,7 +359,25 @@ mod tests {
     fn test_list_command() {
         let input = ClientUserInput::try_from("/list");
         assert!(input.is_ok());
-        assert!(matches!(input.unwrap(), ClientUserInput::ListUsers));
+        assert!(matches!(input.unwrap(), ClientUserInput::ListUsers(None)));
+    }
+
+    #[test]
+    fn test_list_page_command() {
+        let input = ClientUserInput::try_from("/list 3");
+        assert!(matches!(input, Ok(ClientUserInput::ListUsers(Some(3)))));
+        assert!(ClientUserInput::try_from("/list 0").is_err());
+        assert!(ClientUserInput::try_from("/list two").is_err());
+    }
+
+    #[test]
+    fn test_rename_command() {
+        let input = ClientUserInput::try_from("/rename Bob");
+        assert!(matches!(input, Ok(ClientUserInput::Rename(Some(name))) if name == "Bob"));
+
+        // A bare /rename takes the server's suggestion
+        let input = ClientUserInput::try_from("/rename");
+        assert!(matches!(input, Ok(ClientUserInput::Rename(None))));
     }
 
     #[test]
@@ -166,6 +404,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_invalid_command_hint() {
+        assert_eq!(
+            invalid_command_hint("/hepl").as_deref(),
+            Some("Unknown command /hepl - did you mean /help?")
+        );
+        assert_eq!(invalid_command_hint("/xyz"), None);
+        assert_eq!(
+            invalid_command_hint("/dm alice").as_deref(),
+            Some("Usage: /dm <username> <message> - Send direct message")
+        );
+    }
+
+    #[test]
+    fn test_page_command() {
+        let input = ClientUserInput::try_from("/page Bob the build is broken");
+        if let Ok(ClientUserInput::Page { recipient, message }) = input {
+            assert_eq!(recipient, "Bob");
+            assert_eq!(message, "the build is broken");
+        } else {
+            panic!("Expected Page variant");
+        }
+        assert!(ClientUserInput::try_from("/page Bob").is_err());
+    }
+
+    #[test]
+    fn test_poll_command() {
+        let input = ClientUserInput::try_from("/poll Lunch at noon? | pizza | tacos ");
+        if let Ok(ClientUserInput::Poll { question, options }) = input {
+            assert_eq!(question, "Lunch at noon?");
+            assert_eq!(options, vec!["pizza", "tacos"]);
+        } else {
+            panic!("Expected Poll variant");
+        }
+        assert!(ClientUserInput::try_from("/poll Lunch? | pizza").is_err());
+        assert!(ClientUserInput::try_from("/poll Lunch? | pizza | | tacos").is_err());
+    }
+
+    #[test]
+    fn test_vote_command() {
+        assert!(matches!(
+            ClientUserInput::try_from("/vote 3 2"),
+            Ok(ClientUserInput::Vote { id: 3, option: 2 })
+        ));
+        assert!(matches!(
+            ClientUserInput::try_from("/vote #3 1"),
+            Ok(ClientUserInput::Vote { id: 3, option: 1 })
+        ));
+        assert!(ClientUserInput::try_from("/vote 3 0").is_err());
+        assert!(ClientUserInput::try_from("/vote 3").is_err());
+    }
+
     #[test]
     fn test_dm_command_missing_message() {
         let input = ClientUserInput::try_from("/dm Alice");
@@ -268,6 +558,204 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_user_alias_expanded() {
+        let (aliases, _) = Aliases::parse("/w = /dm\n/l = /list");
+        let input = ClientUserInput::parse_with_aliases("/w Alice hi there", &aliases);
+        if let Ok(ClientUserInput::DirectMessage { recipient, message }) = input {
+            assert_eq!(recipient, "Alice");
+            assert_eq!(message, "hi there");
+        } else {
+            panic!("Expected DirectMessage variant");
+        }
+        assert!(matches!(
+            ClientUserInput::parse_with_aliases("/l", &aliases),
+            Ok(ClientUserInput::ListUsers(None))
+        ));
+        // Aliases only apply in command position
+        assert!(matches!(
+            ClientUserInput::parse_with_aliases("hello /w", &aliases),
+            Ok(ClientUserInput::Message(_))
+        ));
+    }
+
+    #[test]
+    fn test_afk_command() {
+        let input = ClientUserInput::try_from("/afk");
+        assert!(input.is_ok());
+        assert!(matches!(input.unwrap(), ClientUserInput::Afk(None)));
+    }
+
+    #[test]
+    fn test_afk_command_with_reason() {
+        let input = ClientUserInput::try_from("/afk getting coffee");
+        assert!(input.is_ok());
+        if let ClientUserInput::Afk(Some(reason)) = input.unwrap() {
+            assert_eq!(reason, "getting coffee");
+        } else {
+            panic!("Expected Afk variant with reason");
+        }
+    }
+
+    #[test]
+    fn test_away_alias() {
+        let input = ClientUserInput::try_from("/away lunch");
+        assert!(matches!(input, Ok(ClientUserInput::Afk(Some(reason))) if reason == "lunch"));
+    }
+
+    #[test]
+    fn test_keys_command() {
+        let input = ClientUserInput::try_from("/keys");
+        assert!(input.is_ok());
+        assert!(matches!(input.unwrap(), ClientUserInput::Keys));
+    }
+
+    #[test]
+    fn test_topic_command() {
+        let input = ClientUserInput::try_from("/topic");
+        assert!(matches!(input.unwrap(), ClientUserInput::Topic(None)));
+        let input = ClientUserInput::try_from("/topic Release day");
+        assert!(
+            matches!(input, Ok(ClientUserInput::Topic(Some(ref text))) if text == "Release day")
+        );
+    }
+
+    #[test]
+    fn test_link_command() {
+        let input = ClientUserInput::try_from("/link");
+        assert!(matches!(input, Ok(ClientUserInput::Link)));
+    }
+
+    #[test]
+    fn test_serverinfo_command() {
+        let input = ClientUserInput::try_from("/serverinfo");
+        assert!(matches!(input, Ok(ClientUserInput::ServerInfo)));
+    }
+
+    #[test]
+    fn test_quality_command() {
+        let input = ClientUserInput::try_from("/quality");
+        assert!(matches!(input, Ok(ClientUserInput::Quality)));
+    }
+
+    #[test]
+    fn test_whois_command() {
+        let input = ClientUserInput::try_from("/whois bob");
+        assert!(matches!(input, Ok(ClientUserInput::Whois(ref name)) if name == "bob"));
+        assert!(ClientUserInput::try_from("/whois").is_err());
+    }
+
+    #[test]
+    fn test_send_command_caption() {
+        let input = ClientUserInput::try_from("/send bob my report.pdf -- here's the report");
+        assert!(matches!(
+            input,
+            Ok(ClientUserInput::SendFile { ref file_path, caption: Some(ref caption), .. })
+                if file_path == "my report.pdf" && caption == "here's the report"
+        ));
+        let input = ClientUserInput::try_from("/send bob report.pdf");
+        assert!(matches!(
+            input,
+            Ok(ClientUserInput::SendFile { caption: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_share_and_get_commands() {
+        let input = ClientUserInput::try_from("/share my notes.md");
+        assert!(matches!(input, Ok(ClientUserInput::ShareFile(ref path)) if path == "my notes.md"));
+        assert!(matches!(
+            ClientUserInput::try_from("/get #3"),
+            Ok(ClientUserInput::GetFile(3))
+        ));
+        assert!(ClientUserInput::try_from("/get three").is_err());
+        assert!(ClientUserInput::try_from("/share").is_err());
+    }
+
+    #[test]
+    fn test_seen_command() {
+        let input = ClientUserInput::try_from("/seen bob");
+        assert!(matches!(input, Ok(ClientUserInput::Seen(ref name)) if name == "bob"));
+        assert!(ClientUserInput::try_from("/seen").is_err());
+    }
+
+    #[test]
+    fn test_search_command() {
+        let input = ClientUserInput::try_from("/search release  notes");
+        assert!(matches!(input, Ok(ClientUserInput::Search(ref term)) if term == "release notes"));
+        assert!(ClientUserInput::try_from("/search").is_err());
+        assert!(ClientUserInput::try_from("/search   ").is_err());
+    }
+
+    #[test]
+    fn test_block_commands() {
+        let input = ClientUserInput::try_from("/block mallory");
+        assert!(matches!(input, Ok(ClientUserInput::Block(ref name)) if name == "mallory"));
+        let input = ClientUserInput::try_from("/unblock mallory");
+        assert!(matches!(input, Ok(ClientUserInput::Unblock(ref name)) if name == "mallory"));
+        assert!(matches!(
+            ClientUserInput::try_from("/blocked"),
+            Ok(ClientUserInput::Blocked)
+        ));
+        assert!(ClientUserInput::try_from("/block").is_err());
+    }
+
+    #[test]
+    fn test_operator_commands() {
+        let input = ClientUserInput::try_from("/oper hunter2");
+        assert!(matches!(input, Ok(ClientUserInput::Oper(ref password)) if password == "hunter2"));
+        let input = ClientUserInput::try_from("/op bob");
+        assert!(matches!(input, Ok(ClientUserInput::Op(ref name)) if name == "bob"));
+        let input = ClientUserInput::try_from("/deop bob");
+        assert!(matches!(input, Ok(ClientUserInput::Deop(ref name)) if name == "bob"));
+        let input = ClientUserInput::try_from("/kick mallory");
+        assert!(matches!(input, Ok(ClientUserInput::Kick(ref name)) if name == "mallory"));
+        assert!(ClientUserInput::try_from("/kick").is_err());
+        assert!(ClientUserInput::try_from("/oper").is_err());
+    }
+
+    #[test]
+    fn test_profile_commands() {
+        let input = ClientUserInput::try_from("/connect work");
+        assert!(matches!(input, Ok(ClientUserInput::Connect(ref name)) if name == "work"));
+        assert!(ClientUserInput::try_from("/connect").is_err());
+        assert!(matches!(
+            ClientUserInput::try_from("/profiles"),
+            Ok(ClientUserInput::Profiles(None))
+        ));
+        let input = ClientUserInput::try_from("/profiles save home");
+        assert!(matches!(input, Ok(ClientUserInput::Profiles(Some(ref name))) if name == "home"));
+        assert!(ClientUserInput::try_from("/profiles home").is_err());
+    }
+
+    #[test]
+    fn test_again_command() {
+        assert!(matches!(
+            ClientUserInput::try_from("/again"),
+            Ok(ClientUserInput::Again)
+        ));
+    }
+
+    #[test]
+    fn test_pause_resume_commands() {
+        assert!(matches!(
+            ClientUserInput::try_from("/pause"),
+            Ok(ClientUserInput::Pause)
+        ));
+        assert!(matches!(
+            ClientUserInput::try_from("/resume"),
+            Ok(ClientUserInput::Resume)
+        ));
+    }
+
+    #[test]
+    fn test_dnd_command() {
+        assert!(matches!(
+            ClientUserInput::try_from("/dnd"),
+            Ok(ClientUserInput::Dnd)
+        ));
+    }
+
     #[test]
     fn test_status_command_clear() {
         let input = ClientUserInput::try_from("/status");
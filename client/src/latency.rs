@@ -0,0 +1,145 @@
+//! Round-trip times measured by `/quality`
+//!
+//! Each `/quality` sends a `Ping` carrying a sequence number, which the server
+//! echoes back in a `Pong`. The time between the two is one sample. The
+//! server's own heartbeat pings carry no number and aren't sampled.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most recent round-trip times kept for min/avg/max
+pub const LATENCY_SAMPLES: usize = 10;
+
+/// A ping unanswered for this long marks the connection unhealthy
+pub const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Summary of the recent samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub samples: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    samples: VecDeque<Duration>,
+    next_seq: u64,
+    /// Sequence number and send time of the ping awaiting its pong
+    pending: Option<(u64, Instant)>,
+}
+
+impl LatencyTracker {
+    /// Start a ping sent at `now` and return its sequence number. A ping
+    /// still awaiting its pong is abandoned.
+    pub fn start(&mut self, now: Instant) -> u64 {
+        self.next_seq += 1;
+        self.pending = Some((self.next_seq, now));
+        self.next_seq
+    }
+
+    /// Record the pong for ping `seq`, returning its round-trip time. Pongs
+    /// for abandoned or unknown pings are ignored.
+    pub fn finish(&mut self, seq: u64, now: Instant) -> Option<Duration> {
+        let (pending_seq, sent_at) = self.pending?;
+        if pending_seq != seq {
+            return None;
+        }
+        self.pending = None;
+
+        let rtt = now.saturating_duration_since(sent_at);
+        if self.samples.len() >= LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        Some(rtt)
+    }
+
+    /// How long the outstanding ping has waited, if it has passed `PONG_TIMEOUT`
+    pub fn overdue(&self, now: Instant) -> Option<Duration> {
+        let (_, sent_at) = self.pending?;
+        let waited = now.saturating_duration_since(sent_at);
+        (waited > PONG_TIMEOUT).then_some(waited)
+    }
+
+    /// Min/avg/max of the recent samples (None before the first pong)
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let min = *self.samples.iter().min()?;
+        let max = *self.samples.iter().max()?;
+        let total: Duration = self.samples.iter().sum();
+        Some(LatencyStats {
+            min,
+            avg: total / self.samples.len() as u32,
+            max,
+            samples: self.samples.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_summarised() {
+        let mut tracker = LatencyTracker::default();
+        assert_eq!(tracker.stats(), None);
+
+        let start = Instant::now();
+        for ms in [30, 10, 20] {
+            let seq = tracker.start(start);
+            tracker.finish(seq, start + Duration::from_millis(ms));
+        }
+        assert_eq!(
+            tracker.stats(),
+            Some(LatencyStats {
+                min: Duration::from_millis(10),
+                avg: Duration::from_millis(20),
+                max: Duration::from_millis(30),
+                samples: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_only_latest_ping_sampled() {
+        let mut tracker = LatencyTracker::default();
+        let start = Instant::now();
+        let first = tracker.start(start);
+        let second = tracker.start(start);
+        assert_eq!(tracker.finish(first, start), None);
+        assert_eq!(
+            tracker.finish(second, start + Duration::from_millis(5)),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(tracker.finish(second, start), None);
+    }
+
+    #[test]
+    fn test_oldest_sample_dropped() {
+        let mut tracker = LatencyTracker::default();
+        let start = Instant::now();
+        for ms in 1..=LATENCY_SAMPLES as u64 + 1 {
+            let seq = tracker.start(start);
+            tracker.finish(seq, start + Duration::from_millis(ms));
+        }
+        let stats = tracker.stats().unwrap();
+        assert_eq!(stats.samples, LATENCY_SAMPLES);
+        assert_eq!(stats.min, Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_overdue_after_timeout() {
+        let mut tracker = LatencyTracker::default();
+        let start = Instant::now();
+        assert_eq!(tracker.overdue(start), None);
+        tracker.start(start);
+        assert_eq!(tracker.overdue(start + Duration::from_secs(1)), None);
+        let late = start + PONG_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(
+            tracker.overdue(late),
+            Some(PONG_TIMEOUT + Duration::from_secs(1))
+        );
+    }
+}
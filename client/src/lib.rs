@@ -0,0 +1,50 @@
+//! Chat client library
+//!
+//! The `client` binary is a thin terminal front end over this crate. Other
+//! programs (GUIs, bots) can embed the client by connecting, joining, and then
+//! spawning the I/O loop, which is driven through the returned handle instead
+//! of stdin:
+//!
+//! ```no_run
+//! use client::{ChatClient, MessageTypes};
+//!
+//! # async fn example() -> Result<(), client::ChatClientError> {
+//! let mut chat = ChatClient::new("127.0.0.1:8080", "Bot".to_string()).await?;
+//! chat.join_server().await?;
+//! let mut incoming = chat.subscribe();
+//! let (handle, _task) = chat.spawn();
+//!
+//! handle.send("hello everyone");
+//! while let Some(message) = incoming.recv().await {
+//!     if message.msg_type == MessageTypes::DirectMessage {
+//!         handle.send("/r thanks!");
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The client keeps handling protocol details itself (file transfers, E2E keys,
+//! reconnects) and still logs to the terminal; call
+//! [`shared::logger::use_plain_stderr`] to keep stdout free.
+
+pub mod aliases;
+mod client;
+mod completer;
+mod downloads;
+mod e2e;
+mod input;
+mod latency;
+mod notify;
+mod pause;
+pub mod profiles;
+mod readline_helper;
+mod resize;
+mod stdio;
+
+pub use client::{
+    ChatClient, ChatClientError, ClientHandle, ServerAddr, TlsFallback, read_piped_file,
+};
+pub use downloads::OverwritePolicy;
+pub use notify::BellEvents;
+pub use shared::message::{ChatMessage, MessageTypes};
@@ -1,15 +1,23 @@
-mod client;
-mod completer;
-mod input;
-mod readline_helper;
-
-use client::ChatClient;
+use client::profiles::Profiles;
+use client::{BellEvents, ChatClient, OverwritePolicy, TlsFallback, aliases, read_piped_file};
 use shared::logger;
 use std::env;
 use std::io::{self, Write};
+use std::process::ExitCode;
 
 const DEFAULT_SERVER: &str = "tls://milesrust.chat:8443";
 const DEFAULT_NAME: &str = "Guest";
+const STDIO_FLAG: &str = "--stdio";
+const SEND_FLAG: &str = "--send";
+const NAME_FLAG: &str = "--name";
+/// File name for `--send` when `--name` isn't given
+const DEFAULT_SEND_FILE_NAME: &str = "stdin.txt";
+const CHAT_STDIO_MODE_ENV_VAR: &str = "CHAT_STDIO_MODE";
+const CHAT_TLS_FALLBACK_ENV_VAR: &str = "CHAT_TLS_FALLBACK";
+const CHAT_VERSION_MISMATCH_EXIT_CODE_ENV_VAR: &str = "CHAT_VERSION_MISMATCH_EXIT_CODE";
+/// Exit status when the server rejects this client's version, so scripts and
+/// supervisors can tell "needs upgrade" apart from other failures (which exit 1)
+const DEFAULT_VERSION_MISMATCH_EXIT_CODE: u8 = 3;
 
 /// Restore terminal to a sane state (cursor visible, line buffered, echo on)
 fn restore_terminal() {
@@ -29,35 +37,162 @@ fn restore_terminal() {
     }
 }
 
+/// Restores the terminal when dropped, so every exit path (an error, Ctrl-C
+/// or a panic) leaves it usable. The input thread only puts it in raw mode
+/// while reading a line, but may still be reading when the client exits.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Check whether the client should run the stdin/stdout line protocol
+fn stdio_mode_requested() -> bool {
+    env::args().skip(1).any(|arg| arg == STDIO_FLAG)
+        || env::var(CHAT_STDIO_MODE_ENV_VAR)
+            .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+}
+
+/// The argument following `flag` on the command line, if given
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
+/// Pick the TLS fallback from `CHAT_TLS_FALLBACK` (auto / prompt / never).
+/// Interactive sessions ask by default; stdio mode and `--send` can't (stdin
+/// isn't the user), so they never fall back.
+fn tls_fallback(no_prompt: bool) -> TlsFallback {
+    match env::var(CHAT_TLS_FALLBACK_ENV_VAR) {
+        Ok(val) if val == "1" || val.eq_ignore_ascii_case("true") => TlsFallback::Auto,
+        Ok(val) if val.eq_ignore_ascii_case("auto") => TlsFallback::Auto,
+        Ok(val) if val.eq_ignore_ascii_case("prompt") && !no_prompt => TlsFallback::Prompt,
+        Ok(_) => TlsFallback::Never,
+        Err(_) if no_prompt => TlsFallback::Never,
+        Err(_) => TlsFallback::Prompt,
+    }
+}
+
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    let (chat_server, chat_name) = get_server_info()?;
+async fn main() -> io::Result<ExitCode> {
+    let stdio_mode = stdio_mode_requested();
+    if stdio_mode {
+        // Keep stdout for protocol lines only
+        logger::use_plain_stderr();
+    }
+
+    // `--send <user>` sends stdin as a file and exits. It's read in full before
+    // connecting so input over the size limit fails straight away.
+    let send = match flag_value(SEND_FLAG) {
+        Some(recipient) => {
+            let data = read_piped_file(io::stdin().lock()).map_err(|e| {
+                logger::log_error(&format!("Failed to read stdin: {}", e));
+                e
+            })?;
+            let name = flag_value(NAME_FLAG).unwrap_or(DEFAULT_SEND_FILE_NAME.to_string());
+            Some((recipient, name, data))
+        }
+        None => None,
+    };
+    let no_prompt = stdio_mode || send.is_some();
 
-    let mut client = ChatClient::new(&chat_server, chat_name)
+    let (chat_server, chat_name) = get_server_info(no_prompt)?;
+    aliases::install(aliases::Aliases::load());
+
+    let fallback = tls_fallback(no_prompt);
+    let mut client = ChatClient::with_tls_fallback(&chat_server, chat_name, fallback)
         .await
         .map_err(|e| {
-            logger::log_error(&format!("Failed to create client: {:?}", e));
-            io::Error::other(format!("Failed to create client: {e:?}"))
+            logger::log_error(&format!("Failed to create client: {}", e));
+            io::Error::other(format!("Failed to create client: {e}"))
         })?;
 
+    if env::var("CHAT_E2E").is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true")) {
+        client.enable_e2e();
+    }
+    if env::var("CHAT_READONLY").is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true")) {
+        client.enable_readonly();
+    }
+    if let Ok(value) = env::var("CHAT_DOWNLOAD_OVERWRITE") {
+        match OverwritePolicy::parse(&value) {
+            Some(policy) => client.set_overwrite_policy(policy),
+            None => logger::log_warning(&format!(
+                "Unknown CHAT_DOWNLOAD_OVERWRITE '{}' (use skip, rename or overwrite); renaming",
+                value
+            )),
+        }
+    }
+    if env::var("CHAT_COMPRESSION").is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+    {
+        client.enable_compression();
+    }
+    if env::var("CHAT_NOTIFY").is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true")) {
+        client.enable_notifications();
+    }
+    if let Ok(value) = env::var("CHAT_BELL") {
+        let (events, unknown) = BellEvents::parse(&value);
+        for name in unknown {
+            logger::log_warning(&format!(
+                "Unknown CHAT_BELL event '{}' (use dm, mention, file or all)",
+                name
+            ));
+        }
+        client.set_bell_events(events);
+    }
+    if stdio_mode {
+        client.enable_stdio();
+    }
+
     client
         .join_server()
         .await
-        .map_err(|e| io::Error::other(format!("Failed to join server: {e:?}")))?;
+        .map_err(|e| io::Error::other(format!("Failed to join server: {e}")))?;
 
-    // Run client with Ctrl+C handling
-    tokio::select! {
-        result = client.run() => {
-            restore_terminal();
-            result
+    let session = async {
+        match send {
+            Some((recipient, name, data)) => {
+                client.wait_until_joined().await?;
+                client
+                    .send_data(&recipient, &name, data, None)
+                    .await
+                    .map_err(|e| io::Error::other(format!("Failed to send file: {e}")))?;
+                client.run_until_sent().await
+            }
+            None => client.run().await,
         }
+    };
+
+    // Run client with Ctrl+C handling
+    let terminal = if no_prompt { None } else { Some(TerminalGuard) };
+    let result = tokio::select! {
+        result = session => result,
         _ = tokio::signal::ctrl_c() => {
-            restore_terminal();
-            println!(); // New line after ^C
+            if let Some(terminal) = terminal {
+                drop(terminal);
+                println!(); // New line after ^C
+            }
             logger::log_info("Interrupted, exiting...");
             Ok(())
         }
+    };
+
+    if client.version_mismatch() {
+        let code = version_mismatch_exit_code();
+        logger::log_info(&format!("Exiting with status {} (version mismatch)", code));
+        return Ok(ExitCode::from(code));
     }
+    result.map(|()| ExitCode::SUCCESS)
+}
+
+/// Exit status for a version mismatch from `CHAT_VERSION_MISMATCH_EXIT_CODE`
+fn version_mismatch_exit_code() -> u8 {
+    env::var(CHAT_VERSION_MISMATCH_EXIT_CODE_ENV_VAR)
+        .unwrap_or(DEFAULT_VERSION_MISMATCH_EXIT_CODE.to_string())
+        .parse::<u8>()
+        .unwrap_or(DEFAULT_VERSION_MISMATCH_EXIT_CODE)
 }
 
 fn prompt_input(prompt: &str, default: &str) -> io::Result<String> {
@@ -73,22 +208,54 @@ fn prompt_input(prompt: &str, default: &str) -> io::Result<String> {
     })
 }
 
-fn get_server_info() -> io::Result<(String, String)> {
-    // Check for environment variables first
+fn get_server_info(no_prompt: bool) -> io::Result<(String, String)> {
+    let profiles = Profiles::load().unwrap_or_else(|e| {
+        logger::log_warning(&format!("Failed to read profiles: {}", e));
+        Profiles::default()
+    });
+
+    // Check for environment variables first. In stdio mode stdin carries
+    // commands (and with --send, the file), so fall back to the defaults
+    // instead of prompting.
     let server = match env::var("CHAT_SERVER") {
         Ok(val) if !val.is_empty() => {
             logger::log_info(&format!("Using server from CHAT_SERVER: {}", val));
             val
         }
-        _ => prompt_input("Enter Chat Server", DEFAULT_SERVER)?,
+        _ if no_prompt => DEFAULT_SERVER.to_string(),
+        _ => {
+            if !profiles.is_empty() {
+                let names: Vec<&str> = profiles.iter().map(|(name, _)| name).collect();
+                logger::log_info(&format!("Saved profiles: {}", names.join(", ")));
+            }
+            prompt_input("Enter Chat Server or profile", DEFAULT_SERVER)?
+        }
+    };
+
+    // A profile name stands in for its address and username
+    let (server, profile_username) = match profiles.get(&server) {
+        Some(profile) => {
+            logger::log_info(&format!("Using profile '{}': {}", server, profile.address));
+            (profile.address.clone(), profile.username.clone())
+        }
+        None => (server, None),
     };
+    // An invite like tls://host:8443#alice suggests a username
+    let suggested_name = ChatClient::parse_server_addr(&server)
+        .ok()
+        .and_then(|addr| addr.nickname)
+        .unwrap_or(DEFAULT_NAME.to_string());
 
     let name = match env::var("CHAT_USERNAME") {
         Ok(val) if !val.is_empty() => {
             logger::log_info(&format!("Using username from CHAT_USERNAME: {}", val));
             val
         }
-        _ => prompt_input("Enter Chat Name", DEFAULT_NAME)?,
+        _ => match profile_username {
+            Some(username) => username,
+            None if no_prompt => suggested_name,
+            None => prompt_input("Enter Chat Name", &suggested_name)?,
+        },
     };
 
     Ok((server, name))
@@ -0,0 +1,142 @@
+//! Desktop notifications and terminal bells for incoming DMs, pages and mentions
+//!
+//! Uses `notify-rust` when the client is built with the `notifications`
+//! feature, and rings the terminal bell otherwise (or when the desktop has no
+//! notification service). Independently of notifications, `CHAT_BELL` picks
+//! which events ring the bell.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Minimum time between notifications, so a busy channel can't flood the desktop
+const NOTIFY_DEBOUNCE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct Notifier {
+    last_sent: Option<Instant>,
+}
+
+impl Notifier {
+    /// Show a notification unless one was shown within the debounce window
+    pub fn notify(&mut self, title: &str, body: &str) {
+        let now = Instant::now();
+        if self
+            .last_sent
+            .is_some_and(|last| now.duration_since(last) < NOTIFY_DEBOUNCE)
+        {
+            return;
+        }
+        self.notify_urgent(title, body);
+    }
+
+    /// Show a notification even within the debounce window (used for pages,
+    /// which the server already limits)
+    pub fn notify_urgent(&mut self, title: &str, body: &str) {
+        self.last_sent = Some(Instant::now());
+        if !show_desktop_notification(title, body) {
+            ring_bell();
+        }
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn show_desktop_notification(title: &str, body: &str) -> bool {
+    notify_rust::Notification::new()
+        .appname("rust_chat")
+        .summary(title)
+        .body(body)
+        .show()
+        .is_ok()
+}
+
+#[cfg(not(feature = "notifications"))]
+fn show_desktop_notification(_title: &str, _body: &str) -> bool {
+    false
+}
+
+/// Event that can ring the terminal bell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BellEvent {
+    DirectMessage,
+    Mention,
+    FileOffer,
+}
+
+/// Which events ring the terminal bell, from `CHAT_BELL=dm,mention,file`
+/// ("all" enables every event). Nothing rings by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BellEvents {
+    pub dm: bool,
+    pub mention: bool,
+    pub file: bool,
+}
+
+impl BellEvents {
+    /// Parse a comma-separated event list, returning any names not recognized
+    pub fn parse(list: &str) -> (Self, Vec<String>) {
+        let mut events = BellEvents::default();
+        let mut unknown = Vec::new();
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match name.to_ascii_lowercase().as_str() {
+                "dm" => events.dm = true,
+                "mention" => events.mention = true,
+                "file" => events.file = true,
+                "all" => {
+                    events = BellEvents {
+                        dm: true,
+                        mention: true,
+                        file: true,
+                    }
+                }
+                _ => unknown.push(name.to_string()),
+            }
+        }
+        (events, unknown)
+    }
+
+    pub fn contains(&self, event: BellEvent) -> bool {
+        match event {
+            BellEvent::DirectMessage => self.dm,
+            BellEvent::Mention => self.mention,
+            BellEvent::FileOffer => self.file,
+        }
+    }
+}
+
+/// Bells rung for a page, which is meant to be hard to miss
+const PAGE_BELLS: usize = 3;
+
+pub fn ring_bell() {
+    print!("\x07");
+    let _ = io::stdout().flush();
+}
+
+/// Ring the bell several times for a page, whatever `CHAT_BELL` says
+pub fn ring_page_bell() {
+    print!("{}", "\x07".repeat(PAGE_BELLS));
+    let _ = io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bell_events() {
+        let (events, unknown) = BellEvents::parse("dm, Mention");
+        assert!(events.contains(BellEvent::DirectMessage));
+        assert!(events.contains(BellEvent::Mention));
+        assert!(!events.contains(BellEvent::FileOffer));
+        assert!(unknown.is_empty());
+
+        let (events, unknown) = BellEvents::parse("all,chat");
+        assert!(events.contains(BellEvent::FileOffer));
+        assert_eq!(unknown, vec!["chat"]);
+
+        assert_eq!(BellEvents::parse("").0, BellEvents::default());
+    }
+}
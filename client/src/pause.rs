@@ -0,0 +1,69 @@
+//! Channel messages held back while the user reads scrollback (`/pause`)
+//!
+//! Only channel chat is held. DMs, pages, join/leave notices and errors still
+//! print while paused, since they are addressed to the user or explain what
+//! happened to the connection and shouldn't wait for `/resume`.
+
+use shared::message::ChatMessage;
+use std::collections::VecDeque;
+
+/// Most channel messages held while paused; older ones are dropped beyond this
+pub const PAUSE_BUFFER_LIMIT: usize = 500;
+
+#[derive(Debug)]
+pub struct PauseBuffer {
+    held: VecDeque<ChatMessage>,
+    /// Messages dropped because the buffer was full
+    dropped: usize,
+    limit: usize,
+}
+
+impl PauseBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            held: VecDeque::new(),
+            dropped: 0,
+            limit,
+        }
+    }
+
+    /// Hold `message`, dropping the oldest held message if the buffer is full
+    pub fn push(&mut self, message: ChatMessage) {
+        if self.held.len() >= self.limit {
+            self.held.pop_front();
+            self.dropped += 1;
+        }
+        self.held.push_back(message);
+    }
+
+    /// The held messages, oldest first, and how many were dropped
+    pub fn into_parts(self) -> (VecDeque<ChatMessage>, usize) {
+        (self.held, self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::message::MessageTypes;
+
+    fn chat(text: &str) -> ChatMessage {
+        ChatMessage::try_new(MessageTypes::ChatMessage, Some(text.as_bytes().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn test_oldest_dropped_when_full() {
+        let mut buffer = PauseBuffer::new(2);
+        buffer.push(chat("one"));
+        buffer.push(chat("two"));
+        buffer.push(chat("three"));
+
+        let (held, dropped) = buffer.into_parts();
+        assert_eq!(dropped, 1);
+        let texts: Vec<String> = held
+            .iter()
+            .filter_map(|message| message.content_as_string())
+            .collect();
+        assert_eq!(texts, vec!["two", "three"]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Named server connection profiles stored in `~/.rust_chat_profiles`
+//!
+//! Each line maps a profile name to a server address (prefixed with `tls://`
+//! for TLS) and an optional default username:
+//!
+//! ```text
+//! # Lines starting with '#' are ignored
+//! work = tls://chat.example.com:8443 alice
+//! home = 192.168.1.10:8080
+//! ```
+
+use shared::logger;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+pub const PROFILES_FILE_NAME: &str = ".rust_chat_profiles";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Server address in the form the client accepts, e.g. `tls://host:8443`
+    pub address: String,
+    /// Username to join as (prompted for when not set)
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiles {
+    /// Sorted by name so listings and the saved file are stable
+    map: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Load profiles from the user's home directory, warning about invalid entries
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = profiles_path() else {
+            return Ok(Self::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let (profiles, warnings) = Self::parse(&contents);
+        for warning in warnings {
+            logger::log_warning(&format!("{}: {}", path.display(), warning));
+        }
+        Ok(profiles)
+    }
+
+    /// Parse profile definitions, returning the valid profiles and a warning per bad line
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut map = BTreeMap::new();
+        let mut warnings = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, rest)) = line.split_once('=') else {
+                warnings.push(format!(
+                    "line {}: expected '<name> = <address> [username]'",
+                    line_number
+                ));
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                warnings.push(format!(
+                    "line {}: profile name '{}' must be a single word",
+                    line_number, name
+                ));
+                continue;
+            }
+            let mut fields = rest.split_whitespace();
+            let Some(address) = fields.next() else {
+                warnings.push(format!(
+                    "line {}: profile '{}' has no address",
+                    line_number, name
+                ));
+                continue;
+            };
+            let username = fields.next().map(str::to_string);
+
+            map.insert(
+                name.to_string(),
+                Profile {
+                    address: address.to_string(),
+                    username,
+                },
+            );
+        }
+
+        (Profiles { map }, warnings)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.map.get(name)
+    }
+
+    /// Add or replace a profile
+    pub fn insert(&mut self, name: &str, profile: Profile) {
+        self.map.insert(name.to_string(), profile);
+    }
+
+    /// Profiles sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Profile)> {
+        self.map
+            .iter()
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Write the profiles back to the user's home directory
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let path = profiles_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        std::fs::write(&path, self.render())?;
+        Ok(path)
+    }
+
+    fn render(&self) -> String {
+        self.iter()
+            .map(|(name, profile)| match &profile.username {
+                Some(username) => format!("{} = {} {}\n", name, profile.address, username),
+                None => format!("{} = {}\n", name, profile.address),
+            })
+            .collect()
+    }
+}
+
+fn profiles_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(PROFILES_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let (profiles, warnings) = Profiles::parse(
+            "# servers\nwork = tls://chat.example.com:8443 alice\n\nhome=192.168.1.10:8080\n",
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(
+            profiles.get("work"),
+            Some(&Profile {
+                address: "tls://chat.example.com:8443".to_string(),
+                username: Some("alice".to_string()),
+            })
+        );
+        assert_eq!(profiles.get("home").unwrap().username, None);
+        assert!(profiles.get("other").is_none());
+    }
+
+    #[test]
+    fn test_parse_invalid_profiles_warned() {
+        let (profiles, warnings) =
+            Profiles::parse("no equals sign\nmy work = host:1\nempty =\nok = host:2\n");
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].contains("line 1"));
+        assert!(warnings[1].contains("single word"));
+        assert!(warnings[2].contains("no address"));
+        assert_eq!(profiles.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let (profiles, _) = Profiles::parse("b = host:2\na = tls://host:1 alice\n");
+        let rendered = profiles.render();
+        assert_eq!(rendered, "a = tls://host:1 alice\nb = host:2\n");
+        let (reparsed, warnings) = Profiles::parse(&rendered);
+        assert!(warnings.is_empty());
+        assert_eq!(reparsed.get("a"), profiles.get("a"));
+    }
+}
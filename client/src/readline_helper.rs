@@ -1,38 +1,211 @@
-use crate::completer::ClientCompleter;
-use rustyline::Editor;
+use crate::aliases;
+use crate::completer::{ClientCompleter, CompletionState};
+use crate::input::ClientUserInput;
 use rustyline::config::Configurer;
-use std::collections::HashSet;
-use std::sync::{Arc, RwLock};
-use tokio::sync::mpsc;
-
-/// Runs rustyline in a blocking thread and sends input via channel
-pub fn spawn_readline_handler(
-    users: Arc<RwLock<HashSet<String>>>,
-    _prompt: String,
-) -> mpsc::UnboundedReceiver<Option<String>> {
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, ExternalPrinter};
+use shared::commands::client as commands;
+use shared::logger;
+use std::thread::JoinHandle;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+
+/// Prompt shown while composing a /multiline message
+const MULTILINE_PROMPT: &str = "... ";
+/// A line containing only this ends a /multiline message
+const MULTILINE_TERMINATOR: &str = ".";
+
+/// Input produced by the readline thread
+#[derive(Debug)]
+pub enum ReadlineInput {
+    /// A single line typed at the prompt
+    Line(String),
+    /// A block composed in /multiline mode, sent as one chat message
+    Block(String),
+    /// Input ended (Ctrl-D, or the end of stdin in stdio mode)
+    Eof,
+    /// Ctrl-C at the prompt
+    Interrupted,
+    /// Reading input failed
+    Failed(String),
+}
+
+impl ReadlineInput {
+    /// The final input sent for a readline error (every error ends input)
+    fn from_error(err: ReadlineError) -> Self {
+        match err {
+            ReadlineError::Eof => ReadlineInput::Eof,
+            ReadlineError::Interrupted => ReadlineInput::Interrupted,
+            err => ReadlineInput::Failed(err.to_string()),
+        }
+    }
+}
+
+/// True for a line that quits the client, after which the input thread stops
+/// reading (so it isn't left holding the terminal in raw mode at exit)
+pub fn ends_input(line: &str) -> bool {
+    matches!(ClientUserInput::try_from(line), Ok(ClientUserInput::Quit))
+}
+
+/// A blocking thread reading user input, and the channel it sends input on.
+/// The thread's last input is always `/quit`, `Eof`, `Interrupted` or
+/// `Failed`, after which it exits.
+#[derive(Debug)]
+pub struct InputReader {
+    pub rx: mpsc::UnboundedReceiver<Option<ReadlineInput>>,
+    thread: JoinHandle<()>,
+}
+
+impl InputReader {
+    pub fn new(rx: mpsc::UnboundedReceiver<Option<ReadlineInput>>, thread: JoinHandle<()>) -> Self {
+        Self { rx, thread }
+    }
+
+    /// Stop reading input, discarding any not yet handled. The thread is
+    /// joined if it has already stopped; one still blocked reading the
+    /// terminal can't be interrupted, so it is left to exit on its next line
+    /// (its send fails) or with the process. Returns whether it was joined.
+    pub fn shutdown(mut self) -> bool {
+        while self.rx.try_recv().is_ok() {}
+        // The sender is dropped as the thread returns, so joining won't block
+        if !matches!(self.rx.try_recv(), Err(TryRecvError::Disconnected)) {
+            return false;
+        }
+        if self.thread.join().is_err() {
+            logger::log_error("Input thread panicked");
+        }
+        true
+    }
+}
+
+/// Runs rustyline in a blocking thread and sends input via channel. The
+/// editor is created before this returns: it takes over SIGWINCH, so
+/// resize listeners registered afterwards are chained to it rather than
+/// replaced by it.
+pub fn spawn_readline_handler(completion_state: CompletionState, _prompt: String) -> InputReader {
     let (tx, rx) = mpsc::unbounded_channel();
+    let editor = Editor::new();
 
-    std::thread::spawn(move || {
-        let completer = ClientCompleter::new(users);
-        let mut rl = Editor::new().expect("Failed to create editor");
+    let thread = std::thread::spawn(move || {
+        let completer = ClientCompleter::new(completion_state);
+        let mut rl: Editor<ClientCompleter, DefaultHistory> = match editor {
+            Ok(rl) => rl,
+            Err(e) => {
+                let _ = tx.send(Some(ReadlineInput::from_error(e)));
+                return;
+            }
+        };
         rl.set_helper(Some(completer));
         rl.set_auto_add_history(true);
         rl.set_max_history_size(1000).ok();
+        // Draw incoming messages above the prompt so they don't garble a
+        // half-typed line (unavailable when stdin/stdout isn't a terminal)
+        if let Ok(mut printer) = rl.create_external_printer() {
+            logger::set_printer(move |line| printer.print(format!("{}\n", line)).is_ok());
+        }
 
         loop {
             match rl.readline("") {
                 Ok(line) => {
-                    if tx.send(Some(line)).is_err() {
-                        break; // Receiver dropped
+                    let cmd = aliases::installed().resolve(line.trim());
+                    let last = ends_input(&line);
+                    let input = if commands::MULTILINE.matches(cmd) {
+                        match read_block(&mut rl) {
+                            Some(block) => ReadlineInput::Block(block),
+                            None => continue,
+                        }
+                    } else {
+                        ReadlineInput::Line(line)
+                    };
+                    if tx.send(Some(input)).is_err() || last {
+                        break; // Receiver dropped, or the user quit
                     }
                 }
-                Err(_) => {
-                    let _ = tx.send(None); // EOF or error
+                Err(e) => {
+                    let _ = tx.send(Some(ReadlineInput::from_error(e)));
                     break;
                 }
             }
         }
     });
 
-    rx
+    InputReader::new(rx, thread)
+}
+
+/// Collect lines until a lone "." or Ctrl-D. Returns None if cancelled with Ctrl-C.
+fn read_block(rl: &mut Editor<ClientCompleter, DefaultHistory>) -> Option<String> {
+    logger::log_info(&format!(
+        "Multi-line mode: end with '{}' on its own line or Ctrl-D (Ctrl-C cancels)",
+        MULTILINE_TERMINATOR
+    ));
+
+    let mut lines = Vec::new();
+    loop {
+        match rl.readline(MULTILINE_PROMPT) {
+            Ok(line) if line.trim_end() == MULTILINE_TERMINATOR => break,
+            Ok(line) => lines.push(line),
+            Err(ReadlineError::Eof) => break,
+            Err(_) => {
+                logger::log_info("Multi-line message cancelled");
+                return None;
+            }
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readline_errors_end_input() {
+        assert!(matches!(
+            ReadlineInput::from_error(ReadlineError::Eof),
+            ReadlineInput::Eof
+        ));
+        assert!(matches!(
+            ReadlineInput::from_error(ReadlineError::Interrupted),
+            ReadlineInput::Interrupted
+        ));
+        let io_error = std::io::Error::other("terminal gone");
+        assert!(matches!(
+            ReadlineInput::from_error(ReadlineError::Io(io_error)),
+            ReadlineInput::Failed(ref e) if e.contains("terminal gone")
+        ));
+    }
+
+    #[test]
+    fn test_quit_ends_input() {
+        assert!(ends_input("/quit"));
+        assert!(ends_input("  /quit  "));
+        assert!(!ends_input("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_joins_finished_thread() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let thread = std::thread::spawn(move || {
+            let _ = tx.send(Some(ReadlineInput::Eof));
+        });
+        let mut reader = InputReader::new(rx, thread);
+        assert!(matches!(
+            reader.rx.recv().await,
+            Some(Some(ReadlineInput::Eof))
+        ));
+        assert!(reader.rx.recv().await.is_none());
+        assert!(reader.shutdown());
+    }
+
+    #[test]
+    fn test_shutdown_leaves_blocked_thread() {
+        let (tx, rx) = mpsc::unbounded_channel::<Option<ReadlineInput>>();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            let _ = release_rx.recv();
+            drop(tx);
+        });
+        assert!(!InputReader::new(rx, thread).shutdown());
+        release_tx.send(()).unwrap();
+    }
 }
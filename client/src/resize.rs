@@ -0,0 +1,54 @@
+//! Keeps the logger's wrap width in step with the terminal
+//!
+//! Chat lines are word-wrapped to the terminal width, which changes when the
+//! window is resized. On Unix the width is read again on each SIGWINCH;
+//! elsewhere it is polled every `RESIZE_POLL_INTERVAL`. Rustyline redraws the
+//! prompt and any half-typed line itself.
+
+use shared::logger;
+#[cfg(not(unix))]
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How often the width is checked where there is no resize signal
+#[cfg(not(unix))]
+pub const RESIZE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Read the terminal width and wrap to it from now on
+fn refresh_width() {
+    logger::set_terminal_width(logger::query_terminal_width());
+}
+
+/// Track the terminal width until the returned task is aborted. On Unix,
+/// start this after the readline editor exists so its SIGWINCH handler is
+/// kept (see `readline_helper::spawn_readline_handler`).
+pub fn spawn_resize_watcher() -> JoinHandle<()> {
+    refresh_width();
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut resized = match signal(SignalKind::window_change()) {
+                Ok(resized) => resized,
+                Err(e) => {
+                    logger::log_warning(&format!(
+                        "Can't watch for terminal resizes ({}); output wraps to the starting width",
+                        e
+                    ));
+                    return;
+                }
+            };
+            while resized.recv().await.is_some() {
+                refresh_width();
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let mut interval = tokio::time::interval(RESIZE_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                refresh_width();
+            }
+        }
+    })
+}
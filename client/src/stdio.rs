@@ -0,0 +1,199 @@
+//! Line protocol for driving the client from another program (`--stdio` or
+//! `CHAT_STDIO_MODE=1`)
+//!
+//! Commands and messages are read from stdin one per line, exactly as they
+//! would be typed at the prompt. Received events are written to stdout as
+//! tab-separated lines:
+//!
+//! ```text
+//! MSG\t<user>\t<text>
+//! DM\t<user>\t<text>
+//! JOIN\t<user>
+//! LEAVE\t<user>
+//! RENAMED\t<new_name>
+//! SERVER\t<server_name>
+//! USERS\t<user>\t<user>...
+//! NOTICE\t<text>
+//! MOTD\t<text>
+//! TOPIC\t<text>
+//! POLL\t<id>\t<creator>\t<question>\t<votes>\t<option>\t<votes>\t<option>...
+//! ERROR\t<text>
+//! ```
+//!
+//! Tabs, newlines and backslashes inside fields are escaped as `\t`, `\n` and
+//! `\\`. Everything else the client logs goes to stderr without colors.
+
+use crate::readline_helper::{InputReader, ReadlineInput, ends_input};
+use shared::message::PollSummary;
+use std::io::{BufRead, Write};
+use tokio::sync::mpsc;
+
+/// A received event rendered as one stdout line in stdio mode
+#[derive(Debug)]
+pub enum StdioEvent<'a> {
+    Message { sender: &'a str, text: &'a str },
+    DirectMessage { sender: &'a str, text: &'a str },
+    Page { sender: &'a str, text: &'a str },
+    Join(&'a str),
+    Leave(&'a str),
+    Renamed(&'a str),
+    Server(&'a str),
+    Users(Vec<&'a str>),
+    Notice(&'a str),
+    Motd(&'a str),
+    Topic(&'a str),
+    Poll(&'a PollSummary),
+    Error(&'a str),
+}
+
+impl StdioEvent<'_> {
+    pub fn to_line(&self) -> String {
+        let poll_fields: Vec<String>;
+        let fields: Vec<&str> = match self {
+            StdioEvent::Message { sender, text } => vec!["MSG", sender, text],
+            StdioEvent::DirectMessage { sender, text } => vec!["DM", sender, text],
+            StdioEvent::Page { sender, text } => vec!["PAGE", sender, text],
+            StdioEvent::Join(user) => vec!["JOIN", user],
+            StdioEvent::Leave(user) => vec!["LEAVE", user],
+            StdioEvent::Renamed(name) => vec!["RENAMED", name],
+            StdioEvent::Server(name) => vec!["SERVER", name],
+            StdioEvent::Users(users) => std::iter::once("USERS")
+                .chain(users.iter().copied())
+                .collect(),
+            StdioEvent::Notice(text) => vec!["NOTICE", text],
+            StdioEvent::Motd(text) => vec!["MOTD", text],
+            StdioEvent::Topic(text) => vec!["TOPIC", text],
+            StdioEvent::Poll(poll) => {
+                poll_fields = [
+                    poll.id.to_string(),
+                    poll.creator.clone(),
+                    poll.question.clone(),
+                ]
+                .into_iter()
+                .chain(
+                    poll.options
+                        .iter()
+                        .flat_map(|(option, votes)| [votes.to_string(), option.clone()]),
+                )
+                .collect();
+                std::iter::once("POLL")
+                    .chain(poll_fields.iter().map(String::as_str))
+                    .collect()
+            }
+            StdioEvent::Error(text) => vec!["ERROR", text],
+        };
+        fields
+            .iter()
+            .map(|field| escape_field(field))
+            .collect::<Vec<_>>()
+            .join("\t")
+    }
+}
+
+/// Write an event to stdout, flushing so the consuming program sees it immediately
+pub fn emit(event: StdioEvent) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", event.to_line());
+    let _ = stdout.flush();
+}
+
+/// Read stdin line by line in a blocking thread (no prompt, no line editing)
+pub fn spawn_stdin_reader() -> InputReader {
+    spawn_line_reader(std::io::BufReader::new(std::io::stdin()))
+}
+
+/// Send each line of `reader` as input until `/quit`, the end of input or a
+/// read error
+fn spawn_line_reader(reader: impl BufRead + Send + 'static) -> InputReader {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let thread = std::thread::spawn(move || {
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    let _ = tx.send(Some(ReadlineInput::Failed(e.to_string())));
+                    return;
+                }
+            };
+            let last = ends_input(&line);
+            if tx.send(Some(ReadlineInput::Line(line))).is_err() || last {
+                return; // Receiver dropped, or the program quit
+            }
+        }
+        let _ = tx.send(Some(ReadlineInput::Eof));
+    });
+
+    InputReader::new(rx, thread)
+}
+
+fn escape_field(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines() {
+        let msg = StdioEvent::Message {
+            sender: "alice",
+            text: "hello",
+        };
+        assert_eq!(msg.to_line(), "MSG\talice\thello");
+        assert_eq!(StdioEvent::Join("carol").to_line(), "JOIN\tcarol");
+        assert_eq!(
+            StdioEvent::Users(vec!["alice", "bob"]).to_line(),
+            "USERS\talice\tbob"
+        );
+        let poll = PollSummary {
+            id: 2,
+            creator: "bob".to_string(),
+            question: "Lunch?".to_string(),
+            options: vec![("pizza".to_string(), 1), ("tacos".to_string(), 0)],
+        };
+        assert_eq!(
+            StdioEvent::Poll(&poll).to_line(),
+            "POLL\t2\tbob\tLunch?\t1\tpizza\t0\ttacos"
+        );
+    }
+
+    #[test]
+    fn test_fields_escaped() {
+        let dm = StdioEvent::DirectMessage {
+            sender: "bob",
+            text: "line one\nline\ttwo \\o/",
+        };
+        assert_eq!(dm.to_line(), "DM\tbob\tline one\\nline\\ttwo \\\\o/");
+    }
+
+    /// Every input the reader sends, as the lines it read plus how it ended
+    async fn read_all(reader: &mut InputReader) -> Vec<String> {
+        let mut inputs = Vec::new();
+        while let Some(Some(input)) = reader.rx.recv().await {
+            inputs.push(match input {
+                ReadlineInput::Line(line) => line,
+                other => format!("{:?}", other),
+            });
+        }
+        inputs
+    }
+
+    #[tokio::test]
+    async fn test_reader_ends_with_eof() {
+        let mut reader = spawn_line_reader(&b"hello\n/list\n"[..]);
+        assert_eq!(read_all(&mut reader).await, vec!["hello", "/list", "Eof"]);
+        assert!(reader.shutdown());
+    }
+
+    #[tokio::test]
+    async fn test_reader_stops_after_quit() {
+        let mut reader = spawn_line_reader(&b"hello\n/quit\nignored\n"[..]);
+        assert_eq!(read_all(&mut reader).await, vec!["hello", "/quit"]);
+        assert!(reader.shutdown());
+    }
+}
@@ -0,0 +1,93 @@
+//! Banned IP list file (`CHAT_SERVER_BAN_FILE`), one address per line
+//!
+//! Loaded at startup and on `/reload`, and rewritten whenever `/ban` or
+//! `/unban` changes the list. Blank lines and `#` comments are ignored.
+
+use shared::logger;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Addresses listed in `contents`, plus any lines that weren't valid addresses
+fn parse(contents: &str) -> (HashSet<IpAddr>, Vec<&str>) {
+    let mut banned = HashSet::new();
+    let mut invalid = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse::<IpAddr>() {
+            Ok(ip) => {
+                banned.insert(ip);
+            }
+            Err(_) => invalid.push(line),
+        }
+    }
+    (banned, invalid)
+}
+
+/// Read the ban list (a missing file is an empty list)
+pub fn load(path: &Path) -> io::Result<HashSet<IpAddr>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    let (banned, invalid) = parse(&contents);
+    for line in invalid {
+        logger::log_warning(&format!(
+            "Ignoring invalid address in {}: {}",
+            path.display(),
+            line
+        ));
+    }
+    Ok(banned)
+}
+
+/// Write `banned` to the ban list file, sorted
+pub fn save(path: &Path, banned: &HashSet<IpAddr>) -> io::Result<()> {
+    let mut ips: Vec<&IpAddr> = banned.iter().collect();
+    ips.sort();
+    let contents: String = ips.iter().map(|ip| format!("{}\n", ip)).collect();
+    // Write then rename, so a crash mid-write never leaves a truncated file
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_skips_comments_and_invalid_lines() {
+        let (banned, invalid) =
+            parse("# abusive bots\n203.0.113.7\n\n2001:db8::1  # spammer\nnot-an-ip\n");
+        assert_eq!(banned.len(), 2);
+        assert!(banned.contains(&"203.0.113.7".parse().unwrap()));
+        assert!(banned.contains(&"2001:db8::1".parse().unwrap()));
+        assert_eq!(invalid, vec!["not-an-ip"]);
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("bans_test_{}", std::process::id()));
+        let banned: HashSet<IpAddr> = ["198.51.100.2", "10.0.0.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+
+        save(&path, &banned).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "10.0.0.1\n198.51.100.2\n"
+        );
+        assert_eq!(load(&path).unwrap(), banned);
+        fs::remove_file(&path).unwrap();
+
+        assert!(load(&path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! Connected user snapshots for the `/export <file>` console command
+//!
+//! Files ending in `.json` are written as a JSON object; anything else is
+//! written as CSV with a header row. Both record when the snapshot was taken
+//! as Unix seconds, so exports can be lined up with logs afterwards.
+
+use crate::webhook::json_string;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One connected user at the time of the export
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConnectedUser {
+    pub username: String,
+    pub ip: Option<IpAddr>,
+    pub status: String,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    /// JSON for a `.json` extension, CSV otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ExportFormat::Json,
+            _ => ExportFormat::Csv,
+        }
+    }
+}
+
+/// Write `users` to `path` in the format its extension selects
+pub fn write(path: &Path, users: &[ConnectedUser], exported_at: SystemTime) -> io::Result<()> {
+    fs::write(
+        path,
+        render(users, ExportFormat::from_path(path), exported_at),
+    )
+}
+
+fn render(users: &[ConnectedUser], format: ExportFormat, exported_at: SystemTime) -> String {
+    let secs = exported_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match format {
+        ExportFormat::Csv => {
+            let mut out = String::from("exported_at,username,ip,status,readonly\n");
+            for user in users {
+                out.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    secs,
+                    csv_field(&user.username),
+                    user.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                    csv_field(&user.status),
+                    user.readonly
+                ));
+            }
+            out
+        }
+        ExportFormat::Json => {
+            let rows: Vec<String> = users
+                .iter()
+                .map(|user| {
+                    format!(
+                        "{{\"username\":{},\"ip\":{},\"status\":{},\"readonly\":{}}}",
+                        json_string(&user.username),
+                        user.ip
+                            .map_or_else(|| "null".to_string(), |ip| json_string(&ip.to_string())),
+                        json_string(&user.status),
+                        user.readonly
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"exported_at\":{},\"users\":[{}]}}\n",
+                secs,
+                rows.join(",")
+            )
+        }
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn users() -> Vec<ConnectedUser> {
+        vec![
+            ConnectedUser {
+                username: "alice".to_string(),
+                ip: Some("203.0.113.7".parse().unwrap()),
+                status: "busy, \"really\"".to_string(),
+                readonly: false,
+            },
+            ConnectedUser {
+                username: "bob".to_string(),
+                ip: None,
+                status: String::new(),
+                readonly: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            render(&users(), ExportFormat::Csv, at),
+            "exported_at,username,ip,status,readonly\n\
+             1700000000,alice,203.0.113.7,\"busy, \"\"really\"\"\",false\n\
+             1700000000,bob,,,true\n"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            render(&users(), ExportFormat::Json, at),
+            "{\"exported_at\":1700000000,\"users\":[\
+             {\"username\":\"alice\",\"ip\":\"203.0.113.7\",\"status\":\"busy, \\\"really\\\"\",\"readonly\":false},\
+             {\"username\":\"bob\",\"ip\":null,\"status\":\"\",\"readonly\":true}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("users.JSON")),
+            ExportFormat::Json
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("users.csv")),
+            ExportFormat::Csv
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("users")),
+            ExportFormat::Csv
+        );
+    }
+}
@@ -0,0 +1,924 @@
+//! Server-to-server federation over dedicated peer links
+//!
+//! Links are one-way: a server pushes its own users' chat messages and
+//! join/leave presence to every address in `CHAT_SERVER_PEERS`, and accepts
+//! pushes from other servers on `CHAT_SERVER_PEER_ADDR`. Two servers that list
+//! each other therefore share one channel. Relayed messages carry the id of
+//! the server they came from and are never relayed again, so links can't loop.
+//!
+//! Both ends of a link prove they know the shared secret before anything is
+//! relayed: each sends a fresh nonce and answers the other's with an
+//! HMAC-SHA256 over the handshake, so the secret itself never crosses the wire.
+//!
+//! Each server tracks the users its peers announce. A link first announces
+//! everyone already online on its server, relayed names that are already in
+//! use are refused, and a server's users leave when its link closes.
+
+use aws_lc_rs::hmac;
+use shared::logger;
+use shared::message::{
+    ChatMessage, MessageTypes, parse_rename_announcement, parse_stamped_chat_content,
+};
+use shared::network::{TcpMessageHandler, TcpMessageHandlerError};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{RwLock, broadcast};
+use tokio::time::timeout;
+
+/// Wait this long before reconnecting a dropped outbound link
+const PEER_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Give up on connecting or the hello after this long
+const PEER_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Random bytes each end of a link contributes to the handshake
+const PEER_NONCE_LEN: usize = 32;
+/// Handshake roles, so one end's proof can't be reflected back as the other's
+const ROLE_ACCEPT: &str = "accept";
+const ROLE_CONNECT: &str = "connect";
+
+/// Where to push local events and where to accept them from other servers
+#[derive(Debug, Clone)]
+pub struct FederationConfig {
+    /// Random id tagging messages that originate on this server
+    pub server_id: String,
+    /// Shared secret every linked server must present
+    pub secret: String,
+    /// Address to accept peer links on (None = push only)
+    pub listen_addr: Option<String>,
+    /// Peer servers to push this server's events to
+    pub peers: Vec<String>,
+}
+
+/// Users online on linked servers, by the id of the server they are on. Each
+/// server's users belong to the link that announced them, so an old link
+/// closing late can't take a newer link's users with it.
+#[derive(Debug, Default)]
+pub struct RemoteUsers {
+    servers: HashMap<String, RemoteServer>,
+    next_link: u64,
+}
+
+#[derive(Debug, Default)]
+struct RemoteServer {
+    link: u64,
+    users: HashSet<String>,
+}
+
+impl RemoteUsers {
+    /// Whether `username` is online on a linked server
+    pub fn contains(&self, username: &str) -> bool {
+        self.servers
+            .values()
+            .any(|server| server.users.contains(username))
+    }
+
+    /// Everyone online on linked servers, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.servers.values().flat_map(|server| server.users.iter())
+    }
+
+    /// Start tracking a new link from `server`, returning its id and the users
+    /// an earlier link from it had announced, who are forgotten
+    fn link(&mut self, server: &str) -> (u64, Vec<String>) {
+        self.next_link += 1;
+        let link = self.next_link;
+        let previous = self.servers.insert(
+            server.to_string(),
+            RemoteServer {
+                link,
+                users: HashSet::new(),
+            },
+        );
+        (
+            link,
+            previous
+                .map(|server| server.users.into_iter().collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Forget the users `link` announced once it closes, returning them
+    fn unlink(&mut self, server: &str, link: u64) -> Vec<String> {
+        match self.servers.get(server) {
+            Some(current) if current.link == link => self
+                .servers
+                .remove(server)
+                .map(|server| server.users.into_iter().collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mark `username` as online on `server`, as its link would
+    #[cfg(test)]
+    pub fn add(&mut self, server: &str, username: &str) {
+        self.servers
+            .entry(server.to_string())
+            .or_default()
+            .users
+            .insert(username.to_string());
+    }
+
+    /// The users `server` announced, while `link` is still its current one
+    fn users_mut(&mut self, server: &str, link: u64) -> Option<&mut HashSet<String>> {
+        self.servers
+            .get_mut(server)
+            .filter(|current| current.link == link)
+            .map(|current| &mut current.users)
+    }
+}
+
+/// What to do with a relayed event
+#[derive(Debug, PartialEq)]
+enum RelayVerdict {
+    /// Pass it on to local users
+    Relay,
+    /// Drop it: a duplicate, or from a user the link never announced
+    Ignore,
+    /// Drop it: the user announced a name that is already in use
+    NameInUse(String),
+}
+
+/// Check a relayed event against the users `server` has announced on `link`,
+/// updating them. Names already online here or on another server are
+/// refused, and chat from users that weren't admitted is dropped.
+fn admit_relay(
+    remote: &mut RemoteUsers,
+    local: &HashSet<String>,
+    server: &str,
+    link: u64,
+    message: &ChatMessage,
+) -> RelayVerdict {
+    let Some(text) = message.content_as_string() else {
+        return RelayVerdict::Ignore;
+    };
+    let (_, body) = parse_stamped_chat_content(&text);
+    let (arriving, departing) = match message.msg_type {
+        MessageTypes::Join => (Some(body), None),
+        MessageTypes::Leave => (None, Some(body)),
+        MessageTypes::ChatMessage => match parse_rename_announcement(body) {
+            Some((old_name, new_name)) => (Some(new_name), Some(old_name)),
+            None => {
+                let sender = body.split_once(": ").map(|(sender, _)| sender);
+                let known = remote
+                    .users_mut(server, link)
+                    .zip(sender)
+                    .is_some_and(|(users, sender)| users.contains(sender));
+                return if known {
+                    RelayVerdict::Relay
+                } else {
+                    RelayVerdict::Ignore
+                };
+            }
+        },
+        _ => return RelayVerdict::Ignore,
+    };
+
+    let taken = arriving.is_some_and(|name| local.contains(name) || remote.contains(name));
+    let Some(users) = remote.users_mut(server, link) else {
+        return RelayVerdict::Ignore;
+    };
+    if let Some(name) = departing
+        && !users.remove(name)
+    {
+        return RelayVerdict::Ignore;
+    }
+    match arriving {
+        // A rename onto a name in use leaves the user untracked, so the rest
+        // of their chat is dropped too
+        Some(name) if taken => {
+            if users.contains(name) {
+                RelayVerdict::Ignore
+            } else {
+                RelayVerdict::NameInUse(name.to_string())
+            }
+        }
+        Some(name) => {
+            users.insert(name.to_string());
+            RelayVerdict::Relay
+        }
+        None => RelayVerdict::Relay,
+    }
+}
+
+/// A fresh id for this server process
+pub fn generate_server_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn generate_nonce() -> String {
+    to_hex(&rand::random::<[u8; PEER_NONCE_LEN]>())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// What `role` signs: its own id and both nonces, the other end's first
+fn proof_transcript(role: &str, server_id: &str, their_nonce: &str, our_nonce: &str) -> String {
+    format!("{}|{}|{}|{}", role, server_id, their_nonce, our_nonce)
+}
+
+/// Prove knowledge of the shared secret over a handshake transcript
+fn sign_proof(secret: &str, transcript: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    to_hex(hmac::sign(&key, transcript.as_bytes()).as_ref())
+}
+
+/// Check the other end's proof in constant time
+fn verify_proof(secret: &str, transcript: &str, proof: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    from_hex(proof).is_some_and(|tag| hmac::verify(&key, transcript.as_bytes(), &tag).is_ok())
+}
+
+/// Only chat and presence cross links
+fn is_relayed_type(msg_type: &MessageTypes) -> bool {
+    matches!(
+        msg_type,
+        MessageTypes::ChatMessage | MessageTypes::Join | MessageTypes::Leave
+    )
+}
+
+/// Broadcasts from connected users carry their socket address; server
+/// announcements and messages received from peers use 0.0.0.0:0
+fn is_from_local_user(addr: &SocketAddr) -> bool {
+    !addr.ip().is_unspecified()
+}
+
+/// Wrap `message` in a PeerRelay frame tagged with `origin`
+fn encode_relay(origin: &str, message: ChatMessage) -> Option<ChatMessage> {
+    let origin_len = u8::try_from(origin.len()).ok()?;
+    let mut content = vec![origin_len];
+    content.extend_from_slice(origin.as_bytes());
+    content.extend_from_slice(&Vec::<u8>::from(message));
+    ChatMessage::try_new(MessageTypes::PeerRelay, Some(content)).ok()
+}
+
+/// Unwrap a PeerRelay frame into its origin and message, rejecting anything
+/// that isn't chat or presence
+fn decode_relay(content: &[u8]) -> Option<(String, ChatMessage)> {
+    let (&origin_len, rest) = content.split_first()?;
+    let origin_len = origin_len as usize;
+    if rest.len() < origin_len {
+        return None;
+    }
+    let origin = std::str::from_utf8(&rest[..origin_len]).ok()?.to_string();
+    let message = ChatMessage::try_from_bytes(rest[origin_len..].to_vec()).ok()?;
+    if !is_relayed_type(&message.msg_type) || message.content_text().is_err() {
+        return None;
+    }
+    Some((origin, message))
+}
+
+/// One end of a server-to-server link (never a user connection)
+struct PeerConnection {
+    stream: TcpStream,
+    addr: String,
+}
+
+impl TcpMessageHandler for PeerConnection {
+    type Stream = TcpStream;
+    fn get_stream(&mut self) -> &mut Self::Stream {
+        &mut self.stream
+    }
+}
+
+impl PeerConnection {
+    async fn send_hello(&mut self, fields: &[&str]) -> io::Result<()> {
+        let hello =
+            ChatMessage::try_new(MessageTypes::PeerHello, Some(fields.join("|").into_bytes()))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid peer hello"))?;
+        timeout(PEER_HANDSHAKE_TIMEOUT, self.send_message_chunked(hello))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "hello timed out"))??;
+        Ok(())
+    }
+
+    /// The `|`-separated fields of the next PeerHello, which must number `count`
+    async fn read_hello(&mut self, count: usize) -> io::Result<Vec<String>> {
+        let hello = timeout(PEER_HANDSHAKE_TIMEOUT, self.read_message_chunked())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no hello"))?
+            .map_err(link_error)?;
+        let fields: Vec<String> = (hello.msg_type == MessageTypes::PeerHello)
+            .then(|| hello.content_as_string())
+            .flatten()
+            .unwrap_or_default()
+            .split('|')
+            .map(str::to_string)
+            .collect();
+        if fields.len() != count || fields.iter().any(String::is_empty) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected peer hello",
+            ));
+        }
+        Ok(fields)
+    }
+}
+
+fn wrong_secret() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "wrong shared secret")
+}
+
+fn linked_to_self() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "server linked to itself")
+}
+
+/// Connecting side of the handshake: send our id and nonce, check the
+/// listener's proof, then prove ourselves. Returns the listener's id
+async fn connect_handshake(
+    link: &mut PeerConnection,
+    config: &FederationConfig,
+) -> io::Result<String> {
+    let our_nonce = generate_nonce();
+    link.send_hello(&[&config.server_id, &our_nonce]).await?;
+
+    let reply = link.read_hello(3).await?;
+    let (peer_id, their_nonce, proof) = (&reply[0], &reply[1], &reply[2]);
+    let transcript = proof_transcript(ROLE_ACCEPT, peer_id, &our_nonce, their_nonce);
+    if !verify_proof(&config.secret, &transcript, proof) {
+        return Err(wrong_secret());
+    }
+    if *peer_id == config.server_id {
+        return Err(linked_to_self());
+    }
+
+    let transcript = proof_transcript(ROLE_CONNECT, &config.server_id, their_nonce, &our_nonce);
+    link.send_hello(&[&sign_proof(&config.secret, &transcript)])
+        .await?;
+    Ok(peer_id.clone())
+}
+
+/// Listening side of the handshake: answer the connecting server's nonce
+/// with our proof and nonce, then check its proof. Returns its id
+async fn accept_handshake(
+    link: &mut PeerConnection,
+    config: &FederationConfig,
+) -> io::Result<String> {
+    let hello = link.read_hello(2).await?;
+    let (peer_id, their_nonce) = (&hello[0], &hello[1]);
+    if *peer_id == config.server_id {
+        return Err(linked_to_self());
+    }
+
+    let our_nonce = generate_nonce();
+    let transcript = proof_transcript(ROLE_ACCEPT, &config.server_id, their_nonce, &our_nonce);
+    let proof = sign_proof(&config.secret, &transcript);
+    link.send_hello(&[&config.server_id, &our_nonce, &proof])
+        .await?;
+
+    let reply = link.read_hello(1).await?;
+    let transcript = proof_transcript(ROLE_CONNECT, peer_id, &our_nonce, their_nonce);
+    if !verify_proof(&config.secret, &transcript, &reply[0]) {
+        return Err(wrong_secret());
+    }
+    Ok(peer_id.clone())
+}
+
+/// Start pushing local events to each configured peer and, if a listen
+/// address is set, accepting events from peers into `tx`. Users announced by
+/// peers are tracked in `remote_users`; `connected_clients` are our own
+pub async fn spawn(
+    config: FederationConfig,
+    tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
+    connected_clients: Arc<RwLock<HashSet<String>>>,
+    remote_users: Arc<RwLock<RemoteUsers>>,
+) -> io::Result<()> {
+    if let Some(listen_addr) = &config.listen_addr {
+        let listener = TcpListener::bind(listen_addr).await?;
+        logger::log_info(&format!("Accepting peer server links on {}", listen_addr));
+        tokio::spawn(accept_peers(
+            listener,
+            config.clone(),
+            tx.clone(),
+            connected_clients.clone(),
+            remote_users,
+        ));
+    }
+    for peer in &config.peers {
+        logger::log_info(&format!("Relaying chat to peer server {}", peer));
+        tokio::spawn(push_to_peer(
+            peer.clone(),
+            config.clone(),
+            tx.clone(),
+            connected_clients.clone(),
+        ));
+    }
+    Ok(())
+}
+
+/// Keep an outbound link to `peer` open, reconnecting after failures
+async fn push_to_peer(
+    peer: String,
+    config: FederationConfig,
+    tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
+    connected_clients: Arc<RwLock<HashSet<String>>>,
+) {
+    loop {
+        match run_outbound_link(&peer, &config, &tx, &connected_clients).await {
+            Ok(()) => logger::log_warning(&format!("Peer link to {} closed", peer)),
+            Err(e) => logger::log_warning(&format!("Peer link to {} failed: {}", peer, e)),
+        }
+        tokio::time::sleep(PEER_RETRY_DELAY).await;
+    }
+}
+
+async fn run_outbound_link(
+    peer: &str,
+    config: &FederationConfig,
+    tx: &broadcast::Sender<(ChatMessage, SocketAddr)>,
+    connected_clients: &RwLock<HashSet<String>>,
+) -> io::Result<()> {
+    let stream = timeout(PEER_HANDSHAKE_TIMEOUT, TcpStream::connect(peer))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+    let mut link = PeerConnection {
+        stream,
+        addr: peer.to_string(),
+    };
+
+    let peer_id = connect_handshake(&mut link, config).await?;
+    logger::log_success(&format!(
+        "Linked to peer server {} at {}",
+        peer_id, link.addr
+    ));
+
+    // Subscribe only once linked, so nothing stale is replayed, then announce
+    // everyone already here. A join racing the snapshot is sent twice, and
+    // the peer ignores the repeat
+    let mut rx = tx.subscribe();
+    let online: Vec<String> = connected_clients.read().await.iter().cloned().collect();
+    for username in online {
+        let join = ChatMessage::try_new(MessageTypes::Join, Some(username.into_bytes())).ok();
+        if let Some(relay) = join.and_then(|join| encode_relay(&config.server_id, join)) {
+            link.send_message_chunked(relay).await?;
+        }
+    }
+    loop {
+        let (message, addr) = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                logger::log_warning(&format!(
+                    "Peer link to {} fell behind, skipped {} messages",
+                    link.addr, skipped
+                ));
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        if !is_from_local_user(&addr) || !is_relayed_type(&message.msg_type) {
+            continue;
+        }
+        if let Some(relay) = encode_relay(&config.server_id, message) {
+            link.send_message_chunked(relay).await?;
+        }
+    }
+}
+
+async fn accept_peers(
+    listener: TcpListener,
+    config: FederationConfig,
+    tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
+    connected_clients: Arc<RwLock<HashSet<String>>>,
+    remote_users: Arc<RwLock<RemoteUsers>>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let link = PeerConnection {
+                    stream,
+                    addr: addr.to_string(),
+                };
+                let config = config.clone();
+                let tx = tx.clone();
+                let connected_clients = connected_clients.clone();
+                let remote_users = remote_users.clone();
+                tokio::spawn(async move {
+                    let peer = link.addr.clone();
+                    let result =
+                        run_inbound_link(link, &config, &tx, &connected_clients, &remote_users);
+                    if let Err(e) = result.await {
+                        logger::log_warning(&format!("Peer link from {} ended: {}", peer, e));
+                    }
+                });
+            }
+            Err(e) => logger::log_error(&format!("Failed to accept peer link: {:?}", e)),
+        }
+    }
+}
+
+async fn run_inbound_link(
+    mut link: PeerConnection,
+    config: &FederationConfig,
+    tx: &broadcast::Sender<(ChatMessage, SocketAddr)>,
+    connected_clients: &RwLock<HashSet<String>>,
+    remote_users: &RwLock<RemoteUsers>,
+) -> io::Result<()> {
+    let peer_id = accept_handshake(&mut link, config).await?;
+    logger::log_success(&format!(
+        "Peer server {} linked from {}",
+        peer_id, link.addr
+    ));
+
+    // A reconnecting peer announces its users again, so an older link's go
+    let (link_id, replaced) = remote_users.write().await.link(&peer_id);
+    announce_leaves(tx, replaced);
+
+    let result: io::Result<()> = async {
+        loop {
+            let frame = link.read_message_chunked().await.map_err(link_error)?;
+            if frame.msg_type != MessageTypes::PeerRelay {
+                continue;
+            }
+            let Some((origin, message)) = decode_relay(frame.content_bytes()) else {
+                logger::log_warning(&format!("Invalid relay from peer {}", link.addr));
+                continue;
+            };
+            // A link only carries its own server's users; anything else,
+            // such as our own messages coming back, is dropped
+            if origin != peer_id {
+                continue;
+            }
+            // Lock order matches joins: local users first
+            let local = connected_clients.read().await;
+            let verdict = admit_relay(
+                &mut *remote_users.write().await,
+                &local,
+                &peer_id,
+                link_id,
+                &message,
+            );
+            drop(local);
+            match verdict {
+                RelayVerdict::Relay => {
+                    let _ = tx.send((message, relayed_addr()));
+                }
+                RelayVerdict::Ignore => {}
+                RelayVerdict::NameInUse(username) => logger::log_warning(&format!(
+                    "Peer server {} announced '{}', which is already in use; ignoring them",
+                    peer_id, username
+                )),
+            }
+        }
+    }
+    .await;
+
+    let departed = remote_users.write().await.unlink(&peer_id, link_id);
+    announce_leaves(tx, departed);
+    result
+}
+
+/// Messages received from peers go out as if from the server itself, so
+/// they are never relayed again
+fn relayed_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+/// Tell local users that users on a linked server have gone
+fn announce_leaves(tx: &broadcast::Sender<(ChatMessage, SocketAddr)>, usernames: Vec<String>) {
+    for username in usernames {
+        if let Ok(leave) = ChatMessage::try_new(MessageTypes::Leave, Some(username.into_bytes())) {
+            let _ = tx.send((leave, relayed_addr()));
+        }
+    }
+}
+
+fn link_error(error: TcpMessageHandlerError) -> io::Error {
+    match error {
+        TcpMessageHandlerError::IoError(e) => e,
+        TcpMessageHandlerError::Disconnect => {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "peer disconnected")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_type: MessageTypes, content: &str) -> ChatMessage {
+        ChatMessage::try_new(msg_type, Some(content.as_bytes().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn test_relay_round_trip() {
+        let relay =
+            encode_relay("a1b2", message(MessageTypes::ChatMessage, "1|alice: hi")).unwrap();
+        assert_eq!(relay.msg_type, MessageTypes::PeerRelay);
+
+        let (origin, inner) = decode_relay(relay.content_bytes()).unwrap();
+        assert_eq!(origin, "a1b2");
+        assert_eq!(inner.msg_type, MessageTypes::ChatMessage);
+        assert_eq!(inner.content_as_string().as_deref(), Some("1|alice: hi"));
+    }
+
+    #[test]
+    fn test_relay_rejects_other_types() {
+        let relay = encode_relay("a1b2", message(MessageTypes::ServerNotice, "hello")).unwrap();
+        assert!(decode_relay(relay.content_bytes()).is_none());
+        assert!(decode_relay(&[10, b'a']).is_none());
+        assert!(decode_relay(&[]).is_none());
+    }
+
+    #[test]
+    fn test_only_local_user_events_relayed() {
+        let user: SocketAddr = "203.0.113.5:50000".parse().unwrap();
+        let server = SocketAddr::from(([0, 0, 0, 0], 0));
+        assert!(is_from_local_user(&user));
+        assert!(!is_from_local_user(&server));
+    }
+
+    fn config(server_id: &str, secret: &str) -> FederationConfig {
+        FederationConfig {
+            server_id: server_id.to_string(),
+            secret: secret.to_string(),
+            listen_addr: None,
+            peers: Vec::new(),
+        }
+    }
+
+    /// Both ends of a fresh loopback link
+    async fn link_pair() -> (PeerConnection, PeerConnection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (connecting, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let connecting = PeerConnection {
+            stream: connecting.unwrap(),
+            addr: addr.to_string(),
+        };
+        let (stream, from) = accepted.unwrap();
+        let accepting = PeerConnection {
+            stream,
+            addr: from.to_string(),
+        };
+        (connecting, accepting)
+    }
+
+    #[test]
+    fn test_proof_needs_secret_and_transcript() {
+        let transcript = proof_transcript(ROLE_ACCEPT, "a1b2", "n1", "n2");
+        let proof = sign_proof("s3cret", &transcript);
+        assert!(verify_proof("s3cret", &transcript, &proof));
+        assert!(!verify_proof("other", &transcript, &proof));
+
+        // A proof made for one role or nonce pair doesn't pass for another
+        let reflected = proof_transcript(ROLE_CONNECT, "a1b2", "n1", "n2");
+        assert!(!verify_proof("s3cret", &reflected, &proof));
+        let replayed = proof_transcript(ROLE_ACCEPT, "a1b2", "n1", "n3");
+        assert!(!verify_proof("s3cret", &replayed, &proof));
+
+        assert!(!verify_proof("s3cret", &transcript, "not hex"));
+        assert!(!verify_proof("s3cret", &transcript, ""));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_authenticates_both_ends() {
+        let (sender, receiver) = (config("sender", "s3cret"), config("receiver", "s3cret"));
+        let (mut connecting, mut accepting) = link_pair().await;
+        let (listener_id, connector_id) = tokio::join!(
+            connect_handshake(&mut connecting, &sender),
+            accept_handshake(&mut accepting, &receiver),
+        );
+        assert_eq!(listener_id.unwrap(), "receiver");
+        assert_eq!(connector_id.unwrap(), "sender");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_refuses_wrong_secret() {
+        let (sender, receiver) = (config("sender", "s3cret"), config("receiver", "s3cret"));
+        let guessing = config("receiver", "guess");
+
+        // The connecting side refuses a listener that can't prove the secret
+        let (mut connecting, mut accepting) = link_pair().await;
+        // (the refusing side drops the link, so the other isn't left waiting)
+        let connect = async move { connect_handshake(&mut connecting, &sender).await };
+        let (connected, _) = tokio::join!(connect, accept_handshake(&mut accepting, &guessing));
+        assert_eq!(
+            connected.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        // ...and the listener refuses a connecting side that can't either
+        let (mut connecting, mut accepting) = link_pair().await;
+        let forged = async {
+            connecting
+                .send_hello(&["sender", &generate_nonce()])
+                .await?;
+            connecting.read_hello(3).await?;
+            connecting
+                .send_hello(&[&sign_proof("guess", "anything")])
+                .await
+        };
+        let (_, accepted) = tokio::join!(forged, accept_handshake(&mut accepting, &receiver));
+        assert_eq!(
+            accepted.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_delivers_chat_to_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listen_addr = listener.local_addr().unwrap().to_string();
+        let receiving = FederationConfig {
+            server_id: "receiver".to_string(),
+            secret: "s3cret".to_string(),
+            listen_addr: Some(listen_addr.clone()),
+            peers: Vec::new(),
+        };
+        let (receiver_tx, mut receiver_rx) = broadcast::channel(16);
+        let remote_users = Arc::new(RwLock::new(RemoteUsers::default()));
+        tokio::spawn(accept_peers(
+            listener,
+            receiving,
+            receiver_tx,
+            Arc::new(RwLock::new(HashSet::new())),
+            remote_users.clone(),
+        ));
+
+        let sending = FederationConfig {
+            server_id: "sender".to_string(),
+            secret: "s3cret".to_string(),
+            listen_addr: None,
+            peers: vec![listen_addr.clone()],
+        };
+        let (sender_tx, _) = broadcast::channel(16);
+        let sender_clients = Arc::new(RwLock::new(HashSet::from(["bob".to_string()])));
+        let link_tx = sender_tx.clone();
+        tokio::spawn(async move {
+            run_outbound_link(&listen_addr, &sending, &link_tx, &sender_clients).await
+        });
+
+        // Users already online are announced as soon as the link is up...
+        let (snapshot, _) = timeout(Duration::from_secs(5), receiver_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.msg_type, MessageTypes::Join);
+        assert_eq!(snapshot.content_as_string().as_deref(), Some("bob"));
+
+        // ...and later events follow
+        let user: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        sender_tx
+            .send((message(MessageTypes::Join, "alice"), user))
+            .unwrap();
+        let relayed = timeout(Duration::from_secs(5), receiver_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(relayed.0.msg_type, MessageTypes::Join);
+        assert_eq!(relayed.0.content_as_string().as_deref(), Some("alice"));
+        assert!(!is_from_local_user(&relayed.1));
+        let remote_users = remote_users.read().await;
+        assert!(remote_users.contains("alice") && remote_users.contains("bob"));
+    }
+
+    #[test]
+    fn test_relays_checked_against_announced_users() {
+        let mut remote = RemoteUsers::default();
+        let local = HashSet::from(["alice".to_string()]);
+        let (link, _) = remote.link("b");
+        let (other_link, _) = remote.link("c");
+        let mut admit = |server: &str, link: u64, msg_type: MessageTypes, content: &str| {
+            admit_relay(
+                &mut remote,
+                &local,
+                server,
+                link,
+                &message(msg_type, content),
+            )
+        };
+
+        // Chat only passes from users the link announced
+        assert_eq!(
+            admit("b", link, MessageTypes::ChatMessage, "1|carol: hi"),
+            RelayVerdict::Ignore
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::Join, "carol"),
+            RelayVerdict::Relay
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::Join, "carol"),
+            RelayVerdict::Ignore
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::ChatMessage, "1|carol: hi"),
+            RelayVerdict::Relay
+        );
+
+        // Names in use here or on another linked server are refused
+        assert_eq!(
+            admit("b", link, MessageTypes::Join, "alice"),
+            RelayVerdict::NameInUse("alice".to_string())
+        );
+        assert_eq!(
+            admit("c", other_link, MessageTypes::Join, "carol"),
+            RelayVerdict::NameInUse("carol".to_string())
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::ChatMessage, "1|alice: hi"),
+            RelayVerdict::Ignore
+        );
+
+        // Renames and leaves move the announced names along
+        assert_eq!(
+            admit(
+                "b",
+                link,
+                MessageTypes::ChatMessage,
+                "carol is now known as cara"
+            ),
+            RelayVerdict::Relay
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::ChatMessage, "1|carol: hi"),
+            RelayVerdict::Ignore
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::Leave, "cara"),
+            RelayVerdict::Relay
+        );
+        assert_eq!(
+            admit("b", link, MessageTypes::Leave, "cara"),
+            RelayVerdict::Ignore
+        );
+    }
+
+    #[test]
+    fn test_newer_link_keeps_its_users() {
+        let mut remote = RemoteUsers::default();
+        let local = HashSet::new();
+        let (old_link, _) = remote.link("b");
+        admit_relay(
+            &mut remote,
+            &local,
+            "b",
+            old_link,
+            &message(MessageTypes::Join, "carol"),
+        );
+
+        // The peer reconnects: the old link's users go, the new link's stay
+        let (new_link, replaced) = remote.link("b");
+        assert_eq!(replaced, vec!["carol".to_string()]);
+        admit_relay(
+            &mut remote,
+            &local,
+            "b",
+            new_link,
+            &message(MessageTypes::Join, "dave"),
+        );
+        assert!(remote.unlink("b", old_link).is_empty());
+        assert!(remote.contains("dave"));
+
+        assert_eq!(remote.unlink("b", new_link), vec!["dave".to_string()]);
+        assert!(!remote.contains("dave"));
+    }
+
+    #[tokio::test]
+    async fn test_closed_link_drops_peer_users() {
+        let (sender, receiver) = (config("sender", "s3cret"), config("receiver", "s3cret"));
+        let (mut connecting, accepting) = link_pair().await;
+        let (tx, mut rx) = broadcast::channel(16);
+        let remote_users = Arc::new(RwLock::new(RemoteUsers::default()));
+        let inbound = tokio::spawn({
+            let remote_users = remote_users.clone();
+            let local = RwLock::new(HashSet::new());
+            async move { run_inbound_link(accepting, &receiver, &tx, &local, &remote_users).await }
+        });
+
+        connect_handshake(&mut connecting, &sender).await.unwrap();
+        let join = encode_relay("sender", message(MessageTypes::Join, "carol")).unwrap();
+        connecting.send_message_chunked(join).await.unwrap();
+        let (joined, _) = rx.recv().await.unwrap();
+        assert_eq!(joined.msg_type, MessageTypes::Join);
+        assert!(remote_users.read().await.contains("carol"));
+
+        // Once the link closes, its users are announced as gone
+        drop(connecting);
+        let (left, _) = timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(left.msg_type, MessageTypes::Leave);
+        assert_eq!(left.content_as_string().as_deref(), Some("carol"));
+        assert!(!remote_users.read().await.contains("carol"));
+        assert!(inbound.await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Operator control over which file types may be relayed between users
+
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct FileTypePolicy {
+    /// If set, only these extensions are relayed
+    allowed: Option<HashSet<String>>,
+    /// Extensions that are never relayed (checked before the allowlist)
+    denied: HashSet<String>,
+}
+
+impl FileTypePolicy {
+    /// Build the policy from comma-separated extension lists in the environment.
+    /// Neither variable set means every file type is allowed.
+    pub fn from_env(allowed_var: &str, denied_var: &str) -> Self {
+        let allowed = std::env::var(allowed_var)
+            .ok()
+            .map(|list| parse_extensions(&list))
+            .filter(|set| !set.is_empty());
+        let denied = std::env::var(denied_var)
+            .map(|list| parse_extensions(&list))
+            .unwrap_or_default();
+        FileTypePolicy { allowed, denied }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.allowed.is_some() || !self.denied.is_empty()
+    }
+
+    /// Check a declared filename against the policy (case-insensitive on the extension)
+    pub fn allows(&self, filename: &str) -> bool {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        if let Some(ext) = &extension
+            && self.denied.contains(ext)
+        {
+            return false;
+        }
+        match (&self.allowed, &extension) {
+            (None, _) => true,
+            (Some(allowed), Some(ext)) => allowed.contains(ext),
+            // An allowlist only admits files that declare an extension
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Human-readable summary for startup logging
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(allowed) = &self.allowed {
+            parts.push(format!("allowed: {}", sorted_list(allowed)));
+        }
+        if !self.denied.is_empty() {
+            parts.push(format!("denied: {}", sorted_list(&self.denied)));
+        }
+        parts.join("; ")
+    }
+}
+
+/// Parse "exe, .BAT,sh" into {"exe", "bat", "sh"}
+fn parse_extensions(list: &str) -> HashSet<String> {
+    list.split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+fn sorted_list(set: &HashSet<String>) -> String {
+    let mut items: Vec<&str> = set.iter().map(String::as_str).collect();
+    items.sort();
+    items.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allowed: Option<&str>, denied: &str) -> FileTypePolicy {
+        FileTypePolicy {
+            allowed: allowed.map(parse_extensions),
+            denied: parse_extensions(denied),
+        }
+    }
+
+    #[test]
+    fn test_no_lists_allows_everything() {
+        let policy = FileTypePolicy::default();
+        assert!(!policy.is_restricted());
+        assert!(policy.allows("setup.exe"));
+        assert!(policy.allows("README"));
+    }
+
+    #[test]
+    fn test_denylist_is_case_insensitive() {
+        let policy = policy(None, "exe, .BAT");
+        assert!(!policy.allows("setup.EXE"));
+        assert!(!policy.allows("run.bat"));
+        assert!(policy.allows("notes.txt"));
+        assert!(policy.allows("README"));
+    }
+
+    #[test]
+    fn test_allowlist_and_deny_precedence() {
+        let policy = policy(Some("png,JPG,pdf"), "pdf");
+        assert!(policy.allows("photo.jpg"));
+        assert!(policy.allows("image.PNG"));
+        assert!(!policy.allows("doc.pdf"));
+        assert!(!policy.allows("archive.tar.gz"));
+        assert!(!policy.allows("README"));
+        assert_eq!(policy.describe(), "allowed: jpg, pdf, png; denied: pdf");
+    }
+}
@@ -0,0 +1,344 @@
+//! Optional chat history in SQLite (`CHAT_SERVER_DB`), searchable with `/search`
+//!
+//! Chat messages, joins and leaves are queued on a bounded channel and written
+//! by a dedicated thread that owns the database connection, so a slow disk
+//! never blocks the chat path; when the queue is full, new events are dropped.
+//! Searches go through the same thread. DMs are never stored. Saving needs the
+//! server built with the `history` feature.
+
+use chrono::{TimeZone, Utc};
+use shared::logger;
+use shared::message::unix_millis_now;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+
+/// Events waiting to be written before new ones are dropped
+#[cfg_attr(not(feature = "history"), allow(dead_code))]
+pub const HISTORY_QUEUE_SIZE: usize = 1024;
+/// Most matches one `/search` returns (the most recent ones)
+pub const SEARCH_RESULT_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    Message,
+    Join,
+    Leave,
+}
+
+impl HistoryKind {
+    /// Value stored in the `kind` column
+    #[cfg_attr(not(feature = "history"), allow(dead_code))]
+    fn as_str(&self) -> &'static str {
+        match self {
+            HistoryKind::Message => "message",
+            HistoryKind::Join => "join",
+            HistoryKind::Leave => "leave",
+        }
+    }
+}
+
+/// A stored chat message that matched a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub timestamp_ms: u64,
+    pub username: String,
+    pub content: String,
+}
+
+impl Found {
+    /// One line of a search reply, e.g. `[2026-10-16 20:31 UTC] alice: hi`.
+    /// Continuation lines of multi-line messages are indented.
+    pub fn to_line(&self) -> String {
+        let at = i64::try_from(self.timestamp_ms)
+            .ok()
+            .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+            .map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| "unknown time".to_string());
+        format!(
+            "[{}] {}: {}",
+            at,
+            self.username,
+            self.content.replace('\n', "\n    ")
+        )
+    }
+}
+
+/// Text of the reply to `/search <term>`, oldest match first
+pub fn search_reply(term: &str, found: &[Found]) -> String {
+    if found.is_empty() {
+        return format!("No messages matching '{}'", term);
+    }
+    let mut reply = if found.len() >= SEARCH_RESULT_LIMIT {
+        format!("Last {} messages matching '{}':", found.len(), term)
+    } else {
+        format!("{} message(s) matching '{}':", found.len(), term)
+    };
+    for found in found {
+        reply.push_str("\n  ");
+        reply.push_str(&found.to_line());
+    }
+    reply
+}
+
+/// `term` as a SQL `LIKE` pattern matching it anywhere, with `\` escaping
+/// the wildcards so `%` and `_` match literally
+#[cfg_attr(not(feature = "history"), allow(dead_code))]
+fn like_pattern(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len() + 2);
+    pattern.push('%');
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Work for the thread that owns the database
+#[cfg_attr(not(feature = "history"), allow(dead_code))]
+enum Request {
+    Record {
+        timestamp_ms: u64,
+        kind: HistoryKind,
+        username: String,
+        content: String,
+    },
+    Search {
+        term: String,
+        reply: oneshot::Sender<Result<Vec<Found>, String>>,
+    },
+}
+
+/// Handle for recording and searching history; cheap to clone into each connection
+#[derive(Debug, Clone)]
+pub struct History {
+    requests: mpsc::Sender<Request>,
+    /// Set while events are being dropped, so a full queue warns once
+    dropping: Arc<AtomicBool>,
+}
+
+impl History {
+    /// Open (or create) the database at `path` and start the writer thread,
+    /// or log why not and return None
+    #[cfg(feature = "history")]
+    pub fn open(path: &Path) -> Option<Self> {
+        let store = match store::Store::open(path) {
+            Ok(store) => store,
+            Err(e) => {
+                logger::log_error(&format!(
+                    "Message history disabled: can't open {}: {}",
+                    path.display(),
+                    e
+                ));
+                return None;
+            }
+        };
+        let (requests, queue) = mpsc::channel(HISTORY_QUEUE_SIZE);
+        std::thread::spawn(move || store.serve(queue));
+        Some(History {
+            requests,
+            dropping: Arc::default(),
+        })
+    }
+
+    #[cfg(not(feature = "history"))]
+    pub fn open(_path: &Path) -> Option<Self> {
+        logger::log_warning(
+            "Message history needs the server built with the `history` feature; not saving messages",
+        );
+        None
+    }
+
+    /// Queue an event without waiting; dropped if the writer is falling behind
+    pub fn record(&self, kind: HistoryKind, username: &str, content: &str) {
+        let request = Request::Record {
+            timestamp_ms: unix_millis_now(),
+            kind,
+            username: username.to_string(),
+            content: content.to_string(),
+        };
+        match self.requests.try_send(request) {
+            Ok(()) => self.dropping.store(false, Ordering::Relaxed),
+            Err(TrySendError::Full(_)) => {
+                if !self.dropping.swap(true, Ordering::Relaxed) {
+                    logger::log_warning("History queue full, dropping events");
+                }
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// The most recent chat messages containing `term` (case-insensitive
+    /// for ASCII), oldest first
+    pub async fn search(&self, term: &str) -> Result<Vec<Found>, String> {
+        let (reply, found) = oneshot::channel();
+        let request = Request::Search {
+            term: term.to_string(),
+            reply,
+        };
+        if self.requests.send(request).await.is_err() {
+            return Err("Message history is unavailable".to_string());
+        }
+        found
+            .await
+            .unwrap_or_else(|_| Err("Message history is unavailable".to_string()))
+    }
+}
+
+#[cfg(feature = "history")]
+mod store {
+    use super::{Found, HistoryKind, Request, SEARCH_RESULT_LIMIT, like_pattern};
+    use rusqlite::{Connection, params};
+    use shared::logger;
+    use std::path::Path;
+    use tokio::sync::mpsc;
+
+    const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        timestamp_ms INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        username TEXT NOT NULL,
+        content TEXT NOT NULL
+    )";
+
+    pub struct Store {
+        conn: Connection,
+    }
+
+    impl Store {
+        pub fn open(path: &Path) -> rusqlite::Result<Self> {
+            let conn = Connection::open(path)?;
+            conn.execute_batch(SCHEMA)?;
+            Ok(Store { conn })
+        }
+
+        /// Handle requests until every `History` handle is dropped
+        pub fn serve(self, mut queue: mpsc::Receiver<Request>) {
+            while let Some(request) = queue.blocking_recv() {
+                match request {
+                    Request::Record {
+                        timestamp_ms,
+                        kind,
+                        username,
+                        content,
+                    } => {
+                        if let Err(e) = self.insert(timestamp_ms, kind, &username, &content) {
+                            logger::log_warning(&format!("Failed to save history: {}", e));
+                        }
+                    }
+                    Request::Search { term, reply } => {
+                        let found = self.search(&term).map_err(|e| {
+                            logger::log_warning(&format!("History search failed: {}", e));
+                            "Search failed".to_string()
+                        });
+                        let _ = reply.send(found);
+                    }
+                }
+            }
+        }
+
+        fn insert(
+            &self,
+            timestamp_ms: u64,
+            kind: HistoryKind,
+            username: &str,
+            content: &str,
+        ) -> rusqlite::Result<()> {
+            self.conn.execute(
+                "INSERT INTO history (timestamp_ms, kind, username, content) VALUES (?1, ?2, ?3, ?4)",
+                params![timestamp_ms as i64, kind.as_str(), username, content],
+            )?;
+            Ok(())
+        }
+
+        fn search(&self, term: &str) -> rusqlite::Result<Vec<Found>> {
+            let mut statement = self.conn.prepare_cached(
+                "SELECT timestamp_ms, username, content FROM history
+                 WHERE kind = 'message' AND content LIKE ?1 ESCAPE '\\'
+                 ORDER BY id DESC LIMIT ?2",
+            )?;
+            let rows = statement.query_map(
+                params![like_pattern(term), SEARCH_RESULT_LIMIT as i64],
+                |row| {
+                    Ok(Found {
+                        timestamp_ms: row.get::<_, i64>(0)?.max(0) as u64,
+                        username: row.get(1)?,
+                        content: row.get(2)?,
+                    })
+                },
+            )?;
+            let mut found = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+            found.reverse();
+            Ok(found)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_search_finds_recent_messages_only() {
+            let store = Store::open(Path::new(":memory:")).unwrap();
+            store.insert(1, HistoryKind::Join, "alice", "").unwrap();
+            store
+                .insert(2, HistoryKind::Message, "alice", "Deploy at 5")
+                .unwrap();
+            store
+                .insert(3, HistoryKind::Message, "bob", "no deploy today")
+                .unwrap();
+            store
+                .insert(4, HistoryKind::Message, "bob", "50% done")
+                .unwrap();
+
+            let found = store.search("deploy").unwrap();
+            let users: Vec<&str> = found.iter().map(|f| f.username.as_str()).collect();
+            assert_eq!(users, ["alice", "bob"]);
+            assert_eq!(store.search("0%").unwrap().len(), 1);
+            assert!(store.search("alice").unwrap().is_empty());
+
+            for n in 0..SEARCH_RESULT_LIMIT as u64 + 5 {
+                store
+                    .insert(10 + n, HistoryKind::Message, "carol", "spam")
+                    .unwrap();
+            }
+            let found = store.search("spam").unwrap();
+            assert_eq!(found.len(), SEARCH_RESULT_LIMIT);
+            assert_eq!(
+                found.last().unwrap().timestamp_ms,
+                10 + SEARCH_RESULT_LIMIT as u64 + 4
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like_pattern_escapes_wildcards() {
+        assert_eq!(like_pattern("deploy"), "%deploy%");
+        assert_eq!(like_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn test_search_reply() {
+        assert_eq!(search_reply("x", &[]), "No messages matching 'x'");
+
+        let found = Found {
+            timestamp_ms: 1_700_000_000_000,
+            username: "alice".to_string(),
+            content: "two\nlines".to_string(),
+        };
+        assert_eq!(
+            search_reply("two", &[found]),
+            "1 message(s) matching 'two':\n  [2023-11-14 22:13 UTC] alice: two\n    lines"
+        );
+    }
+}
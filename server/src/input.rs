@@ -1,18 +1,27 @@
 use shared::commands::server as commands;
 use shared::input::{UserInput, UserInputError};
 
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum ServerUserInput {
     Help,
     ListUsers,
+    ListFull,        // Users with their IPs and statuses (console only)
+    Export(PathBuf), // Write ListFull's data to a CSV or JSON file (console only)
     Kick(String),
+    Conns,                // Every open connection, joined or not
+    KillConn(SocketAddr), // Close a connection by address
     Rename { old_name: String, new_name: String },
     Ban(String),   // Ban by username (will resolve to IP)
     BanIp(IpAddr), // Ban by IP directly
     Unban(IpAddr), // Unban by IP
     BanList,       // List all banned IPs
+    SlowMode(u64), // Min seconds between each user's messages (0 disables)
+    Topic(Option<String>),
+    Reload,
+    Drain(Option<u64>), // Shut down once empty, or after this many seconds
     Quit,
 }
 
@@ -34,6 +43,16 @@ impl TryFrom<&str> for ServerUserInput {
             Ok(ServerUserInput::Quit)
         } else if commands::LIST.matches(cmd) {
             Ok(ServerUserInput::ListUsers)
+        } else if commands::LISTFULL.matches(cmd) {
+            Ok(ServerUserInput::ListFull)
+        } else if commands::EXPORT.matches(cmd) {
+            // The rest of the line, so file names may contain spaces
+            let path = trimmed[cmd.len()..].trim();
+            if path.is_empty() {
+                Err(UserInputError::InvalidCommand)
+            } else {
+                Ok(ServerUserInput::Export(PathBuf::from(path)))
+            }
         } else if commands::HELP.matches(cmd) {
             Ok(ServerUserInput::Help)
         } else if commands::KICK.matches(cmd) {
@@ -44,6 +63,16 @@ impl TryFrom<&str> for ServerUserInput {
             } else {
                 Ok(ServerUserInput::Kick(username.to_string()))
             }
+        } else if commands::CONNS.matches(cmd) {
+            Ok(ServerUserInput::Conns)
+        } else if commands::KILLCONN.matches(cmd) {
+            match parts[1..] {
+                [addr] => addr
+                    .parse::<SocketAddr>()
+                    .map(ServerUserInput::KillConn)
+                    .map_err(|_| UserInputError::InvalidCommand),
+                _ => Err(UserInputError::InvalidCommand),
+            }
         } else if commands::RENAME.matches(cmd) {
             if parts.len() != 3 {
                 Err(UserInputError::InvalidCommand)
@@ -73,6 +102,25 @@ impl TryFrom<&str> for ServerUserInput {
             }
         } else if commands::BANLIST.matches(cmd) {
             Ok(ServerUserInput::BanList)
+        } else if commands::SLOWMODE.matches(cmd) {
+            match parts.get(1).map(|s| s.parse::<u64>()) {
+                Some(Ok(seconds)) if parts.len() == 2 => Ok(ServerUserInput::SlowMode(seconds)),
+                _ => Err(UserInputError::InvalidCommand),
+            }
+        } else if commands::TOPIC.matches(cmd) {
+            let text = parts.get(1..).map(|p| p.join(" ")).unwrap_or_default();
+            Ok(ServerUserInput::Topic((!text.is_empty()).then_some(text)))
+        } else if commands::RELOAD.matches(cmd) {
+            Ok(ServerUserInput::Reload)
+        } else if commands::DRAIN.matches(cmd) {
+            match parts[1..] {
+                [] => Ok(ServerUserInput::Drain(None)),
+                [seconds] => match seconds.parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => Ok(ServerUserInput::Drain(Some(seconds))),
+                    _ => Err(UserInputError::InvalidCommand),
+                },
+                _ => Err(UserInputError::InvalidCommand),
+            }
         } else if trimmed.starts_with('/') {
             Err(UserInputError::InvalidCommand)
         } else {
@@ -167,4 +215,85 @@ mod tests {
         let input = ServerUserInput::try_from("/kick   ");
         assert!(input.is_err());
     }
+
+    #[test]
+    fn test_slowmode_command() {
+        let input = ServerUserInput::try_from("/slowmode 30");
+        assert!(matches!(input.unwrap(), ServerUserInput::SlowMode(30)));
+        let input = ServerUserInput::try_from("/slowmode 0");
+        assert!(matches!(input.unwrap(), ServerUserInput::SlowMode(0)));
+    }
+
+    #[test]
+    fn test_topic_command() {
+        let input = ServerUserInput::try_from("/topic Release  day");
+        assert!(
+            matches!(input, Ok(ServerUserInput::Topic(Some(ref text))) if text == "Release day")
+        );
+        let input = ServerUserInput::try_from("/topic");
+        assert!(matches!(input.unwrap(), ServerUserInput::Topic(None)));
+    }
+
+    #[test]
+    fn test_drain_command() {
+        let input = ServerUserInput::try_from("/drain");
+        assert!(matches!(input.unwrap(), ServerUserInput::Drain(None)));
+        let input = ServerUserInput::try_from("/drain 120");
+        assert!(matches!(input.unwrap(), ServerUserInput::Drain(Some(120))));
+        assert!(ServerUserInput::try_from("/drain 0").is_err());
+        assert!(ServerUserInput::try_from("/drain soon").is_err());
+    }
+
+    #[test]
+    fn test_export_command() {
+        let Ok(ServerUserInput::Export(path)) =
+            ServerUserInput::try_from("/export online users.json")
+        else {
+            panic!("expected an export command");
+        };
+        assert_eq!(path.to_str(), Some("online users.json"));
+        assert!(ServerUserInput::try_from("/export").is_err());
+        assert!(ServerUserInput::try_from("/export   ").is_err());
+    }
+
+    #[test]
+    fn test_listfull_command() {
+        let input = ServerUserInput::try_from("/listfull");
+        assert!(matches!(input.unwrap(), ServerUserInput::ListFull));
+        // /list stays the plain listing
+        let input = ServerUserInput::try_from("/list");
+        assert!(matches!(input.unwrap(), ServerUserInput::ListUsers));
+    }
+
+    #[test]
+    fn test_conns_and_killconn_commands() {
+        let input = ServerUserInput::try_from("/conns");
+        assert!(matches!(input.unwrap(), ServerUserInput::Conns));
+
+        let Ok(ServerUserInput::KillConn(addr)) =
+            ServerUserInput::try_from("/killconn 10.0.0.5:51234")
+        else {
+            panic!("expected a killconn command");
+        };
+        assert_eq!(addr, "10.0.0.5:51234".parse().unwrap());
+        let input = ServerUserInput::try_from("/killconn [::1]:4000");
+        assert!(matches!(input.unwrap(), ServerUserInput::KillConn(_)));
+
+        assert!(ServerUserInput::try_from("/killconn").is_err());
+        assert!(ServerUserInput::try_from("/killconn 10.0.0.5").is_err());
+        assert!(ServerUserInput::try_from("/killconn alice").is_err());
+    }
+
+    #[test]
+    fn test_reload_command() {
+        let input = ServerUserInput::try_from("/reload");
+        assert!(matches!(input.unwrap(), ServerUserInput::Reload));
+    }
+
+    #[test]
+    fn test_slowmode_command_invalid() {
+        assert!(ServerUserInput::try_from("/slowmode").is_err());
+        assert!(ServerUserInput::try_from("/slowmode -5").is_err());
+        assert!(ServerUserInput::try_from("/slowmode 5 10").is_err());
+    }
 }
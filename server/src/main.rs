@@ -1,26 +1,57 @@
-use rustls::ServerConfig;
-use rustls_pemfile::{certs, private_key};
 use shared::commands::server as commands;
+use shared::limits::{
+    MAX_FILE_TRANSFERS, MAX_SERVER_NAME_LENGTH, MAX_SHARED_FILE_SIZE, MAX_STATUS_LENGTH,
+    ServerLimits, strip_control_chars,
+};
 use shared::logger;
-use shared::message::ChatMessage;
+use shared::message::{ChatMessage, MessageTypes, READONLY_USER_MARKER, REMOTE_USER_MARKER};
+use shared::network;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::BufReader;
 use std::net::{IpAddr, SocketAddr};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, io};
-use tokio::net::TcpListener;
-use tokio::sync::{RwLock, broadcast};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, RwLock, broadcast};
 use tokio_rustls::TlsAcceptor;
 
+mod bans;
 mod completer;
+mod export;
+mod federation;
+mod file_policy;
+mod history;
 mod input;
+mod motd;
+mod rdns;
 mod readline_helper;
+mod tls;
+mod topic;
 mod user_connection;
+mod user_registry;
+mod webhook;
+use export::ConnectedUser;
+use federation::{FederationConfig, RemoteUsers};
+use file_policy::FileTypePolicy;
+use history::History;
 use input::ServerUserInput;
-use user_connection::{UserConnection, UserConnectionError};
+use motd::Motd;
+use rdns::{ReverseDns, describe_addr};
+use tls::load_tls_config;
+use user_connection::{
+    Admission, AwayReplies, BYTE_BUDGET_PER_MINUTE, BlockList, ConnectionPhase, ConnectionQueue,
+    DUPLICATE_WINDOW_MS, DirectRoute, FileCache, INBOUND_BUFFER_MESSAGES,
+    JOIN_RATE_LIMIT_PER_MINUTE, JOIN_RATE_LIMIT_WINDOW, JoinRateLimiter, OpenConnections,
+    PageLimiter, PollBoard, SharedState, TokenlessTakeovers, TransferTracker, UserConnection,
+    UserConnectionError, killed,
+};
+use user_registry::{USERDB_FLUSH_INTERVAL, UserRegistry};
+use webhook::{Webhook, WebhookUrl};
+
+/// How long `/drain` waits for users to leave before shutting down anyway
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(600);
 
 #[derive(Debug, Clone)]
 pub enum ServerCommand {
@@ -31,49 +62,317 @@ pub enum ServerCommand {
     SessionTakeover(String),
 }
 
+/// Operator settings read from the environment at startup
+pub struct ServerSettings {
+    max_clients: usize,
+    /// Queue clients at capacity instead of rejecting them
+    queue_enabled: bool,
+    /// Max file transfer bytes per second per connection (0 = unlimited)
+    file_rate_bytes_per_sec: u64,
+    /// Max bytes of other messages per connection per minute (0 = unlimited)
+    byte_budget: usize,
+    /// Max client messages read ahead of processing per connection
+    inbound_buffer: usize,
+    /// Addresses exempt from the message rate limit and byte budget
+    trusted_ips: HashSet<IpAddr>,
+    /// Let clients negotiate compressed frames
+    compression: bool,
+    motd: Option<Motd>,
+    /// Display name sent to each user after they join
+    server_name: Option<String>,
+    /// Greeting sent to each user after they join (`{username}` is substituted)
+    welcome_message: Option<String>,
+    /// Let any user change the topic (otherwise only the server console)
+    open_topic: bool,
+    /// Password that makes a user an operator with `/oper`
+    oper_password: Option<String>,
+    /// Don't broadcast join/leave announcements to clients
+    quiet_joins: bool,
+    limits: ServerLimits,
+    /// Max joins per IP per minute (0 = unlimited)
+    max_joins_per_minute: usize,
+    file_policy: FileTypePolicy,
+    /// Repeats of a user's previous message within this window are dropped
+    duplicate_window: Duration,
+    /// Grace before a tokenless rejoin from the holder's IP takes a name over
+    /// (None = such rejoins are renamed)
+    tokenless_takeover: Option<Duration>,
+    /// Relay of join/leave/message events to an external webhook
+    webhook: Option<Webhook>,
+    /// Chat history saved to SQLite, for `/search`
+    history: Option<History>,
+    /// Hostnames of connecting addresses for the logs
+    reverse_dns: Option<ReverseDns>,
+    /// Links to peer servers sharing this channel
+    federation: Option<FederationConfig>,
+    /// Last-seen times of known users (persisted with `CHAT_SERVER_USERDB`)
+    user_registry: UserRegistry,
+    /// Certificate and key re-read by `/reload`
+    tls_files: Option<TlsFiles>,
+    /// Ban list file loaded at startup and on `/reload`
+    ban_file: Option<PathBuf>,
+}
+
+/// Certificate and key paths, kept so `/reload` can pick up rotated certificates
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    cert_path: String,
+    key_path: String,
+    /// Per-hostname certificates selected by SNI (`TLS_SNI_DIR`)
+    sni_dir: Option<PathBuf>,
+}
+
 pub struct ChatServer {
     listener: TcpListener,
     broadcaster: broadcast::Sender<(ChatMessage, SocketAddr)>,
     server_commands: broadcast::Sender<ServerCommand>,
     connected_clients: Arc<RwLock<HashSet<String>>>,
+    /// Users online on linked servers
+    remote_users: Arc<RwLock<RemoteUsers>>,
     /// Maps username to their IP address
     user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
     /// Maps username to their status message
     user_statuses: Arc<RwLock<HashMap<String, String>>>,
     /// Maps username to their session token (for reconnection validation)
     user_sessions: Arc<RwLock<HashMap<String, String>>>,
+    /// Maps username to the secret issued on their last join, which a
+    /// reconnect must echo to reclaim the session
+    session_secrets: Arc<RwLock<HashMap<String, String>>>,
+    /// Maps username to their connection's point-to-point queues
+    user_channels: Arc<RwLock<HashMap<String, DirectRoute>>>,
+    /// Users that joined read-only (receive only)
+    readonly_users: Arc<RwLock<HashSet<String>>>,
+    /// Users granted operator rights this session
+    operators: Arc<RwLock<HashSet<String>>>,
+    /// Password that makes a user an operator with `/oper`
+    oper_password: Option<Arc<str>>,
     /// Set of banned IP addresses
     banned_ips: Arc<RwLock<HashSet<IpAddr>>>,
-    max_clients: usize,
-    active_connections: Arc<AtomicUsize>,
+    /// Active connection slots and the waiting line for clients at capacity
+    connections: Arc<ConnectionQueue>,
+    /// Every open connection by address, for `/conns` and `/killconn`
+    open_connections: Arc<Mutex<OpenConnections>>,
     tls_acceptor: Option<TlsAcceptor>,
+    /// Certificate and key re-read by `/reload`
+    tls_files: Option<TlsFiles>,
+    /// Ban list file kept in sync with `/ban` and `/unban`
+    ban_file: Option<PathBuf>,
+    /// Max file transfer bytes per second per connection (0 = unlimited)
+    file_rate_bytes_per_sec: u64,
+    /// Max bytes of other messages per connection per minute (0 = unlimited)
+    byte_budget: usize,
+    /// Max client messages read ahead of processing per connection
+    inbound_buffer: usize,
+    /// Addresses exempt from the message rate limit and byte budget
+    trusted_ips: Arc<HashSet<IpAddr>>,
+    /// Whether clients may negotiate compressed frames
+    compression: bool,
+    /// Message of the day sent after join
+    motd: Option<Arc<Motd>>,
+    /// Display name sent to each user after they join
+    server_name: Option<Arc<str>>,
+    /// Greeting sent to each user after they join
+    welcome_message: Option<Arc<str>>,
+    /// Current channel topic, sent to each joiner
+    topic: Arc<RwLock<Option<String>>>,
+    /// Whether any user may change the topic
+    open_topic: bool,
+    /// Whether join/leave announcements are withheld from clients
+    quiet_joins: bool,
+    /// Limits enforced on client input and advertised after join
+    limits: ServerLimits,
+    /// Per-IP join frequency limiter
+    join_limiter: Arc<Mutex<JoinRateLimiter>>,
+    /// In-flight file transfers per user
+    transfers: Arc<Mutex<TransferTracker>>,
+    /// Away auto-replies already sent, per sender and recipient
+    away_replies: Arc<Mutex<AwayReplies>>,
+    /// Pages sent recently, per sender and recipient
+    pages: Arc<Mutex<PageLimiter>>,
+    /// Open channel polls and their votes
+    polls: Arc<Mutex<PollBoard>>,
+    /// Files shared with the room, until fetched or expired
+    file_cache: Arc<Mutex<FileCache>>,
+    /// Users each user has blocked from DMing them
+    blocks: Arc<RwLock<BlockList>>,
+    /// Last-seen times of known users, for `/whois`
+    user_registry: Arc<Mutex<UserRegistry>>,
+    /// File extensions allowed or denied for relay
+    file_policy: Arc<FileTypePolicy>,
+    /// Min seconds between each user's chat messages (0 = slow mode off)
+    slowmode_secs: Arc<AtomicU64>,
+    /// Repeats of a user's previous message within this window are dropped
+    duplicate_window: Duration,
+    /// Grace before a tokenless rejoin from the holder's IP takes a name over
+    tokenless_takeover: Option<Duration>,
+    /// Tokenless rejoins waiting out that grace, one per name
+    tokenless_takeovers: Arc<Mutex<TokenlessTakeovers>>,
+    /// Relay of join/leave/message events to an external webhook
+    webhook: Option<Webhook>,
+    /// Chat history saved to SQLite, for `/search`
+    history: Option<History>,
+    /// Hostnames of connecting addresses, looked up in the background
+    reverse_dns: Option<Arc<ReverseDns>>,
+    /// When the server started, for uptime in `/serverinfo`
+    started_at: Instant,
+    /// Set by `/drain`: no new connections are accepted, and the server shuts
+    /// down once everyone has left or this deadline passes
+    drain_deadline: Option<Instant>,
 }
 
 impl ChatServer {
     async fn new(
         bind_addr: &str,
-        max_clients: usize,
         tls_acceptor: Option<TlsAcceptor>,
+        settings: ServerSettings,
     ) -> io::Result<Self> {
+        let max_clients = settings.max_clients;
         let (tx, _rx) = broadcast::channel(max_clients * 16); // Allow message buffering
         let (cmd_tx, _cmd_rx) = broadcast::channel(100); // Server commands channel
         let listener = TcpListener::bind(bind_addr).await?;
+        let connected_clients = Arc::new(RwLock::new(HashSet::new()));
+        let remote_users = Arc::new(RwLock::new(RemoteUsers::default()));
+        if let Some(config) = settings.federation {
+            federation::spawn(
+                config,
+                tx.clone(),
+                connected_clients.clone(),
+                remote_users.clone(),
+            )
+            .await?;
+        }
+        let banned_ips = match &settings.ban_file {
+            Some(path) => {
+                let banned = bans::load(path)?;
+                logger::log_info(&format!(
+                    "Loaded {} banned IPs from {}",
+                    banned.len(),
+                    path.display()
+                ));
+                banned
+            }
+            None => HashSet::new(),
+        };
+        let persist_users = settings.user_registry.is_persistent();
+        let user_registry = Arc::new(Mutex::new(settings.user_registry));
+        if persist_users {
+            let registry = user_registry.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(USERDB_FLUSH_INTERVAL);
+                interval.tick().await; // First tick fires immediately
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = registry.lock().await.flush() {
+                        logger::log_error(&format!("Failed to save user registry: {}", e));
+                    }
+                }
+            });
+        }
 
         Ok(ChatServer {
             listener,
             broadcaster: tx,
             server_commands: cmd_tx,
-            connected_clients: Arc::new(RwLock::new(HashSet::new())),
+            connected_clients,
+            remote_users,
             user_ips: Arc::new(RwLock::new(HashMap::new())),
             user_statuses: Arc::new(RwLock::new(HashMap::new())),
             user_sessions: Arc::new(RwLock::new(HashMap::new())),
-            banned_ips: Arc::new(RwLock::new(HashSet::new())),
-            max_clients,
-            active_connections: Arc::new(AtomicUsize::new(0)),
+            session_secrets: Arc::new(RwLock::new(HashMap::new())),
+            user_channels: Arc::new(RwLock::new(HashMap::new())),
+            readonly_users: Arc::new(RwLock::new(HashSet::new())),
+            operators: Arc::new(RwLock::new(HashSet::new())),
+            oper_password: settings.oper_password.map(Arc::from),
+            banned_ips: Arc::new(RwLock::new(banned_ips)),
+            connections: Arc::new(ConnectionQueue::new(max_clients, settings.queue_enabled)),
+            open_connections: Arc::new(Mutex::new(OpenConnections::default())),
             tls_acceptor,
+            tls_files: settings.tls_files,
+            ban_file: settings.ban_file,
+            file_rate_bytes_per_sec: settings.file_rate_bytes_per_sec,
+            byte_budget: settings.byte_budget,
+            inbound_buffer: settings.inbound_buffer,
+            trusted_ips: Arc::new(settings.trusted_ips),
+            compression: settings.compression,
+            motd: settings.motd.map(Arc::new),
+            server_name: settings.server_name.map(Arc::from),
+            welcome_message: settings.welcome_message.map(Arc::from),
+            topic: Arc::new(RwLock::new(None)),
+            open_topic: settings.open_topic,
+            quiet_joins: settings.quiet_joins,
+            limits: settings.limits,
+            join_limiter: Arc::new(Mutex::new(JoinRateLimiter::new(
+                settings.max_joins_per_minute,
+                JOIN_RATE_LIMIT_WINDOW,
+            ))),
+            transfers: Arc::new(Mutex::new(TransferTracker::default())),
+            away_replies: Arc::new(Mutex::new(AwayReplies::default())),
+            pages: Arc::new(Mutex::new(PageLimiter::default())),
+            polls: Arc::new(Mutex::new(PollBoard::default())),
+            file_cache: Arc::new(Mutex::new(FileCache::new(MAX_SHARED_FILE_SIZE))),
+            blocks: Arc::new(RwLock::new(BlockList::default())),
+            user_registry,
+            file_policy: Arc::new(settings.file_policy),
+            slowmode_secs: Arc::new(AtomicU64::new(0)),
+            duplicate_window: settings.duplicate_window,
+            tokenless_takeover: settings.tokenless_takeover,
+            tokenless_takeovers: Arc::new(Mutex::new(TokenlessTakeovers::default())),
+            webhook: settings.webhook,
+            history: settings.history,
+            reverse_dns: settings.reverse_dns.map(Arc::new),
+            started_at: Instant::now(),
+            drain_deadline: None,
         })
     }
 
+    /// Build the shared state handed to a new connection
+    fn connection_state(&self) -> SharedState {
+        SharedState {
+            tx: self.broadcaster.clone(),
+            server_commands: self.server_commands.clone(),
+            connected_clients: self.connected_clients.clone(),
+            remote_users: self.remote_users.clone(),
+            user_ips: self.user_ips.clone(),
+            user_statuses: self.user_statuses.clone(),
+            user_sessions: self.user_sessions.clone(),
+            session_secrets: self.session_secrets.clone(),
+            user_channels: self.user_channels.clone(),
+            readonly_users: self.readonly_users.clone(),
+            operators: self.operators.clone(),
+            oper_password: self.oper_password.clone(),
+            open_connections: self.open_connections.clone(),
+            file_rate_bytes_per_sec: self.file_rate_bytes_per_sec,
+            byte_budget: self.byte_budget,
+            inbound_buffer: self.inbound_buffer,
+            trusted_ips: self.trusted_ips.clone(),
+            compression: self.compression,
+            motd: self.motd.clone(),
+            server_name: self.server_name.clone(),
+            welcome_message: self.welcome_message.clone(),
+            topic: self.topic.clone(),
+            open_topic: self.open_topic,
+            quiet_joins: self.quiet_joins,
+            limits: self.limits,
+            join_limiter: self.join_limiter.clone(),
+            transfers: self.transfers.clone(),
+            away_replies: self.away_replies.clone(),
+            pages: self.pages.clone(),
+            polls: self.polls.clone(),
+            file_cache: self.file_cache.clone(),
+            blocks: self.blocks.clone(),
+            user_registry: self.user_registry.clone(),
+            file_policy: self.file_policy.clone(),
+            slowmode_secs: self.slowmode_secs.clone(),
+            duplicate_window: self.duplicate_window,
+            tokenless_takeover: self.tokenless_takeover,
+            tokenless_takeovers: self.tokenless_takeovers.clone(),
+            webhook: self.webhook.clone(),
+            history: self.history.clone(),
+            reverse_dns: self.reverse_dns.clone(),
+            started_at: self.started_at,
+        }
+    }
+
     async fn run(&mut self) -> io::Result<()> {
         // Spawn readline handler in a blocking thread (if TTY available)
         let mut readline_rx = readline_helper::spawn_readline_handler();
@@ -83,10 +382,12 @@ impl ChatServer {
             logger::log_info("Server commands disabled - use docker exec for admin tasks");
         }
 
+        let mut drain_check = tokio::time::interval(Duration::from_secs(1));
+
         loop {
             tokio::select! {
-                // Handle incoming client connections
-                result = self.listener.accept() => {
+                // Handle incoming client connections (until /drain)
+                result = self.listener.accept(), if self.drain_deadline.is_none() => {
                     match result {
                         Ok((socket, addr)) => {
                             // Check if IP is banned
@@ -101,63 +402,78 @@ impl ChatServer {
                             }
                             drop(banned);
 
-                            // Check connection limit
-                            let current_connections = self.active_connections.load(Ordering::Relaxed);
-                            if current_connections >= self.max_clients {
+                            // Only starts the lookup; the name shows up in later log lines
+                            if let Some(reverse_dns) = &self.reverse_dns {
+                                reverse_dns.prefetch(addr.ip());
+                            }
+
+                            // Check connection limit (queue or reject when full)
+                            let Some(admission) = self.connections.admit() else {
                                 logger::log_warning(&format!(
                                     "Connection limit reached ({}/{}), rejecting connection from {}",
-                                    current_connections, self.max_clients, addr
+                                    self.connections.active_connections(),
+                                    self.connections.max_clients(),
+                                    describe_addr(self.reverse_dns.as_deref(), addr)
                                 ));
+                                let state = self.connection_state();
+                                let tls_acceptor = self.tls_acceptor.clone();
+                                tokio::spawn(async move {
+                                    // Tell the client why instead of dropping it silently
+                                    if let Ok(mut client_connection) =
+                                        accept_connection(socket, addr, state, tls_acceptor).await
+                                    {
+                                        let _ = client_connection.reject_full().await;
+                                    }
+                                });
                                 continue;
-                            }
-
-                            // Increment connection count
-                            self.active_connections.fetch_add(1, Ordering::Relaxed);
+                            };
 
-                            let tx_clone = self.broadcaster.clone();
-                            let cmd_tx_clone = self.server_commands.clone();
-                            let active_connections_clone = self.active_connections.clone();
+                            let state = self.connection_state();
                             let tls_acceptor = self.tls_acceptor.clone();
-                            let connected_clients = self.connected_clients.clone();
-                            let user_ips = self.user_ips.clone();
-                            let user_statuses = self.user_statuses.clone();
-                            let user_sessions = self.user_sessions.clone();
+                            let reverse_dns = self.reverse_dns.clone();
+                            let open_connections = self.open_connections.clone();
+                            let mut kill = open_connections.lock().await.open(addr);
 
                             tokio::spawn(async move {
-                                // Wrap socket in TLS if configured
-                                let result = if let Some(acceptor) = tls_acceptor {
-                                    // Add timeout to TLS handshake to prevent hanging connections
-                                    match tokio::time::timeout(
-                                        std::time::Duration::from_secs(30),
-                                        acceptor.accept(socket)
-                                    ).await {
-                                        Ok(Ok(tls_stream)) => {
-                                            let mut client_connection =
-                                                UserConnection::new_tls(tls_stream, addr, tx_clone, cmd_tx_clone, connected_clients, user_ips, user_statuses, user_sessions);
-                                            client_connection.handle().await
-                                        }
-                                        Ok(Err(e)) => {
-                                            logger::log_error(&format!("TLS handshake failed for {}: {:?}", addr, e));
-                                            Err(UserConnectionError::IoError(io::Error::other("TLS handshake failed")))
-                                        }
-                                        Err(_) => {
-                                            logger::log_error(&format!("TLS handshake timed out for {}", addr));
-                                            Err(UserConnectionError::IoError(io::Error::other("TLS handshake timed out")))
+                                // Nothing is set up for the client before it has a slot,
+                                // so a kill until then just drops the connection
+                                let admitted = async {
+                                    let mut client_connection =
+                                        accept_connection(socket, addr, state, tls_acceptor).await?;
+                                    let slot = match admission {
+                                        Admission::Now(permit) => Some(permit),
+                                        Admission::Queued(ticket) => {
+                                            open_connections.lock().await.set_phase(addr, ConnectionPhase::Queued);
+                                            client_connection.wait_in_queue(ticket).await
                                         }
+                                    };
+                                    Ok(slot.map(|slot| (client_connection, slot)))
+                                };
+                                let admitted = tokio::select! {
+                                    admitted = admitted => admitted,
+                                    _ = killed(&mut kill) => {
+                                        logger::log_warning(&format!("Connection {} killed by server", addr));
+                                        Ok(None)
                                     }
-                                } else {
-                                    let mut client_connection =
-                                        UserConnection::new(socket, addr, tx_clone, cmd_tx_clone, connected_clients, user_ips, user_statuses, user_sessions);
-                                    client_connection.handle().await
                                 };
 
+                                let result = match admitted {
+                                    // The slot is held until the connection ends
+                                    Ok(Some((mut client_connection, _slot))) => {
+                                        open_connections.lock().await.set_phase(addr, ConnectionPhase::Joining);
+                                        client_connection.handle(kill).await
+                                    }
+                                    Ok(None) => Ok(()),
+                                    Err(e) => Err(e),
+                                };
+                                open_connections.lock().await.close(addr);
+
+                                let peer = describe_addr(reverse_dns.as_deref(), addr);
                                 if let Err(e) = result {
-                                    logger::log_error(&format!("Error handling client {}: {:?}", addr, e));
+                                    logger::log_error(&format!("Error handling client {}: {:?}", peer, e));
                                 }
 
-                                // Decrement connection count when done
-                                active_connections_clone.fetch_sub(1, Ordering::Relaxed);
-                                logger::log_info(&format!("Connection from {} closed", addr));
+                                logger::log_info(&format!("Connection from {} closed", peer));
                             });
                         }
                         Err(e) => {
@@ -182,9 +498,21 @@ impl ChatServer {
                                 Ok(ServerUserInput::ListUsers) => {
                                     self.handle_list_users().await;
                                 }
+                                Ok(ServerUserInput::ListFull) => {
+                                    self.handle_list_full().await;
+                                }
+                                Ok(ServerUserInput::Export(path)) => {
+                                    self.handle_export(&path).await;
+                                }
                                 Ok(ServerUserInput::Kick(username)) => {
                                     self.handle_kick(username).await;
                                 }
+                                Ok(ServerUserInput::Conns) => {
+                                    self.handle_conns().await;
+                                }
+                                Ok(ServerUserInput::KillConn(addr)) => {
+                                    self.handle_killconn(addr).await;
+                                }
                                 Ok(ServerUserInput::Rename { old_name, new_name }) => {
                                     self.handle_rename(old_name, new_name).await;
                                 }
@@ -200,6 +528,18 @@ impl ChatServer {
                                 Ok(ServerUserInput::BanList) => {
                                     self.handle_banlist().await;
                                 }
+                                Ok(ServerUserInput::SlowMode(seconds)) => {
+                                    self.handle_slowmode(seconds);
+                                }
+                                Ok(ServerUserInput::Topic(text)) => {
+                                    self.handle_topic(text).await;
+                                }
+                                Ok(ServerUserInput::Reload) => {
+                                    self.handle_reload().await;
+                                }
+                                Ok(ServerUserInput::Drain(seconds)) => {
+                                    self.handle_drain(seconds);
+                                }
                                 Ok(ServerUserInput::Help) => {
                                     self.handle_help();
                                 }
@@ -215,20 +555,170 @@ impl ChatServer {
                         }
                     }
                 }
+                // Shut down a draining server once it is empty or out of time
+                _ = drain_check.tick(), if self.drain_deadline.is_some() => {
+                    if self.drain_complete().await {
+                        return Ok(());
+                    }
+                }
             }
         }
     }
 
+    /// Mark everyone still connected as seen now and save the user registry
+    async fn save_user_registry(&self) {
+        let clients = self.connected_clients.read().await;
+        let mut registry = self.user_registry.lock().await;
+        let now = SystemTime::now();
+        for username in clients.iter() {
+            registry.record(username, now);
+        }
+        if let Err(e) = registry.flush() {
+            logger::log_error(&format!("Failed to save user registry: {}", e));
+        }
+    }
+
     async fn handle_list_users(&self) {
         let clients = self.connected_clients.read().await;
-        let count = clients.len();
+        let remote_users = self.remote_users.read().await;
+        let readonly_users = self.readonly_users.read().await;
+        let count = clients.len() + remote_users.names().count();
         if count == 0 {
             logger::log_info("No users currently connected.");
         } else {
             logger::log_info(&format!("Connected users ({}):", count));
             for user in clients.iter() {
-                logger::log_info(&format!("  - {}", user));
+                let marker = if readonly_users.contains(user) {
+                    READONLY_USER_MARKER
+                } else {
+                    ""
+                };
+                logger::log_info(&format!("  - {}{}", user, marker));
             }
+            for user in remote_users.names() {
+                logger::log_info(&format!("  - {}{}", user, REMOTE_USER_MARKER));
+            }
+        }
+    }
+
+    /// Connected users with their IPs and statuses, sorted by name
+    async fn connected_users(&self) -> Vec<ConnectedUser> {
+        let clients = self.connected_clients.read().await;
+        let user_ips = self.user_ips.read().await;
+        let statuses = self.user_statuses.read().await;
+        let readonly_users = self.readonly_users.read().await;
+        let mut users: Vec<ConnectedUser> = clients
+            .iter()
+            .map(|user| ConnectedUser {
+                username: user.clone(),
+                ip: user_ips.get(user).copied(),
+                status: statuses.get(user).cloned().unwrap_or_default(),
+                readonly: readonly_users.contains(user),
+            })
+            .collect();
+        users.sort();
+        users
+    }
+
+    async fn handle_list_full(&self) {
+        let users = self.connected_users().await;
+        if users.is_empty() {
+            logger::log_info("No users currently connected.");
+            return;
+        }
+        let rows: Vec<(String, String, String)> = users
+            .into_iter()
+            .map(|user| {
+                let marker = if user.readonly {
+                    READONLY_USER_MARKER
+                } else {
+                    ""
+                };
+                let ip = user.ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+                (format!("{}{}", user.username, marker), ip, user.status)
+            })
+            .collect();
+
+        let name_width = rows
+            .iter()
+            .map(|(name, _, _)| name.chars().count())
+            .fold("USER".len(), usize::max);
+        let ip_width = rows
+            .iter()
+            .map(|(_, ip, _)| ip.len())
+            .fold("IP".len(), usize::max);
+        logger::log_info(&format!("Connected users ({}):", rows.len()));
+        logger::log_info(&format!(
+            "  {:<name_width$}  {:<ip_width$}  STATUS",
+            "USER", "IP"
+        ));
+        for (name, ip, status) in rows {
+            let line = format!("  {:<name_width$}  {:<ip_width$}  {}", name, ip, status);
+            logger::log_info(line.trim_end());
+        }
+    }
+
+    async fn handle_conns(&self) {
+        let connections = self.open_connections.lock().await.list();
+        if connections.is_empty() {
+            logger::log_info("No open connections.");
+            return;
+        }
+        let rows: Vec<(String, String, String, String, String)> = connections
+            .into_iter()
+            .map(|conn| {
+                (
+                    describe_addr(self.reverse_dns.as_deref(), conn.addr),
+                    conn.username.unwrap_or_else(|| "-".to_string()),
+                    conn.phase.to_string(),
+                    format_span(conn.age),
+                    format_span(conn.idle),
+                )
+            })
+            .collect();
+
+        let addr_width = rows
+            .iter()
+            .map(|(addr, ..)| addr.len())
+            .fold("ADDRESS".len(), usize::max);
+        let name_width = rows
+            .iter()
+            .map(|(_, name, ..)| name.chars().count())
+            .fold("USER".len(), usize::max);
+        logger::log_info(&format!("Open connections ({}):", rows.len()));
+        logger::log_info(&format!(
+            "  {:<addr_width$}  {:<name_width$}  {:<9}  {:>5}  IDLE",
+            "ADDRESS", "USER", "STATE", "AGE"
+        ));
+        for (addr, name, phase, age, idle) in rows {
+            logger::log_info(&format!(
+                "  {:<addr_width$}  {:<name_width$}  {:<9}  {:>5}  {}",
+                addr, name, phase, age, idle
+            ));
+        }
+    }
+
+    async fn handle_killconn(&self, addr: SocketAddr) {
+        if self.open_connections.lock().await.kill(addr) {
+            logger::log_warning(&format!("Killing connection {}", addr));
+        } else {
+            logger::log_error(&format!("No connection from {}", addr));
+        }
+    }
+
+    async fn handle_export(&self, path: &Path) {
+        let users = self.connected_users().await;
+        match export::write(path, &users, SystemTime::now()) {
+            Ok(()) => logger::log_success(&format!(
+                "Exported {} connected user(s) to {}",
+                users.len(),
+                path.display()
+            )),
+            Err(e) => logger::log_error(&format!(
+                "Failed to export users to {}: {}",
+                path.display(),
+                e
+            )),
         }
     }
 
@@ -258,8 +748,8 @@ impl ChatServer {
             return;
         }
 
-        // Check if the new name is already taken
-        if clients.contains(&new_name) {
+        // Check if the new name is already taken, here or on a linked server
+        if clients.contains(&new_name) || self.remote_users.read().await.contains(&new_name) {
             logger::log_error(&format!("Username '{}' is already taken", new_name));
             return;
         }
@@ -310,6 +800,7 @@ impl ChatServer {
         // Add to banned IPs
         let mut banned = self.banned_ips.write().await;
         if banned.insert(ip) {
+            self.save_bans(&banned);
             drop(banned);
             logger::log_warning(&format!("Banned IP {} (user '{}')", ip, username));
 
@@ -325,6 +816,7 @@ impl ChatServer {
     async fn handle_ban_ip(&self, ip: IpAddr) {
         let mut banned = self.banned_ips.write().await;
         if banned.insert(ip) {
+            self.save_bans(&banned);
             drop(banned);
             logger::log_warning(&format!("Banned IP {}", ip));
 
@@ -340,12 +832,69 @@ impl ChatServer {
     async fn handle_unban(&self, ip: IpAddr) {
         let mut banned = self.banned_ips.write().await;
         if banned.remove(&ip) {
+            self.save_bans(&banned);
             logger::log_success(&format!("Unbanned IP {}", ip));
         } else {
             logger::log_error(&format!("IP {} is not banned", ip));
         }
     }
 
+    /// Keep the ban list file (if any) in sync with `banned`
+    fn save_bans(&self, banned: &HashSet<IpAddr>) {
+        if let Some(path) = &self.ban_file
+            && let Err(e) = bans::save(path, banned)
+        {
+            logger::log_error(&format!(
+                "Failed to save ban list {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Re-read the TLS certificate and ban list. New connections use the new
+    /// certificate; existing ones are unaffected.
+    async fn handle_reload(&mut self) {
+        match &self.tls_files {
+            Some(files) => {
+                match load_tls_config(&files.cert_path, &files.key_path, files.sni_dir.as_deref()) {
+                    Ok(config) => {
+                        self.tls_acceptor = Some(TlsAcceptor::from(Arc::new(config)));
+                        logger::log_success("TLS certificate reloaded");
+                    }
+                    Err(e) => logger::log_error(&format!(
+                        "Failed to reload TLS certificate, keeping the current one: {}",
+                        e
+                    )),
+                }
+            }
+            None => logger::log_info("TLS is not configured, no certificate to reload"),
+        }
+
+        let Some(path) = &self.ban_file else {
+            logger::log_info("No ban list file configured");
+            return;
+        };
+        match bans::load(path) {
+            Ok(reloaded) => {
+                let mut banned = self.banned_ips.write().await;
+                let added: Vec<IpAddr> = reloaded.difference(&banned).copied().collect();
+                *banned = reloaded;
+                logger::log_success(&format!("Ban list reloaded ({} banned IPs)", banned.len()));
+                drop(banned);
+
+                // Disconnect anyone connected from a newly banned address
+                for ip in added {
+                    let _ = self.server_commands.send(ServerCommand::Ban(ip));
+                }
+            }
+            Err(e) => logger::log_error(&format!(
+                "Failed to reload ban list, keeping the current one: {}",
+                e
+            )),
+        }
+    }
+
     async fn handle_banlist(&self) {
         let banned = self.banned_ips.read().await;
         if banned.is_empty() {
@@ -358,6 +907,106 @@ impl ChatServer {
         }
     }
 
+    fn handle_slowmode(&self, seconds: u64) {
+        let previous = self.slowmode_secs.swap(seconds, Ordering::Relaxed);
+        if previous == seconds {
+            logger::log_info(&format!("Slow mode is already set to {}s", seconds));
+            return;
+        }
+
+        let notice = if seconds == 0 {
+            "Slow mode disabled".to_string()
+        } else {
+            format!("Slow mode enabled: one message every {}s per user", seconds)
+        };
+        logger::log_success(&notice);
+        self.broadcast_notice(&notice);
+    }
+
+    async fn handle_topic(&self, text: Option<String>) {
+        let Some(text) = text else {
+            match self.topic.read().await.as_deref() {
+                Some(current) => logger::log_info(&format!("Topic: {}", current)),
+                None => logger::log_info("No topic is set."),
+            }
+            return;
+        };
+
+        let new_topic = match topic::sanitize(&text) {
+            Ok(new_topic) => new_topic,
+            Err(e) => {
+                logger::log_error(&e);
+                return;
+            }
+        };
+        *self.topic.write().await = Some(new_topic.clone());
+        logger::log_success(&format!("Topic set: {}", new_topic));
+
+        let Ok(message) = ChatMessage::try_new(MessageTypes::Topic, Some(new_topic.into_bytes()))
+        else {
+            return;
+        };
+        let server_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let _ = self.broadcaster.send((message, server_addr));
+    }
+
+    /// Stop accepting connections and shut down once empty or after the timeout
+    fn handle_drain(&mut self, seconds: Option<u64>) {
+        if self.drain_deadline.is_some() {
+            logger::log_info("Already draining - use /quit to shut down now");
+            return;
+        }
+        let timeout = seconds.map_or(DRAIN_TIMEOUT, Duration::from_secs);
+        self.drain_deadline = Some(Instant::now() + timeout);
+
+        logger::log_success(&format!(
+            "Draining: no new connections; shutting down when the last of {} connections closes or in {}s",
+            self.connections.active_connections(),
+            timeout.as_secs()
+        ));
+        self.broadcast_notice(&format!(
+            "Server is going down for maintenance: it will shut down in {}s, or sooner once everyone has left",
+            timeout.as_secs()
+        ));
+    }
+
+    /// Whether a draining server should shut down now. Users still connected at
+    /// the deadline are told before the server exits.
+    async fn drain_complete(&self) -> bool {
+        let Some(deadline) = self.drain_deadline else {
+            return false;
+        };
+        let remaining = self.connections.active_connections();
+        if remaining == 0 {
+            logger::log_info("All connections closed, server shutting down...");
+            return true;
+        }
+        if Instant::now() < deadline {
+            return false;
+        }
+
+        logger::log_info(&format!(
+            "Drain timeout reached with {} connections open, server shutting down...",
+            remaining
+        ));
+        self.broadcast_notice("Server is shutting down now");
+        // Give connection tasks a moment to deliver the notice
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        true
+    }
+
+    /// Broadcast an operator announcement to every connected client
+    fn broadcast_notice(&self, notice: &str) {
+        let Ok(message) =
+            ChatMessage::try_new(MessageTypes::ServerNotice, Some(notice.as_bytes().to_vec()))
+        else {
+            return;
+        };
+        // The source address only matters for client-originated messages
+        let server_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let _ = self.broadcaster.send((message, server_addr));
+    }
+
     fn handle_help(&self) {
         for line in commands::help_text() {
             logger::log_info(&line);
@@ -365,52 +1014,47 @@ impl ChatServer {
     }
 }
 
-fn load_tls_config(cert_path: &str, key_path: &str) -> io::Result<ServerConfig> {
-    let cert_file = File::open(cert_path).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("Certificate file not found: {}", e),
-        )
-    })?;
-    let key_file = File::open(key_path).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("Key file not found: {}", e),
-        )
-    })?;
-
-    let mut cert_reader = BufReader::new(cert_file);
-    let mut key_reader = BufReader::new(key_file);
-
-    let certs = certs(&mut cert_reader)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Invalid certificate: {}", e),
-            )
-        })?;
+/// Compact duration for `/conns`, e.g. "40s", "5m", "3h", "2d"
+fn format_span(span: Duration) -> String {
+    let secs = span.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3_600 {
+        format!("{}m", secs / 60)
+    } else if secs < 86_400 {
+        format!("{}h", secs / 3_600)
+    } else {
+        format!("{}d", secs / 86_400)
+    }
+}
 
-    let key = private_key(&mut key_reader)
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Invalid private key: {}", e),
-            )
-        })?
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No private key found"))?;
-
-    let config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("TLS config error: {}", e),
-            )
-        })?;
+/// Wrap an accepted socket in TLS if configured, with a handshake timeout
+async fn accept_connection(
+    socket: TcpStream,
+    addr: SocketAddr,
+    state: SharedState,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Result<UserConnection, UserConnectionError> {
+    let Some(acceptor) = tls_acceptor else {
+        return Ok(UserConnection::new(socket, addr, state));
+    };
 
-    Ok(config)
+    // Add timeout to TLS handshake to prevent hanging connections
+    match tokio::time::timeout(std::time::Duration::from_secs(30), acceptor.accept(socket)).await {
+        Ok(Ok(tls_stream)) => Ok(UserConnection::new_tls(tls_stream, addr, state)),
+        Ok(Err(e)) => {
+            logger::log_error(&format!("TLS handshake failed for {}: {:?}", addr, e));
+            Err(UserConnectionError::IoError(io::Error::other(
+                "TLS handshake failed",
+            )))
+        }
+        Err(_) => {
+            logger::log_error(&format!("TLS handshake timed out for {}", addr));
+            Err(UserConnectionError::IoError(io::Error::other(
+                "TLS handshake timed out",
+            )))
+        }
+    }
 }
 
 #[tokio::main]
@@ -419,15 +1063,154 @@ async fn main() -> io::Result<()> {
     const CHAT_SERVER_MAX_CLIENTS_ENV_VAR: &str = "CHAT_SERVER_MAX_CLIENTS";
     const TLS_CERT_PATH_ENV_VAR: &str = "TLS_CERT_PATH";
     const TLS_KEY_PATH_ENV_VAR: &str = "TLS_KEY_PATH";
+    const TLS_SNI_DIR_ENV_VAR: &str = "TLS_SNI_DIR";
+    const CHAT_SERVER_FILE_RATE_ENV_VAR: &str = "CHAT_SERVER_FILE_RATE_BYTES_PER_SEC";
+    const CHAT_SERVER_BYTE_BUDGET_ENV_VAR: &str = "CHAT_SERVER_BYTE_BUDGET_PER_MINUTE";
+    const CHAT_SERVER_INBOUND_BUFFER_ENV_VAR: &str = "CHAT_SERVER_INBOUND_BUFFER";
+    const CHAT_SERVER_TRUSTED_IPS_ENV_VAR: &str = "CHAT_SERVER_TRUSTED_IPS";
+    const CHAT_SERVER_COMPRESSION_ENV_VAR: &str = "CHAT_SERVER_COMPRESSION";
+    const CHAT_SERVER_MOTD_FILE_ENV_VAR: &str = "CHAT_SERVER_MOTD_FILE";
+    const CHAT_SERVER_MOTD_ENV_VAR: &str = "CHAT_SERVER_MOTD";
+    const CHAT_SERVER_NAME_ENV_VAR: &str = "CHAT_SERVER_NAME";
+    const CHAT_SERVER_WELCOME_MESSAGE_ENV_VAR: &str = "CHAT_SERVER_WELCOME_MESSAGE";
+    const CHAT_SERVER_OPEN_TOPIC_ENV_VAR: &str = "CHAT_SERVER_OPEN_TOPIC";
+    const CHAT_SERVER_OPER_PASSWORD_ENV_VAR: &str = "CHAT_SERVER_OPER_PASSWORD";
+    const CHAT_SERVER_QUIET_JOINS_ENV_VAR: &str = "CHAT_SERVER_QUIET_JOINS";
+    const CHAT_SERVER_MAX_STATUS_LENGTH_ENV_VAR: &str = "CHAT_SERVER_MAX_STATUS_LENGTH";
+    const CHAT_SERVER_MAX_FILE_TRANSFERS_ENV_VAR: &str = "CHAT_SERVER_MAX_FILE_TRANSFERS";
+    const CHAT_SERVER_MAX_JOINS_PER_MINUTE_ENV_VAR: &str = "CHAT_SERVER_MAX_JOINS_PER_MINUTE";
+    const CHAT_SERVER_ALLOWED_EXTENSIONS_ENV_VAR: &str = "CHAT_SERVER_ALLOWED_EXTENSIONS";
+    const CHAT_SERVER_DENIED_EXTENSIONS_ENV_VAR: &str = "CHAT_SERVER_DENIED_EXTENSIONS";
+    const CHAT_SERVER_QUEUE_ENV_VAR: &str = "CHAT_SERVER_QUEUE";
+    const CHAT_SERVER_DUPLICATE_WINDOW_MS_ENV_VAR: &str = "CHAT_SERVER_DUPLICATE_WINDOW_MS";
+    const CHAT_SERVER_TOKENLESS_TAKEOVER_ENV_VAR: &str = "CHAT_SERVER_TOKENLESS_TAKEOVER";
+    const CHAT_SERVER_WEBHOOK_URL_ENV_VAR: &str = "CHAT_SERVER_WEBHOOK_URL";
+    const CHAT_SERVER_WEBHOOK_CONTENT_ENV_VAR: &str = "CHAT_SERVER_WEBHOOK_CONTENT";
+    const CHAT_SERVER_PEERS_ENV_VAR: &str = "CHAT_SERVER_PEERS";
+    const CHAT_SERVER_PEER_ADDR_ENV_VAR: &str = "CHAT_SERVER_PEER_ADDR";
+    const CHAT_SERVER_PEER_SECRET_ENV_VAR: &str = "CHAT_SERVER_PEER_SECRET";
+    const CHAT_SERVER_USERDB_ENV_VAR: &str = "CHAT_SERVER_USERDB";
+    const CHAT_SERVER_BAN_FILE_ENV_VAR: &str = "CHAT_SERVER_BAN_FILE";
+    const CHAT_SERVER_RDNS_ENV_VAR: &str = "CHAT_SERVER_RDNS";
+    const CHAT_SERVER_DB_ENV_VAR: &str = "CHAT_SERVER_DB";
 
     let chat_server_addr = env::var(CHAT_SERVER_ADDR_ENV_VAR).unwrap_or("0.0.0.0:8080".to_string());
     let max_clients = env::var(CHAT_SERVER_MAX_CLIENTS_ENV_VAR)
         .unwrap_or("100".to_string())
         .parse::<usize>()
         .unwrap_or(100);
+    let queue_enabled = env::var(CHAT_SERVER_QUEUE_ENV_VAR)
+        .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"));
+    let open_topic = env::var(CHAT_SERVER_OPEN_TOPIC_ENV_VAR)
+        .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"));
+    let oper_password = env::var(CHAT_SERVER_OPER_PASSWORD_ENV_VAR)
+        .ok()
+        .filter(|password| !password.is_empty());
+    let quiet_joins = env::var(CHAT_SERVER_QUIET_JOINS_ENV_VAR)
+        .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"));
+    let file_rate_bytes_per_sec = env::var(CHAT_SERVER_FILE_RATE_ENV_VAR)
+        .unwrap_or("0".to_string())
+        .parse::<u64>()
+        .unwrap_or(0);
+    let byte_budget = env::var(CHAT_SERVER_BYTE_BUDGET_ENV_VAR)
+        .unwrap_or(BYTE_BUDGET_PER_MINUTE.to_string())
+        .parse::<usize>()
+        .unwrap_or(BYTE_BUDGET_PER_MINUTE);
+    let inbound_buffer = env::var(CHAT_SERVER_INBOUND_BUFFER_ENV_VAR)
+        .unwrap_or(INBOUND_BUFFER_MESSAGES.to_string())
+        .parse::<usize>()
+        .unwrap_or(INBOUND_BUFFER_MESSAGES)
+        .max(1);
+    let trusted_ips: HashSet<IpAddr> = env::var(CHAT_SERVER_TRUSTED_IPS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                logger::log_warning(&format!(
+                    "Ignoring invalid address in {}: {}",
+                    CHAT_SERVER_TRUSTED_IPS_ENV_VAR, entry
+                ));
+                None
+            }
+        })
+        .collect();
+    let trusted_count = trusted_ips.len();
+    // On by default: it only takes effect for clients that ask for it
+    let compression = !env::var(CHAT_SERVER_COMPRESSION_ENV_VAR)
+        .is_ok_and(|val| val == "0" || val.eq_ignore_ascii_case("false"));
+    let max_joins_per_minute = env::var(CHAT_SERVER_MAX_JOINS_PER_MINUTE_ENV_VAR)
+        .unwrap_or(JOIN_RATE_LIMIT_PER_MINUTE.to_string())
+        .parse::<usize>()
+        .unwrap_or(JOIN_RATE_LIMIT_PER_MINUTE);
+    let duplicate_window_ms = env::var(CHAT_SERVER_DUPLICATE_WINDOW_MS_ENV_VAR)
+        .unwrap_or(DUPLICATE_WINDOW_MS.to_string())
+        .parse::<u64>()
+        .unwrap_or(DUPLICATE_WINDOW_MS);
+    let tokenless_takeover = env::var(CHAT_SERVER_TOKENLESS_TAKEOVER_ENV_VAR)
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .map(Duration::from_secs);
+    let limits = ServerLimits {
+        max_status_length: env::var(CHAT_SERVER_MAX_STATUS_LENGTH_ENV_VAR)
+            .unwrap_or(MAX_STATUS_LENGTH.to_string())
+            .parse::<usize>()
+            .unwrap_or(MAX_STATUS_LENGTH),
+        max_file_transfers: env::var(CHAT_SERVER_MAX_FILE_TRANSFERS_ENV_VAR)
+            .unwrap_or(MAX_FILE_TRANSFERS.to_string())
+            .parse::<usize>()
+            .unwrap_or(MAX_FILE_TRANSFERS),
+        ..ServerLimits::default()
+    };
+    let file_policy = FileTypePolicy::from_env(
+        CHAT_SERVER_ALLOWED_EXTENSIONS_ENV_VAR,
+        CHAT_SERVER_DENIED_EXTENSIONS_ENV_VAR,
+    );
+    if file_policy.is_restricted() {
+        logger::log_info(&format!(
+            "File type restrictions: {}",
+            file_policy.describe()
+        ));
+    }
+    let motd = Motd::from_env(CHAT_SERVER_MOTD_FILE_ENV_VAR, CHAT_SERVER_MOTD_ENV_VAR);
+    match &motd {
+        Some(Motd::File(path)) => logger::log_info(&format!(
+            "MOTD will be read from {} (re-read on each join)",
+            path.display()
+        )),
+        Some(Motd::Text(_)) => logger::log_info("MOTD set from environment"),
+        None => {}
+    }
+    let server_name = env::var(CHAT_SERVER_NAME_ENV_VAR)
+        .ok()
+        .map(|name| strip_control_chars(&name).trim().to_string())
+        .filter(|name| !name.is_empty());
+    let server_name = match server_name {
+        Some(name) if name.len() > MAX_SERVER_NAME_LENGTH => {
+            logger::log_warning(&format!(
+                "Ignoring {}: longer than {} bytes",
+                CHAT_SERVER_NAME_ENV_VAR, MAX_SERVER_NAME_LENGTH
+            ));
+            None
+        }
+        Some(name) => {
+            logger::log_info(&format!("Server name: {}", name));
+            Some(name)
+        }
+        None => None,
+    };
+    let welcome_message = env::var(CHAT_SERVER_WELCOME_MESSAGE_ENV_VAR)
+        .ok()
+        .filter(|text| !text.is_empty())
+        .map(|text| text.replace("\\n", "\n"));
+    if welcome_message.is_some() {
+        logger::log_info("Welcome message will be sent to each user on join");
+    }
 
     // Check if TLS is configured
-    let tls_acceptor = match (
+    let (tls_acceptor, tls_files) = match (
         env::var(TLS_CERT_PATH_ENV_VAR),
         env::var(TLS_KEY_PATH_ENV_VAR),
     ) {
@@ -435,15 +1218,32 @@ async fn main() -> io::Result<()> {
             if Path::new(&cert_path).exists() && Path::new(&key_path).exists() =>
         {
             logger::log_info("TLS enabled - loading certificates...");
-            match load_tls_config(&cert_path, &key_path) {
+            let sni_dir = env::var(TLS_SNI_DIR_ENV_VAR)
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from);
+            match load_tls_config(&cert_path, &key_path, sni_dir.as_deref()) {
                 Ok(config) => {
                     logger::log_success("TLS certificates loaded successfully");
-                    Some(TlsAcceptor::from(Arc::new(config)))
+                    if let Some(dir) = &sni_dir {
+                        logger::log_info(&format!(
+                            "Selecting certificates by SNI hostname from {}",
+                            dir.display()
+                        ));
+                    }
+                    (
+                        Some(TlsAcceptor::from(Arc::new(config))),
+                        Some(TlsFiles {
+                            cert_path,
+                            key_path,
+                            sni_dir,
+                        }),
+                    )
                 }
                 Err(e) => {
                     logger::log_error(&format!("Failed to load TLS config: {}", e));
                     logger::log_warning("Starting server WITHOUT TLS encryption");
-                    None
+                    (None, None)
                 }
             }
         }
@@ -453,11 +1253,137 @@ async fn main() -> io::Result<()> {
                 "To enable TLS, set {} and {} environment variables",
                 TLS_CERT_PATH_ENV_VAR, TLS_KEY_PATH_ENV_VAR
             ));
-            None
+            (None, None)
         }
     };
 
-    let mut server = ChatServer::new(&chat_server_addr, max_clients, tls_acceptor).await?;
+    let webhook_content = env::var(CHAT_SERVER_WEBHOOK_CONTENT_ENV_VAR)
+        .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"));
+    let webhook = match env::var(CHAT_SERVER_WEBHOOK_URL_ENV_VAR) {
+        Ok(url) if !url.is_empty() => match WebhookUrl::parse(&url) {
+            Ok(parsed) => {
+                logger::log_info(&format!(
+                    "Relaying events to webhook {}{}",
+                    url,
+                    if webhook_content {
+                        " (with message content)"
+                    } else {
+                        ""
+                    }
+                ));
+                Some(Webhook::spawn(parsed, webhook_content))
+            }
+            Err(e) => {
+                logger::log_error(&format!(
+                    "Ignoring {}: {}",
+                    CHAT_SERVER_WEBHOOK_URL_ENV_VAR, e
+                ));
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let history = match env::var(CHAT_SERVER_DB_ENV_VAR) {
+        Ok(path) if !path.is_empty() => {
+            let history = History::open(Path::new(&path));
+            if history.is_some() {
+                logger::log_info(&format!("Saving chat history to {}", path));
+            }
+            history
+        }
+        _ => None,
+    };
+
+    let peers: Vec<String> = env::var(CHAT_SERVER_PEERS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(str::to_string)
+        .collect();
+    let peer_listen_addr = env::var(CHAT_SERVER_PEER_ADDR_ENV_VAR)
+        .ok()
+        .filter(|addr| !addr.is_empty());
+    let federation = if peers.is_empty() && peer_listen_addr.is_none() {
+        None
+    } else {
+        match env::var(CHAT_SERVER_PEER_SECRET_ENV_VAR) {
+            Ok(secret) if !secret.is_empty() => {
+                let server_id = federation::generate_server_id();
+                logger::log_info(&format!("Federation enabled (server id {})", server_id));
+                Some(FederationConfig {
+                    server_id,
+                    secret,
+                    listen_addr: peer_listen_addr,
+                    peers,
+                })
+            }
+            _ => {
+                logger::log_error(&format!(
+                    "Federation disabled: {} must be set to link servers",
+                    CHAT_SERVER_PEER_SECRET_ENV_VAR
+                ));
+                None
+            }
+        }
+    };
+
+    let user_registry = match env::var(CHAT_SERVER_USERDB_ENV_VAR) {
+        Ok(path) if !path.is_empty() => match UserRegistry::load(PathBuf::from(&path)) {
+            Ok(registry) => {
+                logger::log_info(&format!("Saving last-seen times of users to {}", path));
+                registry
+            }
+            Err(e) => {
+                logger::log_error(&format!("Failed to load user registry {}: {}", path, e));
+                UserRegistry::default()
+            }
+        },
+        _ => UserRegistry::default(),
+    };
+
+    let ban_file = env::var(CHAT_SERVER_BAN_FILE_ENV_VAR)
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+
+    let reverse_dns = if env::var(CHAT_SERVER_RDNS_ENV_VAR)
+        .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+    {
+        ReverseDns::new()
+    } else {
+        None
+    };
+
+    let settings = ServerSettings {
+        max_clients,
+        queue_enabled,
+        file_rate_bytes_per_sec,
+        byte_budget,
+        inbound_buffer,
+        trusted_ips,
+        compression,
+        motd,
+        server_name,
+        welcome_message,
+        open_topic,
+        oper_password,
+        quiet_joins,
+        limits,
+        max_joins_per_minute,
+        file_policy,
+        duplicate_window: Duration::from_millis(duplicate_window_ms),
+        tokenless_takeover,
+        webhook,
+        history,
+        reverse_dns,
+        federation,
+        user_registry,
+        tls_files,
+        ban_file,
+    };
+    let mut server = ChatServer::new(&chat_server_addr, tls_acceptor, settings).await?;
 
     logger::log_success(&format!("Chat Server started at {}", chat_server_addr));
     logger::log_info(&format!(
@@ -468,7 +1394,58 @@ async fn main() -> io::Result<()> {
         "To change max clients, set {} environment variable",
         CHAT_SERVER_MAX_CLIENTS_ENV_VAR
     ));
+    if queue_enabled {
+        logger::log_info("Clients arriving at capacity will wait in a queue");
+    }
+    if file_rate_bytes_per_sec > 0 {
+        logger::log_info(&format!(
+            "File-frequency cap: each connection averages at most {} file bytes/sec",
+            file_rate_bytes_per_sec
+        ));
+    }
+    if byte_budget > 0 {
+        logger::log_info(&format!(
+            "Clients sending over {} bytes of messages per minute are disconnected",
+            byte_budget
+        ));
+    }
+    if trusted_count > 0 {
+        logger::log_info(&format!(
+            "{} trusted addresses are exempt from rate limits",
+            trusted_count
+        ));
+    }
+    if !compression {
+        logger::log_info("Compression disabled; clients that offer it will be declined");
+    }
+    if max_joins_per_minute > 0 {
+        logger::log_info(&format!(
+            "Joins limited to {} per minute per IP",
+            max_joins_per_minute
+        ));
+    }
+    if server.oper_password.is_some() {
+        logger::log_info("Users who /oper with the operator password can /op, /deop and /kick");
+    }
+    if quiet_joins {
+        logger::log_info("Join/leave announcements are not sent to clients");
+    }
+    if server.reverse_dns.is_some() {
+        logger::log_info("Connection logs include reverse DNS names of client addresses");
+    }
+    if let Some(grace) = tokenless_takeover {
+        logger::log_warning(&format!(
+            "Rejoins without a session token take over a name held from the same IP after {}s (users sharing an IP can take each other's names)",
+            grace.as_secs()
+        ));
+    }
+    let chunk_size = network::configured_chunk_size();
+    if chunk_size != network::CHUNK_SIZE {
+        logger::log_info(&format!("Network chunk size: {} bytes", chunk_size));
+    }
     logger::log_info("Server commands: /help, /list, /quit");
 
-    server.run().await
+    let result = server.run().await;
+    server.save_user_registry().await;
+    result
 }
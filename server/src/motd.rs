@@ -0,0 +1,96 @@
+//! Message of the day sent to each client right after it joins
+
+use std::path::PathBuf;
+
+/// Max MOTD size sent to clients
+pub const MAX_MOTD_LENGTH: usize = 4096;
+
+#[derive(Debug)]
+pub enum Motd {
+    /// Fixed text taken from the environment at startup
+    Text(String),
+    /// File re-read on every join so edits apply without a restart
+    File(PathBuf),
+}
+
+impl Motd {
+    /// Build the MOTD source from the environment. A file takes precedence
+    /// over inline text; `\n` in inline text is treated as a line break.
+    pub fn from_env(file_var: &str, text_var: &str) -> Option<Self> {
+        match (std::env::var(file_var), std::env::var(text_var)) {
+            (Ok(path), _) if !path.is_empty() => Some(Motd::File(PathBuf::from(path))),
+            (_, Ok(text)) if !text.is_empty() => Some(Motd::Text(text.replace("\\n", "\n"))),
+            _ => None,
+        }
+    }
+
+    /// Current MOTD contents, or None if empty or the file can't be read
+    pub async fn load(&self) -> Option<String> {
+        match self {
+            Motd::Text(text) => normalize(text),
+            Motd::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(text) => normalize(&text),
+                Err(e) => {
+                    shared::logger::log_warning(&format!(
+                        "Failed to read MOTD file {}: {}",
+                        path.display(),
+                        e
+                    ));
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Trim surrounding blank space and cap the length at a char boundary
+fn normalize(text: &str) -> Option<String> {
+    let text = text.trim_end();
+    let text = text.trim_start_matches(['\n', '\r']);
+    if text.is_empty() {
+        return None;
+    }
+    let mut end = text.len().min(MAX_MOTD_LENGTH);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(text[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_text_motd() {
+        let motd = Motd::Text("\nWelcome!\nBe nice.\n\n".to_string());
+        assert_eq!(motd.load().await, Some("Welcome!\nBe nice.".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_empty_motd_is_skipped() {
+        assert_eq!(Motd::Text("  \n ".to_string()).load().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_file_motd_reread_on_load() {
+        let path = std::env::temp_dir().join(format!("motd_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "first").unwrap();
+        let motd = Motd::File(path.clone());
+        assert_eq!(motd.load().await, Some("first".to_string()));
+
+        std::fs::write(&path, "second").unwrap();
+        assert_eq!(motd.load().await, Some("second".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(motd.load().await, None);
+    }
+
+    #[test]
+    fn test_motd_truncated_at_char_boundary() {
+        let long = "é".repeat(MAX_MOTD_LENGTH);
+        let normalized = normalize(&long).unwrap();
+        assert!(normalized.len() <= MAX_MOTD_LENGTH);
+        assert!(normalized.chars().all(|c| c == 'é'));
+    }
+}
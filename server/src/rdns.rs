@@ -0,0 +1,189 @@
+//! Reverse-DNS names in connection logs (`CHAT_SERVER_RDNS=1`)
+//!
+//! The accept loop only starts a lookup. It runs in the background with a
+//! timeout and its answer, or its failure, is cached per IP. Log lines show
+//! the name once it is known and the bare address until then, so a slow or
+//! unreachable resolver never holds up a connection. Lookups need the server
+//! built with the `rdns` feature.
+
+use shared::logger;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Give up on a lookup after this long and keep logging the bare address
+pub const RDNS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Most addresses cached; the cache starts over once it is full
+pub const RDNS_CACHE_SIZE: usize = 4096;
+
+/// Names found so far, keyed by address
+#[derive(Debug, Default)]
+struct NameCache {
+    /// None while a lookup is running, or after it found nothing
+    names: HashMap<IpAddr, Option<String>>,
+}
+
+impl NameCache {
+    /// Whether a lookup of `ip` should start, marking it as running if so.
+    /// Addresses already looked up (or being looked up) aren't retried.
+    fn start(&mut self, ip: IpAddr) -> bool {
+        if self.names.contains_key(&ip) {
+            return false;
+        }
+        if self.names.len() >= RDNS_CACHE_SIZE {
+            self.names.clear();
+        }
+        self.names.insert(ip, None);
+        true
+    }
+
+    fn record(&mut self, ip: IpAddr, name: String) {
+        self.names.insert(ip, Some(name));
+    }
+
+    fn name(&self, ip: IpAddr) -> Option<&str> {
+        self.names.get(&ip)?.as_deref()
+    }
+}
+
+/// A PTR answer fit for a log line. Whoever owns an address picks its PTR
+/// record, so anything but a plain hostname is dropped.
+fn clean_name(answer: &str) -> Option<String> {
+    let name = answer.trim_end_matches('.');
+    let plain = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+    (!name.is_empty() && plain).then(|| name.to_string())
+}
+
+pub struct ReverseDns {
+    cache: Mutex<NameCache>,
+    #[cfg(feature = "rdns")]
+    resolver: hickory_resolver::TokioResolver,
+}
+
+impl ReverseDns {
+    /// A resolver using the system's DNS configuration, or None (with a
+    /// warning) if it can't be set up
+    #[cfg(feature = "rdns")]
+    pub fn new() -> Option<Self> {
+        match hickory_resolver::TokioResolver::builder_tokio() {
+            Ok(mut builder) => {
+                builder.options_mut().timeout = RDNS_TIMEOUT;
+                builder.options_mut().attempts = 1;
+                Some(ReverseDns {
+                    cache: Mutex::default(),
+                    resolver: builder.build(),
+                })
+            }
+            Err(e) => {
+                logger::log_warning(&format!(
+                    "Reverse DNS disabled: can't read the system resolver configuration ({})",
+                    e
+                ));
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "rdns"))]
+    pub fn new() -> Option<Self> {
+        logger::log_warning(
+            "Reverse DNS needs the server built with the `rdns` feature; logging addresses only",
+        );
+        None
+    }
+
+    /// `addr` followed by its name if one is known, e.g.
+    /// `203.0.113.7:55012 [host.example.com]`
+    pub fn describe(&self, addr: SocketAddr) -> String {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        match cache.name(addr.ip()) {
+            Some(name) => format!("{} [{}]", addr, name),
+            None => addr.to_string(),
+        }
+    }
+
+    /// Look `ip` up in the background unless it was looked up before
+    pub fn prefetch(self: &Arc<Self>, ip: IpAddr) {
+        let started = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .start(ip);
+        if !started {
+            return;
+        }
+        let rdns = Arc::clone(self);
+        tokio::spawn(async move {
+            let answer = tokio::time::timeout(RDNS_TIMEOUT, rdns.lookup(ip)).await;
+            if let Some(name) = answer.ok().flatten().as_deref().and_then(clean_name) {
+                logger::log_info(&format!("{} is {}", ip, name));
+                rdns.cache
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record(ip, name);
+            }
+        });
+    }
+
+    /// The first PTR name for `ip`, as the resolver returned it
+    #[cfg(feature = "rdns")]
+    async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let answer = self.resolver.reverse_lookup(ip).await.ok()?;
+        Some(answer.iter().next()?.to_string())
+    }
+
+    #[cfg(not(feature = "rdns"))]
+    async fn lookup(&self, _ip: IpAddr) -> Option<String> {
+        None
+    }
+}
+
+/// `addr` with its name when reverse DNS is on and the name is known
+pub fn describe_addr(rdns: Option<&ReverseDns>, addr: SocketAddr) -> String {
+    match rdns {
+        Some(rdns) => rdns.describe(addr),
+        None => addr.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_each_address_looked_up_once() {
+        let mut cache = NameCache::default();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(cache.start(ip));
+        assert!(!cache.start(ip));
+        assert_eq!(cache.name(ip), None);
+
+        cache.record(ip, "host.example.com".to_string());
+        assert_eq!(cache.name(ip), Some("host.example.com"));
+        assert!(!cache.start(ip));
+    }
+
+    #[test]
+    fn test_full_cache_starts_over() {
+        let mut cache = NameCache::default();
+        for n in 0..RDNS_CACHE_SIZE as u32 {
+            cache.start(IpAddr::from(n.to_be_bytes()));
+        }
+        assert!(cache.start("203.0.113.7".parse().unwrap()));
+        assert_eq!(cache.names.len(), 1);
+    }
+
+    #[test]
+    fn test_clean_name() {
+        assert_eq!(
+            clean_name("host-1.example.com."),
+            Some("host-1.example.com".to_string())
+        );
+        assert_eq!(clean_name("."), None);
+        assert_eq!(clean_name("evil\n[OK] admin joined"), None);
+        assert_eq!(clean_name("spaced name.example"), None);
+    }
+}
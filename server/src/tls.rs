@@ -0,0 +1,180 @@
+//! TLS configuration, including SNI-based certificate selection
+//!
+//! `TLS_CERT_PATH`/`TLS_KEY_PATH` give the default certificate. To host
+//! several chat domains on one port, `TLS_SNI_DIR` can point at a directory
+//! with one subdirectory per hostname, each holding `fullchain.pem` and
+//! `privkey.pem` (the layout of Let's Encrypt's `live` directory). Clients get
+//! the certificate for the hostname they asked for, or the default one.
+
+use rustls::ServerConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls_pemfile::{certs, private_key};
+use shared::logger;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Certificate chain file in each `TLS_SNI_DIR` host directory
+pub const SNI_CERT_FILE: &str = "fullchain.pem";
+/// Private key file in each `TLS_SNI_DIR` host directory
+pub const SNI_KEY_FILE: &str = "privkey.pem";
+
+/// Build the server config from the default certificate, plus per-hostname
+/// certificates from `sni_dir` if given
+pub fn load_tls_config(
+    cert_path: &str,
+    key_path: &str,
+    sni_dir: Option<&Path>,
+) -> io::Result<ServerConfig> {
+    let certs = read_certs(Path::new(cert_path))?;
+    let key = read_key(Path::new(key_path))?;
+
+    let builder = ServerConfig::builder().with_no_client_auth();
+    let Some(sni_dir) = sni_dir else {
+        return builder.with_single_cert(certs, key).map_err(config_error);
+    };
+
+    let provider = builder.crypto_provider().clone();
+    let resolver = SniResolver {
+        default: Arc::new(CertifiedKey::from_der(certs, key, &provider).map_err(config_error)?),
+        hosts: load_host_certs(sni_dir, &provider)?,
+    };
+    Ok(builder.with_cert_resolver(Arc::new(resolver)))
+}
+
+/// Load the certificate in each host directory under `dir`, keyed by
+/// lowercase hostname. Hosts whose files are missing or invalid are skipped
+/// with a warning so one bad certificate doesn't take down the rest.
+fn load_host_certs(
+    dir: &Path,
+    provider: &CryptoProvider,
+) -> io::Result<HashMap<String, Arc<CertifiedKey>>> {
+    let mut hosts = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let Some(host) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        let loaded = read_certs(&path.join(SNI_CERT_FILE)).and_then(|certs| {
+            let key = read_key(&path.join(SNI_KEY_FILE))?;
+            CertifiedKey::from_der(certs, key, provider).map_err(config_error)
+        });
+        match loaded {
+            Ok(cert) => {
+                logger::log_info(&format!("TLS certificate loaded for {}", host));
+                hosts.insert(host.to_ascii_lowercase(), Arc::new(cert));
+            }
+            Err(e) => logger::log_warning(&format!("Skipping TLS certificate for {}: {}", host, e)),
+        }
+    }
+    Ok(hosts)
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let cert_file = File::open(path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Certificate file not found: {}", e),
+        )
+    })?;
+    certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid certificate: {}", e),
+            )
+        })
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let key_file = File::open(path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Key file not found: {}", e),
+        )
+    })?;
+    private_key(&mut BufReader::new(key_file))
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid private key: {}", e),
+            )
+        })?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No private key found"))
+}
+
+fn config_error(e: rustls::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("TLS config error: {}", e),
+    )
+}
+
+/// Picks a certificate by the hostname the client sent in its TLS hello
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name();
+        match lookup_host(&self.hosts, server_name) {
+            Some(cert) => {
+                logger::log_debug(&format!(
+                    "TLS handshake for {}: using its certificate",
+                    server_name.unwrap_or_default()
+                ));
+                Some(cert.clone())
+            }
+            None => {
+                logger::log_debug(&format!(
+                    "TLS handshake for {}: using the default certificate",
+                    server_name.unwrap_or("(no SNI)")
+                ));
+                Some(self.default.clone())
+            }
+        }
+    }
+}
+
+/// The entry for `server_name`, ignoring case (hostnames are case-insensitive)
+fn lookup_host<'a, T>(hosts: &'a HashMap<String, T>, server_name: Option<&str>) -> Option<&'a T> {
+    hosts.get(&server_name?.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_host_ignores_case() {
+        let hosts = HashMap::from([("chat.example.com".to_string(), 1)]);
+        assert_eq!(lookup_host(&hosts, Some("Chat.Example.COM")), Some(&1));
+        assert_eq!(lookup_host(&hosts, Some("other.example.com")), None);
+        assert_eq!(lookup_host(&hosts, None), None);
+    }
+
+    #[test]
+    fn test_host_dirs_without_valid_certs_skipped() {
+        let dir = std::env::temp_dir().join(format!("sni_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("empty.example.com")).unwrap();
+        fs::write(dir.join("README"), "not a host directory").unwrap();
+
+        let provider = ServerConfig::builder().crypto_provider().clone();
+        let hosts = load_host_certs(&dir, &provider).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert!(hosts.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+//! Channel topic: persistent channel state shown to every client and sent to
+//! each new joiner
+
+use shared::limits::strip_control_chars;
+
+/// Max topic size in bytes (after control characters are stripped)
+pub const MAX_TOPIC_LENGTH: usize = 256;
+
+/// Strip control characters and surrounding whitespace from a requested
+/// topic, or explain why it can't be used
+pub fn sanitize(text: &str) -> Result<String, String> {
+    let topic = strip_control_chars(text);
+    let topic = topic.trim();
+    if topic.is_empty() {
+        return Err("Topic cannot be empty".to_string());
+    }
+    if topic.len() > MAX_TOPIC_LENGTH {
+        return Err(format!(
+            "Topic too long ({} bytes, max {})",
+            topic.len(),
+            MAX_TOPIC_LENGTH
+        ));
+    }
+    Ok(topic.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_characters_stripped() {
+        assert_eq!(
+            sanitize("  Release\u{7}\n day\t!\u{1b}[31m "),
+            Ok("Release day![31m".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_topics_rejected() {
+        assert!(sanitize(" \n\t").is_err());
+        assert!(sanitize(&"a".repeat(MAX_TOPIC_LENGTH + 1)).is_err());
+        assert!(sanitize(&"a".repeat(MAX_TOPIC_LENGTH)).is_ok());
+    }
+}
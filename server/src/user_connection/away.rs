@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long before a sender gets another away auto-reply from the same user
+pub const AWAY_REPLY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Auto-replies sent for DMs to away users, keyed by (sender, recipient).
+//
+// A sender firing off several DMs at an away user gets one "alice is away"
+// reply per interval instead of one per message.
+#[derive(Debug, Default)]
+pub struct AwayReplies {
+    last_sent: HashMap<(String, String), Instant>,
+}
+
+impl AwayReplies {
+    /// Whether `sender` should be told `recipient` is away, recording the
+    /// reply if so
+    pub fn should_reply(&mut self, sender: &str, recipient: &str, now: Instant) -> bool {
+        self.last_sent
+            .retain(|_, sent_at| now.duration_since(*sent_at) < AWAY_REPLY_INTERVAL);
+        let key = (sender.to_string(), recipient.to_string());
+        if self.last_sent.contains_key(&key) {
+            return false;
+        }
+        self.last_sent.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_debounced_per_pair() {
+        let mut replies = AwayReplies::default();
+        let start = Instant::now();
+
+        assert!(replies.should_reply("bob", "alice", start));
+        assert!(!replies.should_reply("bob", "alice", start + Duration::from_secs(60)));
+        assert!(replies.should_reply("carol", "alice", start));
+        assert!(replies.should_reply("bob", "dave", start));
+        assert!(replies.should_reply("bob", "alice", start + AWAY_REPLY_INTERVAL));
+    }
+}
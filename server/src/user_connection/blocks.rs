@@ -0,0 +1,101 @@
+use std::collections::{HashMap, HashSet};
+
+/// Most users one user can block
+pub const MAX_BLOCKED_USERS: usize = 100;
+
+// Server-enforced blocks, keyed by the user who set them.
+//
+// Unlike a client-side mute, a block stops the blocked user's DMs from being
+// delivered at all. Blocks are keyed by username so they survive reconnects,
+// and last until the server restarts.
+#[derive(Debug, Default)]
+pub struct BlockList {
+    blocked: HashMap<String, HashSet<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    /// Users can't block themselves
+    SelfBlock,
+    /// The blocker already has `MAX_BLOCKED_USERS` blocks
+    Full,
+}
+
+impl BlockList {
+    /// Block `target` for `owner`; Ok(false) if it was already blocked
+    pub fn block(&mut self, owner: &str, target: &str) -> Result<bool, BlockError> {
+        if owner == target {
+            return Err(BlockError::SelfBlock);
+        }
+        let blocked = self.blocked.entry(owner.to_string()).or_default();
+        if blocked.contains(target) {
+            return Ok(false);
+        }
+        if blocked.len() >= MAX_BLOCKED_USERS {
+            return Err(BlockError::Full);
+        }
+        Ok(blocked.insert(target.to_string()))
+    }
+
+    /// Remove `target` from `owner`'s blocks; false if it wasn't blocked
+    pub fn unblock(&mut self, owner: &str, target: &str) -> bool {
+        let Some(blocked) = self.blocked.get_mut(owner) else {
+            return false;
+        };
+        let removed = blocked.remove(target);
+        if blocked.is_empty() {
+            self.blocked.remove(owner);
+        }
+        removed
+    }
+
+    /// Whether `recipient` has blocked `sender`
+    pub fn is_blocked(&self, recipient: &str, sender: &str) -> bool {
+        self.blocked
+            .get(recipient)
+            .is_some_and(|blocked| blocked.contains(sender))
+    }
+
+    /// Users `owner` has blocked, sorted
+    pub fn list(&self, owner: &str) -> Vec<&str> {
+        let mut blocked: Vec<&str> = self
+            .blocked
+            .get(owner)
+            .map(|blocked| blocked.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        blocked.sort_unstable();
+        blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_and_unblock() {
+        let mut blocks = BlockList::default();
+
+        assert_eq!(blocks.block("alice", "mallory"), Ok(true));
+        assert_eq!(blocks.block("alice", "mallory"), Ok(false));
+        assert_eq!(blocks.block("alice", "alice"), Err(BlockError::SelfBlock));
+        assert!(blocks.is_blocked("alice", "mallory"));
+        // Blocks are one-way
+        assert!(!blocks.is_blocked("mallory", "alice"));
+        assert_eq!(blocks.list("alice"), vec!["mallory"]);
+
+        assert!(blocks.unblock("alice", "mallory"));
+        assert!(!blocks.unblock("alice", "mallory"));
+        assert!(!blocks.is_blocked("alice", "mallory"));
+        assert!(blocks.list("alice").is_empty());
+    }
+
+    #[test]
+    fn test_block_list_capped() {
+        let mut blocks = BlockList::default();
+        for i in 0..MAX_BLOCKED_USERS {
+            assert_eq!(blocks.block("alice", &format!("user{}", i)), Ok(true));
+        }
+        assert_eq!(blocks.block("alice", "one_more"), Err(BlockError::Full));
+    }
+}
@@ -1,27 +1,32 @@
-use shared::message::ChatMessage;
 use std::io;
-use std::net::SocketAddr;
-use tokio::sync::broadcast;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum UserConnectionError {
     IoError(io::Error),
-    BroadcastError(broadcast::error::SendError<(ChatMessage, SocketAddr)>),
     JoinError,
     InvalidMessage,
     ExplicitQuit,
     VersionMismatch,
+    JoinRateLimited,
+    ByteBudgetExceeded,
+    /// Join put off by a tokenless takeover; send it again after this long
+    JoinDeferred(Duration),
 }
 
 impl std::fmt::Display for UserConnectionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             UserConnectionError::IoError(e) => write!(f, "IO Error: {}", e),
-            UserConnectionError::BroadcastError(e) => write!(f, "Broadcast Error: {}", e),
             UserConnectionError::JoinError => write!(f, "Join Error: Username already taken"),
             UserConnectionError::InvalidMessage => write!(f, "Invalid Message Error"),
             UserConnectionError::ExplicitQuit => write!(f, "User explicitly quit"),
             UserConnectionError::VersionMismatch => write!(f, "Client/Server version mismatch"),
+            UserConnectionError::JoinRateLimited => write!(f, "Too many joins from this IP"),
+            UserConnectionError::ByteBudgetExceeded => write!(f, "Sent too much data"),
+            UserConnectionError::JoinDeferred(wait) => {
+                write!(f, "Join deferred for {:.1}s", wait.as_secs_f64())
+            }
         }
     }
 }
@@ -0,0 +1,251 @@
+//! Files shared with the whole room (`/share`), kept for `/get`
+//!
+//! The sender uploads a file once and everyone downloads it from here, so a
+//! big file isn't pushed through every connection at once. A file waits for
+//! the users who were online when it was shared, plus anyone who joins while
+//! it is cached. It is dropped once all of them have fetched it or after
+//! `FILE_CACHE_TTL`, whichever comes first.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a shared file stays downloadable
+pub const FILE_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+/// Most bytes of shared files held at once; sharing more is refused until some go
+pub const MAX_FILE_CACHE_BYTES: usize = 64 * 1024 * 1024;
+/// Most of the cache one sender's files may take, so nobody can fill it alone
+pub const MAX_FILE_CACHE_BYTES_PER_SENDER: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShareError {
+    /// Bigger than any one shared file may be
+    TooLarge { max: usize },
+    /// The sender's files already take their share of the cache
+    QuotaExceeded { quota: usize },
+    /// Not enough room left in the cache
+    Full,
+}
+
+/// A shared file, as handed out to one downloader
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    pub sender: String,
+    pub filename: String,
+    pub data: Arc<[u8]>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: SharedFile,
+    shared_at: Instant,
+    /// Users expected to fetch it who haven't yet
+    waiting: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub struct FileCache {
+    next_id: u64,
+    files: HashMap<u64, Entry>,
+    total_bytes: usize,
+    max_file_size: usize,
+}
+
+impl FileCache {
+    /// An empty cache accepting files of up to `max_file_size` bytes
+    pub fn new(max_file_size: usize) -> Self {
+        FileCache {
+            next_id: 0,
+            files: HashMap::new(),
+            total_bytes: 0,
+            max_file_size,
+        }
+    }
+
+    /// Keep a file from `sender` until `members` have fetched it, returning
+    /// the id they download it by
+    pub fn share(
+        &mut self,
+        sender: &str,
+        filename: &str,
+        data: &[u8],
+        members: HashSet<String>,
+        now: Instant,
+    ) -> Result<u64, ShareError> {
+        if data.len() > self.max_file_size {
+            return Err(ShareError::TooLarge {
+                max: self.max_file_size,
+            });
+        }
+        self.purge_expired(now);
+        let sender_bytes: usize = self
+            .files
+            .values()
+            .filter(|entry| entry.file.sender == sender)
+            .map(|entry| entry.file.data.len())
+            .sum();
+        if sender_bytes + data.len() > MAX_FILE_CACHE_BYTES_PER_SENDER {
+            return Err(ShareError::QuotaExceeded {
+                quota: MAX_FILE_CACHE_BYTES_PER_SENDER,
+            });
+        }
+        if self.total_bytes + data.len() > MAX_FILE_CACHE_BYTES {
+            return Err(ShareError::Full);
+        }
+
+        self.next_id += 1;
+        self.total_bytes += data.len();
+        let file = SharedFile {
+            sender: sender.to_string(),
+            filename: filename.to_string(),
+            data: Arc::from(data),
+        };
+        self.files.insert(
+            self.next_id,
+            Entry {
+                file,
+                shared_at: now,
+                waiting: members,
+            },
+        );
+        Ok(self.next_id)
+    }
+
+    /// Hand file `id` to `username`, dropping it once the last user it was
+    /// waiting for has it. None if it expired or never existed.
+    pub fn fetch(&mut self, id: u64, username: &str, now: Instant) -> Option<SharedFile> {
+        self.purge_expired(now);
+        let entry = self.files.get_mut(&id)?;
+        let file = entry.file.clone();
+        if entry.waiting.remove(username) && entry.waiting.is_empty() {
+            self.remove(id);
+        }
+        Some(file)
+    }
+
+    /// Give a user who just joined the chance to fetch every file still cached
+    pub fn member_joined(&mut self, username: &str) {
+        for entry in self.files.values_mut() {
+            if entry.file.sender != username {
+                entry.waiting.insert(username.to_string());
+            }
+        }
+    }
+
+    fn purge_expired(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .files
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.shared_at) >= FILE_CACHE_TTL)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            self.remove(id);
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(entry) = self.files.remove(&id) {
+            self.total_bytes -= entry.file.data.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dropped_once_everyone_fetched() {
+        let mut cache = FileCache::new(1024);
+        let now = Instant::now();
+        let id = cache
+            .share("alice", "a.txt", b"data", members(&["bob", "carol"]), now)
+            .unwrap();
+
+        let file = cache.fetch(id, "bob", now).unwrap();
+        assert_eq!(file.sender, "alice");
+        assert_eq!(&*file.data, b"data");
+        // Fetching again, or by someone it wasn't waiting for, keeps it
+        assert!(cache.fetch(id, "bob", now).is_some());
+        assert!(cache.fetch(id, "alice", now).is_some());
+
+        assert!(cache.fetch(id, "carol", now).is_some());
+        assert!(cache.fetch(id, "bob", now).is_none());
+        assert_eq!(cache.total_bytes, 0);
+    }
+
+    #[test]
+    fn test_late_joiner_can_fetch() {
+        let mut cache = FileCache::new(1024);
+        let now = Instant::now();
+        let id = cache
+            .share("alice", "a.txt", b"data", members(&["bob"]), now)
+            .unwrap();
+        cache.member_joined("dave");
+
+        assert!(cache.fetch(id, "bob", now).is_some());
+        assert!(cache.fetch(id, "dave", now).is_some());
+        assert!(cache.fetch(id, "dave", now).is_none());
+    }
+
+    #[test]
+    fn test_expires_after_ttl() {
+        let mut cache = FileCache::new(1024);
+        let now = Instant::now();
+        let id = cache
+            .share("alice", "a.txt", b"data", members(&["bob"]), now)
+            .unwrap();
+
+        assert!(cache.fetch(id, "carol", now + FILE_CACHE_TTL).is_none());
+        assert_eq!(cache.total_bytes, 0);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let quota = MAX_FILE_CACHE_BYTES_PER_SENDER;
+        let mut cache = FileCache::new(quota);
+        let now = Instant::now();
+        let file = vec![0; quota];
+        assert_eq!(
+            cache.share("alice", "a.txt", b"data", HashSet::new(), now),
+            Ok(1)
+        );
+        assert_eq!(
+            cache.share("alice", "big", &[0; 2 * 1024], HashSet::new(), now),
+            Ok(2)
+        );
+        assert_eq!(
+            cache.share("alice", "huge", &vec![0; quota + 1], HashSet::new(), now),
+            Err(ShareError::TooLarge { max: quota })
+        );
+        // alice's small files count against her quota
+        assert_eq!(
+            cache.share("alice", "full", &file, HashSet::new(), now),
+            Err(ShareError::QuotaExceeded { quota })
+        );
+
+        let senders = MAX_FILE_CACHE_BYTES / quota;
+        for n in 0..senders - 1 {
+            let sender = format!("user{}", n);
+            cache
+                .share(&sender, "full", &file, HashSet::new(), now)
+                .unwrap();
+        }
+        assert_eq!(
+            cache.share("bob", "full", &file, HashSet::new(), now),
+            Err(ShareError::Full)
+        );
+        // Expired files make room again
+        let later = now + FILE_CACHE_TTL;
+        assert!(
+            cache
+                .share("bob", "full", &file, HashSet::new(), later)
+                .is_ok()
+        );
+    }
+}
@@ -1,21 +1,49 @@
 use crate::ServerCommand;
+use crate::federation::RemoteUsers;
+use crate::file_policy::FileTypePolicy;
+use crate::history::{self, History, HistoryKind};
+use crate::motd::Motd;
+use crate::topic;
+use crate::user_registry::{self, UserRegistry};
+use crate::webhook::{Webhook, WebhookEvent};
 use rand::Rng;
+use shared::compression::COMPRESSION_ALGORITHM;
+use shared::file_transfer::{
+    FileAckHeader, FileOfferHeader, FileRequestHeader, FileResponseHeader, FileTransferHeader,
+    FileUploadHeader,
+};
+use shared::limits::{self, MAX_USERNAME_LENGTH, ServerLimits};
 use shared::logger;
-use shared::message::{ChatMessage, MessageTypes};
+use shared::message::{
+    ChatMessage, E2E_DM_PREFIX, ErrorCode, MAX_MESSAGE_LENGTH, MessageTypes,
+    NAME_SUGGESTION_MARKER, READONLY_JOIN_FLAG, READONLY_USER_MARKER, REMOTE_USER_MARKER,
+    SESSION_SECRET_JOIN_PREFIX, ServerInfo, USER_LIST_PAGE_SIZE, UserListPage, away_reason,
+    rename_announcement, stamp_chat_content, unix_millis_now,
+};
 use shared::network::TcpMessageHandler;
-use shared::version::{self, VERSION};
+use shared::version::VERSION;
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::{RwLock, broadcast};
+use tokio::sync::{Mutex, RwLock, broadcast};
 
+use super::DirectRoute;
+use super::away::AwayReplies;
+use super::blocks::{BlockError, BlockList, MAX_BLOCKED_USERS};
 use super::error::UserConnectionError;
-use super::rate_limiting::RateLimiter;
+use super::file_cache::{FileCache, ShareError};
+use super::pages::PageLimiter;
+use super::polls::{self, PollBoard, VoteError};
+use super::rate_limiting::{ByteRateLimiter, JoinRateLimiter, RateLimiter};
+use super::takeovers::{Takeover, TokenlessTakeovers};
+use super::transfers::TransferTracker;
 
 // Helper struct to implement TcpMessageHandler for any AsyncRead + AsyncWrite stream
 struct StreamWrapper<'a, S> {
     stream: &'a mut S,
+    compress: bool,
 }
 
 impl<'a, S: AsyncRead + AsyncWrite + Unpin> TcpMessageHandler for StreamWrapper<'a, S> {
@@ -23,44 +51,238 @@ impl<'a, S: AsyncRead + AsyncWrite + Unpin> TcpMessageHandler for StreamWrapper<
     fn get_stream(&mut self) -> &mut Self::Stream {
         self.stream
     }
+    fn compression(&self) -> bool {
+        self.compress
+    }
 }
 
 // Security limits
-pub const MAX_USERNAME_LENGTH: usize = 32;
-pub const MAX_MESSAGE_LENGTH: usize = 1024; // 1KB max message content
-pub const MAX_STATUS_LENGTH: usize = 128; // Max status message length
+// Encrypted DMs are hex(nonce || ciphertext || tag), roughly double the plaintext size
+pub const MAX_E2E_MESSAGE_LENGTH: usize = MAX_MESSAGE_LENGTH * 2 + 128;
+const PUBLIC_KEY_HEX_LENGTH: usize = 64; // x25519 public key (32 bytes) as hex
 
 pub struct MessageHandlers<'a> {
     pub addr: SocketAddr,
     pub tx: &'a broadcast::Sender<(ChatMessage, SocketAddr)>,
     pub server_commands: &'a broadcast::Sender<ServerCommand>,
     pub connected_clients: &'a Arc<RwLock<HashSet<String>>>,
+    /// Users online on linked servers, whose names are taken here too
+    pub remote_users: &'a Arc<RwLock<RemoteUsers>>,
     pub user_ips: &'a Arc<RwLock<HashMap<String, IpAddr>>>,
     pub user_statuses: &'a Arc<RwLock<HashMap<String, String>>>,
     pub user_sessions: &'a Arc<RwLock<HashMap<String, String>>>,
+    /// Secret issued on each user's last join; reclaiming needs it echoed
+    pub session_secrets: &'a Arc<RwLock<HashMap<String, String>>>,
+    pub user_channels: &'a Arc<RwLock<HashMap<String, DirectRoute>>>,
+    /// Users that joined read-only (they receive but can't send or be DMed)
+    pub readonly_users: &'a Arc<RwLock<HashSet<String>>>,
+    /// Connected users with operator rights (kick, op, deop)
+    pub operators: &'a Arc<RwLock<HashSet<String>>>,
+    /// Password that makes a user an operator with `/oper` (None = nobody can)
+    pub oper_password: Option<&'a str>,
+    pub motd: Option<&'a Motd>,
+    /// Server display name, sent to each joining user
+    pub server_name: Option<&'a str>,
+    /// Greeting sent only to each joining user; `{username}` is substituted
+    pub welcome_message: Option<&'a str>,
+    /// Current channel topic, sent to each joiner
+    pub topic: &'a Arc<RwLock<Option<String>>>,
+    /// Whether any user may change the topic (otherwise only the server console)
+    pub open_topic: bool,
+    pub limits: ServerLimits,
+    pub join_limiter: &'a Arc<Mutex<JoinRateLimiter>>,
+    /// In-flight file transfers per user, capped by `limits.max_file_transfers`
+    pub transfers: &'a Arc<Mutex<TransferTracker>>,
+    /// Away auto-replies already sent, debounced per sender and recipient
+    pub away_replies: &'a Arc<Mutex<AwayReplies>>,
+    /// Pages already sent, limited per sender and recipient
+    pub pages: &'a Arc<Mutex<PageLimiter>>,
+    /// Open polls; each user has one vote per poll
+    pub polls: &'a Arc<Mutex<PollBoard>>,
+    /// Files shared with the room, until everyone fetched them or they expire
+    pub file_cache: &'a Arc<Mutex<FileCache>>,
+    /// Per-user block lists; blocked senders' DMs are not delivered
+    pub blocks: &'a Arc<RwLock<BlockList>>,
+    /// Last-seen times of known users, for `/whois` on offline users
+    pub user_registry: &'a Arc<Mutex<UserRegistry>>,
+    pub file_policy: &'a FileTypePolicy,
+    /// Min interval between a user's chat messages (zero = slow mode off)
+    pub slowmode: Duration,
+    /// Repeats of a user's previous message within this window are dropped
+    /// (zero = duplicates allowed)
+    pub duplicate_window: Duration,
+    /// Older clients rejoin without a session token; when set, such a rejoin
+    /// from the IP holding the name takes it over after this grace period
+    /// instead of being renamed (None = always rename)
+    pub tokenless_takeover: Option<Duration>,
+    /// Tokenless takeovers scheduled to happen once their grace is over
+    pub tokenless_takeovers: &'a Arc<Mutex<TokenlessTakeovers>>,
+    /// Relay of join/leave/message events to an external webhook
+    pub webhook: Option<&'a Webhook>,
+    /// Chat history saved to SQLite, for `/search`
+    pub history: Option<&'a History>,
+    /// When the server started, for uptime in `/serverinfo`
+    pub started_at: Instant,
+    /// The client negotiated compression: large replies are compressed, and
+    /// joining confirms it
+    pub compress: bool,
+    /// The client passed the version check, so it is issued session secrets
+    pub version_checked: bool,
+    /// This join is a deferred one being retried, already counted by the
+    /// join limiter
+    pub retrying_join: bool,
+}
+
+/// A fresh random secret for a joining client to echo when it reconnects
+fn generate_session_secret() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Deliver a message only to `recipient`'s connection instead of broadcasting it.
+/// Returns false if the recipient has no route or its queue is full.
+async fn deliver_direct(
+    user_channels: &RwLock<HashMap<String, DirectRoute>>,
+    recipient: &str,
+    message: ChatMessage,
+) -> bool {
+    let channels = user_channels.read().await;
+    channels
+        .get(recipient)
+        .is_some_and(|route| route.try_send(message))
+}
+
+/// Refuse a file payload while the connection's earlier files used up the file rate
+async fn send_file_busy<S: AsyncRead + AsyncWrite + Unpin>(
+    tcp_handler: &mut StreamWrapper<'_, S>,
+    wait: Duration,
+) -> Result<(), UserConnectionError> {
+    let error_msg = ChatMessage::error(
+        ErrorCode::RateLimited,
+        &format!(
+            "File transfer rate reached; try again in {}s",
+            wait.as_secs().max(1)
+        ),
+    )
+    .map_err(|_| UserConnectionError::InvalidMessage)?;
+    tcp_handler
+        .send_message_chunked(error_msg)
+        .await
+        .map_err(UserConnectionError::IoError)
+}
+
+/// A user's most recent chat message (for slow mode and duplicate filtering)
+pub struct LastChat {
+    pub at: Instant,
+    pub content: String,
+}
+
+impl LastChat {
+    /// Whether `content` repeats this message within `window`
+    fn is_duplicate(&self, content: &str, window: Duration) -> bool {
+        self.content == content && self.at.elapsed() < window
+    }
+}
+
+/// Time left before a user may chat again under slow mode, if any
+fn slowmode_remaining(slowmode: Duration, last_chat_at: Option<Instant>) -> Option<Duration> {
+    let elapsed = last_chat_at?.elapsed();
+    (elapsed < slowmode).then(|| slowmode - elapsed)
 }
 
 impl<'a> MessageHandlers<'a> {
+    /// Send `message` to every connected client. A broadcast send only fails
+    /// when nobody is subscribed (e.g. the last client just left), which means
+    /// the message had no audience rather than that the operation failed.
+    fn broadcast(&self, message: ChatMessage) {
+        let _ = self.tx.send((message, self.addr));
+    }
+
     pub fn randomize_username(&self, username: &str) -> String {
         let mut rng = rand::thread_rng();
         let random_suffix: u32 = rng.gen_range(1000..9999);
         format!("{}_{}", username, random_suffix)
     }
 
+    /// A randomized variant of `username` that nobody in `taken` is using and
+    /// that still fits the username length limit
+    pub fn suggest_free_username(&self, username: &str, taken: &HashSet<String>) -> String {
+        const SUFFIX_LEN: usize = 5; // "_1234"
+        const MAX_ATTEMPTS: usize = 10;
+
+        let mut base_len = username.len().min(MAX_USERNAME_LENGTH - SUFFIX_LEN);
+        while !username.is_char_boundary(base_len) {
+            base_len -= 1;
+        }
+        let base = &username[..base_len];
+
+        let mut candidate = self.randomize_username(base);
+        for _ in 1..MAX_ATTEMPTS {
+            if !taken.contains(&candidate) {
+                break;
+            }
+            candidate = self.randomize_username(base);
+        }
+        candidate
+    }
+
     pub async fn process_message<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         message: ChatMessage,
         rate_limiter: &mut RateLimiter,
+        file_rate_limiter: &mut ByteRateLimiter,
         stream: &mut S,
         chat_name: &mut Option<String>,
+        last_chat: &mut Option<LastChat>,
     ) -> Result<(), UserConnectionError> {
-        let mut tcp_handler = StreamWrapper { stream };
+        let mut tcp_handler = StreamWrapper {
+            stream,
+            compress: self.compress,
+        };
         // Rate limiting check (except for Join messages)
         if !matches!(message.msg_type, MessageTypes::Join) && !rate_limiter.check_and_consume() {
             logger::log_warning(&format!("Rate limit exceeded for {}", self.addr));
-            let error_msg = ChatMessage::try_new(
-                MessageTypes::Error,
-                Some(b"Rate limit exceeded. Please slow down.".to_vec()),
+            let error_msg = ChatMessage::error(
+                ErrorCode::RateLimited,
+                "Rate limit exceeded. Please slow down.",
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_msg)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
+        }
+
+        // Text messages must decode; tell the client rather than dropping them silently
+        if message.msg_type.carries_text() && message.content_text().is_err() {
+            logger::log_warning(&format!(
+                "Invalid UTF-8 in {:?} message from {}",
+                message.msg_type, self.addr
+            ));
+            let error_msg =
+                ChatMessage::error(ErrorCode::InvalidRequest, "Message contained invalid UTF-8")
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_msg)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
+        }
+
+        // Read-only connections receive messages but may not send them
+        if matches!(
+            message.msg_type,
+            MessageTypes::ChatMessage
+                | MessageTypes::DirectMessage
+                | MessageTypes::Page
+                | MessageTypes::Poll
+                | MessageTypes::Vote
+                | MessageTypes::FileOffer
+        ) && self.is_readonly(chat_name).await
+        {
+            let error_msg = ChatMessage::error(
+                ErrorCode::NotPermitted,
+                "Read-only connections cannot send messages",
             )
             .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
@@ -71,20 +293,22 @@ impl<'a> MessageHandlers<'a> {
         }
 
         match message.msg_type {
-            MessageTypes::VersionCheck => {
-                self.process_version_check(message.content_as_string(), &mut tcp_handler)
-                    .await?;
-            }
             MessageTypes::Join => {
                 self.process_join(message.content_as_string(), &mut tcp_handler, chat_name)
                     .await?;
             }
             MessageTypes::ChatMessage => {
-                self.process_chat_message(message.content_as_string(), chat_name)
-                    .await?;
+                self.process_chat_message(
+                    message.content_as_string(),
+                    &mut tcp_handler,
+                    chat_name,
+                    last_chat,
+                )
+                .await?;
             }
             MessageTypes::ListUsers => {
-                self.process_list_users(&mut tcp_handler).await?;
+                self.process_list_users(message.content_as_string(), &mut tcp_handler)
+                    .await?;
             }
             MessageTypes::DirectMessage => {
                 self.process_direct_message(
@@ -94,6 +318,10 @@ impl<'a> MessageHandlers<'a> {
                 )
                 .await?;
             }
+            MessageTypes::Page => {
+                self.process_page(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
             MessageTypes::RenameRequest => {
                 self.process_rename_request(
                     message.content_as_string(),
@@ -103,8 +331,13 @@ impl<'a> MessageHandlers<'a> {
                 .await?;
             }
             MessageTypes::FileTransfer => {
-                self.process_file_transfer(message.get_content(), &mut tcp_handler, chat_name)
-                    .await?;
+                self.process_file_transfer(
+                    message.get_content(),
+                    &mut tcp_handler,
+                    chat_name,
+                    file_rate_limiter,
+                )
+                .await?;
             }
             MessageTypes::FileTransferRequest => {
                 self.process_file_transfer_request(
@@ -122,10 +355,83 @@ impl<'a> MessageHandlers<'a> {
                 )
                 .await?;
             }
+            MessageTypes::FileTransferAck => {
+                self.process_file_transfer_ack(message.get_content(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::FileOffer => {
+                self.process_file_offer(
+                    message.get_content(),
+                    &mut tcp_handler,
+                    chat_name,
+                    file_rate_limiter,
+                )
+                .await?;
+            }
+            MessageTypes::FileGet => {
+                self.process_file_get(
+                    message.get_content(),
+                    &mut tcp_handler,
+                    chat_name,
+                    file_rate_limiter,
+                )
+                .await?;
+            }
+            MessageTypes::KeyExchange => {
+                self.process_key_exchange(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
             MessageTypes::SetStatus => {
                 self.process_set_status(message.content_as_string(), &mut tcp_handler, chat_name)
                     .await?;
             }
+            MessageTypes::Topic => {
+                self.process_topic(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::Poll => {
+                self.process_poll(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::Vote => {
+                self.process_vote(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::ServerInfo => {
+                self.process_server_info(&mut tcp_handler).await?;
+            }
+            MessageTypes::Whois => {
+                self.process_whois(message.content_as_string(), &mut tcp_handler)
+                    .await?;
+            }
+            MessageTypes::SeenRequest => {
+                self.process_seen(message.content_as_string(), &mut tcp_handler)
+                    .await?;
+            }
+            MessageTypes::Search => {
+                self.process_search(message.content_as_string(), &mut tcp_handler)
+                    .await?;
+            }
+            MessageTypes::Block => {
+                self.process_block(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::Moderation => {
+                self.process_moderation(message.content_as_string(), &mut tcp_handler, chat_name)
+                    .await?;
+            }
+            MessageTypes::Ping => {
+                // Client latency probe: echo its sequence number straight back
+                let pong = ChatMessage::try_new(
+                    MessageTypes::Pong,
+                    message.get_content().map(<[u8]>::to_vec),
+                )
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(pong)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
             MessageTypes::Leave => {
                 // User explicitly quit - signal this to the connection handler
                 return Err(UserConnectionError::ExplicitQuit);
@@ -135,33 +441,98 @@ impl<'a> MessageHandlers<'a> {
         Ok(())
     }
 
+    async fn is_readonly(&self, chat_name: &Option<String>) -> bool {
+        match chat_name {
+            Some(name) => self.readonly_users.read().await.contains(name),
+            None => false,
+        }
+    }
+
     async fn process_list_users<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
+        page: Option<String>,
         tcp_handler: &mut StreamWrapper<'_, S>,
     ) -> Result<(), UserConnectionError> {
+        // No page number means the whole list (clients use it for autocomplete)
+        let page = match page.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(page) => match page.parse::<usize>() {
+                Ok(page) if page > 0 => Some(page),
+                _ => {
+                    let error_msg =
+                        ChatMessage::error(ErrorCode::InvalidRequest, "Invalid page number")
+                            .map_err(|_| UserConnectionError::InvalidMessage)?;
+                    tcp_handler
+                        .send_message_chunked(error_msg)
+                        .await
+                        .map_err(UserConnectionError::IoError)?;
+                    return Ok(());
+                }
+            },
+        };
+
         let clients = self.connected_clients.read().await;
+        let remote_users = self.remote_users.read().await;
         let statuses = self.user_statuses.read().await;
-
-        // Build user list with statuses
-        let user_list: Vec<String> = clients
-            .iter()
-            .map(|username| {
-                if let Some(status) = statuses.get(username) {
-                    format!("{} - {}", username, status)
-                } else {
-                    username.clone()
+        let readonly_users = self.readonly_users.read().await;
+
+        let mut usernames: Vec<&String> = clients.iter().chain(remote_users.names()).collect();
+        usernames.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)));
+
+        let total = usernames.len();
+        let mut lines = Vec::new();
+        let usernames = match page {
+            Some(page) => {
+                let pages = total.div_ceil(USER_LIST_PAGE_SIZE).max(1);
+                if page > pages {
+                    drop(clients);
+                    drop(remote_users);
+                    drop(statuses);
+                    drop(readonly_users);
+                    let error_msg = ChatMessage::error(
+                        ErrorCode::InvalidRequest,
+                        &format!("Page {} out of range (1-{})", page, pages),
+                    )
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                    tcp_handler
+                        .send_message_chunked(error_msg)
+                        .await
+                        .map_err(UserConnectionError::IoError)?;
+                    return Ok(());
                 }
-            })
-            .collect();
+                lines.push(UserListPage { page, pages, total }.header());
+                let start = (page - 1) * USER_LIST_PAGE_SIZE;
+                let end = (start + USER_LIST_PAGE_SIZE).min(total);
+                &usernames[start..end]
+            }
+            None => &usernames[..],
+        };
+
+        // Build user list with statuses (users on linked servers have none here)
+        lines.extend(usernames.iter().map(|username| {
+            if remote_users.contains(username) {
+                return format!("{}{}", username, REMOTE_USER_MARKER);
+            }
+            let marker = if readonly_users.contains(*username) {
+                READONLY_USER_MARKER
+            } else {
+                ""
+            };
+            if let Some(status) = statuses.get(*username) {
+                format!("{}{} - {}", username, marker, status)
+            } else {
+                format!("{}{}", username, marker)
+            }
+        }));
 
         drop(clients);
+        drop(remote_users);
         drop(statuses);
+        drop(readonly_users);
 
-        let list_message = ChatMessage::try_new(
-            MessageTypes::ListUsers,
-            Some(user_list.join("\n").into_bytes()),
-        )
-        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        let list_message =
+            ChatMessage::try_new(MessageTypes::ListUsers, Some(lines.join("\n").into_bytes()))
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
         tcp_handler
             .send_message_chunked(list_message)
             .await
@@ -169,10 +540,12 @@ impl<'a> MessageHandlers<'a> {
         Ok(())
     }
 
-    async fn process_chat_message(
+    async fn process_chat_message<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
         chat_name: &Option<String>,
+        last_chat: &mut Option<LastChat>,
     ) -> Result<(), UserConnectionError> {
         let chat_content = content.ok_or(UserConnectionError::InvalidMessage)?;
 
@@ -187,15 +560,53 @@ impl<'a> MessageHandlers<'a> {
         }
 
         if let Some(chat_name) = chat_name {
+            // Drop accidental double-sends (distinct from rate limiting)
+            if last_chat
+                .as_ref()
+                .is_some_and(|last| last.is_duplicate(&chat_content, self.duplicate_window))
+            {
+                logger::log_info(&format!("Dropped duplicate message from {}", chat_name));
+                // The first copy went out, so the sender still gets its ack
+                return self.send_chat_ack(tcp_handler).await;
+            }
+
+            // Slow mode: reject messages sent before the interval has passed
+            let last_chat_at = last_chat.as_ref().map(|last| last.at);
+            if let Some(wait) = slowmode_remaining(self.slowmode, last_chat_at) {
+                let error_msg = format!(
+                    "Slow mode is on: wait {}s before sending another message",
+                    wait.as_secs_f64().ceil() as u64
+                );
+                let error_message = ChatMessage::error(ErrorCode::RateLimited, &error_msg)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(error_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+                return Ok(());
+            }
             let full_message = format!("{}: {}", chat_name, chat_content);
+            if let Some(webhook) = self.webhook {
+                webhook.send(WebhookEvent::Message {
+                    username: chat_name.clone(),
+                    content: chat_content.clone(),
+                });
+            }
+            if let Some(history) = self.history {
+                history.record(HistoryKind::Message, chat_name, &chat_content);
+            }
+            *last_chat = Some(LastChat {
+                at: Instant::now(),
+                content: chat_content,
+            });
             logger::log_chat(&full_message);
+            // Stamp with the server clock so every client shows the same time
+            let stamped = stamp_chat_content(unix_millis_now(), &full_message);
             let broadcast_message =
-                ChatMessage::try_new(MessageTypes::ChatMessage, Some(full_message.into_bytes()))
+                ChatMessage::try_new(MessageTypes::ChatMessage, Some(stamped.into_bytes()))
                     .map_err(|_| UserConnectionError::InvalidMessage)?;
-            self.tx
-                .send((broadcast_message, self.addr))
-                .map_err(UserConnectionError::BroadcastError)?;
-            Ok(())
+            self.broadcast(broadcast_message);
+            self.send_chat_ack(tcp_handler).await
         } else {
             logger::log_warning(&format!(
                 "User at {} sent chat message before joining",
@@ -205,6 +616,20 @@ impl<'a> MessageHandlers<'a> {
         }
     }
 
+    /// Tell the sender their chat message was accepted, so the client can tell
+    /// delivered messages from rate-limited or lost ones
+    async fn send_chat_ack<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let ack = ChatMessage::try_new(MessageTypes::ChatAck, None)
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(ack)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
     async fn process_direct_message<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         content: Option<String>,
@@ -214,8 +639,14 @@ impl<'a> MessageHandlers<'a> {
         let content = content.ok_or(UserConnectionError::InvalidMessage)?;
 
         if let Some((recipient, message)) = content.split_once('|') {
-            // Validate message length
-            if message.is_empty() || message.len() > MAX_MESSAGE_LENGTH {
+            // Validate message length. End-to-end encrypted bodies are opaque
+            // ciphertext, so they get a larger allowance than plaintext.
+            let max_length = if message.starts_with(E2E_DM_PREFIX) {
+                MAX_E2E_MESSAGE_LENGTH
+            } else {
+                MAX_MESSAGE_LENGTH
+            };
+            if message.is_empty() || message.len() > max_length {
                 logger::log_warning(&format!(
                     "Invalid DM length from {}: {} chars",
                     self.addr,
@@ -224,34 +655,24 @@ impl<'a> MessageHandlers<'a> {
                 return Err(UserConnectionError::InvalidMessage);
             }
             if let Some(sender) = chat_name {
-                // Check if recipient exists
-                let clients = self.connected_clients.read().await;
-                if !clients.contains(recipient) {
-                    drop(clients); // Release the lock before sending error
-
-                    // Send error message back to sender
-                    let error_msg = format!("User '{}' not found", recipient);
-                    logger::log_warning(&format!(
-                        "[DM] {} -> {} (user not found)",
-                        sender, recipient
-                    ));
-
-                    let error_message =
-                        ChatMessage::try_new(MessageTypes::Error, Some(error_msg.into_bytes()))
-                            .map_err(|_| UserConnectionError::InvalidMessage)?;
-
+                if let Some(error_message) = self.undeliverable("DM", sender, recipient).await? {
                     tcp_handler
                         .send_message_chunked(error_message)
                         .await
                         .map_err(UserConnectionError::IoError)?;
                     return Ok(());
                 }
-                drop(clients); // Release the lock
 
                 // Log that a DM is happening, but don't show the content
                 logger::log_system(&format!("[DM] {} -> {}", sender, recipient));
+                if let Some(webhook) = self.webhook {
+                    webhook.send(WebhookEvent::DirectMessage {
+                        username: sender.clone(),
+                        recipient: recipient.to_string(),
+                    });
+                }
 
-                // Format: sender|recipient|message for client filtering
+                // Format: sender|recipient|message
                 let dm_content = format!("{}|{}|{}", sender, recipient, message);
                 let dm_message = ChatMessage::try_new(
                     MessageTypes::DirectMessage,
@@ -259,11 +680,26 @@ impl<'a> MessageHandlers<'a> {
                 )
                 .map_err(|_| UserConnectionError::InvalidMessage)?;
 
-                // Broadcast to all clients (clients will filter)
-                self.tx
-                    .send((dm_message, self.addr))
-                    .map_err(UserConnectionError::BroadcastError)?;
-                Ok(())
+                // Deliver only to the recipient's connection so other clients never
+                // see the DM (the sender already echoes it locally)
+                if !deliver_direct(self.user_channels, recipient, dm_message).await {
+                    logger::log_warning(&format!(
+                        "[DM] {} -> {} (delivery failed)",
+                        sender, recipient
+                    ));
+                    let error_message = ChatMessage::error(
+                        ErrorCode::DeliveryFailed,
+                        &format!("Could not deliver message to '{}'", recipient),
+                    )
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                    tcp_handler
+                        .send_message_chunked(error_message)
+                        .await
+                        .map_err(UserConnectionError::IoError)?;
+                    return Ok(());
+                }
+
+                self.send_away_reply(sender, recipient, tcp_handler).await
             } else {
                 logger::log_warning(&format!("User at {} sent DM before joining", self.addr));
                 Err(UserConnectionError::InvalidMessage)
@@ -273,6 +709,504 @@ impl<'a> MessageHandlers<'a> {
         }
     }
 
+    /// The error to send back if a `kind` ("DM" or "Page") from `sender` can't
+    /// go to `recipient`: they aren't connected, have blocked the sender, or
+    /// are read-only
+    async fn undeliverable(
+        &self,
+        kind: &str,
+        sender: &str,
+        recipient: &str,
+    ) -> Result<Option<ChatMessage>, UserConnectionError> {
+        let (code, text) = if self.remote_users.read().await.contains(recipient) {
+            (
+                ErrorCode::DeliveryFailed,
+                format!(
+                    "User '{}' is on a linked server; {}s don't cross servers",
+                    recipient, kind
+                ),
+            )
+        } else if !self.connected_clients.read().await.contains(recipient) {
+            logger::log_warning(&format!(
+                "[{}] {} -> {} (user not found)",
+                kind, sender, recipient
+            ));
+            (
+                ErrorCode::UserNotFound,
+                format!("User '{}' not found", recipient),
+            )
+        } else if self.blocks.read().await.is_blocked(recipient, sender) {
+            // Same error as a failed delivery, so the sender can't tell
+            // they've been blocked
+            logger::log_system(&format!("[{}] {} -> {} (blocked)", kind, sender, recipient));
+            (
+                ErrorCode::DeliveryFailed,
+                format!("Could not deliver message to '{}'", recipient),
+            )
+        } else if self.readonly_users.read().await.contains(recipient) {
+            (
+                ErrorCode::NotPermitted,
+                format!("User '{}' is read-only", recipient),
+            )
+        } else {
+            return Ok(None);
+        };
+        ChatMessage::error(code, &text)
+            .map(Some)
+            .map_err(|_| UserConnectionError::InvalidMessage)
+    }
+
+    /// Deliver a high-priority page (format: recipient|message) to one user,
+    /// at most once per `PAGE_INTERVAL` for each sender and recipient
+    async fn process_page<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let (recipient, message) = content
+            .split_once('|')
+            .ok_or(UserConnectionError::InvalidMessage)?;
+        if message.is_empty() || message.len() > MAX_MESSAGE_LENGTH {
+            return Err(UserConnectionError::InvalidMessage);
+        }
+        let Some(sender) = chat_name else {
+            logger::log_warning(&format!("User at {} sent page before joining", self.addr));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let error_message = match self.undeliverable("Page", sender, recipient).await? {
+            Some(error_message) => Some(error_message),
+            None => match self
+                .pages
+                .lock()
+                .await
+                .try_page(sender, recipient, Instant::now())
+            {
+                Ok(()) => None,
+                Err(wait) => Some(
+                    ChatMessage::error(
+                        ErrorCode::RateLimited,
+                        &format!(
+                            "You can page {} again in {}s",
+                            recipient,
+                            wait.as_secs().max(1)
+                        ),
+                    )
+                    .map_err(|_| UserConnectionError::InvalidMessage)?,
+                ),
+            },
+        };
+        if let Some(error_message) = error_message {
+            return tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError);
+        }
+
+        logger::log_system(&format!("[Page] {} -> {}", sender, recipient));
+        // Format: sender|message
+        let page = ChatMessage::try_new(
+            MessageTypes::Page,
+            Some(format!("{}|{}", sender, message).into_bytes()),
+        )
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        if !deliver_direct(self.user_channels, recipient, page).await {
+            let error_message = ChatMessage::error(
+                ErrorCode::DeliveryFailed,
+                &format!("Could not deliver page to '{}'", recipient),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+        }
+        Ok(())
+    }
+
+    /// Tell `sender` that `recipient` is away, at most once per
+    /// `AWAY_REPLY_INTERVAL` for each pair
+    async fn send_away_reply<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        sender: &str,
+        recipient: &str,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let reply = match self.user_statuses.read().await.get(recipient) {
+            Some(status) => match away_reason(status) {
+                Some("") => format!("{} is away", recipient),
+                Some(reason) => format!("{} is away: {}", recipient, reason),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        if !self
+            .away_replies
+            .lock()
+            .await
+            .should_reply(sender, recipient, Instant::now())
+        {
+            return Ok(());
+        }
+
+        let notice = ChatMessage::try_new(MessageTypes::ServerNotice, Some(reply.into_bytes()))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(notice)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    async fn process_server_info<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let info = ServerInfo {
+            version: VERSION.to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            users: self.connected_clients.read().await.len(),
+        };
+        let reply = ChatMessage::try_new(
+            MessageTypes::ServerInfo,
+            Some(info.to_content().into_bytes()),
+        )
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(reply)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    async fn process_whois<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        username: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let username = username.ok_or(UserConnectionError::InvalidMessage)?;
+        let username = username.trim();
+
+        let reply = if self.remote_users.read().await.contains(username) {
+            format!("{} is online on a linked server", username)
+        } else if self.connected_clients.read().await.contains(username) {
+            match self.user_statuses.read().await.get(username) {
+                Some(status) => format!("{} is online - {}", username, status),
+                None => format!("{} is online", username),
+            }
+        } else {
+            match self.user_registry.lock().await.last_seen(username) {
+                Some(seen) => format!(
+                    "{} was last seen {}",
+                    username,
+                    user_registry::format_ago(seen.elapsed().unwrap_or_default())
+                ),
+                None => format!("No record of '{}'", username),
+            }
+        };
+
+        let reply = ChatMessage::try_new(MessageTypes::Whois, Some(reply.into_bytes()))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(reply)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    async fn process_seen<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        username: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let username = username.ok_or(UserConnectionError::InvalidMessage)?;
+        let username = username.trim();
+
+        let reply = if self.connected_clients.read().await.contains(username)
+            || self.remote_users.read().await.contains(username)
+        {
+            format!("{} is online", username)
+        } else {
+            match self.user_registry.lock().await.last_seen(username) {
+                Some(seen) => format!(
+                    "{} was last seen {}",
+                    username,
+                    user_registry::format_ago(seen.elapsed().unwrap_or_default())
+                ),
+                None => format!("{} has never been seen", username),
+            }
+        };
+
+        let reply = ChatMessage::try_new(MessageTypes::SeenRequest, Some(reply.into_bytes()))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(reply)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    async fn process_search<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        term: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+    ) -> Result<(), UserConnectionError> {
+        let term = term.ok_or(UserConnectionError::InvalidMessage)?;
+        let term = term.trim();
+        if term.is_empty() {
+            return Err(UserConnectionError::InvalidMessage);
+        }
+
+        let reply = match self.history {
+            Some(history) => match history.search(term).await {
+                Ok(found) => ChatMessage::try_new(
+                    MessageTypes::Search,
+                    Some(history::search_reply(term, &found).into_bytes()),
+                ),
+                Err(e) => ChatMessage::error(ErrorCode::Other, &e),
+            },
+            None => ChatMessage::error(
+                ErrorCode::NotPermitted,
+                "This server doesn't keep message history",
+            ),
+        }
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(reply)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    /// Operator requests (format: oper|password, op|user, deop|user or
+    /// kick|user). Changes are announced to everyone as a server notice.
+    async fn process_moderation<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(sender) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} sent a moderation request before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let (action, target) = content.split_once('|').unwrap_or((content.as_str(), ""));
+        let target = target.trim();
+        if action != "oper" && (target.is_empty() || target.len() > MAX_USERNAME_LENGTH) {
+            return Err(UserConnectionError::InvalidMessage);
+        }
+
+        let is_operator = self.operators.read().await.contains(sender);
+        let outcome = match action {
+            "oper" => match self.oper_password {
+                None => Err((
+                    ErrorCode::NotPermitted,
+                    "This server has no operator password".to_string(),
+                )),
+                Some(password) if target != password => {
+                    logger::log_warning(&format!(
+                        "[MOD] {} ({}) gave a wrong operator password",
+                        sender, self.addr
+                    ));
+                    Err((
+                        ErrorCode::NotPermitted,
+                        "Wrong operator password".to_string(),
+                    ))
+                }
+                Some(_) if is_operator => Err((
+                    ErrorCode::InvalidRequest,
+                    "You are already an operator".to_string(),
+                )),
+                Some(_) => {
+                    self.operators.write().await.insert(sender.clone());
+                    Ok(format!("{} is now an operator", sender))
+                }
+            },
+            "op" | "deop" | "kick" if !is_operator => Err((
+                ErrorCode::NotPermitted,
+                "Only operators can use /op, /deop and /kick".to_string(),
+            )),
+            "op" | "kick" if !self.connected_clients.read().await.contains(target) => Err((
+                ErrorCode::UserNotFound,
+                format!("User '{}' not found", target),
+            )),
+            "op" => {
+                if self.operators.write().await.insert(target.to_string()) {
+                    Ok(format!("{} made {} an operator", sender, target))
+                } else {
+                    Err((
+                        ErrorCode::InvalidRequest,
+                        format!("{} is already an operator", target),
+                    ))
+                }
+            }
+            "deop" => {
+                if self.operators.write().await.remove(target) {
+                    Ok(format!("{} removed {}'s operator rights", sender, target))
+                } else {
+                    Err((
+                        ErrorCode::InvalidRequest,
+                        format!("{} is not an operator", target),
+                    ))
+                }
+            }
+            "kick" => {
+                // Operators can't remove each other outright; deop comes first
+                if self.operators.read().await.contains(target) {
+                    Err((
+                        ErrorCode::NotPermitted,
+                        format!("{} is an operator; /deop them first", target),
+                    ))
+                } else {
+                    let _ = self
+                        .server_commands
+                        .send(ServerCommand::Kick(target.to_string()));
+                    Ok(format!("{} was kicked by {}", target, sender))
+                }
+            }
+            _ => return Err(UserConnectionError::InvalidMessage),
+        };
+
+        match outcome {
+            Ok(text) => {
+                logger::log_system(&format!("[MOD] {}", text));
+                let notice =
+                    ChatMessage::try_new(MessageTypes::ServerNotice, Some(text.into_bytes()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                self.broadcast(notice);
+            }
+            Err((code, text)) => {
+                let error_msg = ChatMessage::error(code, &text)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(error_msg)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Block list changes and queries (format: block|user, unblock|user or list)
+    async fn process_block<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(owner) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} tried to change blocks before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let (action, target) = content.split_once('|').unwrap_or((content.as_str(), ""));
+        let target = target.trim();
+        if action != "list" && (target.is_empty() || target.len() > MAX_USERNAME_LENGTH) {
+            return Err(UserConnectionError::InvalidMessage);
+        }
+
+        let reply = match action {
+            "block" => match self.blocks.write().await.block(owner, target) {
+                Ok(true) => {
+                    logger::log_system(&format!("[BLOCK] {} blocked {}", owner, target));
+                    Ok(format!("Blocked {} - they can no longer DM you", target))
+                }
+                Ok(false) => Ok(format!("{} is already blocked", target)),
+                Err(BlockError::SelfBlock) => Err("You can't block yourself".to_string()),
+                Err(BlockError::Full) => {
+                    Err(format!("Block list full (max {} users)", MAX_BLOCKED_USERS))
+                }
+            },
+            "unblock" => {
+                if self.blocks.write().await.unblock(owner, target) {
+                    logger::log_system(&format!("[BLOCK] {} unblocked {}", owner, target));
+                    Ok(format!("Unblocked {}", target))
+                } else {
+                    Ok(format!("{} is not blocked", target))
+                }
+            }
+            "list" => {
+                let blocks = self.blocks.read().await;
+                let blocked = blocks.list(owner);
+                if blocked.is_empty() {
+                    Ok("You haven't blocked anyone".to_string())
+                } else {
+                    Ok(format!("Blocked users: {}", blocked.join(", ")))
+                }
+            }
+            _ => return Err(UserConnectionError::InvalidMessage),
+        };
+
+        let reply = match reply {
+            Ok(text) => ChatMessage::try_new(MessageTypes::Block, Some(text.into_bytes())),
+            Err(text) => ChatMessage::error(ErrorCode::InvalidRequest, &text),
+        }
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        tcp_handler
+            .send_message_chunked(reply)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
+    async fn process_key_exchange<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+
+        let Some(sender) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} sent key exchange before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        // Format: recipient|hex_public_key
+        let (recipient, public_key) = content
+            .split_once('|')
+            .ok_or(UserConnectionError::InvalidMessage)?;
+        if public_key.len() != PUBLIC_KEY_HEX_LENGTH
+            || !public_key.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            logger::log_warning(&format!("Invalid public key from {}", self.addr));
+            return Err(UserConnectionError::InvalidMessage);
+        }
+
+        logger::log_system(&format!("[KEY EXCHANGE] {} -> {}", sender, recipient));
+
+        // Relay as sender|hex_public_key so the recipient knows whose key it is
+        let relay_content = format!("{}|{}", sender, public_key);
+        let relay_message =
+            ChatMessage::try_new(MessageTypes::KeyExchange, Some(relay_content.into_bytes()))
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+
+        if !deliver_direct(self.user_channels, recipient, relay_message).await {
+            logger::log_warning(&format!(
+                "[KEY EXCHANGE] {} -> {} (delivery failed)",
+                sender, recipient
+            ));
+            let error_message = ChatMessage::error(
+                ErrorCode::UserNotFound,
+                &format!("User '{}' not found", recipient),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+        }
+        Ok(())
+    }
+
     async fn process_join<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         username: Option<String>,
@@ -281,6 +1215,23 @@ impl<'a> MessageHandlers<'a> {
     ) -> Result<(), UserConnectionError> {
         let content = username.ok_or(UserConnectionError::InvalidMessage)?;
 
+        // Throttle join/reconnect cycles per IP (Join bypasses the message rate limiter)
+        let ip = self.addr.ip();
+        let join_allowed = self.retrying_join || self.join_limiter.lock().await.check_and_record(ip);
+        if !join_allowed {
+            logger::log_warning(&format!("Join rate limit exceeded for {}", ip));
+            let error_msg = ChatMessage::error(
+                ErrorCode::RateLimited,
+                "Too many join attempts. Please wait a minute and try again.",
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_msg)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Err(UserConnectionError::JoinRateLimited);
+        }
+
         // Parse username and session token (format: username|session_token)
         let (requested_username, session_token) = if let Some((user, token)) = content.split_once('|') {
             (user.to_string(), Some(token.to_string()))
@@ -289,6 +1240,25 @@ impl<'a> MessageHandlers<'a> {
             (content, None)
         };
 
+        // Optional trailing fields ask for a read-only connection and echo the
+        // secret issued on the last join
+        let (session_token, readonly, secret) = match session_token {
+            Some(token) => {
+                let mut fields = token.split('|');
+                let token = fields.next().unwrap_or_default().to_string();
+                let (mut readonly, mut secret) = (false, None);
+                for field in fields {
+                    if field == READONLY_JOIN_FLAG {
+                        readonly = true;
+                    } else if let Some(value) = field.strip_prefix(SESSION_SECRET_JOIN_PREFIX) {
+                        secret = Some(value.to_string());
+                    }
+                }
+                (Some(token), readonly, secret)
+            }
+            None => (None, false, None),
+        };
+
         // Validate username length
         if requested_username.is_empty() || requested_username.len() > MAX_USERNAME_LENGTH {
             logger::log_warning(&format!(
@@ -314,30 +1284,78 @@ impl<'a> MessageHandlers<'a> {
         let connected_clients = self.connected_clients.clone();
         {
             let mut clients = connected_clients.write().await;
+            // A name in use on a linked server is never reclaimed, only renamed
+            let held_remotely = self.remote_users.read().await.contains(&requested_username);
 
             // Check if username already exists
-            if clients.contains(&requested_username) {
-                // Username exists - check if this is a valid reconnection (same session token and IP)
-                let can_reclaim = if let Some(ref token) = session_token {
-                    let sessions = self.user_sessions.read().await;
-                    let ips = self.user_ips.read().await;
-
-                    let session_matches = sessions.get(&requested_username).is_some_and(|t| t == token);
-                    let ip_matches = ips.get(&requested_username).is_some_and(|ip| *ip == self.addr.ip());
-
-                    drop(sessions);
-                    drop(ips);
+            if clients.contains(&requested_username) || held_remotely {
+                // A session that was issued a secret is only reclaimed by a
+                // client echoing it, whatever else matches
+                let secrets = self.session_secrets.read().await;
+                let secret_matches = secrets
+                    .get(&requested_username)
+                    .is_none_or(|issued| secret.as_ref() == Some(issued));
+                drop(secrets);
+                if !secret_matches {
+                    logger::log_warning(&format!(
+                        "Join as '{}' from {} did not echo the current session secret",
+                        requested_username, self.addr
+                    ));
+                }
 
-                    session_matches && ip_matches
-                } else {
-                    false
+                // Username exists - check if this is a valid reconnection (same session token and IP)
+                let ip_matches = self
+                    .user_ips
+                    .read()
+                    .await
+                    .get(&requested_username)
+                    .is_some_and(|ip| *ip == self.addr.ip());
+                let can_reclaim = match (&session_token, self.tokenless_takeover) {
+                    _ if held_remotely => false,
+                    (Some(token), _) => {
+                        let sessions = self.user_sessions.read().await;
+                        let session_matches = sessions.get(&requested_username).is_some_and(|t| t == token);
+                        drop(sessions);
+
+                        session_matches && ip_matches
+                    }
+                    // Older clients send no token: the same IP may take the
+                    // name over once the grace is over, without waiting here.
+                    // A join that can't reclaim anyway is renamed right away
+                    (None, Some(grace)) if ip_matches && secret_matches => {
+                        let takeover = self.tokenless_takeovers.lock().await.check(
+                            &requested_username,
+                            self.addr,
+                            grace,
+                            Instant::now(),
+                        );
+                        match takeover {
+                            Takeover::Ready => true,
+                            Takeover::Scheduled(wait) => {
+                                logger::log_info(&format!(
+                                    "'{}' rejoined from {} without a session token, taking over in {}s",
+                                    requested_username,
+                                    self.addr,
+                                    wait.as_secs_f64().ceil()
+                                ));
+                                return Err(UserConnectionError::JoinDeferred(wait));
+                            }
+                            Takeover::Refused => false,
+                        }
+                    }
+                    (None, _) => false,
                 };
 
-                if can_reclaim {
+                if can_reclaim && secret_matches {
                     // This is a valid reconnection - reclaim the ghost session
+                    let evidence = if session_token.is_some() {
+                        "same token and IP"
+                    } else {
+                        "same IP, no token"
+                    };
                     logger::log_success(&format!(
-                        "User '{}' reclaiming ghost session from {} (same token and IP)",
-                        requested_username, self.addr
+                        "User '{}' reclaiming ghost session from {} ({})",
+                        requested_username, self.addr, evidence
                     ));
 
                     // Signal the old connection to disconnect silently
@@ -394,17 +1412,257 @@ impl<'a> MessageHandlers<'a> {
             ips.insert(chat_name.clone(), self.addr.ip());
             drop(ips);
 
+            // A reclaimed session takes the mode of the new connection
+            let mut readonly_users = self.readonly_users.write().await;
+            if readonly {
+                readonly_users.insert(chat_name.clone());
+                logger::log_info(&format!("{} joined read-only", chat_name));
+            } else {
+                readonly_users.remove(chat_name);
+            }
+            drop(readonly_users);
+
+            // Rotate the secret on every join, so one seen on an earlier
+            // connection can't reclaim this one. Clients that skipped the
+            // version check wouldn't understand it and keep token-only reclaim.
+            let mut secrets = self.session_secrets.write().await;
+            let issued = if self.version_checked {
+                let secret = generate_session_secret();
+                secrets.insert(chat_name.clone(), secret.clone());
+                Some(secret)
+            } else {
+                secrets.remove(chat_name);
+                None
+            };
+            drop(secrets);
+
             let join_message =
                 ChatMessage::try_new(MessageTypes::Join, Some(chat_name.clone().into_bytes()))
                     .map_err(|_| UserConnectionError::InvalidMessage)?;
-            self.tx
-                .send((join_message, self.addr))
-                .map_err(UserConnectionError::BroadcastError)?;
+            self.broadcast(join_message);
             logger::log_system(&format!("{} has joined the chat", chat_name));
+            if let Some(webhook) = self.webhook {
+                webhook.send(WebhookEvent::Join {
+                    username: chat_name.clone(),
+                });
+            }
+            if let Some(history) = self.history {
+                history.record(HistoryKind::Join, chat_name, "");
+            }
+            self.file_cache.lock().await.member_joined(chat_name);
+
+            // Advertise limits so the client can validate input before sending
+            let limits_message = ChatMessage::try_new(
+                MessageTypes::ServerLimits,
+                Some(self.limits.to_content().into_bytes()),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(limits_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+
+            if let Some(secret) = issued {
+                let secret_message =
+                    ChatMessage::try_new(MessageTypes::SessionSecret, Some(secret.into_bytes()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(secret_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+
+            // Confirm the compression the client offered before joining
+            if self.compress {
+                let compression_message = ChatMessage::try_new(
+                    MessageTypes::Compression,
+                    Some(COMPRESSION_ALGORITHM.as_bytes().to_vec()),
+                )
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(compression_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+
+            // Which server this is, for the client's join confirmation
+            if let Some(name) = self.server_name {
+                let name_message =
+                    ChatMessage::try_new(MessageTypes::ServerName, Some(name.as_bytes().to_vec()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(name_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+
+            // Greet the user with the message of the day, if configured
+            if let Some(motd) = self.motd
+                && let Some(text) = motd.load().await
+            {
+                let motd_message =
+                    ChatMessage::try_new(MessageTypes::Motd, Some(text.into_bytes()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(motd_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+
+            // Current channel topic
+            let current_topic = self.topic.read().await.clone();
+            if let Some(text) = current_topic {
+                let topic_message =
+                    ChatMessage::try_new(MessageTypes::Topic, Some(text.into_bytes()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(topic_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
+
+            // Automated greeting for the joining user only (others saw the join)
+            if let Some(template) = self.welcome_message {
+                let text = template.replace("{username}", chat_name);
+                let welcome_message =
+                    ChatMessage::try_new(MessageTypes::ServerNotice, Some(text.into_bytes()))
+                        .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(welcome_message)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+            }
         }
         Ok(())
     }
 
+    async fn process_topic<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let Some(chat_name) = chat_name else {
+            logger::log_warning(&format!("User at {} set topic before joining", self.addr));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let requested = if self.open_topic || self.operators.read().await.contains(chat_name) {
+            topic::sanitize(&content.unwrap_or_default())
+                .map_err(|text| (ErrorCode::InvalidRequest, text))
+        } else {
+            Err((
+                ErrorCode::NotPermitted,
+                "Only operators can change the topic".to_string(),
+            ))
+        };
+        let new_topic = match requested {
+            Ok(new_topic) => new_topic,
+            Err((code, error_text)) => {
+                let error_msg = ChatMessage::error(code, &error_text)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                tcp_handler
+                    .send_message_chunked(error_msg)
+                    .await
+                    .map_err(UserConnectionError::IoError)?;
+                return Ok(());
+            }
+        };
+
+        *self.topic.write().await = Some(new_topic.clone());
+        logger::log_system(&format!("{} set the topic: {}", chat_name, new_topic));
+
+        let topic_message = ChatMessage::try_new(MessageTypes::Topic, Some(new_topic.into_bytes()))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        self.broadcast(topic_message);
+        Ok(())
+    }
+
+    /// Open a poll (format: question|option|option...) and announce it to everyone
+    async fn process_poll<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(creator) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} started a poll before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let (question, options) = match polls::parse_poll(&content) {
+            Ok(poll) => poll,
+            Err(error_text) => {
+                let error_msg = ChatMessage::error(ErrorCode::InvalidRequest, &error_text)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                return tcp_handler
+                    .send_message_chunked(error_msg)
+                    .await
+                    .map_err(UserConnectionError::IoError);
+            }
+        };
+
+        let summary = self.polls.lock().await.create(creator, &question, options);
+        logger::log_system(&format!(
+            "{} started poll #{}: {}",
+            creator, summary.id, question
+        ));
+        let poll_message =
+            ChatMessage::try_new(MessageTypes::Poll, Some(summary.to_content().into_bytes()))
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+        self.broadcast(poll_message);
+        Ok(())
+    }
+
+    /// Record a vote (format: poll_id|option_number) and broadcast the new tallies
+    async fn process_vote<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<String>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let (id, option) = content
+            .split_once('|')
+            .and_then(|(id, option)| Some((id.parse::<u64>().ok()?, option.parse::<usize>().ok()?)))
+            .ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(voter) = chat_name else {
+            logger::log_warning(&format!("User at {} voted before joining", self.addr));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+
+        let result = self.polls.lock().await.vote(id, voter, option);
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(e) => {
+                let error_text = match e {
+                    VoteError::UnknownPoll(id) => format!("No open poll #{}", id),
+                    VoteError::InvalidOption { options } => {
+                        format!("Choose an option from 1 to {}", options)
+                    }
+                };
+                let error_msg = ChatMessage::error(ErrorCode::InvalidRequest, &error_text)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                return tcp_handler
+                    .send_message_chunked(error_msg)
+                    .await
+                    .map_err(UserConnectionError::IoError);
+            }
+        };
+
+        let result_message = ChatMessage::try_new(
+            MessageTypes::PollResult,
+            Some(summary.to_content().into_bytes()),
+        )
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        self.broadcast(result_message);
+        Ok(())
+    }
+
     async fn process_rename_request<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         new_name: Option<String>,
@@ -420,9 +1678,9 @@ impl<'a> MessageHandlers<'a> {
                 self.addr,
                 new_name.len()
             ));
-            let error_msg = ChatMessage::try_new(
-                MessageTypes::Error,
-                Some(b"Invalid username length (1-32 characters)".to_vec()),
+            let error_msg = ChatMessage::error(
+                ErrorCode::InvalidRequest,
+                "Invalid username length (1-32 characters)",
             )
             .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
@@ -441,11 +1699,9 @@ impl<'a> MessageHandlers<'a> {
                 "Invalid username characters for rename from {}: {}",
                 self.addr, new_name
             ));
-            let error_msg = ChatMessage::try_new(
-                MessageTypes::Error,
-                Some(
-                    b"Invalid characters (only alphanumeric, underscore, hyphen allowed)".to_vec(),
-                ),
+            let error_msg = ChatMessage::error(
+                ErrorCode::InvalidRequest,
+                "Invalid characters (only alphanumeric, underscore, hyphen allowed)",
             )
             .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
@@ -470,14 +1726,16 @@ impl<'a> MessageHandlers<'a> {
         // Try to claim the new name
         let mut clients = self.connected_clients.write().await;
 
-        // Check if new name is already taken
-        if clients.contains(&new_name) {
+        // Check if new name is already taken, here or on a linked server
+        if clients.contains(&new_name) || self.remote_users.read().await.contains(&new_name) {
+            let suggestion = self.suggest_free_username(&new_name, &clients);
             drop(clients);
-            let error_msg = ChatMessage::try_new(
-                MessageTypes::Error,
-                Some(format!("Username '{}' is already taken", new_name).into_bytes()),
-            )
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            let error_text = format!(
+                "Username '{}' is already taken{}{}",
+                new_name, NAME_SUGGESTION_MARKER, suggestion
+            );
+            let error_msg = ChatMessage::error(ErrorCode::NameTaken, &error_text)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
                 .send_message_chunked(error_msg)
                 .await
@@ -497,6 +1755,21 @@ impl<'a> MessageHandlers<'a> {
         }
         drop(ips);
 
+        let mut readonly_users = self.readonly_users.write().await;
+        if readonly_users.remove(&old_name) {
+            readonly_users.insert(new_name.clone());
+        }
+        drop(readonly_users);
+
+        let mut operators = self.operators.write().await;
+        if operators.remove(&old_name) {
+            operators.insert(new_name.clone());
+        }
+        drop(operators);
+
+        // Peers address transfers by name, so pending ones can't complete
+        self.transfers.lock().await.release_user(&old_name);
+
         // Update the chat_name
         *chat_name = Some(new_name.clone());
 
@@ -514,13 +1787,11 @@ impl<'a> MessageHandlers<'a> {
             .map_err(UserConnectionError::IoError)?;
 
         // Broadcast rename announcement to all clients
-        let announcement = format!("{} is now known as {}", old_name, new_name);
+        let announcement = rename_announcement(&old_name, &new_name);
         let broadcast_message =
             ChatMessage::try_new(MessageTypes::ChatMessage, Some(announcement.into_bytes()))
                 .map_err(|_| UserConnectionError::InvalidMessage)?;
-        self.tx
-            .send((broadcast_message, self.addr))
-            .map_err(UserConnectionError::BroadcastError)?;
+        self.broadcast(broadcast_message);
 
         Ok(())
     }
@@ -530,6 +1801,7 @@ impl<'a> MessageHandlers<'a> {
         content: Option<&[u8]>,
         tcp_handler: &mut StreamWrapper<'_, S>,
         chat_name: &Option<String>,
+        file_rate_limiter: &mut ByteRateLimiter,
     ) -> Result<(), UserConnectionError> {
         let content = content.ok_or(UserConnectionError::InvalidMessage)?;
 
@@ -546,32 +1818,28 @@ impl<'a> MessageHandlers<'a> {
         };
 
         // Parse binary format: recipient_len(1)|recipient|filename_len(1)|filename|filedata
-        if content.len() < 2 {
-            logger::log_warning(&format!("Invalid file transfer format from {}", self.addr));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let recipient_len = content[0] as usize;
-        if content.len() < 1 + recipient_len + 1 {
-            logger::log_warning(&format!("Invalid file transfer format from {}", self.addr));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let recipient = std::str::from_utf8(&content[1..1 + recipient_len])
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
-
-        let filename_len = content[1 + recipient_len] as usize;
-        let filename_start = 1 + recipient_len + 1;
-        if content.len() < filename_start + filename_len {
-            logger::log_warning(&format!("Invalid file transfer format from {}", self.addr));
-            return Err(UserConnectionError::InvalidMessage);
+        let header = FileTransferHeader::parse(content).map_err(|e| {
+            logger::log_warning(&format!("Invalid file transfer from {}: {}", self.addr, e));
+            UserConnectionError::InvalidMessage
+        })?;
+        let (recipient, filename, file_data) = (header.recipient, header.filename, header.data);
+
+        // Clients that skip the request step still can't push blocked types
+        if !self.file_policy.allows(filename) {
+            logger::log_warning(&format!(
+                "[FILE] {} -> {} ('{}' blocked by file type policy)",
+                sender, recipient, filename
+            ));
+            let error_msg = format!("File type of '{}' is not allowed on this server", filename);
+            let error_message = ChatMessage::error(ErrorCode::NotPermitted, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
         }
 
-        let filename = std::str::from_utf8(&content[filename_start..filename_start + filename_len])
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
-
-        let file_data = &content[filename_start + filename_len..];
-
         // Check if recipient exists
         let clients = self.connected_clients.read().await;
         if !clients.contains(recipient) {
@@ -581,9 +1849,8 @@ impl<'a> MessageHandlers<'a> {
                 "[FILE] {} -> {} (user not found)",
                 sender, recipient
             ));
-            let error_message =
-                ChatMessage::try_new(MessageTypes::Error, Some(error_msg.into_bytes()))
-                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+            let error_message = ChatMessage::error(ErrorCode::UserNotFound, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
                 .send_message_chunked(error_message)
                 .await
@@ -592,6 +1859,15 @@ impl<'a> MessageHandlers<'a> {
         }
         drop(clients);
 
+        // Files are relayed whole; the byte-rate limiter spaces them out
+        if let Some(wait) = file_rate_limiter.pending() {
+            logger::log_warning(&format!(
+                "[FILE] {} -> {} refused: file rate reached",
+                sender, recipient
+            ));
+            return send_file_busy(tcp_handler, wait).await;
+        }
+
         logger::log_system(&format!(
             "[FILE] {} -> {} ('{}', {} bytes)",
             sender,
@@ -600,33 +1876,219 @@ impl<'a> MessageHandlers<'a> {
             file_data.len()
         ));
 
-        // Build outgoing message with sender instead of recipient
-        // Format: sender_len(1)|sender|filename_len(1)|filename|filedata
-        let mut outgoing_content = Vec::new();
-        outgoing_content.push(sender.len() as u8);
-        outgoing_content.extend_from_slice(sender.as_bytes());
-        outgoing_content.push(filename.len() as u8);
-        outgoing_content.extend_from_slice(filename.as_bytes());
-        outgoing_content.extend_from_slice(file_data);
-
-        // We need to include recipient info for client filtering
-        // Prepend recipient_len(1)|recipient to the outgoing content
-        let mut final_content = Vec::new();
-        final_content.push(recipient.len() as u8);
-        final_content.extend_from_slice(recipient.as_bytes());
-        final_content.extend_from_slice(&outgoing_content);
+        // Build outgoing message with the sender added after the recipient
+        // Format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filedata
+        let final_content = FileTransferHeader {
+            sender: Some(&sender),
+            ..header
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
 
+        let payload_len = final_content.len();
         let file_message = ChatMessage::try_new(MessageTypes::FileTransfer, Some(final_content))
             .map_err(|_| UserConnectionError::InvalidMessage)?;
 
-        // Broadcast to all clients (recipient will filter)
-        self.tx
-            .send((file_message, self.addr))
-            .map_err(UserConnectionError::BroadcastError)?;
+        // Deliver only to the recipient's connection
+        if !deliver_direct(self.user_channels, recipient, file_message).await {
+            logger::log_warning(&format!(
+                "[FILE] {} -> {} (delivery failed)",
+                sender, recipient
+            ));
+            let error_message = ChatMessage::error(
+                ErrorCode::DeliveryFailed,
+                &format!(
+                    "Could not deliver file to '{}' (they may still be receiving another file)",
+                    recipient
+                ),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            return tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError);
+        }
+        file_rate_limiter.charge(payload_len);
+
+        Ok(())
+    }
+
+    /// Keep a file shared with the room (format: filename|filedata) and tell
+    /// everyone how to fetch it. Uploads are charged to the file rate limiter
+    /// like direct transfers.
+    async fn process_file_offer<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<&[u8]>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+        file_rate_limiter: &mut ByteRateLimiter,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(sender) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} tried to share a file before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+        let upload = FileUploadHeader::parse(content).map_err(|e| {
+            logger::log_warning(&format!("Invalid file share from {}: {}", self.addr, e));
+            UserConnectionError::InvalidMessage
+        })?;
+
+        if !self.file_policy.allows(upload.filename) {
+            logger::log_warning(&format!(
+                "[SHARE] {} '{}' blocked by file type policy",
+                sender, upload.filename
+            ));
+            let error_text = format!(
+                "File type of '{}' is not allowed on this server",
+                upload.filename
+            );
+            let error_msg = ChatMessage::error(ErrorCode::NotPermitted, &error_text)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+            return tcp_handler
+                .send_message_chunked(error_msg)
+                .await
+                .map_err(UserConnectionError::IoError);
+        }
+
+        if let Some(wait) = file_rate_limiter.pending() {
+            return send_file_busy(tcp_handler, wait).await;
+        }
+
+        // Everyone else online now is expected to fetch it
+        let mut members = self.connected_clients.read().await.clone();
+        members.remove(sender);
+        let shared = self.file_cache.lock().await.share(
+            sender,
+            upload.filename,
+            upload.data,
+            members,
+            Instant::now(),
+        );
+        let file_id = match shared {
+            Ok(file_id) => file_id,
+            Err(e) => {
+                let (code, error_text) = match e {
+                    ShareError::TooLarge { max } => (
+                        ErrorCode::InvalidRequest,
+                        format!("Shared files can be at most {} MB", max / (1024 * 1024)),
+                    ),
+                    ShareError::QuotaExceeded { quota } => (
+                        ErrorCode::RateLimited,
+                        format!(
+                            "Your shared files already take {} MB; wait for them to be fetched or expire",
+                            quota / (1024 * 1024)
+                        ),
+                    ),
+                    ShareError::Full => (
+                        ErrorCode::Other,
+                        "Too many files are shared right now, try again later".to_string(),
+                    ),
+                };
+                logger::log_warning(&format!(
+                    "[SHARE] {} '{}' refused: {}",
+                    sender, upload.filename, error_text
+                ));
+                let error_msg = ChatMessage::error(code, &error_text)
+                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+                return tcp_handler
+                    .send_message_chunked(error_msg)
+                    .await
+                    .map_err(UserConnectionError::IoError);
+            }
+        };
 
+        // Not in debt (checked above), so this only holds back the next file
+        file_rate_limiter.charge(upload.data.len());
+        logger::log_system(&format!(
+            "[SHARE] {} shared '{}' as #{} ({} bytes)",
+            sender,
+            upload.filename,
+            file_id,
+            upload.data.len()
+        ));
+        let offer = FileOfferHeader {
+            file_id,
+            sender,
+            filename: upload.filename,
+            file_size: upload.data.len() as u64,
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        let offer_message = ChatMessage::try_new(MessageTypes::FileOffer, Some(offer))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        self.broadcast(offer_message);
         Ok(())
     }
 
+    /// Send a shared file (format: file_id(8)) to the user asking for it,
+    /// charged to the file rate limiter like a direct file transfer
+    async fn process_file_get<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<&[u8]>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+        file_rate_limiter: &mut ByteRateLimiter,
+    ) -> Result<(), UserConnectionError> {
+        let file_id = content
+            .and_then(|content| <[u8; 8]>::try_from(content).ok())
+            .map(u64::from_be_bytes)
+            .ok_or(UserConnectionError::InvalidMessage)?;
+        let Some(requester) = chat_name else {
+            logger::log_warning(&format!(
+                "User at {} asked for a shared file before joining",
+                self.addr
+            ));
+            return Err(UserConnectionError::InvalidMessage);
+        };
+        // Checked before fetching, so a refused /get doesn't count as fetched
+        // and no copy of the file is made
+        if let Some(wait) = file_rate_limiter.pending() {
+            return send_file_busy(tcp_handler, wait).await;
+        }
+
+        let fetched = self
+            .file_cache
+            .lock()
+            .await
+            .fetch(file_id, requester, Instant::now());
+        let Some(file) = fetched else {
+            let error_msg = ChatMessage::error(
+                ErrorCode::InvalidRequest,
+                &format!("No shared file #{} (it may have expired)", file_id),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            return tcp_handler
+                .send_message_chunked(error_msg)
+                .await
+                .map_err(UserConnectionError::IoError);
+        };
+
+        logger::log_system(&format!(
+            "[SHARE] {} fetched #{} ('{}')",
+            requester, file_id, file.filename
+        ));
+        let content = FileTransferHeader {
+            recipient: requester,
+            sender: Some(&file.sender),
+            filename: &file.filename,
+            data: &file.data,
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        let payload_len = content.len();
+        let file_message = ChatMessage::try_new(MessageTypes::FileGet, Some(content))
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+
+        file_rate_limiter.charge(payload_len);
+        tcp_handler
+            .send_message_chunked(file_message)
+            .await
+            .map_err(UserConnectionError::IoError)
+    }
+
     async fn process_file_transfer_request<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
         content: Option<&[u8]>,
@@ -647,52 +2109,33 @@ impl<'a> MessageHandlers<'a> {
             }
         };
 
-        // Parse binary format: recipient_len(1)|recipient|filename_len(1)|filename|filesize(8 bytes)
-        if content.len() < 2 {
+        // Parse binary format: recipient_len(1)|recipient|filename_len(1)|filename|filesize(8 bytes)[|caption]
+        let request = FileRequestHeader::parse(content).map_err(|e| {
             logger::log_warning(&format!(
-                "Invalid file transfer request format from {}",
-                self.addr
-            ));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let recipient_len = content[0] as usize;
-        if content.len() < 1 + recipient_len + 1 {
-            logger::log_warning(&format!(
-                "Invalid file transfer request format from {}",
-                self.addr
+                "Invalid file transfer request from {}: {}",
+                self.addr, e
             ));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let recipient = std::str::from_utf8(&content[1..1 + recipient_len])
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            UserConnectionError::InvalidMessage
+        })?;
+        let (recipient, filename, file_size) =
+            (request.recipient, request.filename, request.file_size);
 
-        let filename_len = content[1 + recipient_len] as usize;
-        let filename_start = 1 + recipient_len + 1;
-        if content.len() < filename_start + filename_len + 8 {
+        // Enforce the operator's file type policy before any data is sent
+        if !self.file_policy.allows(filename) {
             logger::log_warning(&format!(
-                "Invalid file transfer request format from {}",
-                self.addr
+                "[FILE REQUEST] {} -> {} ('{}' blocked by file type policy)",
+                sender, recipient, filename
             ));
-            return Err(UserConnectionError::InvalidMessage);
+            let error_msg = format!("File type of '{}' is not allowed on this server", filename);
+            let error_message = ChatMessage::error(ErrorCode::NotPermitted, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
         }
 
-        let filename = std::str::from_utf8(&content[filename_start..filename_start + filename_len])
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
-
-        let size_start = filename_start + filename_len;
-        let file_size = u64::from_be_bytes([
-            content[size_start],
-            content[size_start + 1],
-            content[size_start + 2],
-            content[size_start + 3],
-            content[size_start + 4],
-            content[size_start + 5],
-            content[size_start + 6],
-            content[size_start + 7],
-        ]);
-
         // Check if recipient exists
         let clients = self.connected_clients.read().await;
         if !clients.contains(recipient) {
@@ -702,9 +2145,8 @@ impl<'a> MessageHandlers<'a> {
                 "[FILE REQUEST] {} -> {} (user not found)",
                 sender, recipient
             ));
-            let error_message =
-                ChatMessage::try_new(MessageTypes::Error, Some(error_msg.into_bytes()))
-                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+            let error_message = ChatMessage::error(ErrorCode::UserNotFound, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
                 .send_message_chunked(error_message)
                 .await
@@ -713,30 +2155,67 @@ impl<'a> MessageHandlers<'a> {
         }
         drop(clients);
 
+        // Cap how many transfers one user can have waiting on recipients
+        let max_transfers = self.limits.max_file_transfers;
+        let mut transfers = self.transfers.lock().await;
+        if !transfers.try_start(&sender, recipient, max_transfers) {
+            logger::log_warning(&format!(
+                "[FILE REQUEST] {} -> {} ({} transfers already in progress)",
+                sender,
+                recipient,
+                transfers.count(&sender)
+            ));
+            drop(transfers);
+            let error_msg = format!(
+                "Too many file transfers in progress (max {})",
+                max_transfers
+            );
+            let error_message = ChatMessage::error(ErrorCode::RateLimited, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
+        }
+        drop(transfers);
+
         logger::log_system(&format!(
             "[FILE REQUEST] {} -> {} ('{}', {} bytes)",
             sender, recipient, filename, file_size
         ));
 
         // Build outgoing message with sender info
-        // Format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filesize(8 bytes)
-        let mut outgoing_content = Vec::new();
-        outgoing_content.push(recipient.len() as u8);
-        outgoing_content.extend_from_slice(recipient.as_bytes());
-        outgoing_content.push(sender.len() as u8);
-        outgoing_content.extend_from_slice(sender.as_bytes());
-        outgoing_content.push(filename.len() as u8);
-        outgoing_content.extend_from_slice(filename.as_bytes());
-        outgoing_content.extend_from_slice(&file_size.to_be_bytes());
+        // Format: recipient_len(1)|recipient|sender_len(1)|sender|filename_len(1)|filename|filesize(8 bytes)[|caption]
+        // The optional caption is relayed untouched
+        let outgoing_content = FileRequestHeader {
+            sender: Some(&sender),
+            ..request
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
 
         let request_message =
             ChatMessage::try_new(MessageTypes::FileTransferRequest, Some(outgoing_content))
                 .map_err(|_| UserConnectionError::InvalidMessage)?;
 
-        // Broadcast to all clients (recipient will filter)
-        self.tx
-            .send((request_message, self.addr))
-            .map_err(UserConnectionError::BroadcastError)?;
+        // Deliver only to the recipient's connection
+        if !deliver_direct(self.user_channels, recipient, request_message).await {
+            logger::log_warning(&format!(
+                "[FILE REQUEST] {} -> {} (delivery failed)",
+                sender, recipient
+            ));
+            self.transfers.lock().await.finish(&sender, recipient);
+            let error_message = ChatMessage::error(
+                ErrorCode::DeliveryFailed,
+                &format!("Could not deliver file request to '{}'", recipient),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+        }
 
         Ok(())
     }
@@ -763,27 +2242,14 @@ impl<'a> MessageHandlers<'a> {
 
         // Parse binary format: sender_len(1)|sender|accepted(1)
         // sender here is the original file sender (who we're responding to)
-        if content.len() < 3 {
-            logger::log_warning(&format!(
-                "Invalid file transfer response format from {}",
-                self.addr
-            ));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let original_sender_len = content[0] as usize;
-        if content.len() < 1 + original_sender_len + 1 {
+        let response = FileResponseHeader::parse(content).map_err(|e| {
             logger::log_warning(&format!(
-                "Invalid file transfer response format from {}",
-                self.addr
+                "Invalid file transfer response from {}: {}",
+                self.addr, e
             ));
-            return Err(UserConnectionError::InvalidMessage);
-        }
-
-        let original_sender = std::str::from_utf8(&content[1..1 + original_sender_len])
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
-
-        let accepted = content[1 + original_sender_len] == 1;
+            UserConnectionError::InvalidMessage
+        })?;
+        let (original_sender, accepted) = (response.file_sender, response.accepted);
 
         // Check if original sender exists
         let clients = self.connected_clients.read().await;
@@ -794,9 +2260,8 @@ impl<'a> MessageHandlers<'a> {
                 "[FILE RESPONSE] {} -> {} (user not found)",
                 responder, original_sender
             ));
-            let error_message =
-                ChatMessage::try_new(MessageTypes::Error, Some(error_msg.into_bytes()))
-                    .map_err(|_| UserConnectionError::InvalidMessage)?;
+            let error_message = ChatMessage::error(ErrorCode::UserNotFound, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
                 .send_message_chunked(error_message)
                 .await
@@ -805,6 +2270,14 @@ impl<'a> MessageHandlers<'a> {
         }
         drop(clients);
 
+        // A rejected request no longer counts against the sender's cap
+        if !accepted {
+            self.transfers
+                .lock()
+                .await
+                .finish(original_sender, &responder);
+        }
+
         logger::log_system(&format!(
             "[FILE RESPONSE] {} {} file from {}",
             responder,
@@ -816,21 +2289,122 @@ impl<'a> MessageHandlers<'a> {
         // Format: recipient_len(1)|recipient|sender_len(1)|sender|accepted(1)
         // recipient = original sender (who receives this response)
         // sender = responder (who accepted/rejected)
-        let mut outgoing_content = Vec::new();
-        outgoing_content.push(original_sender.len() as u8);
-        outgoing_content.extend_from_slice(original_sender.as_bytes());
-        outgoing_content.push(responder.len() as u8);
-        outgoing_content.extend_from_slice(responder.as_bytes());
-        outgoing_content.push(if accepted { 1u8 } else { 0u8 });
+        let outgoing_content = FileResponseHeader {
+            responder: Some(&responder),
+            ..response
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
 
         let response_message =
             ChatMessage::try_new(MessageTypes::FileTransferResponse, Some(outgoing_content))
                 .map_err(|_| UserConnectionError::InvalidMessage)?;
 
-        // Broadcast to all clients (original sender will filter)
-        self.tx
-            .send((response_message, self.addr))
-            .map_err(UserConnectionError::BroadcastError)?;
+        // Deliver only to the original sender's connection
+        if !deliver_direct(self.user_channels, original_sender, response_message).await {
+            logger::log_warning(&format!(
+                "[FILE RESPONSE] {} -> {} (delivery failed)",
+                responder, original_sender
+            ));
+            let error_message = ChatMessage::error(
+                ErrorCode::DeliveryFailed,
+                &format!("Could not deliver response to '{}'", original_sender),
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+        }
+
+        Ok(())
+    }
+
+    async fn process_file_transfer_ack<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        content: Option<&[u8]>,
+        tcp_handler: &mut StreamWrapper<'_, S>,
+        chat_name: &Option<String>,
+    ) -> Result<(), UserConnectionError> {
+        let content = content.ok_or(UserConnectionError::InvalidMessage)?;
+
+        // Check if user has joined
+        let receiver = match chat_name {
+            Some(name) => name.clone(),
+            None => {
+                logger::log_warning(&format!(
+                    "User at {} tried to send file ack before joining",
+                    self.addr
+                ));
+                return Err(UserConnectionError::InvalidMessage);
+            }
+        };
+
+        // Parse binary format: sender_len(1)|sender|success(1)|filename_len(1)|filename|error
+        // sender here is the original file sender (who we're acknowledging)
+        let ack = FileAckHeader::parse(content).map_err(|e| {
+            logger::log_warning(&format!(
+                "Invalid file transfer ack from {}: {}",
+                self.addr, e
+            ));
+            UserConnectionError::InvalidMessage
+        })?;
+        let (original_sender, saved) = (ack.file_sender, ack.saved);
+
+        // Check if original sender exists
+        let clients = self.connected_clients.read().await;
+        if !clients.contains(original_sender) {
+            drop(clients);
+            let error_msg = format!("User '{}' not found", original_sender);
+            logger::log_warning(&format!(
+                "[FILE ACK] {} -> {} (user not found)",
+                receiver, original_sender
+            ));
+            let error_message = ChatMessage::error(ErrorCode::UserNotFound, &error_msg)
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+            tcp_handler
+                .send_message_chunked(error_message)
+                .await
+                .map_err(UserConnectionError::IoError)?;
+            return Ok(());
+        }
+        drop(clients);
+
+        // The transfer is over whether or not the file was saved
+        self.transfers
+            .lock()
+            .await
+            .finish(original_sender, &receiver);
+
+        logger::log_system(&format!(
+            "[FILE ACK] {} {} file from {}",
+            receiver,
+            if saved { "saved" } else { "failed to save" },
+            original_sender
+        ));
+
+        // Build outgoing message
+        // Format: recipient_len(1)|recipient|receiver_len(1)|receiver|success(1)|filename_len(1)|filename|error
+        // recipient = original sender (who receives this ack)
+        // receiver = user who received the file
+        let outgoing_content = FileAckHeader {
+            receiver: Some(&receiver),
+            ..ack
+        }
+        .encode()
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+
+        let ack_message =
+            ChatMessage::try_new(MessageTypes::FileTransferAck, Some(outgoing_content))
+                .map_err(|_| UserConnectionError::InvalidMessage)?;
+
+        // Deliver only to the original sender's connection
+        if !deliver_direct(self.user_channels, original_sender, ack_message).await {
+            logger::log_warning(&format!(
+                "[FILE ACK] {} -> {} (delivery failed)",
+                receiver, original_sender
+            ));
+        }
 
         Ok(())
     }
@@ -853,15 +2427,17 @@ impl<'a> MessageHandlers<'a> {
             }
         };
 
-        let status_text = status.unwrap_or_default();
+        // Statuses are shown in /list output, so drop control characters
+        let status_text = limits::strip_control_chars(&status.unwrap_or_default())
+            .trim()
+            .to_string();
 
         // Validate status length
-        if status_text.len() > MAX_STATUS_LENGTH {
-            let error_msg = ChatMessage::try_new(
-                MessageTypes::Error,
-                Some(
-                    format!("Status too long (max {} characters)", MAX_STATUS_LENGTH).into_bytes(),
-                ),
+        let max_status_length = self.limits.max_status_length;
+        if status_text.len() > max_status_length {
+            let error_msg = ChatMessage::error(
+                ErrorCode::InvalidRequest,
+                &format!("Status too long (max {} characters)", max_status_length),
             )
             .map_err(|_| UserConnectionError::InvalidMessage)?;
             tcp_handler
@@ -898,46 +2474,6 @@ impl<'a> MessageHandlers<'a> {
 
         Ok(())
     }
-
-    async fn process_version_check<S: AsyncRead + AsyncWrite + Unpin>(
-        &self,
-        client_version: Option<String>,
-        tcp_handler: &mut StreamWrapper<'_, S>,
-    ) -> Result<(), UserConnectionError> {
-        let client_version = client_version.ok_or(UserConnectionError::InvalidMessage)?;
-
-        if !version::versions_compatible(&client_version, VERSION) {
-            logger::log_warning(&format!(
-                "Version mismatch from {}: client v{} != server v{}",
-                self.addr, client_version, VERSION
-            ));
-
-            // Send version mismatch error with details
-            let mismatch_content = format!(
-                "{}|{}|{}",
-                client_version,
-                VERSION,
-                version::GITHUB_README_URL
-            );
-            let mismatch_msg = ChatMessage::try_new(
-                MessageTypes::VersionMismatch,
-                Some(mismatch_content.into_bytes()),
-            )
-            .map_err(|_| UserConnectionError::InvalidMessage)?;
-            tcp_handler
-                .send_message_chunked(mismatch_msg)
-                .await
-                .map_err(UserConnectionError::IoError)?;
-
-            return Err(UserConnectionError::VersionMismatch);
-        }
-
-        logger::log_info(&format!(
-            "Version check passed for {}: v{}",
-            self.addr, client_version
-        ));
-        Ok(())
-    }
 }
 
 #[cfg(test)]
@@ -1032,4 +2568,24 @@ mod tests {
         let too_long = "x".repeat(MAX_MESSAGE_LENGTH + 1);
         assert!(too_long.len() > MAX_MESSAGE_LENGTH);
     }
+
+    #[test]
+    fn test_e2e_message_length_fits_max_ciphertext() {
+        // prefix + hex(24-byte nonce + max plaintext + 16-byte tag)
+        let max_ciphertext = E2E_DM_PREFIX.len() + (24 + MAX_MESSAGE_LENGTH + 16) * 2;
+        assert!(max_ciphertext <= MAX_E2E_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn test_slowmode_remaining() {
+        let just_now = Some(Instant::now());
+        assert!(slowmode_remaining(Duration::ZERO, just_now).is_none());
+        assert!(slowmode_remaining(Duration::from_secs(10), None).is_none());
+
+        let wait = slowmode_remaining(Duration::from_secs(10), just_now).unwrap();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+
+        let long_ago = Instant::now().checked_sub(Duration::from_secs(20));
+        assert!(slowmode_remaining(Duration::from_secs(10), long_ago).is_none());
+    }
 }
@@ -0,0 +1,128 @@
+use shared::compression;
+use shared::message::ChatMessage;
+use shared::network::{MAX_FILE_SIZE, configured_chunk_size};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Default max client messages read ahead of processing per connection
+pub const INBOUND_BUFFER_MESSAGES: usize = 16;
+
+/// Incremental, cancel-safe reader for client frames. Partial frames stay
+/// buffered between calls, so it can race other `select!` branches where
+/// `read_message_chunked` would lose its place.
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Read whatever the socket has available; Ok(0) means the client closed
+    /// the connection
+    pub async fn fill<S: AsyncRead + Unpin>(&mut self, stream: &mut S) -> io::Result<usize> {
+        self.buf.reserve(configured_chunk_size());
+        stream.read_buf(&mut self.buf).await
+    }
+
+    /// Take the next frame off the buffer once all of it has arrived.
+    /// Compressed frames are a protocol error unless the client negotiated
+    /// `compression`.
+    pub fn next_frame(&mut self, compression: bool) -> io::Result<Option<ChatMessage>> {
+        let Some(&[a, b, c, d]) = self.buf.get(..4) else {
+            return Ok(None);
+        };
+        let msg_len = u32::from_be_bytes([a, b, c, d]) as usize;
+        if msg_len > MAX_FILE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message exceeds maximum size",
+            ));
+        }
+        if self.buf.len() < 4 + msg_len {
+            return Ok(None);
+        }
+
+        let message_bytes = self.buf.drain(..4 + msg_len).skip(4).collect();
+        let message_bytes = compression::decompress_frame(message_bytes, compression)?;
+        ChatMessage::try_from_bytes(message_bytes)
+            .map(Some)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Message length does not match frame size",
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::message::MessageTypes;
+    use tokio::io::AsyncWriteExt;
+
+    fn frame(msg_type: MessageTypes, content: &str) -> Vec<u8> {
+        let message_bytes: Vec<u8> =
+            ChatMessage::try_new(msg_type, Some(content.as_bytes().to_vec()))
+                .unwrap()
+                .into();
+        let mut frame = (message_bytes.len() as u32).to_be_bytes().to_vec();
+        frame.extend(message_bytes);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_frames_reassembled_across_reads() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let mut reader = FrameReader::default();
+
+        let mut bytes = frame(MessageTypes::ChatMessage, "hello");
+        bytes.extend(frame(MessageTypes::ChatMessage, "world"));
+        let (first, rest) = bytes.split_at(7);
+
+        client.write_all(first).await.unwrap();
+        assert_eq!(reader.fill(&mut server).await.unwrap(), 7);
+        assert!(reader.next_frame(false).unwrap().is_none());
+
+        client.write_all(rest).await.unwrap();
+        reader.fill(&mut server).await.unwrap();
+        let hello = reader.next_frame(false).unwrap().unwrap();
+        assert_eq!(hello.get_content(), Some(b"hello".as_slice()));
+        let world = reader.next_frame(false).unwrap().unwrap();
+        assert_eq!(world.get_content(), Some(b"world".as_slice()));
+        assert!(reader.next_frame(false).unwrap().is_none());
+
+        drop(client);
+        assert_eq!(reader.fill(&mut server).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compressed_frame_decoded_once_negotiated() {
+        let text = "compressible ".repeat(100);
+        let message_bytes: Vec<u8> =
+            ChatMessage::try_new(MessageTypes::ChatMessage, Some(text.as_bytes().to_vec()))
+                .unwrap()
+                .into();
+        let payload = compression::compress_frame(message_bytes);
+        let mut reader = FrameReader::default();
+        reader
+            .buf
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        reader.buf.extend(payload);
+        let mut unnegotiated = FrameReader {
+            buf: reader.buf.clone(),
+        };
+        assert!(unnegotiated.next_frame(false).is_err());
+
+        let message = reader.next_frame(true).unwrap().unwrap();
+        assert_eq!(message.content_as_string(), Some(text));
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected() {
+        let mut reader = FrameReader::default();
+        reader
+            .buf
+            .extend_from_slice(&(MAX_FILE_SIZE as u32 + 1).to_be_bytes());
+        assert!(reader.next_frame(false).is_err());
+    }
+}
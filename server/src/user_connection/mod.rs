@@ -1,30 +1,81 @@
+mod away;
+mod blocks;
 mod error;
+mod file_cache;
 mod handlers;
+mod inbound;
+mod open_connections;
+mod pages;
+mod polls;
+mod queue;
 mod rate_limiting;
+mod takeovers;
+#[cfg(test)]
+mod testkit;
+mod transfers;
 
+pub use away::AwayReplies;
+pub use blocks::BlockList;
 pub use error::UserConnectionError;
-use handlers::MessageHandlers;
-use rate_limiting::{RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW, RateLimiter};
+pub use file_cache::FileCache;
+use handlers::{LastChat, MessageHandlers};
+use inbound::FrameReader;
+pub use inbound::INBOUND_BUFFER_MESSAGES;
+pub use open_connections::{ConnectionPhase, OpenConnections, killed};
+pub use pages::PageLimiter;
+pub use polls::PollBoard;
+pub use queue::{Admission, ConnectionQueue, QueueTicket};
+pub use rate_limiting::{
+    BYTE_BUDGET_PER_MINUTE, DUPLICATE_WINDOW_MS, JOIN_RATE_LIMIT_PER_MINUTE,
+    JOIN_RATE_LIMIT_WINDOW, JoinRateLimiter,
+};
+use rate_limiting::{ByteBudget, ByteRateLimiter, RateLimiter, connection_limits};
+pub use takeovers::TokenlessTakeovers;
+pub use transfers::TransferTracker;
+
+use crate::history::{History, HistoryKind};
+use crate::rdns::{ReverseDns, describe_addr};
+use crate::user_registry::UserRegistry;
+use crate::webhook::{Webhook, WebhookEvent};
 
 use crate::ServerCommand;
+use crate::federation::RemoteUsers;
+use crate::file_policy::FileTypePolicy;
+use crate::motd::Motd;
+use shared::compression::COMPRESSION_ALGORITHM;
+use shared::limits::ServerLimits;
 use shared::logger;
-use shared::message::{ChatMessage, MessageTypes};
-use shared::network::{TcpMessageHandler, TcpMessageHandlerError};
-use std::collections::{HashMap, HashSet};
+use shared::message::{
+    ChatMessage, ErrorCode, MessageTypes, SERVER_FULL_ERROR, rename_announcement,
+};
+use shared::network::TcpMessageHandler;
+use shared::version::{self, VERSION};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{Context, Poll};
-use std::time::{Duration, Instant};
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
-use tokio::sync::{RwLock, broadcast};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, broadcast, mpsc, watch};
 use tokio_rustls::server::TlsStream;
 
 /// How often to send ping messages to clients
 const PING_INTERVAL: Duration = Duration::from_secs(30);
 /// How long to wait for a pong response before considering the client dead
 const PONG_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a new connection may take to complete its join
+const JOIN_DEADLINE: Duration = Duration::from_secs(10);
+/// Max queued point-to-point messages per connection
+const DIRECT_CHANNEL_CAPACITY: usize = 64;
+/// Max queued file payloads per connection; each can be up to `MAX_FILE_SIZE`
+const FILE_CHANNEL_CAPACITY: usize = 1;
+/// How long a rejected or queued client gets to send its version check and join
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often queued clients are told their position in line
+const QUEUE_NOTICE_INTERVAL: Duration = Duration::from_secs(10);
 
 pub enum ConnectionStream {
     Plain(TcpStream),
@@ -71,21 +122,155 @@ impl AsyncWrite for ConnectionStream {
     }
 }
 
+/// A connection's point-to-point queues. File payloads get their own small
+/// queue, so a slow recipient holds at most `FILE_CHANNEL_CAPACITY` of them
+/// on top of the one being written, rather than a full queue of files.
+#[derive(Clone)]
+pub struct DirectRoute {
+    /// DMs, pages, file requests and other chat-sized messages
+    messages: mpsc::Sender<ChatMessage>,
+    /// File payloads
+    files: mpsc::Sender<ChatMessage>,
+}
+
+impl DirectRoute {
+    /// A route with fresh queues, plus their receiving ends (messages, files)
+    pub fn open() -> (
+        Self,
+        mpsc::Receiver<ChatMessage>,
+        mpsc::Receiver<ChatMessage>,
+    ) {
+        let (messages, messages_rx) = mpsc::channel(DIRECT_CHANNEL_CAPACITY);
+        let (files, files_rx) = mpsc::channel(FILE_CHANNEL_CAPACITY);
+        (Self { messages, files }, messages_rx, files_rx)
+    }
+
+    /// Queue `message` without waiting. Returns false if its queue is full or
+    /// the connection has gone.
+    pub fn try_send(&self, message: ChatMessage) -> bool {
+        let queue = match message.msg_type {
+            MessageTypes::FileTransfer | MessageTypes::FileGet => &self.files,
+            _ => &self.messages,
+        };
+        queue.try_send(message).is_ok()
+    }
+
+    /// Whether both routes lead to the same connection
+    fn same_route(&self, other: &DirectRoute) -> bool {
+        self.messages.same_channel(&other.messages)
+    }
+}
+
+/// Server-wide state shared by every connection
+#[derive(Clone)]
+pub struct SharedState {
+    pub tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
+    pub server_commands: broadcast::Sender<ServerCommand>,
+    pub connected_clients: Arc<RwLock<HashSet<String>>>,
+    /// Users online on linked servers
+    pub remote_users: Arc<RwLock<RemoteUsers>>,
+    pub user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
+    pub user_statuses: Arc<RwLock<HashMap<String, String>>>,
+    pub user_sessions: Arc<RwLock<HashMap<String, String>>>,
+    /// Secret issued on each user's last join, required to reclaim the session
+    pub session_secrets: Arc<RwLock<HashMap<String, String>>>,
+    /// Maps username to their connection's point-to-point queues
+    pub user_channels: Arc<RwLock<HashMap<String, DirectRoute>>>,
+    /// Users that joined read-only
+    pub readonly_users: Arc<RwLock<HashSet<String>>>,
+    pub operators: Arc<RwLock<HashSet<String>>>,
+    pub oper_password: Option<Arc<str>>,
+    /// Every open connection by address, with its kill switch
+    pub open_connections: Arc<Mutex<OpenConnections>>,
+    /// Max file transfer bytes per second per connection (0 = unlimited)
+    pub file_rate_bytes_per_sec: u64,
+    /// Max bytes of non-file messages per connection per minute (0 = unlimited)
+    pub byte_budget: usize,
+    /// Max client messages read ahead of processing per connection (at least 1)
+    pub inbound_buffer: usize,
+    /// Addresses exempt from the message rate limit and byte budget
+    pub trusted_ips: Arc<HashSet<IpAddr>>,
+    /// Whether clients may negotiate compressed frames
+    pub compression: bool,
+    /// Message of the day sent after join
+    pub motd: Option<Arc<Motd>>,
+    /// Display name sent to each user after they join
+    pub server_name: Option<Arc<str>>,
+    /// Greeting sent to each user after they join (`{username}` is substituted)
+    pub welcome_message: Option<Arc<str>>,
+    /// Current channel topic
+    pub topic: Arc<RwLock<Option<String>>>,
+    /// Whether any user may change the topic
+    pub open_topic: bool,
+    /// Withhold join/leave announcements from clients (still logged here)
+    pub quiet_joins: bool,
+    /// Limits enforced on client input and advertised after join
+    pub limits: ServerLimits,
+    /// Per-IP join frequency limiter shared across connections
+    pub join_limiter: Arc<Mutex<JoinRateLimiter>>,
+    /// In-flight file transfers per user (capped by `limits.max_file_transfers`)
+    pub transfers: Arc<Mutex<TransferTracker>>,
+    /// Away auto-replies already sent, so each sender gets one per interval
+    pub away_replies: Arc<Mutex<AwayReplies>>,
+    /// Pages sent recently, so each sender can page a user once per interval
+    pub pages: Arc<Mutex<PageLimiter>>,
+    /// Open channel polls, with one vote per user in each
+    pub polls: Arc<Mutex<PollBoard>>,
+    /// Files shared with the whole room, kept for `/get`
+    pub file_cache: Arc<Mutex<FileCache>>,
+    /// Users each user has blocked from DMing them
+    pub blocks: Arc<RwLock<BlockList>>,
+    /// Last-seen times of known users, updated on disconnect
+    pub user_registry: Arc<Mutex<UserRegistry>>,
+    /// File extensions allowed or denied for relay
+    pub file_policy: Arc<FileTypePolicy>,
+    /// Min seconds between each user's chat messages (0 = slow mode off)
+    pub slowmode_secs: Arc<AtomicU64>,
+    /// Repeats of a user's previous message within this window are dropped
+    pub duplicate_window: Duration,
+    /// Grace before a tokenless rejoin from the holder's IP takes a name over
+    pub tokenless_takeover: Option<Duration>,
+    /// Tokenless rejoins waiting out that grace, one per name
+    pub tokenless_takeovers: Arc<Mutex<TokenlessTakeovers>>,
+    /// Relay of join/leave/message events to an external webhook
+    pub webhook: Option<Webhook>,
+    /// Chat history saved to SQLite, for `/search`
+    pub history: Option<History>,
+    /// Hostnames of client addresses for the logs
+    pub reverse_dns: Option<Arc<ReverseDns>>,
+    /// When the server started, for uptime in `/serverinfo`
+    pub started_at: Instant,
+}
+
 pub struct UserConnection {
     socket: ConnectionStream,
     addr: SocketAddr,
-    tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
-    server_commands: broadcast::Sender<ServerCommand>,
-    connected_clients: Arc<RwLock<HashSet<String>>>,
-    user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
-    user_statuses: Arc<RwLock<HashMap<String, String>>>,
-    user_sessions: Arc<RwLock<HashMap<String, String>>>,
+    state: SharedState,
     chat_name: Option<String>,
     rate_limiter: RateLimiter,
+    file_rate_limiter: ByteRateLimiter,
+    /// Rolling cap on the bytes this client sends; exceeding it disconnects
+    byte_budget: ByteBudget,
     /// True if user explicitly quit (vs connection drop which may be a reconnect)
     clear_status_on_disconnect: bool,
     /// True if session was taken over by a reconnecting client - don't clean up username
     session_taken_over: bool,
+    /// This user's last chat message (for slow mode and duplicate filtering)
+    last_chat: Option<LastChat>,
+    /// Buffers client bytes until whole frames have arrived
+    reader: FrameReader,
+    /// Client messages read but not yet processed, starting with any
+    /// handshake read while queued (bounded by `state.inbound_buffer`)
+    inbound: VecDeque<ChatMessage>,
+    /// The client negotiated compression, so large frames to it are compressed
+    compress: bool,
+    /// Reply to a failed version check, held until the client's join
+    version_mismatch: Option<ChatMessage>,
+    /// The client passed the version check, so it understands session secrets
+    version_checked: bool,
+    /// The next join processed is a deferred one, already counted by the join
+    /// limiter
+    retrying_join: bool,
 }
 
 impl TcpMessageHandler for UserConnection {
@@ -93,66 +278,150 @@ impl TcpMessageHandler for UserConnection {
     fn get_stream(&mut self) -> &mut Self::Stream {
         &mut self.socket
     }
+    fn compression(&self) -> bool {
+        self.compress
+    }
 }
 
 impl UserConnection {
-    pub fn new(
-        socket: TcpStream,
-        addr: SocketAddr,
-        tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
-        server_commands: broadcast::Sender<ServerCommand>,
-        connected_clients: Arc<RwLock<HashSet<String>>>,
-        user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
-        user_statuses: Arc<RwLock<HashMap<String, String>>>,
-        user_sessions: Arc<RwLock<HashMap<String, String>>>,
-    ) -> Self {
+    pub fn new(socket: TcpStream, addr: SocketAddr, state: SharedState) -> Self {
+        Self::with_stream(ConnectionStream::Plain(socket), addr, state)
+    }
+
+    pub fn new_tls(socket: TlsStream<TcpStream>, addr: SocketAddr, state: SharedState) -> Self {
+        Self::with_stream(ConnectionStream::Tls(Box::new(socket)), addr, state)
+    }
+
+    fn with_stream(socket: ConnectionStream, addr: SocketAddr, state: SharedState) -> Self {
+        let file_rate_limiter = ByteRateLimiter::new(state.file_rate_bytes_per_sec);
+        let trusted = state.trusted_ips.contains(&addr.ip());
+        if trusted {
+            logger::log_info(&format!("{} is trusted: rate limits not applied", addr));
+        }
+        let (rate_limiter, byte_budget) = connection_limits(trusted, state.byte_budget);
         UserConnection {
-            socket: ConnectionStream::Plain(socket),
+            socket,
             addr,
-            tx,
-            server_commands,
-            connected_clients,
-            user_ips,
-            user_statuses,
-            user_sessions,
+            state,
             chat_name: None,
-            rate_limiter: RateLimiter::new(RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW),
+            rate_limiter,
+            file_rate_limiter,
+            byte_budget,
             clear_status_on_disconnect: false,
             session_taken_over: false,
+            last_chat: None,
+            reader: FrameReader::default(),
+            inbound: VecDeque::new(),
+            compress: false,
+            version_mismatch: None,
+            version_checked: false,
+            retrying_join: false,
         }
     }
 
-    pub fn new_tls(
-        socket: TlsStream<TcpStream>,
-        addr: SocketAddr,
-        tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
-        server_commands: broadcast::Sender<ServerCommand>,
-        connected_clients: Arc<RwLock<HashSet<String>>>,
-        user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
-        user_statuses: Arc<RwLock<HashMap<String, String>>>,
-        user_sessions: Arc<RwLock<HashMap<String, String>>>,
-    ) -> Self {
-        UserConnection {
-            socket: ConnectionStream::Tls(Box::new(socket)),
-            addr,
-            tx,
-            server_commands,
-            connected_clients,
-            user_ips,
-            user_statuses,
-            user_sessions,
-            chat_name: None,
-            rate_limiter: RateLimiter::new(RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW),
-            clear_status_on_disconnect: false,
-            session_taken_over: false,
+    /// Acknowledge the client's version check and join (and anything before
+    /// them) so it moves on to reading server messages
+    async fn read_handshake(&mut self) -> Option<Vec<ChatMessage>> {
+        let handshake = async {
+            let mut messages = Vec::new();
+            while let Ok(msg) = self.read_message_chunked().await {
+                let is_join = msg.msg_type == MessageTypes::Join;
+                messages.push(msg);
+                if is_join {
+                    return Some(messages);
+                }
+            }
+            None
+        };
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Turn the client away because the server is at capacity. The error is
+    /// sent after the client's version check and join are acknowledged, so it
+    /// arrives while the client is reading rather than waiting for an "OK".
+    pub async fn reject_full(&mut self) -> Result<(), UserConnectionError> {
+        let _ = self.read_handshake().await;
+
+        let error_msg = ChatMessage::error(ErrorCode::ServerFull, SERVER_FULL_ERROR)
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, self.send_message_chunked(error_msg)).await {
+            Ok(result) => result.map_err(UserConnectionError::IoError),
+            Err(_) => Ok(()), // Client never acknowledged; just close
         }
     }
 
-    pub async fn handle(&mut self) -> Result<(), UserConnectionError> {
-        logger::log_info(&format!("New client connected: {}", self.addr));
+    /// Hold the client in the waiting line until a slot frees up, telling it
+    /// its position periodically. Returns None if it disconnects first.
+    pub async fn wait_in_queue(&mut self, ticket: QueueTicket) -> Option<OwnedSemaphorePermit> {
+        self.inbound = self.read_handshake().await?.into();
+        logger::log_info(&format!(
+            "Server full, {} queued at position {}",
+            self.addr,
+            ticket.position()
+        ));
 
-        let mut rx = self.tx.subscribe();
-        let mut cmd_rx = self.server_commands.subscribe();
+        let admitted = ticket.admitted();
+        tokio::pin!(admitted);
+        let mut notice_interval = tokio::time::interval(QUEUE_NOTICE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                permit = &mut admitted => {
+                    logger::log_info(&format!("Admitted {} from the queue", self.addr));
+                    return Some(permit);
+                }
+                _ = notice_interval.tick() => {
+                    let notice = format!(
+                        "Server full: you are number {} in the queue and will join when a slot frees up",
+                        ticket.position()
+                    );
+                    let notice_msg =
+                        ChatMessage::try_new(MessageTypes::ServerNotice, Some(notice.into_bytes()))
+                            .ok()?;
+                    self.send_message_chunked(notice_msg).await.ok()?;
+                }
+                result = self.read_message_chunked() => {
+                    // Anything sent while queued is dropped; a read error means the client left
+                    if result.is_err() {
+                        logger::log_info(&format!("{} left the queue", self.addr));
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move complete frames from the reader into the inbound queue while it
+    /// has room, acknowledging each so the client can send the next
+    async fn queue_frames(&mut self) -> std::io::Result<()> {
+        while self.inbound.len() < self.state.inbound_buffer {
+            let Some(msg) = self.reader.next_frame(self.compress)? else {
+                break;
+            };
+            self.socket.write_all(b"OK").await?;
+            self.socket.flush().await?;
+            // Pongs only mark activity, which reading them already did
+            if msg.msg_type != MessageTypes::Pong {
+                self.inbound.push_back(msg);
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve the client until it leaves, is disconnected, or `kill` turns true
+    pub async fn handle(
+        &mut self,
+        mut kill: watch::Receiver<bool>,
+    ) -> Result<(), UserConnectionError> {
+        logger::log_info(&format!("New client connected: {}", self.address()));
+
+        let mut rx = self.state.tx.subscribe();
+        let mut cmd_rx = self.state.server_commands.subscribe();
+        // Point-to-point messages addressed only to this connection
+        let (direct_route, mut direct_rx, mut file_rx) = DirectRoute::open();
 
         // Heartbeat tracking
         let mut last_activity = Instant::now();
@@ -161,64 +430,140 @@ impl UserConnection {
         // Skip the first immediate tick - we don't want to ping right away
         ping_interval.tick().await;
 
+        // Connections that never join would otherwise hold a slot until PONG_TIMEOUT
+        let join_deadline = tokio::time::sleep(JOIN_DEADLINE);
+        tokio::pin!(join_deadline);
+        // A join put off by a tokenless takeover, retried once its grace is over
+        let mut deferred_join = None;
+        let retry_join = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(retry_join);
+
+        let mut disconnected = false;
         loop {
+            // Stop once the client has gone and everything it sent is handled
+            if disconnected && self.inbound.is_empty() {
+                logger::log_warning(&format!("Client {} disconnected", self.peer()));
+                break;
+            }
+
             tokio::select! {
-                // Branch 1: Receive from client
-                result = self.read_message_chunked() => {
+                // Branch 1: Receive from client. Reading continues while earlier
+                // messages wait in the inbound queue, so bursts are absorbed and
+                // disconnects are noticed without waiting on processing
+                result = self.reader.fill(&mut self.socket),
+                    if !disconnected && self.inbound.len() < self.state.inbound_buffer => {
                     match result {
-                        Ok(msg) => {
-                            // Update last activity on any message received
+                        Ok(0) => disconnected = true,
+                        Ok(_) => {
+                            // Update last activity on any data received
                             last_activity = Instant::now();
-
-                            // Handle Pong silently (just updates last_activity above)
-                            if msg.msg_type == MessageTypes::Pong {
-                                continue;
+                            if let Err(e) = self.queue_frames().await {
+                                logger::log_error(&format!("IO error reading from {}: {:?}", self.peer(), e));
+                                break;
                             }
+                        }
+                        Err(e) => {
+                            logger::log_error(&format!("IO error reading from {}: {:?}", self.peer(), e));
+                            break;
+                        }
+                    }
+                }
+                // Branch 2: Process the oldest queued client message
+                _ = std::future::ready(()), if !self.inbound.is_empty() => {
+                    let Some(msg) = self.inbound.pop_front() else {
+                        continue;
+                    };
+                    let previous_name = self.chat_name.clone();
+                    let join = (msg.msg_type == MessageTypes::Join).then(|| msg.clone());
+                    let result = self.process_message(msg).await;
+                    if join.is_some() {
+                        self.retrying_join = false;
+                    }
+                    if self.chat_name != previous_name {
+                        self.update_direct_route(previous_name.as_deref(), &direct_route).await;
+                        self.record_username().await;
+                    }
 
-                            match self.process_message(msg).await {
-                                Ok(()) => {}
-                                Err(UserConnectionError::ExplicitQuit) => {
-                                    // User explicitly quit - clear status on disconnect
-                                    self.clear_status_on_disconnect = true;
-                                    break;
-                                }
-                                Err(UserConnectionError::VersionMismatch) => {
-                                    // Version mismatch - disconnect client (error already sent)
-                                    logger::log_warning(&format!("Client {} disconnected due to version mismatch", self.addr));
-                                    break;
-                                }
-                                Err(e) => {
-                                    logger::log_error(&format!("Error handling message from {}: {:?}", self.addr, e));
-                                }
+                    match result {
+                        Ok(()) => {}
+                        Err(UserConnectionError::ExplicitQuit) => {
+                            // User explicitly quit - clear status on disconnect, and
+                            // confirm so the client can close without racing us
+                            self.clear_status_on_disconnect = true;
+                            if let Ok(ack) = ChatMessage::try_new(MessageTypes::LeaveAck, None) {
+                                let _ = self.send_message_chunked(ack).await;
                             }
+                            break;
                         }
-                        Err(TcpMessageHandlerError::IoError(e)) => {
-                            logger::log_error(&format!("IO error reading from {}: {:?}", self.addr, e));
+                        Err(UserConnectionError::VersionMismatch) => {
+                            // Version mismatch - disconnect client (error already sent)
+                            logger::log_warning(&format!("Client {} disconnected due to version mismatch", self.peer()));
                             break;
                         }
-                        Err(TcpMessageHandlerError::Disconnect) => {
-                            logger::log_warning(&format!("Client {} disconnected", self.addr));
+                        Err(UserConnectionError::JoinRateLimited) => {
+                            // Join flood - disconnect client (error already sent)
+                            logger::log_warning(&format!("Client {} disconnected for joining too often", self.peer()));
                             break;
                         }
-                    };
+                        Err(UserConnectionError::JoinDeferred(wait)) => {
+                            // Tokenless takeover scheduled - keep serving the
+                            // client and send its join through again after the grace
+                            retry_join.as_mut().reset(tokio::time::Instant::now() + wait);
+                            deferred_join = join;
+                        }
+                        Err(UserConnectionError::ByteBudgetExceeded) => {
+                            // Volume flood - disconnect client (error already sent)
+                            logger::log_warning(&format!("Client {} disconnected for sending too much data", self.peer()));
+                            break;
+                        }
+                        // Replies can't reach a client that has already gone
+                        Err(UserConnectionError::IoError(_)) if disconnected => {}
+                        Err(e) => {
+                            logger::log_error(&format!("Error handling message from {}: {:?}", self.peer(), e));
+                        }
+                    }
+
+                    // Frames that arrived while the queue was full can move in now
+                    if !disconnected && let Err(e) = self.queue_frames().await {
+                        logger::log_error(&format!("IO error reading from {}: {:?}", self.peer(), e));
+                        break;
+                    }
                 }
-                // Branch 2: Broadcast to other clients
+                // Branch 3: Broadcast to other clients
                 result = rx.recv() => {
                     match result {
                         Ok((msg, _src_addr)) => {
+                            if self.state.quiet_joins
+                                && matches!(msg.msg_type, MessageTypes::Join | MessageTypes::Leave)
+                            {
+                                continue;
+                            }
                             if let Err(e) = self.send_message_chunked(msg).await {
-                                logger::log_warning(&format!("Failed to send message to {}: {:?}", self.addr, e));
+                                logger::log_warning(&format!("Failed to send message to {}: {:?}", self.peer(), e));
                                 // Client likely disconnected, break to clean up
                                 break;
                             }
                         }
                         Err(e) => {
-                            logger::log_error(&format!("Broadcast receive error for {}: {:?}", self.addr, e));
+                            logger::log_error(&format!("Broadcast receive error for {}: {:?}", self.peer(), e));
                             break;
                         }
                     }
                 }
-                // Branch 3: Server commands (kick, rename, etc.)
+                // Branch 4: Point-to-point messages and files for this client only
+                Some(msg) = direct_rx.recv() => {
+                    if let Err(e) = self.send_message_chunked(msg).await {
+                        logger::log_warning(&format!("Failed to send direct message to {}: {:?}", self.peer(), e));
+                        break;
+                    }
+                }
+                Some(msg) = file_rx.recv() => {
+                    if let Err(e) = self.send_message_chunked(msg).await {
+                        logger::log_warning(&format!("Failed to send file to {}: {:?}", self.peer(), e));
+                        break;
+                    }
+                }
+                // Branch 5: Server commands (kick, rename, etc.)
                 result = cmd_rx.recv() => {
                     match result {
                         Ok(ServerCommand::Kick(username)) => {
@@ -226,9 +571,9 @@ impl UserConnection {
                                 && chat_name == &username {
                                 logger::log_info(&format!("User {} kicked by server", chat_name));
                                 // Send error message to client before disconnecting
-                                if let Ok(kick_msg) = ChatMessage::try_new(
-                                    MessageTypes::Error,
-                                    Some("You have been kicked by the server".as_bytes().to_vec())
+                                if let Ok(kick_msg) = ChatMessage::error(
+                                    ErrorCode::Kicked,
+                                    "You have been kicked by the server"
                                 ) {
                                     let _ = self.send_message_chunked(kick_msg).await;
                                 }
@@ -241,14 +586,29 @@ impl UserConnection {
                             if let Some(chat_name) = &self.chat_name
                                 && chat_name == &old_name {
                                 // Update user_ips mapping
-                                let mut ips = self.user_ips.write().await;
+                                let mut ips = self.state.user_ips.write().await;
                                 if let Some(ip) = ips.remove(&old_name) {
                                     ips.insert(new_name.clone(), ip);
                                 }
                                 drop(ips);
 
+                                let mut readonly_users = self.state.readonly_users.write().await;
+                                if readonly_users.remove(&old_name) {
+                                    readonly_users.insert(new_name.clone());
+                                }
+                                drop(readonly_users);
+
+                                let mut operators = self.state.operators.write().await;
+                                if operators.remove(&old_name) {
+                                    operators.insert(new_name.clone());
+                                }
+                                drop(operators);
+                                self.state.transfers.lock().await.release_user(&old_name);
+
                                 // Update the local chat_name
                                 self.chat_name = Some(new_name.clone());
+                                self.update_direct_route(Some(&old_name), &direct_route).await;
+                                self.record_username().await;
 
                                 // Send UserRename message to client
                                 if let Ok(rename_msg) = ChatMessage::try_new(
@@ -261,12 +621,12 @@ impl UserConnection {
                                 logger::log_info(&format!("User {} renamed to {} by server", old_name, new_name));
 
                                 // Broadcast announcement to all clients
-                                let announcement = format!("{} is now known as {} (renamed by server)", old_name, new_name);
+                                let announcement = format!("{} (renamed by server)", rename_announcement(&old_name, &new_name));
                                 if let Ok(broadcast_msg) = ChatMessage::try_new(
                                     MessageTypes::ChatMessage,
                                     Some(announcement.into_bytes())
                                 ) {
-                                    let _ = self.tx.send((broadcast_msg, self.addr));
+                                    let _ = self.state.tx.send((broadcast_msg, self.addr));
                                 }
                             }
                         }
@@ -275,9 +635,9 @@ impl UserConnection {
                             if self.addr.ip() == ip {
                                 logger::log_info(&format!("User {:?} banned (IP {})", self.chat_name, ip));
                                 // Send error message to client before disconnecting
-                                if let Ok(ban_msg) = ChatMessage::try_new(
-                                    MessageTypes::Error,
-                                    Some("You have been banned from the server".as_bytes().to_vec())
+                                if let Ok(ban_msg) = ChatMessage::error(
+                                    ErrorCode::Banned,
+                                    "You have been banned from the server"
                                 ) {
                                     let _ = self.send_message_chunked(ban_msg).await;
                                 }
@@ -304,14 +664,36 @@ impl UserConnection {
                         }
                     }
                 }
-                // Branch 4: Periodic ping and timeout check
+                // Branch 6: Closed from the server console (/killconn). Nothing
+                // is sent, since a stuck client wouldn't acknowledge it
+                _ = killed(&mut kill) => {
+                    logger::log_warning(&format!("Connection {} killed by server", self.peer()));
+                    break;
+                }
+                // Branch 7: Disconnect clients that haven't joined in time
+                // (a deferred join has its own deadline)
+                _ = &mut join_deadline, if self.chat_name.is_none() && deferred_join.is_none() => {
+                    logger::log_warning(&format!(
+                        "Client {} did not join within {:?}, disconnecting",
+                        self.addr, JOIN_DEADLINE
+                    ));
+                    break;
+                }
+                // Branch 8: Retry a deferred join ahead of anything sent since
+                _ = &mut retry_join, if deferred_join.is_some() => {
+                    if let Some(join) = deferred_join.take() {
+                        self.inbound.push_front(join);
+                        self.retrying_join = true;
+                    }
+                }
+                // Branch 9: Periodic ping and timeout check
                 _ = ping_interval.tick() => {
+                    self.state.open_connections.lock().await.heard(self.addr, last_activity);
                     // Check if client has timed out (no activity for PONG_TIMEOUT)
                     if last_activity.elapsed() > PONG_TIMEOUT {
                         logger::log_warning(&format!(
-                            "Client {} ({:?}) timed out - no response for {:?}",
-                            self.addr,
-                            self.chat_name,
+                            "Client {} timed out - no response for {:?}",
+                            self.peer(),
                             last_activity.elapsed()
                         ));
                         break;
@@ -321,7 +703,7 @@ impl UserConnection {
                     if let Ok(ping_msg) = ChatMessage::try_new(MessageTypes::Ping, None)
                         && let Err(e) = self.send_message_chunked(ping_msg).await
                     {
-                        logger::log_warning(&format!("Failed to send ping to {}: {:?}", self.addr, e));
+                        logger::log_warning(&format!("Failed to send ping to {}: {:?}", self.peer(), e));
                         break;
                     }
                 }
@@ -329,6 +711,11 @@ impl UserConnection {
         }
 
         // Cleanup on disconnect
+        self.state
+            .tokenless_takeovers
+            .lock()
+            .await
+            .cancel(self.addr);
         if let Some(chat_name) = &self.chat_name {
             // If session was taken over by a reconnecting client, don't clean up
             // The new connection now owns the username and session
@@ -340,55 +727,247 @@ impl UserConnection {
                 return Ok(());
             }
 
-            let mut clients = self.connected_clients.write().await;
+            let mut clients = self.state.connected_clients.write().await;
             clients.remove(chat_name);
             drop(clients);
 
             // Remove from user_ips mapping
-            let mut ips = self.user_ips.write().await;
+            let mut ips = self.state.user_ips.write().await;
             ips.remove(chat_name);
             drop(ips);
 
+            let mut readonly_users = self.state.readonly_users.write().await;
+            readonly_users.remove(chat_name);
+            drop(readonly_users);
+
+            // Operator rights last for one session
+            let mut operators = self.state.operators.write().await;
+            operators.remove(chat_name);
+            drop(operators);
+
+            // Transfers to or from this user can't complete any more
+            self.state.transfers.lock().await.release_user(chat_name);
+
+            // Stop routing point-to-point messages to this connection
+            let mut channels = self.state.user_channels.write().await;
+            if channels
+                .get(chat_name)
+                .is_some_and(|route| route.same_route(&direct_route))
+            {
+                channels.remove(chat_name);
+            }
+            drop(channels);
+
             // Only remove status and session on explicit quit/kick/ban, not on connection drops
             // (which may be reconnection attempts)
             if self.clear_status_on_disconnect {
-                let mut statuses = self.user_statuses.write().await;
+                let mut statuses = self.state.user_statuses.write().await;
                 statuses.remove(chat_name);
                 drop(statuses);
 
-                let mut sessions = self.user_sessions.write().await;
+                let mut sessions = self.state.user_sessions.write().await;
                 sessions.remove(chat_name);
                 drop(sessions);
+
+                let mut secrets = self.state.session_secrets.write().await;
+                secrets.remove(chat_name);
+                drop(secrets);
             }
 
             if let Ok(leave_message) =
                 ChatMessage::try_new(MessageTypes::Leave, Some(chat_name.clone().into_bytes()))
             {
-                let _ = self.tx.send((leave_message, self.addr));
+                let _ = self.state.tx.send((leave_message, self.addr));
             }
             logger::log_system(&format!("{} has left the chat", chat_name));
+            self.state
+                .user_registry
+                .lock()
+                .await
+                .record(chat_name, SystemTime::now());
+            if let Some(webhook) = &self.state.webhook {
+                webhook.send(WebhookEvent::Leave {
+                    username: chat_name.clone(),
+                });
+            }
+            if let Some(history) = &self.state.history {
+                history.record(HistoryKind::Leave, chat_name, "");
+            }
         }
 
         Ok(())
     }
 
+    /// How this connection appears in logs: `alice (1.2.3.4:55012)` once the
+    /// user has joined, otherwise just the address
+    fn peer(&self) -> String {
+        match &self.chat_name {
+            Some(chat_name) => format!("{} ({})", chat_name, self.address()),
+            None => self.address(),
+        }
+    }
+
+    /// The client's address, with its hostname once reverse DNS has found it
+    fn address(&self) -> String {
+        describe_addr(self.state.reverse_dns.as_deref(), self.addr)
+    }
+
+    /// Re-key this connection's point-to-point route after a join or rename
+    async fn update_direct_route(&self, previous_name: Option<&str>, direct_route: &DirectRoute) {
+        let mut channels = self.state.user_channels.write().await;
+        if let Some(old_name) = previous_name
+            && channels
+                .get(old_name)
+                .is_some_and(|route| route.same_route(direct_route))
+        {
+            channels.remove(old_name);
+        }
+        if let Some(chat_name) = &self.chat_name {
+            channels.insert(chat_name.clone(), direct_route.clone());
+        }
+    }
+
+    /// Show this connection's current username in `/conns`
+    async fn record_username(&self) {
+        self.state
+            .open_connections
+            .lock()
+            .await
+            .set_username(self.addr, self.chat_name.clone());
+    }
+
+    /// Accept a compression offer (format: algorithm,algorithm...) sent
+    /// before joining. Frames to the client are compressed from then on, and
+    /// the join replies confirm it; the client is still waiting on its join
+    /// ack here, so replying now would interleave with it.
+    fn negotiate_compression(&mut self, offer: Option<String>) {
+        if self.chat_name.is_some() || !self.state.compression {
+            return;
+        }
+        let accepted = offer
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .any(|algorithm| algorithm.trim() == COMPRESSION_ALGORITHM);
+        if accepted {
+            logger::log_info(&format!("Compression enabled for {}", self.peer()));
+            self.compress = true;
+        }
+    }
+
+    /// Check the version a client sends before joining. A mismatch is reported
+    /// when the client's join arrives: the client is already waiting on the
+    /// ack for its next frame, so replying now would interleave with it.
+    fn check_version(&mut self, client_version: Option<String>) -> Result<(), UserConnectionError> {
+        let client_version = client_version.ok_or(UserConnectionError::InvalidMessage)?;
+        if version::versions_compatible(&client_version, VERSION) {
+            logger::log_info(&format!(
+                "Version check passed for {}: v{}",
+                self.addr, client_version
+            ));
+            self.version_checked = true;
+            return Ok(());
+        }
+
+        logger::log_warning(&format!(
+            "Version mismatch from {}: client v{} != server v{}",
+            self.addr, client_version, VERSION
+        ));
+        // Version mismatch error with details
+        let mismatch_content = format!(
+            "{}|{}|{}",
+            client_version,
+            VERSION,
+            version::GITHUB_README_URL
+        );
+        let mismatch_msg = ChatMessage::try_new(
+            MessageTypes::VersionMismatch,
+            Some(mismatch_content.into_bytes()),
+        )
+        .map_err(|_| UserConnectionError::InvalidMessage)?;
+        self.version_mismatch = Some(mismatch_msg);
+        Ok(())
+    }
+
     async fn process_message(&mut self, message: ChatMessage) -> Result<(), UserConnectionError> {
+        if message.msg_type == MessageTypes::VersionCheck {
+            return self.check_version(message.content_as_string());
+        }
+        if message.msg_type == MessageTypes::Compression {
+            self.negotiate_compression(message.content_as_string());
+            return Ok(());
+        }
+        // Past the pre-join frames, the client can read the reply
+        if let Some(mismatch_msg) = self.version_mismatch.take() {
+            let _ = self.send_message_chunked(mismatch_msg).await;
+            return Err(UserConnectionError::VersionMismatch);
+        }
+
+        // File data is spaced out by the file rate limiter instead
+        let size = message.get_content().map_or(0, <[u8]>::len);
+        let file_data = matches!(
+            message.msg_type,
+            MessageTypes::FileTransfer | MessageTypes::FileOffer
+        );
+        if !file_data && !self.byte_budget.record(size) {
+            let error_msg = ChatMessage::error(
+                ErrorCode::RateLimited,
+                "You sent too much data in the last minute. Disconnecting.",
+            )
+            .map_err(|_| UserConnectionError::InvalidMessage)?;
+            let _ = self.send_message_chunked(error_msg).await;
+            return Err(UserConnectionError::ByteBudgetExceeded);
+        }
+
         let handlers = MessageHandlers {
             addr: self.addr,
-            tx: &self.tx,
-            server_commands: &self.server_commands,
-            connected_clients: &self.connected_clients,
-            user_ips: &self.user_ips,
-            user_statuses: &self.user_statuses,
-            user_sessions: &self.user_sessions,
+            tx: &self.state.tx,
+            server_commands: &self.state.server_commands,
+            connected_clients: &self.state.connected_clients,
+            remote_users: &self.state.remote_users,
+            user_ips: &self.state.user_ips,
+            user_statuses: &self.state.user_statuses,
+            user_sessions: &self.state.user_sessions,
+            session_secrets: &self.state.session_secrets,
+            user_channels: &self.state.user_channels,
+            readonly_users: &self.state.readonly_users,
+            operators: &self.state.operators,
+            oper_password: self.state.oper_password.as_deref(),
+            motd: self.state.motd.as_deref(),
+            server_name: self.state.server_name.as_deref(),
+            welcome_message: self.state.welcome_message.as_deref(),
+            topic: &self.state.topic,
+            open_topic: self.state.open_topic,
+            limits: self.state.limits,
+            join_limiter: &self.state.join_limiter,
+            transfers: &self.state.transfers,
+            away_replies: &self.state.away_replies,
+            pages: &self.state.pages,
+            polls: &self.state.polls,
+            file_cache: &self.state.file_cache,
+            blocks: &self.state.blocks,
+            user_registry: &self.state.user_registry,
+            file_policy: &self.state.file_policy,
+            slowmode: Duration::from_secs(self.state.slowmode_secs.load(Ordering::Relaxed)),
+            duplicate_window: self.state.duplicate_window,
+            tokenless_takeover: self.state.tokenless_takeover,
+            tokenless_takeovers: &self.state.tokenless_takeovers,
+            webhook: self.state.webhook.as_ref(),
+            history: self.state.history.as_ref(),
+            started_at: self.state.started_at,
+            compress: self.compress,
+            version_checked: self.version_checked,
+            retrying_join: self.retrying_join,
         };
 
         handlers
             .process_message(
                 message,
                 &mut self.rate_limiter,
+                &mut self.file_rate_limiter,
                 &mut self.socket,
                 &mut self.chat_name,
+                &mut self.last_chat,
             )
             .await
     }
@@ -0,0 +1,186 @@
+//! Every open connection keyed by address, for `/conns` and `/killconn`
+//!
+//! Usernames only identify joined users, and a ghost session can still hold a
+//! name while its replacement connects. Each entry carries a kill switch its
+//! connection watches: joined connections leave through their normal cleanup,
+//! and ones still in the TLS handshake or the queue are simply dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How far a connection has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    /// TLS handshake not yet finished
+    Handshake,
+    /// Waiting in line for a free slot
+    Queued,
+    /// Holding a slot, but not joined yet
+    Joining,
+    Joined,
+}
+
+impl fmt::Display for ConnectionPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectionPhase::Handshake => "handshake",
+            ConnectionPhase::Queued => "queued",
+            ConnectionPhase::Joining => "joining",
+            ConnectionPhase::Joined => "joined",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    opened_at: Instant,
+    /// When the client last sent anything, as of the last heartbeat
+    last_heard: Instant,
+    phase: ConnectionPhase,
+    username: Option<String>,
+    kill: watch::Sender<bool>,
+}
+
+/// One line of `/conns`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionSummary {
+    pub addr: SocketAddr,
+    pub username: Option<String>,
+    pub phase: ConnectionPhase,
+    pub age: Duration,
+    pub idle: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct OpenConnections {
+    entries: HashMap<SocketAddr, Entry>,
+}
+
+impl OpenConnections {
+    /// Track a newly accepted connection. The returned receiver turns true
+    /// when the connection is killed.
+    pub fn open(&mut self, addr: SocketAddr) -> watch::Receiver<bool> {
+        let (kill, killed) = watch::channel(false);
+        let now = Instant::now();
+        self.entries.insert(
+            addr,
+            Entry {
+                opened_at: now,
+                last_heard: now,
+                phase: ConnectionPhase::Handshake,
+                username: None,
+                kill,
+            },
+        );
+        killed
+    }
+
+    pub fn set_phase(&mut self, addr: SocketAddr, phase: ConnectionPhase) {
+        if let Some(entry) = self.entries.get_mut(&addr) {
+            entry.phase = phase;
+        }
+    }
+
+    /// Record the connection's username after a join or rename
+    pub fn set_username(&mut self, addr: SocketAddr, username: Option<String>) {
+        if let Some(entry) = self.entries.get_mut(&addr) {
+            entry.phase = if username.is_some() {
+                ConnectionPhase::Joined
+            } else {
+                ConnectionPhase::Joining
+            };
+            entry.username = username;
+        }
+    }
+
+    pub fn heard(&mut self, addr: SocketAddr, at: Instant) {
+        if let Some(entry) = self.entries.get_mut(&addr) {
+            entry.last_heard = at;
+        }
+    }
+
+    pub fn close(&mut self, addr: SocketAddr) {
+        self.entries.remove(&addr);
+    }
+
+    /// Tell the connection at `addr` to close. False if there is none.
+    pub fn kill(&self, addr: SocketAddr) -> bool {
+        self.entries
+            .get(&addr)
+            .is_some_and(|entry| entry.kill.send(true).is_ok())
+    }
+
+    /// Every open connection, oldest first
+    pub fn list(&self) -> Vec<ConnectionSummary> {
+        let mut summaries: Vec<ConnectionSummary> = self
+            .entries
+            .iter()
+            .map(|(addr, entry)| ConnectionSummary {
+                addr: *addr,
+                username: entry.username.clone(),
+                phase: entry.phase,
+                age: entry.opened_at.elapsed(),
+                idle: entry.last_heard.elapsed(),
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.age.cmp(&a.age).then(a.addr.cmp(&b.addr)));
+        summaries
+    }
+}
+
+/// Resolves once the connection is killed; never if it is closed first
+pub async fn killed(kill: &mut watch::Receiver<bool>) {
+    if kill.wait_for(|killed| *killed).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_kill_reaches_connection() {
+        let mut connections = OpenConnections::default();
+        let addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let mut kill = connections.open(addr);
+
+        assert!(!connections.kill("127.0.0.1:40001".parse().unwrap()));
+        assert!(connections.kill(addr));
+        tokio::time::timeout(Duration::from_secs(1), killed(&mut kill))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_closed_connection_never_killed() {
+        let mut connections = OpenConnections::default();
+        let addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let mut kill = connections.open(addr);
+        connections.close(addr);
+
+        assert!(!connections.kill(addr));
+        let waited = tokio::time::timeout(Duration::from_millis(50), killed(&mut kill)).await;
+        assert!(waited.is_err());
+    }
+
+    #[test]
+    fn test_phase_follows_username() {
+        let mut connections = OpenConnections::default();
+        let addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        connections.open(addr);
+        assert_eq!(connections.list()[0].phase, ConnectionPhase::Handshake);
+
+        connections.set_phase(addr, ConnectionPhase::Joining);
+        connections.set_username(addr, Some("alice".to_string()));
+        let listed = &connections.list()[0];
+        assert_eq!(listed.phase, ConnectionPhase::Joined);
+        assert_eq!(listed.username.as_deref(), Some("alice"));
+
+        connections.close(addr);
+        assert!(connections.list().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a sender must wait before paging the same user again
+pub const PAGE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Pages sent recently, keyed by (sender, recipient).
+//
+// A page rings the recipient's bell and bypasses their notification
+// debounce, so it is limited much harder than a DM: one per interval for
+// each sender and recipient.
+#[derive(Debug, Default)]
+pub struct PageLimiter {
+    last_sent: HashMap<(String, String), Instant>,
+}
+
+impl PageLimiter {
+    /// Record a page from `sender` to `recipient`, or return how long until
+    /// the next one is allowed
+    pub fn try_page(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.last_sent
+            .retain(|_, sent_at| now.duration_since(*sent_at) < PAGE_INTERVAL);
+        let key = (sender.to_string(), recipient.to_string());
+        if let Some(sent_at) = self.last_sent.get(&key) {
+            return Err(PAGE_INTERVAL - now.duration_since(*sent_at));
+        }
+        self.last_sent.insert(key, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_page_per_interval_per_pair() {
+        let mut pages = PageLimiter::default();
+        let start = Instant::now();
+
+        assert!(pages.try_page("bob", "alice", start).is_ok());
+        assert_eq!(
+            pages.try_page("bob", "alice", start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert!(pages.try_page("carol", "alice", start).is_ok());
+        assert!(pages.try_page("bob", "dave", start).is_ok());
+        assert!(
+            pages
+                .try_page("bob", "alice", start + PAGE_INTERVAL)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use shared::message::{
+    MAX_POLL_OPTION_LENGTH, MAX_POLL_OPTIONS, MAX_POLL_QUESTION_LENGTH, PollSummary,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// Most polls kept open at once; creating another closes the oldest
+pub const MAX_OPEN_POLLS: usize = 20;
+
+/// Split a poll request (format: question|option|option...) into its
+/// question and options, or explain why it was rejected
+pub fn parse_poll(content: &str) -> Result<(String, Vec<String>), String> {
+    if content.contains('\n') {
+        return Err("Polls must fit on one line".to_string());
+    }
+    let mut parts = content.split('|').map(str::trim);
+    let question = parts.next().unwrap_or_default();
+    let options: Vec<String> = parts.map(str::to_string).collect();
+
+    if question.is_empty() || question.len() > MAX_POLL_QUESTION_LENGTH {
+        return Err(format!(
+            "Poll question must be 1-{} characters",
+            MAX_POLL_QUESTION_LENGTH
+        ));
+    }
+    if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+        return Err(format!("Polls need 2-{} options", MAX_POLL_OPTIONS));
+    }
+    if options
+        .iter()
+        .any(|option| option.is_empty() || option.len() > MAX_POLL_OPTION_LENGTH)
+    {
+        return Err(format!(
+            "Poll options must be 1-{} characters",
+            MAX_POLL_OPTION_LENGTH
+        ));
+    }
+    Ok((question.to_string(), options))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VoteError {
+    UnknownPoll(u64),
+    InvalidOption { options: usize },
+}
+
+#[derive(Debug)]
+struct Poll {
+    creator: String,
+    question: String,
+    options: Vec<String>,
+    /// Each voter's chosen option (0-based); voting again changes it
+    votes: HashMap<String, usize>,
+}
+
+impl Poll {
+    fn summary(&self, id: u64) -> PollSummary {
+        let mut tallies = vec![0; self.options.len()];
+        for &choice in self.votes.values() {
+            tallies[choice] += 1;
+        }
+        PollSummary {
+            id,
+            creator: self.creator.clone(),
+            question: self.question.clone(),
+            options: self.options.iter().cloned().zip(tallies).collect(),
+        }
+    }
+}
+
+// Open polls, keyed by server-assigned id.
+//
+// Polls live until the server restarts or `MAX_OPEN_POLLS` newer ones push
+// them out, so a burst of polls can't grow the board without bound.
+#[derive(Debug, Default)]
+pub struct PollBoard {
+    next_id: u64,
+    polls: BTreeMap<u64, Poll>,
+}
+
+impl PollBoard {
+    /// Open a poll and return its (empty) tallies
+    pub fn create(&mut self, creator: &str, question: &str, options: Vec<String>) -> PollSummary {
+        self.next_id += 1;
+        let poll = Poll {
+            creator: creator.to_string(),
+            question: question.to_string(),
+            options,
+            votes: HashMap::new(),
+        };
+        let summary = poll.summary(self.next_id);
+        self.polls.insert(self.next_id, poll);
+        while self.polls.len() > MAX_OPEN_POLLS {
+            self.polls.pop_first();
+        }
+        summary
+    }
+
+    /// Record `voter`'s choice of `option` (1-based) in poll `id`, replacing
+    /// any earlier vote of theirs, and return the updated tallies
+    pub fn vote(&mut self, id: u64, voter: &str, option: usize) -> Result<PollSummary, VoteError> {
+        let poll = self.polls.get_mut(&id).ok_or(VoteError::UnknownPoll(id))?;
+        if option == 0 || option > poll.options.len() {
+            return Err(VoteError::InvalidOption {
+                options: poll.options.len(),
+            });
+        }
+        poll.votes.insert(voter.to_string(), option - 1);
+        Ok(poll.summary(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_poll() {
+        assert_eq!(
+            parse_poll("Lunch? | pizza | tacos"),
+            Ok(("Lunch?".to_string(), options(&["pizza", "tacos"])))
+        );
+        assert!(parse_poll("Lunch? | pizza").is_err());
+        assert!(parse_poll("Lunch? | pizza | ").is_err());
+        assert!(parse_poll(" | pizza | tacos").is_err());
+        assert!(parse_poll("Lunch?\nDinner? | pizza | tacos").is_err());
+        let too_many = ["x"; MAX_POLL_OPTIONS + 1].join("|");
+        assert!(parse_poll(&format!("Pick one|{}", too_many)).is_err());
+    }
+
+    #[test]
+    fn test_one_vote_per_user() {
+        let mut board = PollBoard::default();
+        let poll = board.create("alice", "Lunch?", options(&["pizza", "tacos"]));
+        assert_eq!(poll.id, 1);
+        assert_eq!(poll.total_votes(), 0);
+
+        board.vote(1, "bob", 1).unwrap();
+        board.vote(1, "carol", 1).unwrap();
+        let result = board.vote(1, "bob", 2).unwrap();
+        assert_eq!(
+            result.options,
+            vec![("pizza".to_string(), 1), ("tacos".to_string(), 1)]
+        );
+
+        assert_eq!(
+            board.vote(1, "bob", 3),
+            Err(VoteError::InvalidOption { options: 2 })
+        );
+        assert_eq!(board.vote(2, "bob", 1), Err(VoteError::UnknownPoll(2)));
+    }
+
+    #[test]
+    fn test_oldest_poll_closed_when_full() {
+        let mut board = PollBoard::default();
+        for _ in 0..=MAX_OPEN_POLLS {
+            board.create("alice", "Again?", options(&["yes", "no"]));
+        }
+        assert_eq!(board.vote(1, "bob", 1), Err(VoteError::UnknownPoll(1)));
+        assert!(board.vote(2, "bob", 1).is_ok());
+    }
+}
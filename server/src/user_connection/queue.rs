@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+// Active connection slots, with an optional waiting line for clients that
+// arrive at capacity.
+//
+// Each active connection holds a semaphore permit for its lifetime. Queued
+// clients hold a ticket; only the ticket at the front of the line waits on the
+// semaphore, so slots are handed out strictly in arrival order even when
+// queued clients finish their handshake at different times.
+pub struct ConnectionQueue {
+    slots: Arc<Semaphore>,
+    max_clients: usize,
+    /// None when queueing is disabled (clients at capacity are rejected)
+    waiting: Option<Arc<WaitingLine>>,
+}
+
+struct WaitingLine {
+    tickets: Mutex<VecDeque<u64>>,
+    /// Woken whenever a ticket leaves the line
+    changed: Notify,
+    next_ticket: AtomicU64,
+    capacity: usize,
+}
+
+/// How a new connection gets its slot
+pub enum Admission {
+    /// A slot was free
+    Now(OwnedSemaphorePermit),
+    /// Wait in line for a slot
+    Queued(QueueTicket),
+}
+
+/// A queued client's place in line (leaves the line when dropped)
+pub struct QueueTicket {
+    id: u64,
+    line: Arc<WaitingLine>,
+    slots: Arc<Semaphore>,
+}
+
+impl ConnectionQueue {
+    /// `max_clients` active slots; if `queue_enabled`, up to `max_clients` more
+    /// clients may wait in line
+    pub fn new(max_clients: usize, queue_enabled: bool) -> Self {
+        let waiting = queue_enabled.then(|| {
+            Arc::new(WaitingLine {
+                tickets: Mutex::new(VecDeque::new()),
+                changed: Notify::new(),
+                next_ticket: AtomicU64::new(0),
+                capacity: max_clients,
+            })
+        });
+        ConnectionQueue {
+            slots: Arc::new(Semaphore::new(max_clients)),
+            max_clients,
+            waiting,
+        }
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.max_clients - self.slots.available_permits()
+    }
+
+    /// Admit a new connection now or place it in line. None means reject it.
+    pub fn admit(&self) -> Option<Admission> {
+        match self.try_admit() {
+            Some(permit) => Some(Admission::Now(permit)),
+            None => self.join_queue().map(Admission::Queued),
+        }
+    }
+
+    /// Take a free slot, unless the server is full or others are waiting
+    fn try_admit(&self) -> Option<OwnedSemaphorePermit> {
+        if self.waiting.as_ref().is_some_and(|line| line.len() > 0) {
+            return None;
+        }
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    /// Join the waiting line, or None if queueing is disabled or the line is full
+    fn join_queue(&self) -> Option<QueueTicket> {
+        let line = self.waiting.as_ref()?;
+        let mut tickets = line.tickets.lock().unwrap();
+        if tickets.len() >= line.capacity {
+            return None;
+        }
+        let id = line.next_ticket.fetch_add(1, Ordering::Relaxed);
+        tickets.push_back(id);
+        Some(QueueTicket {
+            id,
+            line: line.clone(),
+            slots: self.slots.clone(),
+        })
+    }
+}
+
+impl WaitingLine {
+    fn len(&self) -> usize {
+        self.tickets.lock().unwrap().len()
+    }
+}
+
+impl QueueTicket {
+    /// 1-based position in line
+    pub fn position(&self) -> usize {
+        let tickets = self.line.tickets.lock().unwrap();
+        tickets
+            .iter()
+            .position(|&id| id == self.id)
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Wait until this ticket reaches the front of the line and a slot frees up
+    pub async fn admitted(&self) -> OwnedSemaphorePermit {
+        loop {
+            // Register before checking so a change in between isn't missed
+            let changed = self.line.changed.notified();
+            if self.position() == 1 {
+                return self
+                    .slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("connection slots are never closed");
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.line
+            .tickets
+            .lock()
+            .unwrap()
+            .retain(|&id| id != self.id);
+        self.line.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rejects_at_capacity_without_queue() {
+        let queue = ConnectionQueue::new(1, false);
+        let permit = queue.try_admit();
+        assert!(permit.is_some());
+        assert_eq!(queue.active_connections(), 1);
+        assert!(queue.try_admit().is_none());
+        assert!(queue.join_queue().is_none());
+
+        drop(permit);
+        assert_eq!(queue.active_connections(), 0);
+        assert!(queue.try_admit().is_some());
+    }
+
+    #[test]
+    fn test_queue_positions_and_capacity() {
+        let queue = ConnectionQueue::new(2, true);
+        let _slots = (queue.try_admit(), queue.try_admit());
+
+        let first = queue.join_queue().unwrap();
+        let second = queue.join_queue().unwrap();
+        assert!(queue.join_queue().is_none()); // Line holds max_clients
+        assert_eq!((first.position(), second.position()), (1, 2));
+
+        drop(first);
+        assert_eq!(second.position(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_admitted_in_arrival_order() {
+        let queue = ConnectionQueue::new(2, true);
+        let _busy = queue.try_admit().unwrap();
+        let slot = queue.try_admit().unwrap();
+        let first = queue.join_queue().unwrap();
+        let second = queue.join_queue().unwrap();
+
+        // Later arrivals can't jump the line while others wait
+        drop(slot);
+        assert!(queue.try_admit().is_none());
+
+        let waiting = tokio::time::timeout(Duration::from_millis(50), second.admitted()).await;
+        assert!(waiting.is_err());
+
+        let permit = first.admitted().await;
+        drop(first);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), second.admitted())
+                .await
+                .is_err()
+        );
+
+        drop(permit);
+        let _permit = second.admitted().await;
+    }
+}
@@ -1,8 +1,15 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 
 // Security limits
 pub const RATE_LIMIT_MESSAGES: usize = 10; // Max messages per window
 pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1); // 1 second window
+pub const JOIN_RATE_LIMIT_PER_MINUTE: usize = 10; // Default max joins per IP per window
+pub const JOIN_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60); // 1 minute window
+pub const DUPLICATE_WINDOW_MS: u64 = 2000; // Default window for dropping repeated chat messages
+pub const BYTE_BUDGET_PER_MINUTE: usize = 256 * 1024; // Default bytes a connection may send per window
+pub const BYTE_BUDGET_WINDOW: Duration = Duration::from_secs(60); // 1 minute window
 
 // Simple rate limiter using token bucket
 pub struct RateLimiter {
@@ -42,10 +49,233 @@ impl RateLimiter {
     }
 }
 
+/// Message and byte limits for a new connection. Trusted connections (listed
+/// by address in `CHAT_SERVER_TRUSTED_IPS`, e.g. bots) get neither limit;
+/// keying on the peer address means a client can't claim trust by its name.
+pub fn connection_limits(trusted: bool, byte_budget: usize) -> (RateLimiter, ByteBudget) {
+    if trusted {
+        (
+            RateLimiter::new(usize::MAX, RATE_LIMIT_WINDOW),
+            ByteBudget::new(0, BYTE_BUDGET_WINDOW),
+        )
+    } else {
+        (
+            RateLimiter::new(RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW),
+            ByteBudget::new(byte_budget, BYTE_BUDGET_WINDOW),
+        )
+    }
+}
+
+/// Per-connection file-frequency cap: a token bucket over relayed file bytes
+/// that refuses the next file while in debt. It does not throttle the link.
+pub struct ByteRateLimiter {
+    bytes_per_sec: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl ByteRateLimiter {
+    /// Create a limiter allowing `bytes_per_sec` (0 disables limiting)
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            tokens: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Time left until another file may move, if earlier ones used up the rate
+    pub fn pending(&mut self) -> Option<Duration> {
+        if self.bytes_per_sec == 0 {
+            return None;
+        }
+        self.refill();
+        (self.tokens < 0.0).then(|| self.debt())
+    }
+
+    /// Charge a relayed payload of `bytes`, possibly putting the bucket in debt
+    pub fn charge(&mut self, bytes: usize) {
+        if self.bytes_per_sec == 0 {
+            return;
+        }
+        self.refill();
+        self.tokens -= bytes as f64;
+    }
+
+    /// Time until the bucket is out of debt
+    fn debt(&self) -> Duration {
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_sec as f64)
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        let capacity = self.bytes_per_sec as f64;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec as f64).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+// Sliding window cap on the bytes one connection sends.
+//
+// The message-count `RateLimiter` lets a client send max-size messages just
+// under its limit indefinitely; this caps the volume instead. File transfer
+// payloads are spaced out separately by `ByteRateLimiter` and aren't counted.
+pub struct ByteBudget {
+    max_bytes: usize,
+    window: Duration,
+    sent: VecDeque<(Instant, usize)>,
+    total: usize,
+}
+
+impl ByteBudget {
+    /// Create a budget of `max_bytes` per `window` (0 disables it)
+    pub fn new(max_bytes: usize, window: Duration) -> Self {
+        Self {
+            max_bytes,
+            window,
+            sent: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    /// Record `bytes` sent now, returning false once the window's total exceeds the budget
+    pub fn record(&mut self, bytes: usize) -> bool {
+        if self.max_bytes == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        while let Some(&(at, size)) = self.sent.front() {
+            if now.duration_since(at) < self.window {
+                break;
+            }
+            self.total -= size;
+            self.sent.pop_front();
+        }
+        self.sent.push_back((now, bytes));
+        self.total += bytes;
+        self.total <= self.max_bytes
+    }
+}
+
+// Sliding window limiter for joins, keyed by IP.
+//
+// Join messages bypass the per-connection `RateLimiter`, and reconnecting gets
+// a fresh one anyway, so join/leave cycles are counted here across connections.
+// Timestamps older than the window are dropped on each check, and IPs with no
+// recent joins are pruned once per window to keep the map small.
+pub struct JoinRateLimiter {
+    max_joins: usize,
+    window: Duration,
+    joins: HashMap<IpAddr, VecDeque<Instant>>,
+    last_prune: Instant,
+}
+
+impl JoinRateLimiter {
+    /// Create a limiter allowing `max_joins` per IP within `window` (0 disables limiting)
+    pub fn new(max_joins: usize, window: Duration) -> Self {
+        Self {
+            max_joins,
+            window,
+            joins: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// Record a join from `ip`, returning false if it exceeds the limit
+    pub fn check_and_record(&mut self, ip: IpAddr) -> bool {
+        if self.max_joins == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        self.prune(now);
+
+        let window = self.window;
+        let joins = self.joins.entry(ip).or_default();
+        while joins
+            .front()
+            .is_some_and(|joined| now.duration_since(*joined) >= window)
+        {
+            joins.pop_front();
+        }
+        if joins.len() >= self.max_joins {
+            return false;
+        }
+        joins.push_back(now);
+        true
+    }
+
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.last_prune) < self.window {
+            return;
+        }
+        let window = self.window;
+        self.joins.retain(|_, joins| {
+            joins
+                .back()
+                .is_some_and(|joined| now.duration_since(*joined) < window)
+        });
+        self.last_prune = now;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_byte_budget_trips_on_large_messages_only() {
+        let window = Duration::from_secs(60);
+
+        // Many small messages stay within the budget
+        let mut budget = ByteBudget::new(10 * 1024, window);
+        for _ in 0..500 {
+            assert!(budget.record(16));
+        }
+
+        // Max-size messages exhaust it
+        let mut budget = ByteBudget::new(10 * 1024, window);
+        for _ in 0..10 {
+            assert!(budget.record(1024));
+        }
+        assert!(!budget.record(1024));
+
+        // Zero disables the budget
+        let mut budget = ByteBudget::new(0, window);
+        assert!(budget.record(usize::MAX / 2));
+    }
+
+    #[test]
+    fn test_byte_budget_window_expires() {
+        let mut budget = ByteBudget::new(100, Duration::from_millis(100));
+        assert!(budget.record(100));
+        assert!(!budget.record(1));
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(budget.record(100));
+    }
+
+    #[test]
+    fn test_trusted_connection_not_throttled() {
+        let (mut limiter, mut budget) = connection_limits(true, 1024);
+        for _ in 0..RATE_LIMIT_MESSAGES * 10 {
+            assert!(limiter.check_and_consume());
+            assert!(budget.record(1024));
+        }
+
+        let (mut limiter, mut budget) = connection_limits(false, 1024);
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(limiter.check_and_consume());
+        }
+        assert!(!limiter.check_and_consume());
+        assert!(budget.record(1024));
+        assert!(!budget.record(1));
+    }
+
     #[test]
     fn test_rate_limiter_allows_messages_within_limit() {
         let mut limiter = RateLimiter::new(5, Duration::from_secs(1));
@@ -96,4 +326,75 @@ mod tests {
             std::thread::sleep(Duration::from_millis(60)); // Wait for refill
         }
     }
+
+    #[test]
+    fn test_byte_rate_limiter_unlimited() {
+        let mut limiter = ByteRateLimiter::new(0);
+        limiter.charge(100 * 1024 * 1024);
+        limiter.charge(100 * 1024 * 1024);
+        assert_eq!(limiter.pending(), None);
+    }
+
+    #[test]
+    fn test_byte_rate_limiter_allows_burst() {
+        let mut limiter = ByteRateLimiter::new(1000);
+        limiter.charge(600);
+        assert_eq!(limiter.pending(), None);
+        limiter.charge(400);
+        assert_eq!(limiter.pending(), None);
+    }
+
+    #[test]
+    fn test_byte_rate_limiter_debt_holds_next_file() {
+        let mut limiter = ByteRateLimiter::new(1000);
+
+        // 3000 bytes against a 1000 byte bucket leaves ~2s of debt
+        limiter.charge(3000);
+        let wait = limiter.pending().unwrap();
+        assert!(wait > Duration::from_millis(1900));
+        assert!(wait <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_join_rate_limiter_blocks_excess_joins() {
+        let mut limiter = JoinRateLimiter::new(3, Duration::from_secs(60));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(limiter.check_and_record(ip));
+        }
+        assert!(!limiter.check_and_record(ip));
+
+        // Other IPs have their own budget
+        assert!(limiter.check_and_record(other));
+    }
+
+    #[test]
+    fn test_join_rate_limiter_window_expires_and_prunes() {
+        let mut limiter = JoinRateLimiter::new(1, Duration::from_millis(50));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.check_and_record(ip));
+        assert!(!limiter.check_and_record(ip));
+        assert_eq!(limiter.joins.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        // Stale IPs are dropped once the window has passed
+        assert!(limiter.check_and_record(other));
+        assert_eq!(limiter.joins.len(), 1);
+        assert!(limiter.check_and_record(ip));
+    }
+
+    #[test]
+    fn test_join_rate_limiter_disabled() {
+        let mut limiter = JoinRateLimiter::new(0, Duration::from_secs(60));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..100 {
+            assert!(limiter.check_and_record(ip));
+        }
+        assert_eq!(limiter.joins.len(), 0);
+    }
 }
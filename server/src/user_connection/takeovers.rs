@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// What a tokenless rejoin from the holder's IP may do with the name
+#[derive(Debug, PartialEq)]
+pub enum Takeover {
+    /// The grace is over: take the session over now
+    Ready,
+    /// Join again after this long; the old connection keeps the name until then
+    Scheduled(Duration),
+    /// Another connection is already waiting to take this name over
+    Refused,
+}
+
+// Tokenless takeovers waiting out their grace period, keyed by username.
+//
+// The joining connection isn't held up during the grace: it keeps serving its
+// client and retries the join once the grace is over. Checks are made while
+// `connected_clients` is write-locked, so only one connection at a time can be
+// scheduled for a name. An entry past its grace that was never retried (its
+// connection left) gives way to the next rejoin.
+#[derive(Debug, Default)]
+pub struct TokenlessTakeovers {
+    pending: HashMap<String, (SocketAddr, Instant)>,
+}
+
+impl TokenlessTakeovers {
+    /// Schedule `addr` to take `username` over after `grace`, or tell it how
+    /// its scheduled takeover stands
+    pub fn check(
+        &mut self,
+        username: &str,
+        addr: SocketAddr,
+        grace: Duration,
+        now: Instant,
+    ) -> Takeover {
+        match self.pending.get(username) {
+            Some(&(holder, due)) if holder == addr => {
+                if now < due {
+                    return Takeover::Scheduled(due - now);
+                }
+                self.pending.remove(username);
+                Takeover::Ready
+            }
+            Some(&(_, due)) if now < due => Takeover::Refused,
+            _ if grace.is_zero() => Takeover::Ready,
+            _ => {
+                self.pending
+                    .insert(username.to_string(), (addr, now + grace));
+                Takeover::Scheduled(grace)
+            }
+        }
+    }
+
+    /// Drop anything `addr` had scheduled, once its connection closes
+    pub fn cancel(&mut self, addr: SocketAddr) {
+        self.pending.retain(|_, (holder, _)| *holder != addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_takeover_scheduled_per_name() {
+        let mut takeovers = TokenlessTakeovers::default();
+        let first: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.1:5001".parse().unwrap();
+        let grace = Duration::from_secs(5);
+        let start = Instant::now();
+
+        assert_eq!(
+            takeovers.check("alice", first, grace, start),
+            Takeover::Scheduled(grace)
+        );
+        assert_eq!(
+            takeovers.check("alice", second, grace, start),
+            Takeover::Refused
+        );
+        assert_eq!(
+            takeovers.check("alice", first, grace, start + Duration::from_secs(2)),
+            Takeover::Scheduled(Duration::from_secs(3))
+        );
+        assert_eq!(
+            takeovers.check("alice", first, grace, start + grace),
+            Takeover::Ready
+        );
+    }
+
+    #[test]
+    fn test_abandoned_takeover_gives_way() {
+        let mut takeovers = TokenlessTakeovers::default();
+        let first: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.1:5001".parse().unwrap();
+        let grace = Duration::from_secs(5);
+        let start = Instant::now();
+
+        takeovers.check("alice", first, grace, start);
+        assert_eq!(
+            takeovers.check("alice", second, grace, start + grace),
+            Takeover::Scheduled(grace)
+        );
+
+        takeovers.cancel(second);
+        assert_eq!(
+            takeovers.check("alice", first, grace, start + grace),
+            Takeover::Scheduled(grace)
+        );
+    }
+}
@@ -0,0 +1,1527 @@
+//! Offline harness for `MessageHandlers` tests
+//!
+//! Feeds messages through `process_message` over an in-memory duplex stream
+//! with throwaway channels and shared maps, so join/rename/DM/status flows can
+//! be tested without real sockets:
+//!
+//! ```ignore
+//! let mut harness = HandlerHarness::new();
+//! harness.add_user("bob").await;
+//! let output = harness
+//!     .run(vec![join("alice"), message(MessageTypes::RenameRequest, "bob")])
+//!     .await;
+//! assert_eq!(output.errors().len(), 1);
+//! ```
+
+use super::DirectRoute;
+use super::away::AwayReplies;
+use super::blocks::BlockList;
+use super::error::UserConnectionError;
+use super::file_cache::FileCache;
+use super::handlers::{LastChat, MessageHandlers};
+use super::pages::PageLimiter;
+use super::polls::PollBoard;
+use super::rate_limiting::{ByteRateLimiter, JoinRateLimiter, RateLimiter};
+use super::takeovers::TokenlessTakeovers;
+use super::transfers::TransferTracker;
+use crate::ServerCommand;
+use crate::federation::RemoteUsers;
+use crate::file_policy::FileTypePolicy;
+use crate::user_registry::UserRegistry;
+use shared::limits::{MAX_SHARED_FILE_SIZE, ServerLimits};
+use shared::message::{ChatMessage, ErrorCode, MessageTypes, PollSummary, ServerInfo, parse_error};
+use shared::network::TcpMessageHandler;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::DuplexStream;
+use tokio::sync::{Mutex, RwLock, broadcast, mpsc};
+
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Simulated client end of the duplex stream; acknowledges every reply and reads
+/// compressed frames if it negotiated them
+struct Peer(DuplexStream, bool);
+
+impl TcpMessageHandler for Peer {
+    type Stream = DuplexStream;
+    fn get_stream(&mut self) -> &mut Self::Stream {
+        &mut self.0
+    }
+    fn accepts_compressed(&self) -> bool {
+        self.1
+    }
+}
+
+/// What a connection observed while its messages were processed
+#[derive(Debug)]
+pub struct HarnessOutput {
+    /// Messages sent back over the connection's own stream
+    pub replies: Vec<ChatMessage>,
+    /// Messages broadcast to every client
+    pub broadcasts: Vec<ChatMessage>,
+    /// Error that ended processing early, if any
+    pub error: Option<UserConnectionError>,
+}
+
+impl HarnessOutput {
+    /// Text of every Error reply, in order
+    pub fn errors(&self) -> Vec<String> {
+        self.error_replies().map(|(_, text)| text).collect()
+    }
+
+    /// Code of every Error reply, in order
+    pub fn error_codes(&self) -> Vec<ErrorCode> {
+        self.error_replies().map(|(code, _)| code).collect()
+    }
+
+    fn error_replies(&self) -> impl Iterator<Item = (ErrorCode, String)> + '_ {
+        self.replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Error)
+            .filter_map(ChatMessage::content_as_string)
+            .map(|content| {
+                let (code, text) = parse_error(&content);
+                (code, text.to_string())
+            })
+    }
+}
+
+/// Shared server state plus one simulated connection
+pub struct HandlerHarness {
+    pub addr: SocketAddr,
+    tx: broadcast::Sender<(ChatMessage, SocketAddr)>,
+    /// None simulates a server with no subscribed clients
+    broadcast_rx: Option<broadcast::Receiver<(ChatMessage, SocketAddr)>>,
+    server_commands: broadcast::Sender<ServerCommand>,
+    pub connected_clients: Arc<RwLock<HashSet<String>>>,
+    pub remote_users: Arc<RwLock<RemoteUsers>>,
+    pub user_ips: Arc<RwLock<HashMap<String, IpAddr>>>,
+    pub user_statuses: Arc<RwLock<HashMap<String, String>>>,
+    pub user_sessions: Arc<RwLock<HashMap<String, String>>>,
+    pub session_secrets: Arc<RwLock<HashMap<String, String>>>,
+    pub user_channels: Arc<RwLock<HashMap<String, DirectRoute>>>,
+    pub readonly_users: Arc<RwLock<HashSet<String>>>,
+    pub operators: Arc<RwLock<HashSet<String>>>,
+    pub oper_password: Option<String>,
+    join_limiter: Arc<Mutex<JoinRateLimiter>>,
+    pub transfers: Arc<Mutex<TransferTracker>>,
+    away_replies: Arc<Mutex<AwayReplies>>,
+    pages: Arc<Mutex<PageLimiter>>,
+    polls: Arc<Mutex<PollBoard>>,
+    file_cache: Arc<Mutex<FileCache>>,
+    pub blocks: Arc<RwLock<BlockList>>,
+    pub user_registry: Arc<Mutex<UserRegistry>>,
+    file_policy: FileTypePolicy,
+    pub limits: ServerLimits,
+    pub slowmode: Duration,
+    pub duplicate_window: Duration,
+    pub tokenless_takeover: Option<Duration>,
+    tokenless_takeovers: Arc<Mutex<TokenlessTakeovers>>,
+    pub server_name: Option<String>,
+    /// Whether the simulated client negotiated compression
+    pub compress: bool,
+    /// Whether the simulated client passed the version check
+    pub version_checked: bool,
+    /// Whether the next join is a retried deferred one
+    pub retrying_join: bool,
+    pub welcome_message: Option<String>,
+    pub topic: Arc<RwLock<Option<String>>>,
+    pub open_topic: bool,
+    started_at: Instant,
+    rate_limiter: RateLimiter,
+    file_rate_limiter: ByteRateLimiter,
+    /// Username of the simulated connection once it has joined
+    pub chat_name: Option<String>,
+    last_chat: Option<LastChat>,
+}
+
+impl HandlerHarness {
+    pub fn new() -> Self {
+        let (tx, broadcast_rx) = broadcast::channel(100);
+        let (server_commands, _) = broadcast::channel(16);
+        HandlerHarness {
+            addr: "127.0.0.1:40000".parse().unwrap(),
+            tx,
+            broadcast_rx: Some(broadcast_rx),
+            server_commands,
+            connected_clients: Arc::new(RwLock::new(HashSet::new())),
+            remote_users: Arc::new(RwLock::new(RemoteUsers::default())),
+            user_ips: Arc::new(RwLock::new(HashMap::new())),
+            user_statuses: Arc::new(RwLock::new(HashMap::new())),
+            user_sessions: Arc::new(RwLock::new(HashMap::new())),
+            session_secrets: Arc::new(RwLock::new(HashMap::new())),
+            user_channels: Arc::new(RwLock::new(HashMap::new())),
+            readonly_users: Arc::new(RwLock::new(HashSet::new())),
+            operators: Arc::new(RwLock::new(HashSet::new())),
+            oper_password: None,
+            join_limiter: Arc::new(Mutex::new(JoinRateLimiter::new(0, Duration::ZERO))),
+            transfers: Arc::new(Mutex::new(TransferTracker::default())),
+            away_replies: Arc::new(Mutex::new(AwayReplies::default())),
+            pages: Arc::new(Mutex::new(PageLimiter::default())),
+            polls: Arc::new(Mutex::new(PollBoard::default())),
+            file_cache: Arc::new(Mutex::new(FileCache::new(MAX_SHARED_FILE_SIZE))),
+            blocks: Arc::new(RwLock::new(BlockList::default())),
+            user_registry: Arc::new(Mutex::new(UserRegistry::default())),
+            file_policy: FileTypePolicy::default(),
+            limits: ServerLimits::default(),
+            slowmode: Duration::ZERO,
+            duplicate_window: Duration::ZERO,
+            tokenless_takeover: None,
+            tokenless_takeovers: Arc::new(Mutex::new(TokenlessTakeovers::default())),
+            server_name: None,
+            compress: false,
+            version_checked: false,
+            retrying_join: false,
+            welcome_message: None,
+            topic: Arc::new(RwLock::new(None)),
+            open_topic: false,
+            started_at: Instant::now(),
+            rate_limiter: RateLimiter::new(1000, Duration::from_millis(1)),
+            file_rate_limiter: ByteRateLimiter::new(0),
+            chat_name: None,
+            last_chat: None,
+        }
+    }
+
+    /// Drop the broadcast receiver so broadcasts have no subscribers at all
+    pub fn without_broadcast_subscribers(mut self) -> Self {
+        self.broadcast_rx = None;
+        self
+    }
+
+    /// Mark another user as connected
+    pub async fn add_user(&self, name: &str) {
+        self.connected_clients
+            .write()
+            .await
+            .insert(name.to_string());
+    }
+
+    /// Mark a user as online on a linked server
+    pub async fn add_remote_user(&self, name: &str) {
+        self.remote_users.write().await.add("peer", name);
+    }
+
+    /// Give a connected user point-to-point queues, returning their receiving
+    /// ends (messages, files)
+    pub async fn add_route(
+        &self,
+        name: &str,
+    ) -> (mpsc::Receiver<ChatMessage>, mpsc::Receiver<ChatMessage>) {
+        let (route, messages, files) = DirectRoute::open();
+        self.user_channels
+            .write()
+            .await
+            .insert(name.to_string(), route);
+        (messages, files)
+    }
+
+    /// Process `messages` in order as if they arrived on the simulated
+    /// connection, stopping at the first error
+    pub async fn run(&mut self, messages: Vec<ChatMessage>) -> HarnessOutput {
+        let (mut server_end, client_end) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        let compress = self.compress;
+        let peer = tokio::spawn(async move {
+            let mut peer = Peer(client_end, compress);
+            let mut replies = Vec::new();
+            while let Ok(message) = peer.read_message_chunked().await {
+                replies.push(message);
+            }
+            replies
+        });
+
+        let handlers = MessageHandlers {
+            addr: self.addr,
+            tx: &self.tx,
+            server_commands: &self.server_commands,
+            connected_clients: &self.connected_clients,
+            remote_users: &self.remote_users,
+            user_ips: &self.user_ips,
+            user_statuses: &self.user_statuses,
+            user_sessions: &self.user_sessions,
+            session_secrets: &self.session_secrets,
+            user_channels: &self.user_channels,
+            readonly_users: &self.readonly_users,
+            operators: &self.operators,
+            oper_password: self.oper_password.as_deref(),
+            motd: None,
+            server_name: self.server_name.as_deref(),
+            welcome_message: self.welcome_message.as_deref(),
+            topic: &self.topic,
+            open_topic: self.open_topic,
+            limits: self.limits,
+            join_limiter: &self.join_limiter,
+            transfers: &self.transfers,
+            away_replies: &self.away_replies,
+            pages: &self.pages,
+            polls: &self.polls,
+            file_cache: &self.file_cache,
+            blocks: &self.blocks,
+            user_registry: &self.user_registry,
+            file_policy: &self.file_policy,
+            slowmode: self.slowmode,
+            duplicate_window: self.duplicate_window,
+            tokenless_takeover: self.tokenless_takeover,
+            tokenless_takeovers: &self.tokenless_takeovers,
+            webhook: None,
+            history: None,
+            started_at: self.started_at,
+            compress: self.compress,
+            version_checked: self.version_checked,
+            retrying_join: self.retrying_join,
+        };
+
+        let mut error = None;
+        for message in messages {
+            let result = handlers
+                .process_message(
+                    message,
+                    &mut self.rate_limiter,
+                    &mut self.file_rate_limiter,
+                    &mut server_end,
+                    &mut self.chat_name,
+                    &mut self.last_chat,
+                )
+                .await;
+            if let Err(e) = result {
+                error = Some(e);
+                break;
+            }
+        }
+
+        // Closing our end lets the peer task finish
+        drop(server_end);
+        let replies = peer.await.unwrap();
+
+        let mut broadcasts = Vec::new();
+        if let Some(broadcast_rx) = &mut self.broadcast_rx {
+            while let Ok((message, _)) = broadcast_rx.try_recv() {
+                broadcasts.push(message);
+            }
+        }
+
+        HarnessOutput {
+            replies,
+            broadcasts,
+            error,
+        }
+    }
+}
+
+/// Build a message with string content
+pub fn message(msg_type: MessageTypes, content: &str) -> ChatMessage {
+    ChatMessage::try_new(msg_type, Some(content.as_bytes().to_vec())).unwrap()
+}
+
+/// Join message for `name` with a fixed session token
+pub fn join(name: &str) -> ChatMessage {
+    message(
+        MessageTypes::Join,
+        &format!("{}|test-session-{}", name, name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::file_transfer::{FileOfferHeader, FileTransferHeader, FileUploadHeader};
+
+    use crate::user_connection::takeovers::Takeover;
+    #[tokio::test]
+    async fn test_rename_to_taken_name_rejected() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::RenameRequest, "bob"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let errors = output.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Username 'bob' is already taken"));
+        let suggestion = shared::message::parse_name_suggestion(&errors[0]).unwrap();
+        assert!(suggestion.starts_with("bob_"));
+        assert_eq!(harness.chat_name.as_deref(), Some("alice"));
+        assert!(
+            !output
+                .broadcasts
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::UserRename)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_suggestion_fits_length_limit() {
+        let mut harness = HandlerHarness::new();
+        let long_name = "a".repeat(shared::limits::MAX_USERNAME_LENGTH);
+        harness.add_user(&long_name).await;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::RenameRequest, &long_name),
+            ])
+            .await;
+
+        let errors = output.errors();
+        let suggestion = shared::message::parse_name_suggestion(&errors[0]).unwrap();
+        assert!(suggestion.len() <= shared::limits::MAX_USERNAME_LENGTH);
+        assert_ne!(suggestion, long_name);
+    }
+
+    #[tokio::test]
+    async fn test_welcome_message_sent_to_joiner() {
+        let mut harness = HandlerHarness::new();
+        harness.welcome_message = Some("Hi {username}, see the rules at /rules".to_string());
+
+        let output = harness.run(vec![join("alice")]).await;
+
+        let notices: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ServerNotice)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(notices, vec!["Hi alice, see the rules at /rules"]);
+        assert!(
+            !output
+                .broadcasts
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::ServerNotice)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_name_sent_to_joiner() {
+        let mut harness = HandlerHarness::new();
+        let output = harness.run(vec![join("alice")]).await;
+        assert!(
+            !output
+                .replies
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::ServerName)
+        );
+
+        let mut harness = HandlerHarness::new();
+        harness.server_name = Some("Rusty Lounge".to_string());
+        let output = harness.run(vec![join("alice")]).await;
+        let names: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ServerName)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(names, vec!["Rusty Lounge"]);
+    }
+
+    #[tokio::test]
+    async fn test_compression_confirmed_on_join() {
+        let mut harness = HandlerHarness::new();
+        harness.compress = true;
+        for i in 0..50 {
+            harness.add_user(&format!("user_{:02}", i)).await;
+        }
+        let output = harness
+            .run(vec![
+                join("alice"),
+                ChatMessage::try_new(MessageTypes::ListUsers, None).unwrap(),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let confirmations: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Compression)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(confirmations, vec!["lz4"]);
+        // A list this long is sent compressed and still arrives intact
+        let lists = user_lists(&output);
+        assert_eq!(lists[0].len(), 51);
+        assert_eq!(lists[0][50], "user_49");
+    }
+
+    #[tokio::test]
+    async fn test_client_ping_echoed_as_pong() {
+        let mut harness = HandlerHarness::new();
+        let output = harness
+            .run(vec![
+                join("alice"),
+                ChatMessage::try_new(MessageTypes::Ping, Some(b"42".to_vec())).unwrap(),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let pongs: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Pong)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(pongs, vec!["42"]);
+    }
+
+    /// Content of every ListUsers reply, split into lines
+    fn user_lists(output: &HarnessOutput) -> Vec<Vec<String>> {
+        output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ListUsers)
+            .filter_map(ChatMessage::content_as_string)
+            .map(|content| content.lines().map(str::to_string).collect())
+            .collect()
+    }
+
+    fn chat_acks(output: &HarnessOutput) -> usize {
+        output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ChatAck)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_user_list_sorted() {
+        let mut harness = HandlerHarness::new();
+        for name in ["carol", "Bob", "dave"] {
+            harness.add_user(name).await;
+        }
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                ChatMessage::try_new(MessageTypes::ListUsers, None).unwrap(),
+            ])
+            .await;
+
+        assert_eq!(
+            user_lists(&output),
+            vec![vec!["alice", "Bob", "carol", "dave"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_users_listed_and_reserved() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        harness.add_remote_user("carol").await;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                ChatMessage::try_new(MessageTypes::ListUsers, None).unwrap(),
+                message(MessageTypes::Whois, "carol"),
+                message(MessageTypes::DirectMessage, "carol|hi"),
+                message(MessageTypes::RenameRequest, "carol"),
+            ])
+            .await;
+
+        assert_eq!(
+            user_lists(&output),
+            vec![vec!["alice", "bob", "carol [remote]"]]
+        );
+        let whois: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Whois)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(whois, vec!["carol is online on a linked server"]);
+        assert_eq!(
+            output.error_codes(),
+            vec![ErrorCode::DeliveryFailed, ErrorCode::NameTaken]
+        );
+        assert_eq!(harness.chat_name.as_deref(), Some("alice"));
+
+        // Joining under a remote user's name gets a different one
+        harness.chat_name = None;
+        harness.run(vec![join("carol")]).await;
+        assert!(harness.chat_name.is_some());
+        assert_ne!(harness.chat_name.as_deref(), Some("carol"));
+    }
+
+    #[tokio::test]
+    async fn test_user_list_paginated() {
+        let mut harness = HandlerHarness::new();
+        for i in 0..shared::message::USER_LIST_PAGE_SIZE {
+            harness.add_user(&format!("user{:02}", i)).await;
+        }
+
+        let output = harness
+            .run(vec![
+                join("zed"),
+                message(MessageTypes::ListUsers, "2"),
+                message(MessageTypes::ListUsers, "3"),
+            ])
+            .await;
+
+        assert_eq!(user_lists(&output), vec![vec!["#page 2/2 21", "zed"]]);
+        assert_eq!(output.errors(), vec!["Page 3 out of range (1-2)"]);
+    }
+
+    #[tokio::test]
+    async fn test_readonly_user_cannot_send() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+
+        let output = harness
+            .run(vec![
+                message(MessageTypes::Join, "screen|test-session|readonly"),
+                message(MessageTypes::ChatMessage, "hello"),
+                message(MessageTypes::DirectMessage, "bob|hi"),
+                ChatMessage::try_new(MessageTypes::ListUsers, None).unwrap(),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        assert_eq!(
+            output.errors(),
+            vec![
+                "Read-only connections cannot send messages",
+                "Read-only connections cannot send messages"
+            ]
+        );
+        assert!(
+            !output
+                .broadcasts
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::ChatMessage)
+        );
+        assert_eq!(user_lists(&output), vec![vec!["bob", "screen [readonly]"]]);
+        assert_eq!(
+            harness
+                .user_sessions
+                .read()
+                .await
+                .get("screen")
+                .map(String::as_str),
+            Some("test-session")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dm_to_readonly_user_rejected() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("screen").await;
+        harness
+            .readonly_users
+            .write()
+            .await
+            .insert("screen".to_string());
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::DirectMessage, "screen|hi"),
+            ])
+            .await;
+
+        assert_eq!(output.errors(), vec!["User 'screen' is read-only"]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_chat_dropped() {
+        let mut harness = HandlerHarness::new();
+        harness.duplicate_window = Duration::from_secs(2);
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::ChatMessage, "hello"),
+                message(MessageTypes::ChatMessage, "hello"),
+                message(MessageTypes::ChatMessage, "hello again"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        assert!(output.errors().is_empty());
+        let chats: Vec<String> = output
+            .broadcasts
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ChatMessage)
+            .filter_map(ChatMessage::content_as_string)
+            .map(|content| {
+                shared::message::parse_stamped_chat_content(&content)
+                    .1
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(chats, vec!["alice: hello", "alice: hello again"]);
+        // The dropped duplicate is still acknowledged
+        assert_eq!(chat_acks(&output), 3);
+    }
+
+    #[tokio::test]
+    async fn test_only_accepted_chats_acked() {
+        let mut harness = HandlerHarness::new();
+        harness.slowmode = Duration::from_secs(60);
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::ChatMessage, "first"),
+                message(MessageTypes::ChatMessage, "too soon"),
+            ])
+            .await;
+
+        assert_eq!(output.error_codes(), vec![ErrorCode::RateLimited]);
+        assert_eq!(chat_acks(&output), 1);
+    }
+
+    #[tokio::test]
+    async fn test_topic_requires_open_topic() {
+        let mut harness = HandlerHarness::new();
+
+        let output = harness
+            .run(vec![join("alice"), message(MessageTypes::Topic, "Hello")])
+            .await;
+
+        assert_eq!(output.errors(), vec!["Only operators can change the topic"]);
+        assert_eq!(output.error_codes(), vec![ErrorCode::NotPermitted]);
+        assert!(harness.topic.read().await.is_none());
+
+        harness.operators.write().await.insert("alice".to_string());
+        let output = harness
+            .run(vec![message(MessageTypes::Topic, "Hello")])
+            .await;
+        assert!(output.errors().is_empty());
+        assert_eq!(harness.topic.read().await.as_deref(), Some("Hello"));
+    }
+
+    fn notices(output: &HarnessOutput) -> Vec<String> {
+        output
+            .broadcasts
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ServerNotice)
+            .filter_map(ChatMessage::content_as_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_oper_password_grants_operator() {
+        let mut harness = HandlerHarness::new();
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Moderation, "oper|hunter2"),
+            ])
+            .await;
+        assert_eq!(
+            output.errors(),
+            vec!["This server has no operator password"]
+        );
+
+        harness.oper_password = Some("hunter2".to_string());
+        let output = harness
+            .run(vec![
+                message(MessageTypes::Moderation, "oper|guess"),
+                message(MessageTypes::Moderation, "oper|hunter2"),
+            ])
+            .await;
+        assert_eq!(output.errors(), vec!["Wrong operator password"]);
+        assert_eq!(notices(&output), vec!["alice is now an operator"]);
+        assert!(harness.operators.read().await.contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_operators_op_deop_and_kick() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Moderation, "kick|bob"),
+            ])
+            .await;
+        assert_eq!(output.error_codes(), vec![ErrorCode::NotPermitted]);
+
+        harness.operators.write().await.insert("alice".to_string());
+        let output = harness
+            .run(vec![
+                message(MessageTypes::Moderation, "op|bob"),
+                message(MessageTypes::Moderation, "kick|bob"),
+                message(MessageTypes::Moderation, "deop|bob"),
+                message(MessageTypes::Moderation, "kick|bob"),
+                message(MessageTypes::Moderation, "op|carol"),
+            ])
+            .await;
+        assert_eq!(
+            output.errors(),
+            vec![
+                "bob is an operator; /deop them first",
+                "User 'carol' not found"
+            ]
+        );
+        assert_eq!(
+            notices(&output),
+            vec![
+                "alice made bob an operator",
+                "alice removed bob's operator rights",
+                "bob was kicked by alice"
+            ]
+        );
+        assert!(!harness.operators.read().await.contains("bob"));
+    }
+
+    #[tokio::test]
+    async fn test_topic_set_and_sent_to_joiners() {
+        let mut harness = HandlerHarness::new();
+        harness.open_topic = true;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Topic, " Release\u{7} day "),
+            ])
+            .await;
+
+        let topics: Vec<String> = output
+            .broadcasts
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Topic)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(topics, vec!["Release day"]);
+
+        // The next joiner receives the stored topic directly
+        let mut joiner = HandlerHarness::new();
+        joiner.topic = harness.topic.clone();
+        let output = joiner.run(vec![join("bob")]).await;
+        assert!(
+            output
+                .replies
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::Topic
+                    && msg.content_as_string().as_deref() == Some("Release day"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_chat_reported() {
+        let mut harness = HandlerHarness::new();
+        let invalid =
+            ChatMessage::try_new(MessageTypes::ChatMessage, Some(vec![b'h', 0xff, 0xfe])).unwrap();
+
+        let output = harness.run(vec![join("alice"), invalid]).await;
+
+        assert!(output.error.is_none());
+        assert_eq!(output.errors(), vec!["Message contained invalid UTF-8"]);
+        assert!(
+            !output
+                .broadcasts
+                .iter()
+                .any(|msg| msg.msg_type == MessageTypes::ChatMessage)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dm_to_missing_user() {
+        let mut harness = HandlerHarness::new();
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::DirectMessage, "ghost|are you there?"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        assert_eq!(output.errors(), vec!["User 'ghost' not found"]);
+    }
+
+    #[tokio::test]
+    async fn test_join_is_broadcast() {
+        let mut harness = HandlerHarness::new();
+
+        let output = harness.run(vec![join("alice")]).await;
+
+        assert!(output.error.is_none());
+        let joins: Vec<String> = output
+            .broadcasts
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Join)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(joins, vec!["alice"]);
+        assert!(harness.connected_clients.read().await.contains("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_file_transfer_cap_per_user() {
+        let mut harness = HandlerHarness::new();
+        harness.limits.max_file_transfers = 1;
+        // Keep the receivers alive so requests can be delivered
+        let mut receivers = Vec::new();
+        for name in ["bob", "carol"] {
+            harness.add_user(name).await;
+            receivers.push(harness.add_route(name).await);
+        }
+        let request = |recipient: &str| {
+            let mut content = vec![recipient.len() as u8];
+            content.extend_from_slice(recipient.as_bytes());
+            content.push(5);
+            content.extend_from_slice(b"a.txt");
+            content.extend_from_slice(&10u64.to_be_bytes());
+            ChatMessage::try_new(MessageTypes::FileTransferRequest, Some(content)).unwrap()
+        };
+
+        let output = harness
+            .run(vec![join("alice"), request("bob"), request("carol")])
+            .await;
+
+        assert!(output.error.is_none());
+        assert_eq!(
+            output.errors(),
+            vec!["Too many file transfers in progress (max 1)"]
+        );
+        assert_eq!(harness.transfers.lock().await.count("alice"), 1);
+        assert!(receivers[1].0.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_request_caption_relayed() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (mut receiver, _files) = harness.add_route("bob").await;
+
+        let mut content = vec![3];
+        content.extend_from_slice(b"bob");
+        content.push(5);
+        content.extend_from_slice(b"a.txt");
+        content.extend_from_slice(&10u64.to_be_bytes());
+        content.push(7);
+        content.extend_from_slice(b"for you");
+        let request = ChatMessage::try_new(MessageTypes::FileTransferRequest, Some(content));
+
+        let output = harness.run(vec![join("alice"), request.unwrap()]).await;
+
+        assert!(output.error.is_none());
+        let relayed = receiver.try_recv().unwrap();
+        let relayed = relayed.get_content().unwrap();
+        // recipient, then the sender inserted by the server, then the rest untouched
+        assert!(relayed.starts_with(b"\x03bob\x05alice\x05a.txt"));
+        assert!(relayed.ends_with(b"\x07for you"));
+    }
+
+    #[tokio::test]
+    async fn test_server_info_reply() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                ChatMessage::try_new(MessageTypes::ServerInfo, None).unwrap(),
+            ])
+            .await;
+
+        let info = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ServerInfo)
+            .find_map(ChatMessage::content_as_string)
+            .and_then(|content| ServerInfo::parse(&content))
+            .unwrap();
+        assert_eq!(info.version, shared::version::VERSION);
+        assert_eq!(info.users, 2);
+    }
+
+    #[tokio::test]
+    async fn test_whois_online_and_offline() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let three_hours_ago = SystemTime::now() - Duration::from_secs(3 * 3_600 + 30);
+        harness
+            .user_registry
+            .lock()
+            .await
+            .record("carol", three_hours_ago);
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Whois, "bob"),
+                message(MessageTypes::Whois, "carol"),
+                message(MessageTypes::Whois, "dave"),
+            ])
+            .await;
+
+        let replies: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Whois)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(
+            replies,
+            vec![
+                "bob is online",
+                "carol was last seen 3h ago",
+                "No record of 'dave'"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_seen_online_offline_and_unknown() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let ten_minutes_ago = SystemTime::now() - Duration::from_secs(10 * 60 + 5);
+        harness
+            .user_registry
+            .lock()
+            .await
+            .record("carol", ten_minutes_ago);
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::SeenRequest, "bob"),
+                message(MessageTypes::SeenRequest, "carol"),
+                message(MessageTypes::SeenRequest, "dave"),
+            ])
+            .await;
+
+        let replies: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::SeenRequest)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(
+            replies,
+            vec![
+                "bob is online",
+                "carol was last seen 10m ago",
+                "dave has never been seen"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_without_history_is_refused() {
+        let output = HandlerHarness::new()
+            .run(vec![join("alice"), message(MessageTypes::Search, "deploy")])
+            .await;
+
+        assert_eq!(output.error_codes(), vec![ErrorCode::NotPermitted]);
+        assert_eq!(
+            output.errors(),
+            vec!["This server doesn't keep message history"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dm_to_away_user_auto_replies_once() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (mut receiver, _files) = harness.add_route("bob").await;
+        harness
+            .user_statuses
+            .write()
+            .await
+            .insert("bob".to_string(), "AFK: lunch".to_string());
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::DirectMessage, "bob|hi"),
+                message(MessageTypes::DirectMessage, "bob|you there?"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let notices: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::ServerNotice)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(notices, vec!["bob is away: lunch"]);
+        // Both DMs are still delivered
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_blocked_dm_not_delivered() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (mut receiver, _files) = harness.add_route("bob").await;
+        harness.blocks.write().await.block("bob", "alice").unwrap();
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::DirectMessage, "bob|hi"),
+            ])
+            .await;
+
+        // The sender sees the same error as a failed delivery
+        assert!(output.error.is_none());
+        assert_eq!(output.errors(), vec!["Could not deliver message to 'bob'"]);
+        assert_eq!(output.error_codes(), vec![ErrorCode::DeliveryFailed]);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_page_limited_per_recipient() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (mut receiver, _files) = harness.add_route("bob").await;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Page, "bob|server is down"),
+                message(MessageTypes::Page, "bob|still down"),
+                message(MessageTypes::Page, "carol|are you there?"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let page = receiver.try_recv().unwrap();
+        assert_eq!(page.msg_type, MessageTypes::Page);
+        assert_eq!(
+            page.content_as_string().as_deref(),
+            Some("alice|server is down")
+        );
+        // The second page within the interval is refused, not delivered
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(
+            output.error_codes(),
+            vec![ErrorCode::RateLimited, ErrorCode::UserNotFound]
+        );
+        assert!(output.errors()[0].starts_with("You can page bob again in"));
+    }
+
+    #[tokio::test]
+    async fn test_poll_votes_tallied_and_broadcast() {
+        let mut harness = HandlerHarness::new();
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Poll, "Lunch? | pizza | tacos"),
+                message(MessageTypes::Vote, "1|2"),
+                message(MessageTypes::Vote, "1|1"),
+                message(MessageTypes::Vote, "1|3"),
+                message(MessageTypes::Vote, "2|1"),
+                message(MessageTypes::Poll, "Lunch? | pizza"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        let polls: Vec<(MessageTypes, PollSummary)> = output
+            .broadcasts
+            .iter()
+            .filter(|msg| matches!(msg.msg_type, MessageTypes::Poll | MessageTypes::PollResult))
+            .filter_map(|msg| Some((msg.msg_type, PollSummary::parse(&msg.content_as_string()?)?)))
+            .collect();
+        assert_eq!(polls.len(), 3);
+        assert_eq!(polls[0].0, MessageTypes::Poll);
+        assert_eq!(polls[0].1.creator, "alice");
+        assert_eq!(polls[0].1.question, "Lunch?");
+        // Voting again moves the user's vote rather than adding another
+        assert_eq!(polls[2].0, MessageTypes::PollResult);
+        assert_eq!(
+            polls[2].1.options,
+            vec![("pizza".to_string(), 1), ("tacos".to_string(), 0)]
+        );
+        assert_eq!(
+            output.errors(),
+            vec![
+                "Choose an option from 1 to 2",
+                "No open poll #2",
+                "Polls need 2-10 options"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shared_file_offered_then_fetched() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let upload = |filename: &str| {
+            let content = FileUploadHeader {
+                filename,
+                data: b"hello",
+            }
+            .encode()
+            .unwrap();
+            ChatMessage::try_new(MessageTypes::FileOffer, Some(content)).unwrap()
+        };
+        let get = |file_id: u64| {
+            ChatMessage::try_new(MessageTypes::FileGet, Some(file_id.to_be_bytes().to_vec()))
+                .unwrap()
+        };
+
+        let output = harness
+            .run(vec![join("alice"), upload("notes.txt"), get(1), get(2)])
+            .await;
+
+        let offer = output
+            .broadcasts
+            .iter()
+            .find(|msg| msg.msg_type == MessageTypes::FileOffer)
+            .unwrap();
+        let offer = FileOfferHeader::parse(offer.get_content().unwrap()).unwrap();
+        assert_eq!(
+            (offer.file_id, offer.sender, offer.filename, offer.file_size),
+            (1, "alice", "notes.txt", 5)
+        );
+
+        let file = output
+            .replies
+            .iter()
+            .find(|msg| msg.msg_type == MessageTypes::FileGet)
+            .unwrap();
+        let file = FileTransferHeader::parse_relayed(file.get_content().unwrap()).unwrap();
+        assert_eq!(file.recipient, "alice");
+        assert_eq!(file.sender, Some("alice"));
+        assert_eq!(file.data, b"hello");
+        assert_eq!(
+            output.errors(),
+            vec!["No shared file #2 (it may have expired)"]
+        );
+
+        // bob was the only one waiting for it, so it's gone once he has it
+        let mut file_cache = harness.file_cache.lock().await;
+        assert!(file_cache.fetch(1, "bob", Instant::now()).is_some());
+        assert!(file_cache.fetch(1, "bob", Instant::now()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shared_files_paced_by_file_rate_limit() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        harness.file_rate_limiter = ByteRateLimiter::new(1);
+        let upload = FileUploadHeader {
+            filename: "notes.txt",
+            data: b"hello",
+        }
+        .encode()
+        .unwrap();
+        let upload = ChatMessage::try_new(MessageTypes::FileOffer, Some(upload)).unwrap();
+        let get =
+            ChatMessage::try_new(MessageTypes::FileGet, Some(1u64.to_be_bytes().to_vec())).unwrap();
+
+        let output = harness
+            .run(vec![join("alice"), upload.clone(), upload, get])
+            .await;
+
+        // The first upload leaves the connection in debt, which refuses the rest
+        assert_eq!(
+            output.error_codes(),
+            vec![ErrorCode::RateLimited, ErrorCode::RateLimited]
+        );
+        // The refused /get didn't count, so the file still waits for bob
+        let mut file_cache = harness.file_cache.lock().await;
+        assert!(file_cache.fetch(1, "bob", Instant::now()).is_some());
+        assert!(file_cache.fetch(2, "bob", Instant::now()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_queued_files_per_recipient_bounded() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (mut messages, mut files) = harness.add_route("bob").await;
+        let content = FileTransferHeader {
+            recipient: "bob",
+            sender: None,
+            filename: "a.bin",
+            data: &[0; 1024],
+        }
+        .encode()
+        .unwrap();
+        let file = ChatMessage::try_new(MessageTypes::FileTransfer, Some(content)).unwrap();
+
+        // bob isn't reading: the first file waits in his queue, the second is refused
+        let output = harness
+            .run(vec![
+                join("alice"),
+                file.clone(),
+                file,
+                message(MessageTypes::DirectMessage, "bob|hi"),
+            ])
+            .await;
+
+        assert_eq!(output.error_codes(), vec![ErrorCode::DeliveryFailed]);
+        assert!(files.try_recv().is_ok());
+        assert!(files.try_recv().is_err());
+        // Chat still has its own queue
+        assert_eq!(
+            messages.try_recv().unwrap().msg_type,
+            MessageTypes::DirectMessage
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_bytes_per_second_capped_by_file_rate() {
+        const RATE: usize = 20_000;
+        let mut harness = HandlerHarness::new();
+        harness.add_user("bob").await;
+        let (_messages, mut files) = harness.add_route("bob").await;
+        harness.file_rate_limiter = ByteRateLimiter::new(RATE as u64);
+        harness.run(vec![join("alice")]).await;
+        let content = FileTransferHeader {
+            recipient: "bob",
+            sender: None,
+            filename: "a.bin",
+            data: &[0; 5_000],
+        }
+        .encode()
+        .unwrap();
+        let file = ChatMessage::try_new(MessageTypes::FileTransfer, Some(content)).unwrap();
+
+        // Keep offering files, far faster than the rate allows
+        let started = Instant::now();
+        let mut delivered = 0;
+        let mut largest = 0;
+        while started.elapsed() < Duration::from_millis(600) {
+            harness.run(vec![file.clone()]).await;
+            while let Ok(relayed) = files.try_recv() {
+                let size = relayed.get_content().map_or(0, <[u8]>::len);
+                delivered += size;
+                largest = largest.max(size);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+
+        // One second of burst, the rate since, and the one file that went into debt
+        let allowed = RATE + (RATE as f64 * elapsed) as usize + largest;
+        assert!(
+            delivered <= allowed,
+            "{} bytes in {:.2}s",
+            delivered,
+            elapsed
+        );
+        // Files keep flowing after the burst rather than being refused for good
+        assert!(delivered > RATE, "{} bytes in {:.2}s", delivered, elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_block_unblock_and_list() {
+        let mut harness = HandlerHarness::new();
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::Block, "block|mallory"),
+                message(MessageTypes::Block, "block|alice"),
+                message(MessageTypes::Block, "list"),
+                message(MessageTypes::Block, "unblock|mallory"),
+                message(MessageTypes::Block, "list"),
+            ])
+            .await;
+
+        let replies: Vec<String> = output
+            .replies
+            .iter()
+            .filter(|msg| msg.msg_type == MessageTypes::Block)
+            .filter_map(ChatMessage::content_as_string)
+            .collect();
+        assert_eq!(
+            replies,
+            vec![
+                "Blocked mallory - they can no longer DM you",
+                "Blocked users: mallory",
+                "Unblocked mallory",
+                "You haven't blocked anyone"
+            ]
+        );
+        assert_eq!(output.errors(), vec!["You can't block yourself"]);
+    }
+
+    #[tokio::test]
+    async fn test_tokenless_rejoin_takeover() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("alice").await;
+        let ip = harness.addr.ip();
+        harness
+            .user_ips
+            .write()
+            .await
+            .insert("alice".to_string(), ip);
+        let tokenless_join = || message(MessageTypes::Join, "alice");
+
+        // Off by default: the rejoin is renamed
+        let output = harness.run(vec![tokenless_join()]).await;
+        assert!(output.error.is_none());
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+
+        // Enabled: the same IP takes the name over
+        harness.tokenless_takeover = Some(Duration::ZERO);
+        harness.chat_name = None;
+        let mut takeovers = harness.server_commands.subscribe();
+        let output = harness.run(vec![tokenless_join()]).await;
+        assert!(output.error.is_none());
+        assert_eq!(harness.chat_name.as_deref(), Some("alice"));
+        assert!(matches!(
+            takeovers.try_recv(),
+            Ok(ServerCommand::SessionTakeover(name)) if name == "alice"
+        ));
+
+        // ...but not from another IP
+        harness.chat_name = None;
+        harness
+            .user_ips
+            .write()
+            .await
+            .insert("alice".to_string(), "192.0.2.1".parse().unwrap());
+        harness.run(vec![tokenless_join()]).await;
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_tokenless_takeover_scheduled_without_waiting() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("alice").await;
+        let ip = harness.addr.ip();
+        harness
+            .user_ips
+            .write()
+            .await
+            .insert("alice".to_string(), ip);
+        let grace = Duration::from_millis(300);
+        harness.tokenless_takeover = Some(grace);
+        harness.join_limiter =
+            Arc::new(Mutex::new(JoinRateLimiter::new(2, Duration::from_secs(60))));
+        let mut takeovers = harness.server_commands.subscribe();
+        let tokenless_join = || message(MessageTypes::Join, "alice");
+
+        // The join is put off rather than waited on...
+        let started = Instant::now();
+        let output = harness.run(vec![tokenless_join()]).await;
+        assert!(matches!(
+            output.error,
+            Some(UserConnectionError::JoinDeferred(wait)) if wait <= grace
+        ));
+        assert!(harness.chat_name.is_none());
+
+        // ...so the connection's next message is answered during the grace
+        let output = harness.run(vec![message(MessageTypes::Ping, "1")]).await;
+        assert!(started.elapsed() < grace);
+        assert_eq!(output.replies[0].msg_type, MessageTypes::Pong);
+        assert!(takeovers.try_recv().is_err());
+
+        // Another tokenless rejoin meanwhile can't take the name over too
+        let first_addr = harness.addr;
+        harness.addr = "127.0.0.1:40001".parse().unwrap();
+        harness.run(vec![tokenless_join()]).await;
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+        harness.addr = first_addr;
+        harness.chat_name = None;
+
+        // Once the grace is over the retried join takes the session over,
+        // without counting against the join limit a second time
+        tokio::time::sleep(grace).await;
+        harness.retrying_join = true;
+        let output = harness.run(vec![tokenless_join()]).await;
+        assert!(output.error.is_none());
+        assert_eq!(harness.chat_name.as_deref(), Some("alice"));
+        assert!(matches!(
+            takeovers.try_recv(),
+            Ok(ServerCommand::SessionTakeover(name)) if name == "alice"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tokenless_takeover_needs_issued_secret() {
+        let mut harness = HandlerHarness::new();
+        harness.add_user("alice").await;
+        let ip = harness.addr.ip();
+        harness
+            .user_ips
+            .write()
+            .await
+            .insert("alice".to_string(), ip);
+        harness
+            .session_secrets
+            .write()
+            .await
+            .insert("alice".to_string(), "issued".to_string());
+        let grace = Duration::from_secs(5);
+        harness.tokenless_takeover = Some(grace);
+
+        // A tokenless join can't echo the secret, so it is renamed at once
+        // instead of being scheduled
+        let output = harness
+            .run(vec![message(MessageTypes::Join, "alice")])
+            .await;
+        assert!(output.error.is_none());
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+        let other: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        assert_eq!(
+            harness
+                .tokenless_takeovers
+                .lock()
+                .await
+                .check("alice", other, grace, Instant::now()),
+            Takeover::Scheduled(grace)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reclaim_requires_issued_secret() {
+        let mut harness = HandlerHarness::new();
+        harness.version_checked = true;
+        let secret_of = |output: &HarnessOutput| {
+            output
+                .replies
+                .iter()
+                .find(|msg| msg.msg_type == MessageTypes::SessionSecret)
+                .and_then(ChatMessage::content_as_string)
+                .unwrap()
+        };
+        let rejoin = |secret: &str| {
+            message(
+                MessageTypes::Join,
+                &format!("alice|test-session-alice|secret={}", secret),
+            )
+        };
+
+        let first = secret_of(&harness.run(vec![join("alice")]).await);
+
+        // Same token and IP, but without the secret the ghost isn't reclaimed
+        harness.chat_name = None;
+        harness.run(vec![join("alice")]).await;
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+
+        harness.chat_name = None;
+        let second = secret_of(&harness.run(vec![rejoin(&first)]).await);
+        assert_eq!(harness.chat_name.as_deref(), Some("alice"));
+        assert_ne!(first, second);
+
+        // Each join rotates the secret, so an earlier one can't be replayed
+        harness.chat_name = None;
+        harness.run(vec![rejoin(&first)]).await;
+        assert_ne!(harness.chat_name.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_without_subscribers_succeed() {
+        let mut harness = HandlerHarness::new().without_broadcast_subscribers();
+        harness.open_topic = true;
+
+        let output = harness
+            .run(vec![
+                join("alice"),
+                message(MessageTypes::ChatMessage, "anyone here?"),
+                message(MessageTypes::Topic, "Quiet day"),
+            ])
+            .await;
+
+        assert!(output.error.is_none());
+        assert!(output.errors().is_empty());
+        assert_eq!(harness.topic.read().await.as_deref(), Some("Quiet day"));
+    }
+}
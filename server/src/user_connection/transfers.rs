@@ -0,0 +1,86 @@
+use std::collections::{HashMap, HashSet};
+
+// In-flight file transfers per sender, keyed by username.
+//
+// A transfer starts with a FileTransferRequest and ends when the recipient
+// rejects it or acknowledges the data. Clients key pending transfers by
+// recipient, so each (sender, recipient) pair counts once; repeating a request
+// to the same recipient replaces the old one instead of adding another.
+#[derive(Debug, Default)]
+pub struct TransferTracker {
+    in_flight: HashMap<String, HashSet<String>>,
+}
+
+impl TransferTracker {
+    /// Record a transfer from `sender` to `recipient`, returning false if the
+    /// sender already has `max_per_user` others in flight (0 = unlimited)
+    pub fn try_start(&mut self, sender: &str, recipient: &str, max_per_user: usize) -> bool {
+        let recipients = self.in_flight.entry(sender.to_string()).or_default();
+        if recipients.contains(recipient) {
+            return true;
+        }
+        if max_per_user > 0 && recipients.len() >= max_per_user {
+            return false;
+        }
+        recipients.insert(recipient.to_string());
+        true
+    }
+
+    /// Forget a finished, rejected or undeliverable transfer
+    pub fn finish(&mut self, sender: &str, recipient: &str) {
+        if let Some(recipients) = self.in_flight.get_mut(sender) {
+            recipients.remove(recipient);
+            if recipients.is_empty() {
+                self.in_flight.remove(sender);
+            }
+        }
+    }
+
+    /// Forget every transfer to or from `username` (disconnect or rename)
+    pub fn release_user(&mut self, username: &str) {
+        self.in_flight.remove(username);
+        self.in_flight.retain(|_, recipients| {
+            recipients.remove(username);
+            !recipients.is_empty()
+        });
+    }
+
+    /// Transfers `sender` currently has in flight
+    pub fn count(&self, sender: &str) -> usize {
+        self.in_flight.get(sender).map_or(0, HashSet::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_per_sender() {
+        let mut tracker = TransferTracker::default();
+        assert!(tracker.try_start("alice", "bob", 2));
+        assert!(tracker.try_start("alice", "carol", 2));
+        assert!(!tracker.try_start("alice", "dave", 2));
+        // Another sender has its own budget, and repeats don't count twice
+        assert!(tracker.try_start("bob", "alice", 2));
+        assert!(tracker.try_start("alice", "bob", 2));
+        assert_eq!(tracker.count("alice"), 2);
+
+        tracker.finish("alice", "bob");
+        assert!(tracker.try_start("alice", "dave", 2));
+        assert!(tracker.try_start("alice", "erin", 0));
+    }
+
+    #[test]
+    fn test_release_user() {
+        let mut tracker = TransferTracker::default();
+        tracker.try_start("alice", "bob", 3);
+        tracker.try_start("carol", "bob", 3);
+        tracker.try_start("bob", "alice", 3);
+
+        tracker.release_user("bob");
+        assert_eq!(tracker.count("alice"), 0);
+        assert_eq!(tracker.count("carol"), 0);
+        assert_eq!(tracker.count("bob"), 0);
+    }
+}
@@ -0,0 +1,158 @@
+//! Known usernames and when they were last seen, for `/whois` and `/seen`
+//!
+//! Always kept in memory; with `CHAT_SERVER_USERDB` set it is also loaded at
+//! startup and flushed to that file periodically and on shutdown. The file
+//! holds one `username<TAB>unix_seconds` entry per line.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Most usernames remembered; the least recently seen are dropped beyond this
+pub const MAX_KNOWN_USERS: usize = 10_000;
+/// How often a changed registry is written to disk
+pub const USERDB_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+pub struct UserRegistry {
+    path: Option<PathBuf>,
+    last_seen: HashMap<String, SystemTime>,
+    /// Changed since the last flush
+    dirty: bool,
+}
+
+impl UserRegistry {
+    /// Registry persisted to `path`, starting from its contents if it exists
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut registry = UserRegistry {
+            path: Some(path),
+            ..UserRegistry::default()
+        };
+        for line in contents.lines() {
+            let Some((username, secs)) = line.split_once('\t') else {
+                continue;
+            };
+            if let Ok(secs) = secs.trim().parse::<u64>() {
+                registry.insert(username, UNIX_EPOCH + Duration::from_secs(secs));
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Remember that `username` was last seen at `at`
+    pub fn record(&mut self, username: &str, at: SystemTime) {
+        self.insert(username, at);
+        self.dirty = true;
+    }
+
+    fn insert(&mut self, username: &str, at: SystemTime) {
+        self.last_seen.insert(username.to_string(), at);
+        while self.last_seen.len() > MAX_KNOWN_USERS {
+            let Some(oldest) = self
+                .last_seen
+                .iter()
+                .min_by_key(|(_, seen)| **seen)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            self.last_seen.remove(&oldest);
+        }
+    }
+
+    pub fn last_seen(&self, username: &str) -> Option<SystemTime> {
+        self.last_seen.get(username).copied()
+    }
+
+    /// Write the registry to its file if it changed since the last flush
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut contents = String::new();
+        for (username, seen) in &self.last_seen {
+            let secs = seen
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            contents.push_str(&format!("{}\t{}\n", username, secs));
+        }
+        // Write then rename, so a crash mid-write never leaves a truncated file
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Rough age for display, e.g. "just now", "40s ago", "5m ago", "3h ago", "2d ago"
+pub fn format_ago(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 5 {
+        "just now".to_string()
+    } else if secs < 60 {
+        format!("{}s ago", secs)
+    } else if secs < 3_600 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 86_400 {
+        format!("{}h ago", secs / 3_600)
+    } else {
+        format!("{}d ago", secs / 86_400)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_pruned_over_cap() {
+        let mut registry = UserRegistry::default();
+        for i in 0..MAX_KNOWN_USERS as u64 + 1 {
+            registry.record(&format!("user{}", i), UNIX_EPOCH + Duration::from_secs(i));
+        }
+        assert_eq!(registry.last_seen.len(), MAX_KNOWN_USERS);
+        assert!(registry.last_seen("user0").is_none());
+        assert!(registry.last_seen("user1").is_some());
+    }
+
+    #[test]
+    fn test_flush_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("userdb_test_{}", std::process::id()));
+        let seen = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut registry = UserRegistry::load(path.clone()).unwrap();
+        registry.record("alice", seen);
+        registry.flush().unwrap();
+
+        let reloaded = UserRegistry::load(path.clone()).unwrap();
+        assert_eq!(reloaded.last_seen("alice"), Some(seen));
+        assert!(reloaded.last_seen("bob").is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_format_ago() {
+        assert_eq!(format_ago(Duration::from_secs(2)), "just now");
+        assert_eq!(format_ago(Duration::from_secs(40)), "40s ago");
+        assert_eq!(format_ago(Duration::from_secs(5 * 60)), "5m ago");
+        assert_eq!(format_ago(Duration::from_secs(3 * 3_600 + 59)), "3h ago");
+        assert_eq!(format_ago(Duration::from_secs(2 * 86_400)), "2d ago");
+    }
+}
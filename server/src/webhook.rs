@@ -0,0 +1,312 @@
+//! Optional relay of join/leave/message events to an HTTP webhook
+//!
+//! Events are queued on a bounded channel and POSTed as JSON by a background
+//! task, so a slow or unreachable webhook never blocks the chat path. When the
+//! queue is full, new events are dropped.
+
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use shared::logger;
+use shared::message::unix_millis_now;
+use shared::version::VERSION;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+/// Events waiting to be posted before new ones are dropped
+pub const WEBHOOK_QUEUE_SIZE: usize = 256;
+/// Give up on a single POST after this long
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookEvent {
+    Join {
+        username: String,
+    },
+    Leave {
+        username: String,
+    },
+    Message {
+        username: String,
+        content: String,
+    },
+    /// DM content is never relayed, only who messaged whom
+    DirectMessage {
+        username: String,
+        recipient: String,
+    },
+}
+
+impl WebhookEvent {
+    /// JSON body for this event; message content is only included when
+    /// `include_content` is set
+    fn to_json(&self, timestamp_ms: u64, include_content: bool) -> String {
+        let (event, username, extra) = match self {
+            WebhookEvent::Join { username } => ("join", username, String::new()),
+            WebhookEvent::Leave { username } => ("leave", username, String::new()),
+            WebhookEvent::Message { username, content } => {
+                let extra = if include_content {
+                    format!(",\"content\":{}", json_string(content))
+                } else {
+                    String::new()
+                };
+                ("message", username, extra)
+            }
+            WebhookEvent::DirectMessage {
+                username,
+                recipient,
+            } => (
+                "direct_message",
+                username,
+                format!(",\"recipient\":{}", json_string(recipient)),
+            ),
+        };
+        format!(
+            "{{\"event\":\"{}\",\"username\":{},\"timestamp\":{}{}}}",
+            event,
+            json_string(username),
+            timestamp_ms,
+            extra
+        )
+    }
+}
+
+/// Quote and escape `value` as a JSON string
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A parsed `http://` or `https://` webhook URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl WebhookUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err("webhook URL must start with http:// or https://".to_string());
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("invalid port '{}'", port))?,
+            ),
+            None => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err("webhook URL has no host".to_string());
+        }
+
+        Ok(WebhookUrl {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Host header value (the port is only included when it isn't the default)
+    fn host_header(&self) -> String {
+        let default_port = if self.tls { 443 } else { 80 };
+        if self.port == default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Handle for queueing webhook events; cheap to clone into each connection
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    events: mpsc::Sender<(WebhookEvent, u64)>,
+}
+
+impl Webhook {
+    /// Start the background task that posts queued events to `url`
+    pub fn spawn(url: WebhookUrl, include_content: bool) -> Self {
+        let (events, mut queue) = mpsc::channel::<(WebhookEvent, u64)>(WEBHOOK_QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some((event, timestamp_ms)) = queue.recv().await {
+                let body = event.to_json(timestamp_ms, include_content);
+                match timeout(WEBHOOK_TIMEOUT, post(&url, &body)).await {
+                    Ok(Ok(status)) if (200..300).contains(&status) => {}
+                    Ok(Ok(status)) => {
+                        logger::log_warning(&format!("Webhook returned HTTP {}", status))
+                    }
+                    Ok(Err(e)) => logger::log_warning(&format!("Webhook request failed: {}", e)),
+                    Err(_) => logger::log_warning("Webhook request timed out"),
+                }
+            }
+        });
+        Webhook { events }
+    }
+
+    /// Queue an event without waiting; dropped if the webhook is falling behind
+    pub fn send(&self, event: WebhookEvent) {
+        if let Err(TrySendError::Full(_)) = self.events.try_send((event, unix_millis_now())) {
+            logger::log_warning("Webhook queue full, dropping event");
+        }
+    }
+}
+
+/// POST `body` as JSON to `url`, returning the HTTP status code
+async fn post(url: &WebhookUrl, body: &str) -> io::Result<u16> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    if !url.tls {
+        return send_request(stream, url, body).await;
+    }
+
+    let mut root_cert_store = rustls::RootCertStore::empty();
+    root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder()
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(url.host.clone())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid webhook host"))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await?;
+    send_request(stream, url, body).await
+}
+
+async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    url: &WebhookUrl,
+    body: &str,
+) -> io::Result<u16> {
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rust_chat/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host_header(),
+        VERSION,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    // Only the status line matters, e.g. "HTTP/1.1 204 No Content"
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).await?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid HTTP response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let join = WebhookEvent::Join {
+            username: "alice".to_string(),
+        };
+        assert_eq!(
+            join.to_json(1000, false),
+            r#"{"event":"join","username":"alice","timestamp":1000}"#
+        );
+
+        let message = WebhookEvent::Message {
+            username: "bob".to_string(),
+            content: "say \"hi\"\n".to_string(),
+        };
+        assert_eq!(
+            message.to_json(5, true),
+            r#"{"event":"message","username":"bob","timestamp":5,"content":"say \"hi\"\n"}"#
+        );
+        assert!(!message.to_json(5, false).contains("content"));
+
+        let dm = WebhookEvent::DirectMessage {
+            username: "bob".to_string(),
+            recipient: "alice".to_string(),
+        };
+        assert_eq!(
+            dm.to_json(5, true),
+            r#"{"event":"direct_message","username":"bob","timestamp":5,"recipient":"alice"}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let url = WebhookUrl::parse("https://hooks.example.com/services/abc").unwrap();
+        assert!(url.tls);
+        assert_eq!(url.host, "hooks.example.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/services/abc");
+        assert_eq!(url.host_header(), "hooks.example.com");
+
+        let url = WebhookUrl::parse("http://127.0.0.1:9000").unwrap();
+        assert!(!url.tls);
+        assert_eq!(url.port, 9000);
+        assert_eq!(url.path, "/");
+        assert_eq!(url.host_header(), "127.0.0.1:9000");
+
+        assert!(WebhookUrl::parse("ftp://example.com").is_err());
+        assert!(WebhookUrl::parse("http://example.com:port/").is_err());
+        assert!(WebhookUrl::parse("http:///path").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_post_reads_status() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 1024];
+            let read = tokio::io::AsyncReadExt::read(&mut socket, &mut request)
+                .await
+                .unwrap();
+            socket
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+
+        let url = WebhookUrl::parse(&format!("http://127.0.0.1:{}/hook", port)).unwrap();
+        let status = post(&url, "{}").await.unwrap();
+        let request = server.await.unwrap();
+
+        assert_eq!(status, 204);
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{}"));
+    }
+}
@@ -67,6 +67,35 @@ impl Command {
     }
 }
 
+/// Levenshtein distance between `a` and `b`, counted in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the prefix of `a` seen so far to each prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The name in `names` closest to the mistyped command `input`, if any is
+/// close enough to be a plausible typo (at most 2 edits, fewer for short input)
+pub fn suggest<'a>(input: &str, names: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (input.chars().count().saturating_sub(1) / 2).min(2);
+    names
+        .iter()
+        .map(|name| (edit_distance(input, name), *name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
 /// Client commands
 pub mod client {
     use super::Command;
@@ -75,8 +104,9 @@ pub mod client {
 
     pub const QUIT: Command = Command::new("/quit").with_description("Exit the chat");
 
-    pub const LIST: Command =
-        Command::new("/list").with_description("List all users (with statuses)");
+    pub const LIST: Command = Command::new("/list")
+        .with_usage("[page]")
+        .with_description("List users (with statuses), optionally one page at a time");
 
     pub const DM: Command = Command::new("/dm")
         .with_usage("<username> <message>")
@@ -86,9 +116,21 @@ pub mod client {
         .with_usage("<message>")
         .with_description("Reply to last direct message");
 
+    pub const PAGE: Command = Command::new("/page")
+        .with_usage("<username> <message>")
+        .with_description("Page a user: rings their bell and notifies them (once a minute each)");
+
+    pub const POLL: Command = Command::new("/poll")
+        .with_usage("<question> | <option> | <option>...")
+        .with_description("Start a channel poll (up to 10 options)");
+
+    pub const VOTE: Command = Command::new("/vote")
+        .with_usage("<poll_id> <option>")
+        .with_description("Vote in a poll by option number (voting again changes your vote)");
+
     pub const SEND: Command = Command::new("/send")
-        .with_usage("<username> <filepath>")
-        .with_description("Send a file (max 100MB, requires acceptance)");
+        .with_usage("<username> <filepath> [-- caption]")
+        .with_description("Send a file with an optional note (max 100MB, requires acceptance)");
 
     pub const ACCEPT: Command = Command::new("/accept")
         .with_usage("<sender>")
@@ -98,9 +140,17 @@ pub mod client {
         .with_usage("<sender>")
         .with_description("Reject a pending file transfer");
 
+    pub const SHARE: Command = Command::new("/share")
+        .with_usage("<filepath>")
+        .with_description("Share a file with everyone in the room (max 10MB, fetched with /get)");
+
+    pub const GET: Command = Command::new("/get")
+        .with_usage("<file_id>")
+        .with_description("Download a file someone shared with the room");
+
     pub const RENAME: Command = Command::new("/rename")
-        .with_usage("<new_name>")
-        .with_description("Change your username");
+        .with_usage("[new_name]")
+        .with_description("Change your username (no name takes the suggested one)");
 
     pub const STATUS: Command = Command::new("/status")
         .with_usage("<message>")
@@ -108,9 +158,94 @@ pub mod client {
 
     pub const STATUS_CLEAR: Command = Command::new("/status").with_description("Clear your status");
 
+    pub const AFK: Command = Command::new("/afk")
+        .with_alias("/away")
+        .with_usage("[reason]")
+        .with_description("Mark yourself away (cleared when you next send a message)");
+
+    pub const MULTILINE: Command = Command::new("/multiline")
+        .with_description("Compose a multi-line message (end with '.' on its own line)");
+
+    pub const KEYS: Command =
+        Command::new("/keys").with_description("Show end-to-end encryption key fingerprints");
+
+    pub const TOPIC: Command = Command::new("/topic")
+        .with_usage("[text]")
+        .with_description("Show the channel topic, or set it if the server allows");
+
+    pub const LINK: Command =
+        Command::new("/link").with_description("Print a shareable address for this server");
+
+    pub const WHOIS: Command = Command::new("/whois")
+        .with_usage("<username>")
+        .with_description("Show whether a user is online, or when they were last seen");
+
+    pub const SEEN: Command = Command::new("/seen")
+        .with_usage("<username>")
+        .with_description("Show when a user was last active");
+
+    pub const SEARCH: Command = Command::new("/search")
+        .with_usage("<text>")
+        .with_description("Search the server's chat history (if it keeps one)");
+
+    pub const BLOCK: Command = Command::new("/block")
+        .with_usage("<username>")
+        .with_description("Stop a user from sending you direct messages");
+
+    pub const UNBLOCK: Command = Command::new("/unblock")
+        .with_usage("<username>")
+        .with_description("Allow a blocked user to DM you again");
+
+    pub const BLOCKED: Command =
+        Command::new("/blocked").with_description("List the users you have blocked");
+
+    pub const OPER: Command = Command::new("/oper")
+        .with_usage("<password>")
+        .with_description("Become an operator using the server's operator password");
+
+    pub const OP: Command = Command::new("/op")
+        .with_usage("<username>")
+        .with_description("Make a user an operator (operators only)");
+
+    pub const DEOP: Command = Command::new("/deop")
+        .with_usage("<username>")
+        .with_description("Take away a user's operator rights (operators only)");
+
+    pub const KICK: Command = Command::new("/kick")
+        .with_usage("<username>")
+        .with_description("Disconnect a user (operators only)");
+
+    pub const SERVERINFO: Command = Command::new("/serverinfo")
+        .with_description("Show the server's version, uptime and user count");
+
+    pub const QUALITY: Command = Command::new("/quality")
+        .with_description("Ping the server and show recent latency (min/avg/max)");
+
+    pub const CONNECT: Command = Command::new("/connect")
+        .with_usage("<profile>")
+        .with_description("Switch to the server in a saved profile");
+
+    pub const PROFILES: Command = Command::new("/profiles")
+        .with_usage("[save <name>]")
+        .with_description("List saved profiles, or save this server as one");
+
+    pub const AGAIN: Command =
+        Command::new("/again").with_description("Resend your last message or DM");
+
+    pub const PAUSE: Command = Command::new("/pause")
+        .with_description("Hold channel messages while you read back (DMs still show)");
+
+    pub const RESUME: Command =
+        Command::new("/resume").with_description("Show the messages held by /pause");
+
+    pub const DND: Command = Command::new("/dnd")
+        .with_description("Toggle do not disturb: only DMs, pages and errors are shown");
+
     /// All client commands (for completion - excludes STATUS_CLEAR as it's same command)
     pub const ALL: &[Command] = &[
-        HELP, LIST, DM, REPLY, SEND, ACCEPT, REJECT, RENAME, STATUS, QUIT,
+        HELP, LIST, DM, REPLY, PAGE, POLL, VOTE, SEND, ACCEPT, REJECT, SHARE, GET, RENAME, STATUS,
+        AFK, MULTILINE, KEYS, TOPIC, LINK, WHOIS, SEEN, SEARCH, BLOCK, UNBLOCK, BLOCKED, OPER, OP,
+        DEOP, KICK, SERVERINFO, QUALITY, CONNECT, PROFILES, AGAIN, PAUSE, RESUME, DND, QUIT,
     ];
 
     /// All help entries (includes STATUS_CLEAR for documentation)
@@ -119,12 +254,40 @@ pub mod client {
         LIST,
         DM,
         REPLY,
+        PAGE,
+        POLL,
+        VOTE,
         SEND,
         ACCEPT,
         REJECT,
+        SHARE,
+        GET,
         RENAME,
         STATUS,
         STATUS_CLEAR,
+        AFK,
+        MULTILINE,
+        KEYS,
+        TOPIC,
+        LINK,
+        WHOIS,
+        SEEN,
+        SEARCH,
+        BLOCK,
+        UNBLOCK,
+        BLOCKED,
+        OPER,
+        OP,
+        DEOP,
+        KICK,
+        SERVERINFO,
+        QUALITY,
+        CONNECT,
+        PROFILES,
+        AGAIN,
+        PAUSE,
+        RESUME,
+        DND,
         QUIT,
     ];
 
@@ -133,6 +296,11 @@ pub mod client {
         ALL.iter().flat_map(|cmd| cmd.all_names()).collect()
     }
 
+    /// The command named `cmd` (by name or alias), if there is one
+    pub fn find(cmd: &str) -> Option<&'static Command> {
+        ALL.iter().find(|command| command.matches(cmd))
+    }
+
     /// Generate help text for all commands
     pub fn help_text() -> Vec<String> {
         let mut lines = vec!["Available commands:".to_string()];
@@ -157,10 +325,24 @@ pub mod server {
 
     pub const LIST: Command = Command::new("/list").with_description("List all connected users");
 
+    pub const LISTFULL: Command = Command::new("/listfull")
+        .with_description("List connected users with their IP addresses and statuses");
+
+    pub const EXPORT: Command = Command::new("/export")
+        .with_usage("<file>")
+        .with_description("Write connected users, IPs and statuses to a CSV (or .json) file");
+
     pub const KICK: Command = Command::new("/kick")
         .with_usage("<user>")
         .with_description("Kick a user from the server");
 
+    pub const CONNS: Command = Command::new("/conns")
+        .with_description("List every open connection with its address, user and state");
+
+    pub const KILLCONN: Command = Command::new("/killconn")
+        .with_usage("<ip:port>")
+        .with_description("Close one connection by address, joined or not");
+
     pub const RENAME: Command = Command::new("/rename")
         .with_usage("<user> <newname>")
         .with_description("Rename a user");
@@ -175,8 +357,26 @@ pub mod server {
 
     pub const BANLIST: Command = Command::new("/banlist").with_description("List all banned IPs");
 
+    pub const SLOWMODE: Command = Command::new("/slowmode")
+        .with_usage("<seconds>")
+        .with_description("Set minimum seconds between each user's messages (0 disables)");
+
+    pub const TOPIC: Command = Command::new("/topic")
+        .with_usage("[text]")
+        .with_description("Set the channel topic (no text shows the current topic)");
+
+    pub const RELOAD: Command =
+        Command::new("/reload").with_description("Reload the TLS certificate and ban list");
+
+    pub const DRAIN: Command = Command::new("/drain")
+        .with_usage("[seconds]")
+        .with_description("Stop accepting connections and shut down once everyone leaves");
+
     /// All server commands
-    pub const ALL: &[Command] = &[LIST, KICK, RENAME, BAN, UNBAN, BANLIST, HELP, QUIT];
+    pub const ALL: &[Command] = &[
+        LIST, LISTFULL, EXPORT, KICK, CONNS, KILLCONN, RENAME, BAN, UNBAN, BANLIST, SLOWMODE,
+        TOPIC, RELOAD, DRAIN, HELP, QUIT,
+    ];
 
     /// Get all command names for completion (includes aliases)
     pub fn completion_names() -> Vec<&'static str> {
@@ -197,6 +397,19 @@ pub mod server {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_suggest_close_commands_only() {
+        let names = client::completion_names();
+        assert_eq!(edit_distance("/hepl", "/help"), 2);
+        assert_eq!(edit_distance("", "/dm"), 3);
+        assert_eq!(suggest("/hepl", &names), Some("/help"));
+        assert_eq!(suggest("/stauts", &names), Some("/status"));
+        assert_eq!(suggest("/lsit", &names), Some("/list"));
+        assert_eq!(suggest("/xyz", &names), None);
+        // Short input needs a closer match so everything isn't a typo of /dm or /r
+        assert_eq!(suggest("/ab", &names), None);
+    }
+
     #[test]
     fn test_client_completion_names() {
         let names = client::completion_names();
@@ -205,7 +418,22 @@ mod tests {
         assert!(names.contains(&"/status"));
         assert!(names.contains(&"/accept"));
         assert!(names.contains(&"/reject"));
-        assert_eq!(names.len(), 10); // 10 commands, no aliases
+        assert!(names.contains(&"/keys"));
+        assert!(names.contains(&"/multiline"));
+        assert!(names.contains(&"/afk"));
+        assert!(names.contains(&"/away"));
+        assert!(names.contains(&"/topic"));
+        assert!(names.contains(&"/link"));
+        assert!(names.contains(&"/serverinfo"));
+        assert!(names.contains(&"/whois"));
+        assert!(names.contains(&"/seen"));
+        assert!(names.contains(&"/block"));
+        assert!(names.contains(&"/blocked"));
+        assert!(names.contains(&"/connect"));
+        assert!(names.contains(&"/profiles"));
+        assert!(names.contains(&"/page"));
+        assert!(names.contains(&"/search"));
+        assert_eq!(names.len(), 39); // 38 commands + 1 alias
     }
 
     #[test]
@@ -216,7 +444,15 @@ mod tests {
         assert!(names.contains(&"/quit"));
         assert!(names.contains(&"/q"));
         assert!(names.contains(&"/ban"));
-        assert_eq!(names.len(), 10); // 8 commands + 2 aliases
+        assert!(names.contains(&"/slowmode"));
+        assert!(names.contains(&"/topic"));
+        assert!(names.contains(&"/reload"));
+        assert!(names.contains(&"/listfull"));
+        assert!(names.contains(&"/drain"));
+        assert!(names.contains(&"/export"));
+        assert!(names.contains(&"/conns"));
+        assert!(names.contains(&"/killconn"));
+        assert_eq!(names.len(), 18); // 16 commands + 2 aliases
     }
 
     #[test]
@@ -0,0 +1,164 @@
+//! Optional LZ4 compression of whole frames for slow links
+//!
+//! A compressed frame payload is `COMPRESSED_FRAME_FLAG`, the uncompressed
+//! length (4 bytes, big-endian) and an LZ4 block. Raw payloads start with the
+//! inner message length, whose first byte is at most 0x06 for anything under
+//! `MAX_FILE_SIZE`, so readers can always tell the two apart. Negotiation
+//! (`MessageTypes::Compression`) decides whether a side *sends* compressed
+//! frames; peers that never asked for it are never sent any, and a reader
+//! that never negotiated it treats one as a protocol error.
+
+use std::io;
+
+/// First byte of a compressed frame payload
+pub const COMPRESSED_FRAME_FLAG: u8 = 0xFF;
+/// Payloads smaller than this are sent as-is (LZ4 rarely helps below it)
+pub const COMPRESSION_THRESHOLD: usize = 256;
+/// Algorithm name exchanged during negotiation
+pub const COMPRESSION_ALGORITHM: &str = "lz4";
+/// Largest payload sent compressed. Bigger frames (file data, mostly) go
+/// raw, so readers never inflate a frame past this.
+pub const MAX_COMPRESSED_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Compress a frame payload if it is big enough and compression makes it
+/// smaller; otherwise return it unchanged
+pub fn compress_frame(payload: Vec<u8>) -> Vec<u8> {
+    if !(COMPRESSION_THRESHOLD..=MAX_COMPRESSED_FRAME_SIZE).contains(&payload.len()) {
+        return payload;
+    }
+    let Ok(len) = u32::try_from(payload.len()) else {
+        return payload;
+    };
+    let block = lz4_flex::block::compress(&payload);
+    if block.len() + 5 >= payload.len() {
+        return payload;
+    }
+
+    let mut frame = Vec::with_capacity(block.len() + 5);
+    frame.push(COMPRESSED_FRAME_FLAG);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&block);
+    frame
+}
+
+/// Undo `compress_frame`. Payloads without the flag are returned unchanged;
+/// compressed ones are only accepted once compression was `negotiated`.
+pub fn decompress_frame(payload: Vec<u8>, negotiated: bool) -> io::Result<Vec<u8>> {
+    if payload.first() != Some(&COMPRESSED_FRAME_FLAG) {
+        return Ok(payload);
+    }
+    if !negotiated {
+        return Err(invalid("Compressed frame without negotiated compression"));
+    }
+    let Some(&[a, b, c, d]) = payload.get(1..5) else {
+        return Err(invalid("Compressed frame is truncated"));
+    };
+    let len = u32::from_be_bytes([a, b, c, d]) as usize;
+    // Checked before allocating, so a tiny frame can't claim a huge size
+    if len > MAX_COMPRESSED_FRAME_SIZE {
+        return Err(invalid("Compressed frame exceeds maximum size"));
+    }
+
+    let decompressed = lz4_flex::block::decompress(&payload[5..], len)
+        .map_err(|e| invalid(&format!("Invalid compressed frame: {}", e)))?;
+    if decompressed.len() != len {
+        return Err(invalid("Compressed frame length mismatch"));
+    }
+    Ok(decompressed)
+}
+
+fn invalid(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{ChatMessage, MessageTypes};
+    use proptest::prelude::*;
+
+    fn payload(text: &str) -> Vec<u8> {
+        ChatMessage::try_new(MessageTypes::ChatMessage, Some(text.as_bytes().to_vec()))
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let raw = payload(&"the quick brown fox ".repeat(50));
+        let compressed = compress_frame(raw.clone());
+        assert_eq!(compressed[0], COMPRESSED_FRAME_FLAG);
+        assert!(compressed.len() < raw.len());
+        assert_eq!(decompress_frame(compressed, true).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_small_and_incompressible_payloads_sent_raw() {
+        let small = payload("hi");
+        assert_eq!(compress_frame(small.clone()), small);
+
+        // Bytes with no repetition don't shrink
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let raw = ChatMessage::try_new(MessageTypes::FileTransfer, Some(noise))
+            .unwrap()
+            .into();
+        let sent = compress_frame(raw);
+        assert_ne!(sent[0], COMPRESSED_FRAME_FLAG);
+        assert_eq!(decompress_frame(sent.clone(), false).unwrap(), sent);
+    }
+
+    #[test]
+    fn test_invalid_compressed_frames_rejected() {
+        assert!(decompress_frame(vec![COMPRESSED_FRAME_FLAG, 0, 0], true).is_err());
+
+        let mut oversized = vec![COMPRESSED_FRAME_FLAG];
+        oversized.extend_from_slice(&(MAX_COMPRESSED_FRAME_SIZE as u32 + 1).to_be_bytes());
+        oversized.extend_from_slice(&[0x10, b'a']);
+        assert!(decompress_frame(oversized, true).is_err());
+
+        let mut wrong_len = compress_frame(payload(&"abc".repeat(200)));
+        wrong_len[4] = wrong_len[4].wrapping_add(1);
+        assert!(decompress_frame(wrong_len, true).is_err());
+    }
+
+    #[test]
+    fn test_compressed_frame_needs_negotiation() {
+        let compressed = compress_frame(payload(&"abc".repeat(200)));
+        assert!(decompress_frame(compressed, false).is_err());
+    }
+
+    #[test]
+    fn test_large_payloads_sent_raw() {
+        let raw = payload(&"a".repeat(MAX_COMPRESSED_FRAME_SIZE));
+        assert_eq!(compress_frame(raw.clone()), raw);
+    }
+
+    proptest! {
+        #[test]
+        fn fuzz_compressed_frames(
+            declared_len in prop_oneof![0u32..512, any::<u32>()],
+            block in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut frame = vec![COMPRESSED_FRAME_FLAG];
+            frame.extend_from_slice(&declared_len.to_be_bytes());
+            frame.extend_from_slice(&block);
+            if let Ok(decompressed) = decompress_frame(frame, true) {
+                prop_assert_eq!(decompressed.len(), declared_len as usize);
+            }
+        }
+
+        #[test]
+        fn fuzz_round_trip(text in "[ab ]{0,2000}") {
+            let raw = payload(&text);
+            prop_assert_eq!(decompress_frame(compress_frame(raw.clone()), true).unwrap(), raw);
+        }
+    }
+}
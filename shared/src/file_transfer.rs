@@ -0,0 +1,612 @@
+//! Binary headers of the file transfer messages
+//!
+//! Every file transfer message starts with the user it is addressed to. When
+//! the server relays it, the server inserts the name of the user it came from
+//! right after that, so each header has a client form (`parse`, read by the
+//! server) and a relayed form (`parse_relayed`, read by clients). `encode`
+//! writes whichever form the header holds. Names are length-prefixed:
+//! `len(1)|bytes`.
+//!
+//! Files shared with the whole room (`FileOffer`) have no addressee, so the
+//! upload and the server's announcement have headers of their own. The reply
+//! to a `FileGet` is the relayed `FileTransfer` form.
+
+/// Longest name or text field a one-byte length prefix can describe
+pub const MAX_FIELD_LENGTH: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileTransferError {
+    /// The content ended before a field it announced
+    Truncated,
+    /// A name or text field was not valid UTF-8
+    InvalidUtf8,
+    /// A field of this many bytes doesn't fit its one-byte length prefix
+    FieldTooLong(usize),
+}
+
+impl std::fmt::Display for FileTransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileTransferError::Truncated => write!(f, "truncated file transfer header"),
+            FileTransferError::InvalidUtf8 => write!(f, "invalid UTF-8 in file transfer header"),
+            FileTransferError::FieldTooLong(len) => write!(
+                f,
+                "file transfer field too long ({} bytes, max {})",
+                len, MAX_FIELD_LENGTH
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FileTransferError {}
+
+/// Append `value` with a one-byte length prefix, refusing values that
+/// wouldn't fit rather than truncating the prefix
+pub fn encode_lp_string(out: &mut Vec<u8>, value: &str) -> Result<(), FileTransferError> {
+    let len =
+        u8::try_from(value.len()).map_err(|_| FileTransferError::FieldTooLong(value.len()))?;
+    out.push(len);
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Split a length-prefixed string off the front of `content`, returning it
+/// and whatever follows
+pub fn decode_lp_string(content: &[u8]) -> Result<(&str, &[u8]), FileTransferError> {
+    let (&len, rest) = content.split_first().ok_or(FileTransferError::Truncated)?;
+    let (value, rest) = rest
+        .split_at_checked(len as usize)
+        .ok_or(FileTransferError::Truncated)?;
+    let value = std::str::from_utf8(value).map_err(|_| FileTransferError::InvalidUtf8)?;
+    Ok((value, rest))
+}
+
+/// Write the addressee, plus the name the server inserts when relaying
+fn encode_route(out: &mut Vec<u8>, to: &str, from: Option<&str>) -> Result<(), FileTransferError> {
+    encode_lp_string(out, to)?;
+    if let Some(from) = from {
+        encode_lp_string(out, from)?;
+    }
+    Ok(())
+}
+
+/// Reads fields off the front of a message, failing instead of panicking
+/// when the content is shorter than a field claims
+struct Fields<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(content: &'a [u8]) -> Self {
+        Fields { remaining: content }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FileTransferError> {
+        let (field, rest) = self
+            .remaining
+            .split_at_checked(len)
+            .ok_or(FileTransferError::Truncated)?;
+        self.remaining = rest;
+        Ok(field)
+    }
+
+    fn flag(&mut self) -> Result<bool, FileTransferError> {
+        Ok(self.bytes(1)?[0] == 1)
+    }
+
+    fn string(&mut self) -> Result<&'a str, FileTransferError> {
+        let (value, rest) = decode_lp_string(self.remaining)?;
+        self.remaining = rest;
+        Ok(value)
+    }
+
+    fn u64(&mut self) -> Result<u64, FileTransferError> {
+        let bytes = self.bytes(8)?;
+        let bytes = bytes.try_into().map_err(|_| FileTransferError::Truncated)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// The addressee, plus the name the server inserted when `relayed`
+    fn route(&mut self, relayed: bool) -> Result<(&'a str, Option<&'a str>), FileTransferError> {
+        let to = self.string()?;
+        let from = if relayed { Some(self.string()?) } else { None };
+        Ok((to, from))
+    }
+
+    fn rest(self) -> &'a [u8] {
+        self.remaining
+    }
+}
+
+/// `FileTransfer`: recipient|[sender|]filename|filedata
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileTransferHeader<'a> {
+    pub recipient: &'a str,
+    /// Set by the server when relaying
+    pub sender: Option<&'a str>,
+    pub filename: &'a str,
+    pub data: &'a [u8],
+}
+
+impl<'a> FileTransferHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, false)
+    }
+
+    pub fn parse_relayed(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, true)
+    }
+
+    fn parse_fields(content: &'a [u8], relayed: bool) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        let (recipient, sender) = fields.route(relayed)?;
+        let filename = fields.string()?;
+        Ok(FileTransferHeader {
+            recipient,
+            sender,
+            filename,
+            data: fields.rest(),
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::with_capacity(self.data.len() + 64);
+        encode_route(&mut out, self.recipient, self.sender)?;
+        encode_lp_string(&mut out, self.filename)?;
+        out.extend_from_slice(self.data);
+        Ok(out)
+    }
+}
+
+/// `FileTransferRequest`: recipient|[sender|]filename|filesize(8)[|caption]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRequestHeader<'a> {
+    pub recipient: &'a str,
+    /// Set by the server when relaying
+    pub sender: Option<&'a str>,
+    pub filename: &'a str,
+    pub file_size: u64,
+    /// Absent in requests from clients that predate captions
+    pub caption: Option<&'a str>,
+}
+
+impl<'a> FileRequestHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, false)
+    }
+
+    pub fn parse_relayed(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, true)
+    }
+
+    fn parse_fields(content: &'a [u8], relayed: bool) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        let (recipient, sender) = fields.route(relayed)?;
+        let filename = fields.string()?;
+        let file_size = fields.u64()?;
+        let caption = if fields.remaining.is_empty() {
+            None
+        } else {
+            Some(fields.string()?)
+        };
+        Ok(FileRequestHeader {
+            recipient,
+            sender,
+            filename,
+            file_size,
+            caption,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::new();
+        encode_route(&mut out, self.recipient, self.sender)?;
+        encode_lp_string(&mut out, self.filename)?;
+        out.extend_from_slice(&self.file_size.to_be_bytes());
+        if let Some(caption) = self.caption {
+            encode_lp_string(&mut out, caption)?;
+        }
+        Ok(out)
+    }
+}
+
+/// `FileTransferResponse`: file_sender|[responder|]accepted(1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileResponseHeader<'a> {
+    /// Who offered the file, and receives this response
+    pub file_sender: &'a str,
+    /// Who accepted or rejected it; set by the server when relaying
+    pub responder: Option<&'a str>,
+    pub accepted: bool,
+}
+
+impl<'a> FileResponseHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, false)
+    }
+
+    pub fn parse_relayed(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, true)
+    }
+
+    fn parse_fields(content: &'a [u8], relayed: bool) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        let (file_sender, responder) = fields.route(relayed)?;
+        Ok(FileResponseHeader {
+            file_sender,
+            responder,
+            accepted: fields.flag()?,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::new();
+        encode_route(&mut out, self.file_sender, self.responder)?;
+        out.push(u8::from(self.accepted));
+        Ok(out)
+    }
+}
+
+/// `FileTransferAck`: file_sender|[receiver|]success(1)|filename|error
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileAckHeader<'a> {
+    /// Who sent the file, and receives this ack
+    pub file_sender: &'a str,
+    /// Who received the file; set by the server when relaying
+    pub receiver: Option<&'a str>,
+    pub saved: bool,
+    pub filename: &'a str,
+    /// Why the file couldn't be saved (empty on success)
+    pub error: &'a str,
+}
+
+impl<'a> FileAckHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, false)
+    }
+
+    pub fn parse_relayed(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        Self::parse_fields(content, true)
+    }
+
+    fn parse_fields(content: &'a [u8], relayed: bool) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        let (file_sender, receiver) = fields.route(relayed)?;
+        let saved = fields.flag()?;
+        let filename = fields.string()?;
+        let error =
+            std::str::from_utf8(fields.rest()).map_err(|_| FileTransferError::InvalidUtf8)?;
+        Ok(FileAckHeader {
+            file_sender,
+            receiver,
+            saved,
+            filename,
+            error,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::new();
+        encode_route(&mut out, self.file_sender, self.receiver)?;
+        out.push(u8::from(self.saved));
+        encode_lp_string(&mut out, self.filename)?;
+        out.extend_from_slice(self.error.as_bytes());
+        Ok(out)
+    }
+}
+
+/// `FileOffer` from a client: filename|filedata, kept by the server for `/get`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileUploadHeader<'a> {
+    pub filename: &'a str,
+    pub data: &'a [u8],
+}
+
+impl<'a> FileUploadHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        let filename = fields.string()?;
+        Ok(FileUploadHeader {
+            filename,
+            data: fields.rest(),
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::with_capacity(self.data.len() + 64);
+        encode_lp_string(&mut out, self.filename)?;
+        out.extend_from_slice(self.data);
+        Ok(out)
+    }
+}
+
+/// `FileOffer` from the server: file_id(8)|sender|filename|filesize(8)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileOfferHeader<'a> {
+    /// What members pass to `FileGet` to download it
+    pub file_id: u64,
+    pub sender: &'a str,
+    pub filename: &'a str,
+    pub file_size: u64,
+}
+
+impl<'a> FileOfferHeader<'a> {
+    pub fn parse(content: &'a [u8]) -> Result<Self, FileTransferError> {
+        let mut fields = Fields::new(content);
+        Ok(FileOfferHeader {
+            file_id: fields.u64()?,
+            sender: fields.string()?,
+            filename: fields.string()?,
+            file_size: fields.u64()?,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileTransferError> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.file_id.to_be_bytes());
+        encode_lp_string(&mut out, self.sender)?;
+        encode_lp_string(&mut out, self.filename)?;
+        out.extend_from_slice(&self.file_size.to_be_bytes());
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn lp(value: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_lp_string(&mut out, value).unwrap();
+        out
+    }
+
+    fn relayed_request() -> Vec<u8> {
+        let mut content = [lp("bob"), lp("alice"), lp("a.txt")].concat();
+        content.extend_from_slice(&10u64.to_be_bytes());
+        content.extend_from_slice(&lp("for you"));
+        content
+    }
+
+    #[test]
+    fn test_parse_file_transfer() {
+        let mut content = [lp("bob"), lp("a.txt")].concat();
+        content.extend_from_slice(b"data");
+        let header = FileTransferHeader::parse(&content).unwrap();
+        assert_eq!(header.recipient, "bob");
+        assert_eq!(header.sender, None);
+        assert_eq!(header.filename, "a.txt");
+        assert_eq!(header.data, b"data");
+
+        let mut content = [lp("bob"), lp("alice"), lp("a.txt")].concat();
+        content.extend_from_slice(b"data");
+        let header = FileTransferHeader::parse_relayed(&content).unwrap();
+        assert_eq!(header.sender, Some("alice"));
+        assert_eq!(header.data, b"data");
+    }
+
+    #[test]
+    fn test_parse_request_with_and_without_caption() {
+        let content = relayed_request();
+        let header = FileRequestHeader::parse_relayed(&content).unwrap();
+        assert_eq!(header.recipient, "bob");
+        assert_eq!(header.sender, Some("alice"));
+        assert_eq!(header.filename, "a.txt");
+        assert_eq!(header.file_size, 10);
+        assert_eq!(header.caption, Some("for you"));
+
+        let mut content = [lp("bob"), lp("a.txt")].concat();
+        content.extend_from_slice(&10u64.to_be_bytes());
+        let header = FileRequestHeader::parse(&content).unwrap();
+        assert_eq!(header.caption, None);
+    }
+
+    #[test]
+    fn test_parse_response_and_ack() {
+        let content = [lp("alice"), vec![1]].concat();
+        let response = FileResponseHeader::parse(&content).unwrap();
+        assert_eq!(response.file_sender, "alice");
+        assert!(response.accepted);
+
+        let content = [
+            lp("alice"),
+            lp("bob"),
+            vec![0],
+            lp("a.txt"),
+            b"disk full".to_vec(),
+        ]
+        .concat();
+        let ack = FileAckHeader::parse_relayed(&content).unwrap();
+        assert_eq!(ack.receiver, Some("bob"));
+        assert!(!ack.saved);
+        assert_eq!(ack.filename, "a.txt");
+        assert_eq!(ack.error, "disk full");
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let request = FileRequestHeader {
+            recipient: "bob",
+            sender: Some("alice"),
+            filename: "a.txt",
+            file_size: 10,
+            caption: Some("for you"),
+        };
+        let content = request.encode().unwrap();
+        assert_eq!(content, relayed_request());
+        assert_eq!(FileRequestHeader::parse_relayed(&content).unwrap(), request);
+
+        let ack = FileAckHeader {
+            file_sender: "alice",
+            receiver: None,
+            saved: true,
+            filename: "a.txt",
+            error: "",
+        };
+        assert_eq!(FileAckHeader::parse(&ack.encode().unwrap()).unwrap(), ack);
+
+        let response = FileResponseHeader {
+            file_sender: "alice",
+            responder: Some("bob"),
+            accepted: false,
+        };
+        let content = response.encode().unwrap();
+        assert_eq!(
+            FileResponseHeader::parse_relayed(&content).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn test_shared_file_headers() {
+        let upload = FileUploadHeader {
+            filename: "notes.md",
+            data: b"# notes",
+        };
+        let content = upload.encode().unwrap();
+        assert_eq!(content, [lp("notes.md"), b"# notes".to_vec()].concat());
+        assert_eq!(FileUploadHeader::parse(&content).unwrap(), upload);
+
+        let offer = FileOfferHeader {
+            file_id: 7,
+            sender: "alice",
+            filename: "notes.md",
+            file_size: 7,
+        };
+        let content = offer.encode().unwrap();
+        assert_eq!(FileOfferHeader::parse(&content).unwrap(), offer);
+        for len in 0..content.len() {
+            assert!(FileOfferHeader::parse(&content[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_long_field_is_an_error_not_truncated() {
+        let recipient = "r".repeat(300);
+        let header = FileTransferHeader {
+            recipient: &recipient,
+            sender: None,
+            filename: "a.txt",
+            data: b"data",
+        };
+        assert_eq!(header.encode(), Err(FileTransferError::FieldTooLong(300)));
+
+        let mut out = Vec::new();
+        encode_lp_string(&mut out, &"x".repeat(MAX_FIELD_LENGTH)).unwrap();
+        assert_eq!(out.len(), MAX_FIELD_LENGTH + 1);
+        assert_eq!(decode_lp_string(&out).unwrap().1, b"");
+    }
+
+    #[test]
+    fn test_invalid_utf8_rejected() {
+        let content = [vec![2, 0xff, 0xfe], lp("a.txt")].concat();
+        assert_eq!(
+            FileTransferHeader::parse(&content),
+            Err(FileTransferError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn test_truncated_buffers_never_panic() {
+        let request = relayed_request();
+        let ack = [lp("alice"), lp("bob"), vec![1], lp("a.txt")].concat();
+        for len in 0..request.len() {
+            // Every strict prefix that cuts into a field is an error, never a panic
+            let truncated = &request[..len];
+            let _ = FileTransferHeader::parse_relayed(truncated);
+            let _ = FileResponseHeader::parse_relayed(truncated);
+            if len < request.len() - 8 {
+                assert!(FileRequestHeader::parse_relayed(truncated).is_err());
+            }
+        }
+        for len in 0..ack.len() {
+            assert!(FileAckHeader::parse_relayed(&ack[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_random_buffers_never_panic() {
+        // Small xorshift generator so the test is deterministic without extra deps
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..10_000 {
+            let len = (next() % 64) as usize;
+            let content: Vec<u8> = (0..len).map(|_| (next() % 24) as u8).collect();
+            for relayed in [false, true] {
+                let _ = FileTransferHeader::parse_fields(&content, relayed);
+                let _ = FileRequestHeader::parse_fields(&content, relayed);
+                let _ = FileResponseHeader::parse_fields(&content, relayed);
+                let _ = FileAckHeader::parse_fields(&content, relayed);
+            }
+        }
+    }
+
+    /// Mostly small bytes, so length prefixes often fit inside the buffer and
+    /// the parsers get past the first field
+    fn header_bytes() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(prop_oneof![0u8..16, any::<u8>()], 0..96)
+    }
+
+    proptest! {
+        #[test]
+        fn fuzz_headers_reencode_what_they_parse(content in header_bytes(), relayed in any::<bool>()) {
+            // A transfer's data runs to the end, so it has exactly one encoding
+            if let Ok(header) = FileTransferHeader::parse_fields(&content, relayed) {
+                prop_assert_eq!(header.encode().unwrap(), content.clone());
+            }
+            // Flags read any non-1 byte as false and bytes after the last field
+            // are ignored, so these only have to survive a second round
+            if let Ok(header) = FileRequestHeader::parse_fields(&content, relayed) {
+                let encoded = header.encode().unwrap();
+                prop_assert_eq!(FileRequestHeader::parse_fields(&encoded, relayed), Ok(header));
+            }
+            if let Ok(header) = FileResponseHeader::parse_fields(&content, relayed) {
+                let encoded = header.encode().unwrap();
+                prop_assert_eq!(FileResponseHeader::parse_fields(&encoded, relayed), Ok(header));
+            }
+            if let Ok(header) = FileAckHeader::parse_fields(&content, relayed) {
+                let encoded = header.encode().unwrap();
+                prop_assert_eq!(FileAckHeader::parse_fields(&encoded, relayed), Ok(header));
+            }
+        }
+
+        #[test]
+        fn fuzz_truncated_requests(
+            recipient in ".{0,20}",
+            sender in proptest::option::of(".{0,20}"),
+            filename in ".{0,40}",
+            file_size in any::<u64>(),
+            caption in proptest::option::of(".{0,40}"),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let request = FileRequestHeader {
+                recipient: &recipient,
+                sender: sender.as_deref(),
+                filename: &filename,
+                file_size,
+                caption: caption.as_deref(),
+            };
+            let content = request.encode().unwrap();
+            let relayed = sender.is_some();
+            prop_assert_eq!(FileRequestHeader::parse_fields(&content, relayed), Ok(request.clone()));
+
+            // Cut short anywhere, the request is refused unless the cut
+            // dropped exactly the optional caption
+            let truncated = &content[..cut.index(content.len())];
+            match FileRequestHeader::parse_fields(truncated, relayed) {
+                Ok(parsed) => {
+                    prop_assert!(request.caption.is_some());
+                    prop_assert_eq!(parsed.caption, None);
+                    prop_assert_eq!(parsed.filename, request.filename);
+                }
+                Err(error) => prop_assert_eq!(error, FileTransferError::Truncated),
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod commands;
+pub mod compression;
+pub mod file_transfer;
 pub mod input;
+pub mod limits;
 pub mod logger;
 pub mod message;
 pub mod network;
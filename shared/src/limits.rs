@@ -0,0 +1,99 @@
+//! Limits enforced by the server and advertised to clients after they join,
+//! so clients can validate input before sending it
+
+use crate::message::MAX_MESSAGE_LENGTH;
+
+pub const MAX_USERNAME_LENGTH: usize = 32;
+pub const MAX_STATUS_LENGTH: usize = 128; // Default max status message length
+pub const MAX_FILE_TRANSFERS: usize = 3; // Default max in-flight file transfers per user
+pub const MAX_CAPTION_LENGTH: usize = 255; // File transfer caption, sent with a one-byte length
+pub const MAX_SERVER_NAME_LENGTH: usize = 64; // CHAT_SERVER_NAME, shown to clients on join
+pub const MAX_SHARED_FILE_SIZE: usize = 10 * 1024 * 1024; // /share upload, kept in server memory
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerLimits {
+    pub max_message_length: usize,
+    pub max_status_length: usize,
+    pub max_username_length: usize,
+    /// Max file transfers a user may have in flight at once (0 = unlimited)
+    pub max_file_transfers: usize,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        ServerLimits {
+            max_message_length: MAX_MESSAGE_LENGTH,
+            max_status_length: MAX_STATUS_LENGTH,
+            max_username_length: MAX_USERNAME_LENGTH,
+            max_file_transfers: MAX_FILE_TRANSFERS,
+        }
+    }
+}
+
+impl ServerLimits {
+    /// Encode as `key=value` pairs separated by '|'
+    pub fn to_content(&self) -> String {
+        format!(
+            "max_message_length={}|max_status_length={}|max_username_length={}|max_file_transfers={}",
+            self.max_message_length,
+            self.max_status_length,
+            self.max_username_length,
+            self.max_file_transfers
+        )
+    }
+
+    /// Parse advertised limits. Unknown keys are ignored (so newer servers can add
+    /// limits) and missing or malformed values keep their defaults.
+    pub fn parse(content: &str) -> Self {
+        let mut limits = ServerLimits::default();
+        for (key, value) in content.split('|').filter_map(|pair| pair.split_once('=')) {
+            let Ok(value) = value.trim().parse::<usize>() else {
+                continue;
+            };
+            match key.trim() {
+                "max_message_length" => limits.max_message_length = value,
+                "max_status_length" => limits.max_status_length = value,
+                "max_username_length" => limits.max_username_length = value,
+                "max_file_transfers" => limits.max_file_transfers = value,
+                _ => {}
+            }
+        }
+        limits
+    }
+}
+
+/// Remove control characters (newlines, escapes, etc.) from text shown in listings
+pub fn strip_control_chars(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_roundtrip() {
+        let limits = ServerLimits {
+            max_message_length: 2048,
+            max_status_length: 64,
+            max_username_length: 16,
+            max_file_transfers: 5,
+        };
+        assert_eq!(ServerLimits::parse(&limits.to_content()), limits);
+    }
+
+    #[test]
+    fn test_limits_parse_tolerates_unknown_and_missing_keys() {
+        let limits = ServerLimits::parse("max_status_length=40|max_widgets=3|max_message_length=x");
+        assert_eq!(limits.max_status_length, 40);
+        assert_eq!(limits.max_message_length, MAX_MESSAGE_LENGTH);
+        assert_eq!(limits.max_username_length, MAX_USERNAME_LENGTH);
+        assert_eq!(limits.max_file_transfers, MAX_FILE_TRANSFERS);
+    }
+
+    #[test]
+    fn test_strip_control_chars() {
+        assert_eq!(strip_control_chars("away\n\x1b[31mred\t!"), "away[31mred!");
+        assert_eq!(strip_control_chars("plain status"), "plain status");
+    }
+}
@@ -1,77 +1,356 @@
-use chrono::Local;
-use colored::Colorize;
+use crate::message::mentions_user;
+use chrono::{Local, TimeZone};
+use colored::{Color, Colorize};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use terminal_size::{Width, terminal_size};
+
+static PLAIN_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Wrap width set by `set_terminal_width` (0 = not set)
+static TERMINAL_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+const CHAT_DEBUG_ENV_VAR: &str = "CHAT_DEBUG";
+
+static DEBUG_ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Writes one log line, returning false if it couldn't
+type Printer = Box<dyn FnMut(&str) -> bool + Send>;
+
+/// Set by an interactive prompt so log lines are drawn above the input line
+static PRINTER: Mutex<Option<Printer>> = Mutex::new(None);
+
+/// Send all log output to stderr without colors, keeping stdout free for
+/// machine-readable output (used by the client's stdio mode)
+pub fn use_plain_stderr() {
+    colored::control::set_override(false);
+    PLAIN_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Route log lines through `printer` instead of writing them directly, e.g.
+/// rustyline's external printer, which redraws the prompt and the user's
+/// partial input below each line. Lines fall back to stdout/stderr if the
+/// printer fails.
+pub fn set_printer(printer: impl FnMut(&str) -> bool + Send + 'static) {
+    if let Ok(mut current) = PRINTER.lock() {
+        *current = Some(Box::new(printer));
+    }
+}
+
+/// Print a log line through the installed printer if any, otherwise to
+/// stdout (stderr for errors). Plain stderr output bypasses the printer.
+fn print_line(line: &str, is_error: bool) {
+    if PLAIN_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+        return;
+    }
+    if let Ok(mut printer) = PRINTER.lock()
+        && let Some(printer) = printer.as_mut()
+        && printer(line)
+    {
+        return;
+    }
+    if is_error {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Print a log line to stdout, or stderr when plain stderr output is enabled
+macro_rules! log_println {
+    ($($arg:tt)*) => {
+        print_line(&format!($($arg)*), false)
+    };
+}
 
 fn get_timestamp() -> String {
     Local::now().format("%H:%M:%S").to_string()
 }
 
+/// Format a Unix millisecond timestamp in local time, falling back to now
+fn format_unix_millis(timestamp_ms: u64) -> String {
+    i64::try_from(timestamp_ms)
+        .ok()
+        .and_then(|ms| Local.timestamp_millis_opt(ms).single())
+        .map(|time| time.format("%H:%M:%S").to_string())
+        .unwrap_or_else(get_timestamp)
+}
+
+/// Width of the widest level tag, "[SYSTEM]"; narrower tags are padded to it
+/// so messages start in the same column
+const LEVEL_TAG_WIDTH: usize = 8;
+
+/// Colored level tag padded to `LEVEL_TAG_WIDTH`. The padding is added after
+/// coloring so the invisible ANSI codes don't count toward the width.
+fn level_tag(tag: &str, color: Color) -> String {
+    let padding = LEVEL_TAG_WIDTH.saturating_sub(tag.chars().count());
+    format!("{}{}", tag.color(color).bold(), " ".repeat(padding))
+}
+
 pub fn log_info(message: &str) {
-    println!(
+    log_println!(
         "{} {} {}",
         format!("[{}]", get_timestamp()).dimmed(),
-        "[INFO]".cyan().bold(),
+        level_tag("[INFO]", Color::Cyan),
         message
     );
 }
 
 pub fn log_success(message: &str) {
-    println!(
+    log_println!(
         "{} {} {}",
         format!("[{}]", get_timestamp()).dimmed(),
-        "[OK]".green().bold(),
+        level_tag("[OK]", Color::Green),
         message
     );
 }
 
 pub fn log_error(message: &str) {
-    eprintln!(
+    print_line(
+        &format!(
+            "{} {} {}",
+            format!("[{}]", get_timestamp()).dimmed(),
+            level_tag("[ERROR]", Color::Red),
+            message
+        ),
+        true,
+    );
+}
+
+pub fn log_warning(message: &str) {
+    log_println!(
         "{} {} {}",
         format!("[{}]", get_timestamp()).dimmed(),
-        "[ERROR]".red().bold(),
+        level_tag("[WARN]", Color::Yellow),
         message
     );
 }
 
-pub fn log_warning(message: &str) {
-    println!(
+pub fn log_system(message: &str) {
+    log_println!(
         "{} {} {}",
         format!("[{}]", get_timestamp()).dimmed(),
-        "[WARN]".yellow().bold(),
+        level_tag("[SYSTEM]", Color::Magenta),
         message
     );
 }
 
-pub fn log_system(message: &str) {
-    println!(
+/// Whether `CHAT_DEBUG` is set, so debug lines are printed. Read once, on first use.
+fn debug_enabled() -> bool {
+    *DEBUG_ENABLED.get_or_init(|| {
+        std::env::var(CHAT_DEBUG_ENV_VAR)
+            .is_ok_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+    })
+}
+
+/// Detail only useful when diagnosing problems; printed only with `CHAT_DEBUG=1`
+pub fn log_debug(message: &str) {
+    if !debug_enabled() {
+        return;
+    }
+    log_println!(
         "{} {} {}",
         format!("[{}]", get_timestamp()).dimmed(),
-        "[SYSTEM]".magenta().bold(),
+        level_tag("[DEBUG]", Color::BrightBlack),
         message
     );
 }
 
+/// Width of the "[HH:MM:SS] [CHAT]   " prefix, used to indent multi-line messages
+const CHAT_PREFIX_WIDTH: usize = 11 + LEVEL_TAG_WIDTH + 1;
+
+/// Narrowest text column worth wrapping into; below this lines are left to the terminal
+const MIN_WRAP_COLUMNS: usize = 20;
+
+/// Indent continuation lines so multi-line messages line up under the first line
+fn indent_continuation_lines(message: &str) -> String {
+    message.replace('\n', &format!("\n{}", " ".repeat(CHAT_PREFIX_WIDTH)))
+}
+
+/// Terminal width from the terminal itself or `$COLUMNS`, if either is known
+pub fn query_terminal_width() -> Option<usize> {
+    terminal_size()
+        .map(|(Width(width), _)| usize::from(width))
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+}
+
+/// Wrap chat lines to `width` columns from now on. Callers that follow
+/// terminal resizes set this so each line isn't measured again; None goes
+/// back to querying the terminal for every line.
+pub fn set_terminal_width(width: Option<usize>) {
+    TERMINAL_WIDTH.store(width.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Width to wrap chat lines to. Plain stderr output is for machines, so it is
+/// never wrapped.
+fn terminal_width() -> Option<usize> {
+    if PLAIN_STDERR.load(Ordering::Relaxed) {
+        return None;
+    }
+    match TERMINAL_WIDTH.load(Ordering::Relaxed) {
+        0 => query_terminal_width(),
+        width => Some(width),
+    }
+}
+
+/// Word-wrap a chat message to `width` columns. Wrapped lines are indented to
+/// line up under the message text (after "user: "), and explicit newlines are
+/// indented like `indent_continuation_lines`.
+fn wrap_chat_message(message: &str, width: usize) -> String {
+    let text_column = match message.split_once(": ") {
+        Some((username, _)) => CHAT_PREFIX_WIDTH + username.chars().count() + 2,
+        None => CHAT_PREFIX_WIDTH,
+    };
+    if width < text_column + MIN_WRAP_COLUMNS {
+        return indent_continuation_lines(message);
+    }
+
+    let wrap_indent = format!("\n{}", " ".repeat(text_column));
+    let lines: Vec<String> = message
+        .split('\n')
+        .map(|line| wrap_line(line, CHAT_PREFIX_WIDTH, text_column, width).join(&wrap_indent))
+        .collect();
+    lines.join(&format!("\n{}", " ".repeat(CHAT_PREFIX_WIDTH)))
+}
+
+/// Break `line` at spaces so no piece runs past `width`, splitting words longer
+/// than a whole line. The first piece starts at `start_column`, the rest at `indent`.
+fn wrap_line(line: &str, start_column: usize, indent: usize, width: usize) -> Vec<String> {
+    if start_column + line.chars().count() <= width {
+        return vec![line.to_string()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut column = start_column;
+    let mut at_piece_start = true;
+    for word in line.split(' ') {
+        let mut word: Vec<char> = word.chars().collect();
+        let separator = usize::from(!at_piece_start);
+        // Words too long for any line start on this one unless it is already full
+        let fits_fresh_line = indent + word.len() <= width;
+        let line_full = column + separator >= width;
+        if !at_piece_start
+            && column + separator + word.len() > width
+            && (fits_fresh_line || line_full)
+        {
+            pieces.push(std::mem::take(&mut current));
+            column = indent;
+            at_piece_start = true;
+        }
+        if !at_piece_start {
+            current.push(' ');
+            column += 1;
+        }
+        // A word longer than a whole line is split wherever the line ends
+        while column + word.len() > width {
+            let fits = width - column;
+            current.extend(word.drain(..fits));
+            pieces.push(std::mem::take(&mut current));
+            column = indent;
+        }
+        column += word.len();
+        current.extend(word);
+        at_piece_start = false;
+    }
+    pieces.push(current);
+    pieces
+}
+
 pub fn log_chat(message: &str) {
+    print_chat(&get_timestamp(), message, false);
+}
+
+/// Log a chat message received by `viewer`, using the server-assigned time
+/// (when known) instead of the local clock, and highlighting the message text
+/// if it mentions the viewer
+pub fn log_incoming_chat(message: &str, timestamp_ms: Option<u64>, viewer: &str) {
+    let timestamp = match timestamp_ms {
+        Some(timestamp_ms) => format_unix_millis(timestamp_ms),
+        None => get_timestamp(),
+    };
+    let mentioned = message
+        .split_once(": ")
+        .is_some_and(|(_, text)| mentions_user(text, viewer));
+    print_chat(&timestamp, message, mentioned);
+}
+
+/// Give each line of `text` a highlight background, leaving continuation
+/// indentation uncolored
+fn highlight_lines(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            let content = line.trim_start_matches(' ');
+            let indent = &line[..line.len() - content.len()];
+            format!("{}{}", indent, content.black().on_yellow())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn print_chat(timestamp: &str, message: &str, highlight: bool) {
+    let message = &match terminal_width() {
+        Some(width) => wrap_chat_message(message, width),
+        None => indent_continuation_lines(message),
+    };
     if let Some((username, msg)) = message.split_once(": ") {
         let colored_username = colorize_username(username);
-        println!(
+        let msg = if highlight {
+            highlight_lines(msg)
+        } else {
+            msg.to_string()
+        };
+        log_println!(
             "{} {} {}: {}",
-            format!("[{}]", get_timestamp()).dimmed(),
-            "[CHAT]".white().bold(),
+            format!("[{}]", timestamp).dimmed(),
+            level_tag("[CHAT]", Color::White),
             colored_username,
             msg
         );
     } else {
-        println!(
+        log_println!(
             "{} {} {}",
-            format!("[{}]", get_timestamp()).dimmed(),
-            "[CHAT]".white().bold(),
+            format!("[{}]", timestamp).dimmed(),
+            level_tag("[CHAT]", Color::White),
             message
         );
     }
 }
 
+/// Print a page (high-priority DM) from `sender` with its text highlighted
+pub fn log_page(sender: &str, message: &str) {
+    log_println!(
+        "{} {} {}: {}",
+        format!("[{}]", get_timestamp()).dimmed(),
+        level_tag("[PAGE]", Color::Red),
+        colorize_username(sender),
+        highlight_lines(&indent_continuation_lines(message))
+    );
+}
+
+/// Print a multi-line message inside a box (used for the server MOTD)
+pub fn log_banner(message: &str) {
+    for line in banner_lines(message) {
+        log_println!("{}", line.magenta().bold());
+    }
+}
+
+fn banner_lines(message: &str) -> Vec<String> {
+    let lines: Vec<String> = message.lines().map(|l| l.replace('\t', "    ")).collect();
+    let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+
+    let mut banner = vec![format!("╔{}╗", "═".repeat(width + 2))];
+    for line in &lines {
+        let padding = width - line.chars().count();
+        banner.push(format!("║ {}{} ║", line, " ".repeat(padding)));
+    }
+    banner.push(format!("╚{}╝", "═".repeat(width + 2)));
+    banner
+}
+
 fn colorize_username(username: &str) -> colored::ColoredString {
     let mut hasher = DefaultHasher::new();
     username.hash(&mut hasher);
@@ -95,3 +374,96 @@ fn colorize_username(username: &str) -> colored::ColoredString {
     let color_index = (hash as usize) % colors.len();
     username.color(colors[color_index]).bold()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiline_chat_indented() {
+        assert_eq!(indent_continuation_lines("single"), "single");
+        assert_eq!(
+            indent_continuation_lines("a: fn main() {\n}"),
+            format!("a: fn main() {{\n{}}}", " ".repeat(CHAT_PREFIX_WIDTH))
+        );
+    }
+
+    #[test]
+    fn test_long_chat_wrapped_under_message_text() {
+        // "bob: " puts the text at column 25, leaving 20 columns at width 45
+        let wrapped = wrap_chat_message("bob: the quick brown fox jumps over the lazy dog", 45);
+        let indent = " ".repeat(CHAT_PREFIX_WIDTH + 5);
+        assert_eq!(
+            wrapped,
+            format!("bob: the quick brown fox\n{indent}jumps over the lazy\n{indent}dog")
+        );
+
+        // Unbroken text is split at the line end
+        let wrapped = wrap_chat_message(&format!("bob: {}", "x".repeat(45)), 45);
+        let lines: Vec<&str> = wrapped.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("bob: {}", "x".repeat(20)));
+
+        // Short messages and narrow terminals are left alone
+        assert_eq!(wrap_chat_message("bob: hi", 45), "bob: hi");
+        assert_eq!(wrap_chat_message("bob: a b c d e", 30), "bob: a b c d e");
+    }
+
+    #[test]
+    fn test_set_terminal_width_used_for_wrapping() {
+        set_terminal_width(Some(77));
+        assert_eq!(terminal_width(), Some(77));
+        set_terminal_width(None);
+        assert_eq!(terminal_width(), query_terminal_width());
+    }
+
+    /// Character count with ANSI color sequences removed
+    fn visible_width(text: &str) -> usize {
+        let mut width = 0;
+        let mut in_escape = false;
+        for c in text.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => {}
+                _ => width += 1,
+            }
+        }
+        width
+    }
+
+    #[test]
+    fn test_level_tags_padded_to_same_width() {
+        for tag in ["[OK]", "[INFO]", "[WARN]", "[ERROR]", "[SYSTEM]", "[CHAT]"] {
+            let padded = level_tag(tag, Color::Cyan);
+            assert_eq!(visible_width(&padded), LEVEL_TAG_WIDTH, "{}", tag);
+            // Padding goes after the color codes so it is never styled
+            assert!(padded.ends_with(&" ".repeat(LEVEL_TAG_WIDTH - tag.len())));
+        }
+        assert_eq!(visible_width("\x1b[1;36m[OK]\x1b[0m  "), 6);
+    }
+
+    #[test]
+    fn test_format_unix_millis_uses_local_time() {
+        let expected = Local
+            .timestamp_millis_opt(1_700_000_000_000)
+            .unwrap()
+            .format("%H:%M:%S")
+            .to_string();
+        assert_eq!(format_unix_millis(1_700_000_000_000), expected);
+    }
+
+    #[test]
+    fn test_banner_lines_padded_to_widest_line() {
+        let banner = banner_lines("Welcome!\nBe nice");
+        assert_eq!(
+            banner,
+            vec![
+                "╔══════════╗",
+                "║ Welcome! ║",
+                "║ Be nice  ║",
+                "╚══════════╝",
+            ]
+        );
+    }
+}
@@ -17,9 +17,50 @@ pub enum MessageTypes {
     Pong,                 // Client response to Ping
     VersionCheck,         // Client sends version to server on connection: version string
     VersionMismatch, // Server responds with mismatch error: client_version|server_version|readme_url
+    KeyExchange,     // E2E public key for direct messages: peer|hex_public_key
+    Motd,            // Message of the day sent after join (may span multiple lines)
+    ServerLimits,    // Limits advertised after join: key=value|key=value...
+    ServerNotice,    // Announcement from the server operator (e.g. slow mode changes)
+    Topic,           // Channel topic: set request from a client, or current topic from the server
+    ServerInfo,      // Empty request from a client; reply is version=..|uptime=..|users=..
+    PeerHello,       // Peer link handshake: server_id|nonce, then HMAC proofs of the secret
+    PeerRelay,       // Message relayed between servers: origin_len|origin|inner message bytes
+    Whois,           // Username from a client; reply is a one-line description of that user
+    SeenRequest,     // Username from a client; reply says when that user was last active
+    Block,           // Block list change from a client: block|user, unblock|user or list
+    ChatAck,         // Empty reply to the sender once their chat message was accepted and broadcast
+    LeaveAck,        // Empty reply to an explicit Leave; the server closes the session after it
+    Page,            // High-priority DM: recipient|message from a client, sender|message to them
+    Poll,            // New poll: question|option|option... from a client, a PollSummary to everyone
+    Vote,            // Vote from a client: poll_id|option_number (1-based)
+    PollResult,      // Updated tallies for a poll (PollSummary), broadcast after each vote
+    ServerName,      // The server's configured display name, sent to each joiner
+    Compression,     // Client offer before joining (e.g. lz4); join replies confirm it
+    SessionSecret,   // Server-issued secret a client must echo to reclaim its session
+    Moderation,      // Operator request: oper|password, op|user, deop|user or kick|user
+    Search,          // Search term from a client; reply lists recent matching chat messages
+    FileOffer,       // File for the room: filename|data from a client, an announcement to everyone
+    FileGet,         // Shared file id from a client; reply is the file as a relayed FileTransfer
     Unknown(u8),
 }
 
+impl MessageTypes {
+    /// Whether the payload is UTF-8 text. File transfer payloads carry raw bytes.
+    pub fn carries_text(&self) -> bool {
+        !matches!(
+            self,
+            MessageTypes::FileTransfer
+                | MessageTypes::FileTransferAck
+                | MessageTypes::FileTransferRequest
+                | MessageTypes::FileTransferResponse
+                | MessageTypes::FileOffer
+                | MessageTypes::FileGet
+                | MessageTypes::PeerRelay
+                | MessageTypes::Unknown(_)
+        )
+    }
+}
+
 impl From<u8> for MessageTypes {
     fn from(value: u8) -> Self {
         match value {
@@ -40,11 +81,384 @@ impl From<u8> for MessageTypes {
             15 => MessageTypes::Pong,
             16 => MessageTypes::VersionCheck,
             17 => MessageTypes::VersionMismatch,
+            18 => MessageTypes::KeyExchange,
+            19 => MessageTypes::Motd,
+            20 => MessageTypes::ServerLimits,
+            21 => MessageTypes::ServerNotice,
+            22 => MessageTypes::Topic,
+            23 => MessageTypes::ServerInfo,
+            24 => MessageTypes::PeerHello,
+            25 => MessageTypes::PeerRelay,
+            26 => MessageTypes::Whois,
+            27 => MessageTypes::SeenRequest,
+            28 => MessageTypes::Block,
+            29 => MessageTypes::ChatAck,
+            30 => MessageTypes::LeaveAck,
+            31 => MessageTypes::Page,
+            32 => MessageTypes::Poll,
+            33 => MessageTypes::Vote,
+            34 => MessageTypes::PollResult,
+            35 => MessageTypes::ServerName,
+            36 => MessageTypes::Compression,
+            37 => MessageTypes::SessionSecret,
+            38 => MessageTypes::Moderation,
+            39 => MessageTypes::Search,
+            40 => MessageTypes::FileOffer,
+            41 => MessageTypes::FileGet,
             other => MessageTypes::Unknown(other),
         }
     }
 }
 
+/// Max chat/DM message content in bytes (enforced by the server, pre-checked by the client)
+pub const MAX_MESSAGE_LENGTH: usize = 1024;
+
+/// Prefix marking a direct message body as end-to-end encrypted ciphertext.
+/// The server relays these without inspecting the payload.
+pub const E2E_DM_PREFIX: &str = "e2e:";
+
+/// Text of the `ServerFull` error sent to clients turned away at the connection limit
+pub const SERVER_FULL_ERROR: &str = "Server full, try again later";
+
+/// Machine-readable reason at the start of an `Error` message's content
+/// (format: CODE|text), so clients can branch on it instead of the wording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Disconnected by the server operator
+    Kicked,
+    /// IP banned by the server operator
+    Banned,
+    /// Sending or joining too fast
+    RateLimited,
+    /// No user by that name is online
+    UserNotFound,
+    /// Turned away at the connection limit; clients back off before reconnecting
+    ServerFull,
+    /// Requested username is in use (the text ends with a suggested alternative)
+    NameTaken,
+    /// Malformed, oversized or out-of-range request
+    InvalidRequest,
+    /// Not allowed for this user or on this server
+    NotPermitted,
+    /// The recipient couldn't be reached
+    DeliveryFailed,
+    /// Anything else, including errors from servers that don't send codes
+    Other,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 10] = [
+        ErrorCode::Kicked,
+        ErrorCode::Banned,
+        ErrorCode::RateLimited,
+        ErrorCode::UserNotFound,
+        ErrorCode::ServerFull,
+        ErrorCode::NameTaken,
+        ErrorCode::InvalidRequest,
+        ErrorCode::NotPermitted,
+        ErrorCode::DeliveryFailed,
+        ErrorCode::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Kicked => "KICKED",
+            ErrorCode::Banned => "BANNED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::UserNotFound => "USER_NOT_FOUND",
+            ErrorCode::ServerFull => "SERVER_FULL",
+            ErrorCode::NameTaken => "NAME_TAKEN",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::NotPermitted => "NOT_PERMITTED",
+            ErrorCode::DeliveryFailed => "DELIVERY_FAILED",
+            ErrorCode::Other => "OTHER",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == code)
+    }
+}
+
+/// Split an `Error` message's content into its code and human-readable text.
+/// Content without a known code (e.g. from an older server) is `Other`.
+pub fn parse_error(content: &str) -> (ErrorCode, &str) {
+    content
+        .split_once('|')
+        .and_then(|(code, text)| Some((ErrorCode::parse(code)?, text)))
+        .unwrap_or((ErrorCode::Other, content))
+}
+
+/// Separates a "username taken" error from the free alternative the server
+/// suggests, e.g. `Username 'bob' is already taken. Suggested: bob_4821`
+pub const NAME_SUGGESTION_MARKER: &str = ". Suggested: ";
+
+/// The name suggested in a "username taken" error, if any
+pub fn parse_name_suggestion(error: &str) -> Option<&str> {
+    error
+        .rsplit_once(NAME_SUGGESTION_MARKER)
+        .map(|(_, name)| name.trim())
+        .filter(|name| !name.is_empty())
+}
+
+/// Status set by the client's `/afk` (`/away`) command, optionally followed by
+/// `: reason`, e.g. `AFK: lunch`
+pub const AWAY_STATUS_PREFIX: &str = "AFK";
+
+/// Status set while the client's `/dnd` (do not disturb) mode is on
+pub const DND_STATUS: &str = "DND";
+
+/// The away reason if `status` was set by `/afk` ("" when no reason was given)
+pub fn away_reason(status: &str) -> Option<&str> {
+    match status.strip_prefix(AWAY_STATUS_PREFIX)? {
+        "" => Some(""),
+        rest => rest.strip_prefix(':').map(str::trim),
+    }
+}
+
+/// Trailing Join field requesting a read-only connection
+/// (`username|session_token|readonly`)
+pub const READONLY_JOIN_FLAG: &str = "readonly";
+
+/// Starts the trailing Join field that echoes the secret issued on the last
+/// join (`username|session_token|secret=...`), proving a reconnect is ours
+pub const SESSION_SECRET_JOIN_PREFIX: &str = "secret=";
+
+/// Marks read-only users in the user list, e.g. `dashboard [readonly]`
+pub const READONLY_USER_MARKER: &str = " [readonly]";
+
+/// Marks users on a linked server in the user list, e.g. `carol [remote]`
+pub const REMOTE_USER_MARKER: &str = " [remote]";
+
+/// Users per page for a paginated user list (`/list <page>`)
+pub const USER_LIST_PAGE_SIZE: usize = 20;
+
+/// Starts the first line of a paginated user list, e.g. `#page 2/5 87`.
+/// Usernames can't contain '#' or spaces, so it never collides with a user.
+pub const USER_LIST_PAGE_MARKER: &str = "#page ";
+
+/// Position of one page within the full user list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserListPage {
+    /// 1-based page number
+    pub page: usize,
+    pub pages: usize,
+    /// Users online across all pages
+    pub total: usize,
+}
+
+impl UserListPage {
+    pub fn header(&self) -> String {
+        format!(
+            "{}{}/{} {}",
+            USER_LIST_PAGE_MARKER, self.page, self.pages, self.total
+        )
+    }
+
+    /// Parse a header line produced by `header`
+    pub fn parse_header(line: &str) -> Option<Self> {
+        let rest = line.strip_prefix(USER_LIST_PAGE_MARKER)?;
+        let (position, total) = rest.split_once(' ')?;
+        let (page, pages) = position.split_once('/')?;
+        Some(UserListPage {
+            page: page.parse().ok()?,
+            pages: pages.parse().ok()?,
+            total: total.parse().ok()?,
+        })
+    }
+}
+
+/// Server version and runtime details returned for `/serverinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Users currently joined
+    pub users: usize,
+}
+
+impl ServerInfo {
+    /// Encode as `key=value` pairs separated by '|'
+    pub fn to_content(&self) -> String {
+        format!(
+            "version={}|uptime={}|users={}",
+            self.version, self.uptime_secs, self.users
+        )
+    }
+
+    /// Parse a reply produced by `to_content`. Unknown keys are ignored so
+    /// newer servers can report more.
+    pub fn parse(content: &str) -> Option<Self> {
+        let (mut version, mut uptime_secs, mut users) = (None, None, None);
+        for (key, value) in content.split('|').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "version" => version = Some(value.to_string()),
+                "uptime" => uptime_secs = value.parse().ok(),
+                "users" => users = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(ServerInfo {
+            version: version?,
+            uptime_secs: uptime_secs?,
+            users: users?,
+        })
+    }
+
+    /// One-line summary, e.g. `Server v0.1.13, up 2d 3h 5m, 12 users online`
+    pub fn summary(&self) -> String {
+        let (days, hours, minutes) = (
+            self.uptime_secs / 86_400,
+            self.uptime_secs / 3_600 % 24,
+            self.uptime_secs / 60 % 60,
+        );
+        let uptime = if days > 0 {
+            format!("{}d {}h {}m", days, hours, minutes)
+        } else if hours > 0 {
+            format!("{}h {}m", hours, minutes)
+        } else if minutes > 0 {
+            format!("{}m", minutes)
+        } else {
+            format!("{}s", self.uptime_secs)
+        };
+        let users = if self.users == 1 { "user" } else { "users" };
+        format!(
+            "Server v{}, up {}, {} {} online",
+            self.version, uptime, self.users, users
+        )
+    }
+}
+
+/// Most options a poll may have
+pub const MAX_POLL_OPTIONS: usize = 10;
+/// Max poll question length in bytes
+pub const MAX_POLL_QUESTION_LENGTH: usize = 200;
+/// Max length of each poll option in bytes
+pub const MAX_POLL_OPTION_LENGTH: usize = 100;
+
+/// A poll and its current tallies, sent with `Poll` and `PollResult`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollSummary {
+    pub id: u64,
+    pub creator: String,
+    pub question: String,
+    /// Each option with its vote count, in the order they were given
+    pub options: Vec<(String, usize)>,
+}
+
+impl PollSummary {
+    /// Encode as an `id|creator|question` line followed by one
+    /// `count|option` line per option
+    pub fn to_content(&self) -> String {
+        let mut lines = vec![format!("{}|{}|{}", self.id, self.creator, self.question)];
+        lines.extend(
+            self.options
+                .iter()
+                .map(|(option, votes)| format!("{}|{}", votes, option)),
+        );
+        lines.join("\n")
+    }
+
+    /// Parse a summary produced by `to_content`
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let mut header = lines.next()?.splitn(3, '|');
+        let id = header.next()?.parse().ok()?;
+        let creator = header.next()?.to_string();
+        let question = header.next()?.to_string();
+        let options = lines
+            .map(|line| {
+                let (votes, option) = line.split_once('|')?;
+                Some((option.to_string(), votes.parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(PollSummary {
+            id,
+            creator,
+            question,
+            options,
+        })
+    }
+
+    pub fn total_votes(&self) -> usize {
+        self.options.iter().map(|(_, votes)| votes).sum()
+    }
+
+    /// The question and numbered options with their tallies, one per line
+    pub fn render(&self) -> String {
+        let mut lines = vec![format!(
+            "Poll #{} by {}: {}",
+            self.id, self.creator, self.question
+        )];
+        for (number, (option, votes)) in self.options.iter().enumerate() {
+            let noun = if *votes == 1 { "vote" } else { "votes" };
+            lines.push(format!("  {}. {} ({} {})", number + 1, option, votes, noun));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Current time as Unix milliseconds (the server's canonical clock for chat)
+pub fn unix_millis_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Prefix a broadcast chat body with the server timestamp: "<unix_ms>|<user>: <message>"
+pub fn stamp_chat_content(timestamp_ms: u64, body: &str) -> String {
+    format!("{}|{}", timestamp_ms, body)
+}
+
+/// Split a broadcast chat body into its server timestamp and the "<user>: <message>"
+/// text. Unstamped bodies are returned as-is; usernames can't contain '|', so a
+/// digits-only prefix before the first '|' is always a timestamp.
+pub fn parse_stamped_chat_content(content: &str) -> (Option<u64>, &str) {
+    match content.split_once('|') {
+        Some((stamp, body)) if !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit()) => {
+            (stamp.parse().ok(), body)
+        }
+        _ => (None, content),
+    }
+}
+
+/// The broadcast announcing a rename: "<old> is now known as <new>"
+pub fn rename_announcement(old_name: &str, new_name: &str) -> String {
+    format!("{} is now known as {}", old_name, new_name)
+}
+
+/// The old and new names in a rename announcement, ignoring any note after
+/// the new name. Usernames can't contain spaces, so chat text can't pass for one.
+pub fn parse_rename_announcement(content: &str) -> Option<(&str, &str)> {
+    let (old_name, rest) = content.split_once(" is now known as ")?;
+    let new_name = rest.split(' ').next()?;
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    };
+    (is_name(old_name) && is_name(new_name)).then_some((old_name, new_name))
+}
+
+/// Whether `text` mentions `username` as a whole word (case-insensitive).
+/// Usernames are alphanumeric plus '_' and '-', so any other character
+/// (including a leading '@') counts as a word boundary.
+pub fn mentions_user(text: &str, username: &str) -> bool {
+    if username.is_empty() {
+        return false;
+    }
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let text = text.to_lowercase();
+    let username = username.to_lowercase();
+    text.match_indices(&username).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + username.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char)
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct ChatMessage {
     msg_len: u32,
@@ -57,6 +471,17 @@ impl ChatMessage {
         self.content.as_deref()
     }
 
+    /// Raw payload bytes (empty if the message has no content)
+    pub fn content_bytes(&self) -> &[u8] {
+        self.content.as_deref().unwrap_or_default()
+    }
+
+    /// Payload decoded as UTF-8, or an error if it isn't valid text.
+    /// Unlike `content_as_string`, invalid text is distinguishable from no content.
+    pub fn content_text(&self) -> Result<Option<&str>, std::str::Utf8Error> {
+        self.content.as_deref().map(std::str::from_utf8).transpose()
+    }
+
     pub fn content_as_string(&self) -> Option<String> {
         self.content
             .as_ref()
@@ -70,6 +495,17 @@ pub enum ChatMessageError {
     InvalidLength,
 }
 
+impl std::fmt::Display for ChatMessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatMessageError::InvalidFormat => write!(f, "Invalid message format"),
+            ChatMessageError::InvalidLength => write!(f, "Invalid message length"),
+        }
+    }
+}
+
+impl std::error::Error for ChatMessageError {}
+
 impl ChatMessage {
     pub fn try_new(
         msg_type: MessageTypes,
@@ -88,6 +524,25 @@ impl ChatMessage {
             content,
         })
     }
+
+    /// `Error` message with a code clients can match on (format: CODE|text)
+    pub fn error(code: ErrorCode, text: &str) -> Result<Self, ChatMessageError> {
+        let content = format!("{}|{}", code.as_str(), text);
+        Self::try_new(MessageTypes::Error, Some(content.into_bytes()))
+    }
+
+    /// Decode a received frame, rejecting truncated buffers and frames whose
+    /// declared `msg_len` doesn't match the bytes actually received
+    pub fn try_from_bytes(buffer: Vec<u8>) -> Result<Self, ChatMessageError> {
+        if buffer.len() < 5 {
+            return Err(ChatMessageError::InvalidFormat);
+        }
+        let msg_len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+        if usize::try_from(msg_len).ok() != Some(buffer.len()) {
+            return Err(ChatMessageError::InvalidLength);
+        }
+        Ok(ChatMessage::from(buffer))
+    }
 }
 
 // Protocol: [msg_len (4 bytes)][msg_type (1 byte)][content (msg_len - 5 bytes)]
@@ -145,6 +600,30 @@ impl From<ChatMessage> for Vec<u8> {
             MessageTypes::Pong => 15,
             MessageTypes::VersionCheck => 16,
             MessageTypes::VersionMismatch => 17,
+            MessageTypes::KeyExchange => 18,
+            MessageTypes::Motd => 19,
+            MessageTypes::ServerLimits => 20,
+            MessageTypes::ServerNotice => 21,
+            MessageTypes::Topic => 22,
+            MessageTypes::ServerInfo => 23,
+            MessageTypes::PeerHello => 24,
+            MessageTypes::PeerRelay => 25,
+            MessageTypes::Whois => 26,
+            MessageTypes::SeenRequest => 27,
+            MessageTypes::Block => 28,
+            MessageTypes::ChatAck => 29,
+            MessageTypes::LeaveAck => 30,
+            MessageTypes::Page => 31,
+            MessageTypes::Poll => 32,
+            MessageTypes::Vote => 33,
+            MessageTypes::PollResult => 34,
+            MessageTypes::ServerName => 35,
+            MessageTypes::Compression => 36,
+            MessageTypes::SessionSecret => 37,
+            MessageTypes::Moderation => 38,
+            MessageTypes::Search => 39,
+            MessageTypes::FileOffer => 40,
+            MessageTypes::FileGet => 41,
             MessageTypes::Unknown(val) => val,
         });
         if let Some(content) = message.content {
@@ -157,6 +636,7 @@ impl From<ChatMessage> for Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_message_creation_valid() {
@@ -224,9 +704,68 @@ mod tests {
         assert!(matches!(MessageTypes::from(5), MessageTypes::ListUsers));
         assert!(matches!(MessageTypes::from(6), MessageTypes::DirectMessage));
         assert!(matches!(MessageTypes::from(7), MessageTypes::Error));
+        assert!(matches!(MessageTypes::from(18), MessageTypes::KeyExchange));
+        assert!(matches!(MessageTypes::from(19), MessageTypes::Motd));
+        assert!(matches!(MessageTypes::from(20), MessageTypes::ServerLimits));
+        assert!(matches!(MessageTypes::from(21), MessageTypes::ServerNotice));
+        assert!(matches!(MessageTypes::from(22), MessageTypes::Topic));
+        assert!(matches!(MessageTypes::from(23), MessageTypes::ServerInfo));
+        assert!(matches!(MessageTypes::from(24), MessageTypes::PeerHello));
+        assert!(matches!(MessageTypes::from(25), MessageTypes::PeerRelay));
+        assert!(matches!(MessageTypes::from(26), MessageTypes::Whois));
+        assert!(matches!(MessageTypes::from(27), MessageTypes::SeenRequest));
+        assert!(matches!(MessageTypes::from(28), MessageTypes::Block));
+        assert!(matches!(MessageTypes::from(29), MessageTypes::ChatAck));
+        assert!(matches!(MessageTypes::from(30), MessageTypes::LeaveAck));
+        assert!(matches!(MessageTypes::from(31), MessageTypes::Page));
+        assert!(matches!(MessageTypes::from(32), MessageTypes::Poll));
+        assert!(matches!(MessageTypes::from(33), MessageTypes::Vote));
+        assert!(matches!(MessageTypes::from(34), MessageTypes::PollResult));
+        assert!(matches!(MessageTypes::from(35), MessageTypes::ServerName));
+        assert!(matches!(MessageTypes::from(36), MessageTypes::Compression));
+        assert!(matches!(
+            MessageTypes::from(37),
+            MessageTypes::SessionSecret
+        ));
+        assert!(matches!(MessageTypes::from(38), MessageTypes::Moderation));
+        assert!(matches!(MessageTypes::from(39), MessageTypes::Search));
+        assert!(matches!(MessageTypes::from(40), MessageTypes::FileOffer));
+        assert!(matches!(MessageTypes::from(41), MessageTypes::FileGet));
         assert!(matches!(MessageTypes::from(99), MessageTypes::Unknown(99)));
     }
 
+    #[test]
+    fn test_stamped_chat_content_roundtrip() {
+        let stamped = stamp_chat_content(1_700_000_000_123, "alice: a|b");
+        assert_eq!(stamped, "1700000000123|alice: a|b");
+        assert_eq!(
+            parse_stamped_chat_content(&stamped),
+            (Some(1_700_000_000_123), "alice: a|b")
+        );
+        assert_eq!(
+            parse_stamped_chat_content("alice: a|b"),
+            (None, "alice: a|b")
+        );
+    }
+
+    #[test]
+    fn test_rename_announcement_roundtrip() {
+        let announcement = rename_announcement("alice", "alice_2");
+        assert_eq!(
+            parse_rename_announcement(&announcement),
+            Some(("alice", "alice_2"))
+        );
+        assert_eq!(
+            parse_rename_announcement(&format!("{} (renamed by server)", announcement)),
+            Some(("alice", "alice_2"))
+        );
+        assert_eq!(
+            parse_rename_announcement("bob: alice is now known as eve"),
+            None
+        );
+        assert_eq!(parse_rename_announcement("alice: hi"), None);
+    }
+
     #[test]
     fn test_empty_buffer_deserialization() {
         let msg = ChatMessage::from(vec![]);
@@ -242,6 +781,112 @@ mod tests {
         assert!(matches!(msg.msg_type, MessageTypes::Unknown(0)));
     }
 
+    #[test]
+    fn test_header_only_buffer_has_no_content() {
+        // Exactly the 5-byte header: type is read, content must be None (not Some(empty))
+        let msg = ChatMessage::from(vec![0, 0, 0, 5, 5]);
+        assert!(matches!(msg.msg_type, MessageTypes::ListUsers));
+        assert_eq!(msg.content, None);
+
+        // One byte short of a header is treated as malformed
+        let msg = ChatMessage::from(vec![0, 0, 0, 5]);
+        assert!(matches!(msg.msg_type, MessageTypes::Unknown(0)));
+        assert_eq!(msg.content, None);
+    }
+
+    #[test]
+    fn test_content_starts_after_header() {
+        let msg = ChatMessage::from(vec![0, 0, 0, 6, 1, b'x']);
+        assert_eq!(msg.content, Some(b"x".to_vec()));
+
+        let msg = ChatMessage::from(vec![0, 0, 0, 8, 1, b'a', b'b', b'c']);
+        assert_eq!(msg.content, Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_error_code_round_trip() {
+        for code in ErrorCode::ALL {
+            let message = ChatMessage::error(code, "details | with a pipe").unwrap();
+            let content = message.content_as_string().unwrap();
+            assert_eq!(parse_error(&content), (code, "details | with a pipe"));
+        }
+        // Plain text from an older server, even if it contains a '|'
+        assert_eq!(
+            parse_error("You have been kicked | bye"),
+            (ErrorCode::Other, "You have been kicked | bye")
+        );
+    }
+
+    #[test]
+    fn test_parse_name_suggestion() {
+        let error = format!("Name taken{}bob_4821", NAME_SUGGESTION_MARKER);
+        assert_eq!(parse_name_suggestion(&error), Some("bob_4821"));
+        assert_eq!(parse_name_suggestion("Name taken"), None);
+    }
+
+    #[test]
+    fn test_user_list_page_header_roundtrip() {
+        let page = UserListPage {
+            page: 2,
+            pages: 5,
+            total: 87,
+        };
+        assert_eq!(page.header(), "#page 2/5 87");
+        assert_eq!(UserListPage::parse_header(&page.header()), Some(page));
+        assert_eq!(UserListPage::parse_header("alice - #page 1/1 1"), None);
+        assert_eq!(UserListPage::parse_header("#page x/1 1"), None);
+    }
+
+    #[test]
+    fn test_try_from_bytes_roundtrip() {
+        let msg = ChatMessage::try_new(MessageTypes::ChatMessage, Some(b"hello".to_vec())).unwrap();
+        let bytes: Vec<u8> = msg.into();
+        let decoded = ChatMessage::try_from_bytes(bytes).unwrap();
+        assert_eq!(decoded.msg_type, MessageTypes::ChatMessage);
+        assert_eq!(decoded.content, Some(b"hello".to_vec()));
+
+        let ping = ChatMessage::try_new(MessageTypes::Ping, None).unwrap();
+        let bytes: Vec<u8> = ping.into();
+        assert!(ChatMessage::try_from_bytes(bytes).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_bytes_rejects_corrupted_length() {
+        let msg = ChatMessage::try_new(MessageTypes::ChatMessage, Some(b"hello".to_vec())).unwrap();
+        let mut bytes: Vec<u8> = msg.into();
+        bytes[3] += 1; // Claims one more byte than was sent
+        assert!(matches!(
+            ChatMessage::try_from_bytes(bytes.clone()),
+            Err(ChatMessageError::InvalidLength)
+        ));
+
+        bytes[3] -= 1;
+        bytes.pop(); // Truncated frame
+        assert!(matches!(
+            ChatMessage::try_from_bytes(bytes),
+            Err(ChatMessageError::InvalidLength)
+        ));
+
+        assert!(matches!(
+            ChatMessage::try_from_bytes(vec![0, 0, 0, 5]),
+            Err(ChatMessageError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn test_content_bytes_and_text() {
+        let msg = ChatMessage::try_new(MessageTypes::ChatMessage, Some(vec![0xff, 0xfe])).unwrap();
+        assert_eq!(msg.content_bytes(), &[0xff, 0xfe]);
+        assert!(msg.content_text().is_err());
+
+        let msg = ChatMessage::try_new(MessageTypes::Ping, None).unwrap();
+        assert!(msg.content_bytes().is_empty());
+        assert_eq!(msg.content_text().unwrap(), None);
+
+        let msg = ChatMessage::try_new(MessageTypes::ChatMessage, Some(b"hi".to_vec())).unwrap();
+        assert_eq!(msg.content_text().unwrap(), Some("hi"));
+    }
+
     #[test]
     fn test_content_as_string_valid_utf8() {
         let msg =
@@ -258,4 +903,130 @@ mod tests {
         .unwrap();
         assert_eq!(msg.content_as_string(), None);
     }
+
+    #[test]
+    fn test_server_info_round_trip() {
+        let info = ServerInfo {
+            version: "0.1.13".to_string(),
+            uptime_secs: 2 * 86_400 + 3 * 3_600 + 5 * 60 + 9,
+            users: 12,
+        };
+        assert_eq!(info.to_content(), "version=0.1.13|uptime=183909|users=12");
+        assert_eq!(ServerInfo::parse(&info.to_content()), Some(info.clone()));
+        assert_eq!(
+            info.summary(),
+            "Server v0.1.13, up 2d 3h 5m, 12 users online"
+        );
+        assert_eq!(ServerInfo::parse("version=0.1.13|uptime=5"), None);
+
+        let fresh = ServerInfo {
+            uptime_secs: 42,
+            users: 1,
+            ..info
+        };
+        assert_eq!(fresh.summary(), "Server v0.1.13, up 42s, 1 user online");
+    }
+
+    #[test]
+    fn test_poll_summary_round_trip() {
+        let poll = PollSummary {
+            id: 7,
+            creator: "alice".to_string(),
+            question: "Lunch?".to_string(),
+            options: vec![("pizza".to_string(), 2), ("tacos".to_string(), 0)],
+        };
+        assert_eq!(poll.to_content(), "7|alice|Lunch?\n2|pizza\n0|tacos");
+        assert_eq!(PollSummary::parse(&poll.to_content()), Some(poll.clone()));
+        assert_eq!(poll.total_votes(), 2);
+        assert_eq!(
+            poll.render(),
+            "Poll #7 by alice: Lunch?\n  1. pizza (2 votes)\n  2. tacos (0 votes)"
+        );
+        assert_eq!(PollSummary::parse("7|alice|Lunch?\nmany|pizza"), None);
+        assert_eq!(PollSummary::parse("x|alice|Lunch?"), None);
+    }
+
+    #[test]
+    fn test_away_reason() {
+        assert_eq!(away_reason("AFK"), Some(""));
+        assert_eq!(away_reason("AFK: lunch"), Some("lunch"));
+        assert_eq!(away_reason("AFK for lunch"), None);
+        assert_eq!(away_reason("coding"), None);
+    }
+
+    #[test]
+    fn test_mentions_user_whole_word() {
+        assert!(mentions_user("hey Alice, lunch?", "alice"));
+        assert!(mentions_user("alice", "alice"));
+        assert!(!mentions_user("that was malice", "alice"));
+        assert!(!mentions_user("alice_2 is here", "alice"));
+        assert!(!mentions_user("anything", ""));
+        assert!(mentions_user("thanks @alice!", "alice"));
+        assert!(!mentions_user("the alices are here", "alice"));
+    }
+
+    /// A frame whose declared length matches its size
+    fn frame(type_byte: u8, content: &[u8]) -> Vec<u8> {
+        let mut buffer = ((content.len() + 5) as u32).to_be_bytes().to_vec();
+        buffer.push(type_byte);
+        buffer.extend_from_slice(content);
+        buffer
+    }
+
+    proptest! {
+        #[test]
+        fn fuzz_frame_parsing(buffer in prop::collection::vec(any::<u8>(), 0..64)) {
+            // Anything too short for a header becomes an empty Unknown(0)
+            let message = ChatMessage::from(buffer.clone());
+            if buffer.len() < 5 {
+                prop_assert_eq!(message.msg_type, MessageTypes::Unknown(0));
+                prop_assert_eq!(message.content, None);
+            }
+
+            let declared_matches = buffer.len() >= 5
+                && u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize
+                    == buffer.len();
+            match ChatMessage::try_from_bytes(buffer.clone()) {
+                Ok(message) => {
+                    prop_assert!(declared_matches);
+                    prop_assert_eq!(Vec::<u8>::from(message), buffer);
+                }
+                Err(_) => prop_assert!(!declared_matches),
+            }
+        }
+
+        #[test]
+        fn fuzz_well_formed_frames(
+            type_byte in any::<u8>(),
+            content in prop::collection::vec(any::<u8>(), 0..256),
+        ) {
+            let buffer = frame(type_byte, &content);
+            let message = ChatMessage::try_from_bytes(buffer.clone()).unwrap();
+            prop_assert_eq!(message.get_content().unwrap_or_default(), &content[..]);
+            prop_assert_eq!(Vec::<u8>::from(message), buffer.clone());
+
+            // Dropping or adding a byte breaks the declared length
+            let mut longer = buffer.clone();
+            longer.push(0);
+            prop_assert!(ChatMessage::try_from_bytes(longer).is_err());
+            prop_assert!(ChatMessage::try_from_bytes(buffer[..buffer.len() - 1].to_vec()).is_err());
+        }
+
+        #[test]
+        fn fuzz_text_parsers(content in any::<String>()) {
+            let (_, text) = parse_error(&content);
+            prop_assert!(content.ends_with(text));
+            let (stamp, body) = parse_stamped_chat_content(&content);
+            prop_assert!(content.ends_with(body));
+            prop_assert_eq!(stamp.is_some(), body.len() < content.len());
+            let _ = parse_name_suggestion(&content);
+            let _ = away_reason(&content);
+            let _ = mentions_user(&content, "alice");
+            let _ = UserListPage::parse_header(&content);
+            let _ = PollSummary::parse(&content);
+            if let Some(info) = ServerInfo::parse(&content) {
+                prop_assert_eq!(ServerInfo::parse(&info.to_content()), Some(info));
+            }
+        }
+    }
 }
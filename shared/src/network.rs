@@ -1,10 +1,44 @@
+use crate::compression;
+use crate::logger;
 use crate::message::ChatMessage;
+use std::sync::OnceLock;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// Default bytes written or read at a time while framing a message
 pub const CHUNK_SIZE: usize = 8192;
+/// Smallest chunk size accepted from `CHAT_CHUNK_SIZE`
+pub const MIN_CHUNK_SIZE: usize = 512;
+/// Largest chunk size accepted from `CHAT_CHUNK_SIZE`
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
 pub const MAX_MESSAGE_SIZE: usize = 8192; // 8KB max message size for regular messages
 pub const MAX_FILE_SIZE: usize = 100 * 1024 * 1024; // 100MB max file size
 
+const CHAT_CHUNK_SIZE_ENV_VAR: &str = "CHAT_CHUNK_SIZE";
+
+static CONFIGURED_CHUNK_SIZE: OnceLock<usize> = OnceLock::new();
+
+/// Chunk size for this process: `CHAT_CHUNK_SIZE` (clamped to
+/// `MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE`) if set, otherwise `CHUNK_SIZE`.
+/// Read once, on first use.
+///
+/// Frames are length-prefixed, so the chunk size only decides how much each
+/// side writes or reads per call; the two ends don't need to agree on it.
+pub fn configured_chunk_size() -> usize {
+    *CONFIGURED_CHUNK_SIZE.get_or_init(|| match std::env::var(CHAT_CHUNK_SIZE_ENV_VAR) {
+        Ok(val) => match val.trim().parse::<usize>() {
+            Ok(size) => size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE),
+            Err(_) => {
+                logger::log_warning(&format!(
+                    "Invalid {} '{}', using {} bytes",
+                    CHAT_CHUNK_SIZE_ENV_VAR, val, CHUNK_SIZE
+                ));
+                CHUNK_SIZE
+            }
+        },
+        Err(_) => CHUNK_SIZE,
+    })
+}
+
 pub enum TcpMessageHandlerError {
     IoError(std::io::Error),
     Disconnect,
@@ -15,8 +49,29 @@ pub trait TcpMessageHandler {
     type Stream: AsyncRead + AsyncWrite + Unpin;
     fn get_stream(&mut self) -> &mut Self::Stream;
 
+    /// Bytes written or read at a time while framing messages
+    fn chunk_size(&self) -> usize {
+        configured_chunk_size()
+    }
+
+    /// Whether the peer negotiated compression, so large frames sent to it
+    /// may be compressed
+    fn compression(&self) -> bool {
+        false
+    }
+
+    /// Whether compressed frames may be read from the peer. Only once
+    /// compression was negotiated; a compressed frame before that is a
+    /// protocol error.
+    fn accepts_compressed(&self) -> bool {
+        self.compression()
+    }
+
     async fn send_message_chunked(&mut self, message: ChatMessage) -> Result<(), std::io::Error> {
-        let message_bytes: Vec<u8> = message.into();
+        let mut message_bytes: Vec<u8> = message.into();
+        if self.compression() {
+            message_bytes = compression::compress_frame(message_bytes);
+        }
 
         // Validate message size to prevent integer overflow
         let msg_len = u32::try_from(message_bytes.len()).map_err(|_| {
@@ -27,13 +82,8 @@ pub trait TcpMessageHandler {
         self.get_stream().write_all(&msg_len.to_be_bytes()).await?;
 
         // Send the message in chunks
-        let mut bytes_sent = 0;
-        while bytes_sent < message_bytes.len() {
-            let chunk_size = std::cmp::min(CHUNK_SIZE, message_bytes.len() - bytes_sent);
-            let chunk = &message_bytes[bytes_sent..bytes_sent + chunk_size];
-
+        for chunk in message_bytes.chunks(self.chunk_size()) {
             self.get_stream().write_all(chunk).await?;
-            bytes_sent += chunk_size;
         }
 
         self.get_stream().flush().await?;
@@ -79,13 +129,14 @@ pub trait TcpMessageHandler {
 
         // Read the message in chunks to handle large messages
         let mut message_bytes = Vec::with_capacity(msg_len);
+        let mut chunk = vec![0u8; std::cmp::min(self.chunk_size(), msg_len)];
         let mut bytes_read = 0;
 
         while bytes_read < msg_len {
-            let mut chunk = vec![0u8; std::cmp::min(CHUNK_SIZE, msg_len - bytes_read)];
+            let want = std::cmp::min(chunk.len(), msg_len - bytes_read);
             let n = self
                 .get_stream()
-                .read(&mut chunk)
+                .read(&mut chunk[..want])
                 .await
                 .map_err(TcpMessageHandlerError::IoError)?;
 
@@ -97,6 +148,18 @@ pub trait TcpMessageHandler {
             bytes_read += n;
         }
 
+        let message_bytes = compression::decompress_frame(message_bytes, self.accepts_compressed())
+            .map_err(TcpMessageHandlerError::IoError)?;
+
+        // Reject frames whose declared length doesn't match what arrived
+        // (before acknowledging, so the sender never sees an OK for them)
+        let message = ChatMessage::try_from_bytes(message_bytes).map_err(|_| {
+            TcpMessageHandlerError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Message length does not match frame size",
+            ))
+        })?;
+
         // Send OK response to acknowledge receipt
         self.get_stream().write_all(b"OK").await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::UnexpectedEof {
@@ -112,8 +175,100 @@ pub trait TcpMessageHandler {
                 TcpMessageHandlerError::IoError(e)
             }
         })?;
-        let message = ChatMessage::from(message_bytes);
 
         Ok(message)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageTypes;
+    use tokio::io::DuplexStream;
+
+    /// Framing endpoint with a tiny chunk size
+    struct SmallChunks(DuplexStream);
+
+    impl TcpMessageHandler for SmallChunks {
+        type Stream = DuplexStream;
+        fn get_stream(&mut self) -> &mut Self::Stream {
+            &mut self.0
+        }
+        fn chunk_size(&self) -> usize {
+            16
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_spanning_many_chunks() {
+        // A small pipe buffer keeps the two sides interleaving chunk by chunk
+        let (a, b) = tokio::io::duplex(64);
+        let (mut sender, mut receiver) = (SmallChunks(a), SmallChunks(b));
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let message = ChatMessage::try_new(MessageTypes::FileTransfer, Some(payload.clone()))
+            .expect("valid message");
+
+        let (sent, received) = tokio::join!(
+            sender.send_message_chunked(message),
+            receiver.read_message_chunked()
+        );
+        sent.expect("send succeeds");
+        let Ok(received) = received else {
+            panic!("receive failed");
+        };
+        assert_eq!(received.msg_type, MessageTypes::FileTransfer);
+        assert_eq!(received.get_content(), Some(payload.as_slice()));
+    }
+
+    /// Framing endpoint that sends compressed frames
+    struct Compressed(DuplexStream);
+
+    impl TcpMessageHandler for Compressed {
+        type Stream = DuplexStream;
+        fn get_stream(&mut self) -> &mut Self::Stream {
+            &mut self.0
+        }
+        fn compression(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_frames_round_trip() {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let (mut sender, mut receiver) = (Compressed(a), Compressed(b));
+        let text = "compressible chat text ".repeat(100);
+        for content in [text.as_str(), "short"] {
+            let message =
+                ChatMessage::try_new(MessageTypes::ChatMessage, Some(content.as_bytes().to_vec()))
+                    .expect("valid message");
+            let (sent, received) = tokio::join!(
+                sender.send_message_chunked(message),
+                receiver.read_message_chunked()
+            );
+            sent.expect("send succeeds");
+            let Ok(received) = received else {
+                panic!("receive failed");
+            };
+            assert_eq!(received.content_as_string().as_deref(), Some(content));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_frame_rejected_without_negotiation() {
+        let (mut a, b) = tokio::io::duplex(64 * 1024);
+        let mut receiver = SmallChunks(b);
+        let text = "compressible chat text ".repeat(100);
+        let message_bytes: Vec<u8> =
+            ChatMessage::try_new(MessageTypes::ChatMessage, Some(text.into_bytes()))
+                .expect("valid message")
+                .into();
+        let payload = compression::compress_frame(message_bytes);
+        a.write_all(&(payload.len() as u32).to_be_bytes())
+            .await
+            .unwrap();
+        a.write_all(&payload).await.unwrap();
+
+        assert!(receiver.read_message_chunked().await.is_err());
+    }
+}
@@ -5,10 +5,58 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// GitHub README URL for upgrade instructions
 pub const GITHUB_README_URL: &str = "https://github.com/mikemiles-dev/rust_chat#readme";
 
-/// Check if two versions are compatible
-/// For now, versions must match exactly
+/// A `major.minor.patch[-pre][+build]` version; build metadata is dropped
+#[derive(Debug, PartialEq)]
+struct Version<'a> {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Option<&'a str>,
+}
+
+impl<'a> Version<'a> {
+    fn parse(text: &'a str) -> Option<Self> {
+        let without_build = text.split_once('+').map_or(text, |(version, _)| version);
+        let (core, pre) = match without_build.split_once('-') {
+            Some((_, "")) => return None,
+            Some((core, pre)) => (core, Some(pre)),
+            None => (without_build, None),
+        };
+        let mut numbers = core.split('.').map(parse_number);
+        let version = Version {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+            pre,
+        };
+        numbers.next().is_none().then_some(version)
+    }
+}
+
+/// Plain decimal digits only (no signs or whitespace, which `parse` would accept)
+fn parse_number(text: &str) -> Option<u64> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Check if a client and server can talk to each other, following semver:
+/// releases are compatible when the major version matches, and while the major
+/// version is 0, when the minor version matches too (so patch releases mix).
+/// Pre-releases only match the exact same pre-release, and versions that don't
+/// parse only match themselves.
 pub fn versions_compatible(client_version: &str, server_version: &str) -> bool {
-    client_version == server_version
+    let (Some(client), Some(server)) = (
+        Version::parse(client_version),
+        Version::parse(server_version),
+    ) else {
+        return client_version == server_version;
+    };
+    if client.pre.is_some() || server.pre.is_some() {
+        return client == server;
+    }
+    client.major == server.major && (client.major > 0 || client.minor == server.minor)
 }
 
 /// Format version mismatch error message
@@ -31,11 +79,73 @@ mod tests {
     #[test]
     fn test_versions_compatible_same() {
         assert!(versions_compatible("0.1.8", "0.1.8"));
+        assert!(versions_compatible(VERSION, VERSION));
     }
 
     #[test]
     fn test_versions_compatible_different() {
-        assert!(!versions_compatible("0.1.7", "0.1.8"));
+        assert!(!versions_compatible("0.1.8", "0.2.0"));
+        assert!(!versions_compatible("1.4.0", "2.0.0"));
+    }
+
+    #[test]
+    fn test_patch_releases_compatible_before_1_0() {
+        // Older and newer clients within the same 0.minor series
+        assert!(versions_compatible("0.1.7", "0.1.8"));
+        assert!(versions_compatible("0.1.9", "0.1.8"));
+        // 0.x minor bumps are breaking
+        assert!(!versions_compatible("0.2.0", "0.1.8"));
+        assert!(!versions_compatible("0.0.3", "0.1.0"));
+    }
+
+    #[test]
+    fn test_minor_releases_compatible_after_1_0() {
+        assert!(versions_compatible("1.2.0", "1.5.3"));
+        assert!(versions_compatible("1.5.3", "1.0.0"));
+        assert!(!versions_compatible("1.9.9", "2.0.0"));
+        assert!(!versions_compatible("0.9.0", "1.0.0"));
+    }
+
+    #[test]
+    fn test_pre_release_versions_match_exactly() {
+        assert!(versions_compatible("0.2.0-beta.1", "0.2.0-beta.1"));
+        assert!(!versions_compatible("0.2.0-beta.1", "0.2.0-beta.2"));
+        assert!(!versions_compatible("0.2.0-beta.1", "0.2.0"));
+        assert!(!versions_compatible("1.0.0", "1.0.0-rc.1"));
+    }
+
+    #[test]
+    fn test_build_metadata_ignored() {
+        assert!(versions_compatible("0.1.8+abc123", "0.1.8"));
+        assert!(versions_compatible("0.1.8+linux", "0.1.9+macos"));
+        assert!(versions_compatible("0.2.0-rc.1+1", "0.2.0-rc.1+2"));
+    }
+
+    #[test]
+    fn test_malformed_versions_only_match_themselves() {
+        for malformed in [
+            "", "0.1", "0.1.8.1", "v0.1.8", "0.1.x", "0.-1.8", " 0.1.8", "0.1.8-",
+        ] {
+            assert!(Version::parse(malformed).is_none(), "{:?}", malformed);
+            assert!(!versions_compatible(malformed, "0.1.8"), "{:?}", malformed);
+            assert!(!versions_compatible("0.1.8", malformed), "{:?}", malformed);
+        }
+        assert!(versions_compatible("dev", "dev"));
+        assert!(!versions_compatible("dev", "devel"));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            Version::parse("1.22.333-alpha.1+build.5"),
+            Some(Version {
+                major: 1,
+                minor: 22,
+                patch: 333,
+                pre: Some("alpha.1"),
+            })
+        );
+        assert!(Version::parse(VERSION).is_some());
     }
 
     #[test]